@@ -1,191 +1,96 @@
 //! DeepVault GUI - Tauri application
 
+use deepvault_core::header::DEFAULT_DATA_OFFSET;
 use deepvault_core::*;
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Mutex;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct EncryptedFile {
-    name: String,
-    path: String,
-    is_directory: bool,
-    size: u64,
-    modified: i64,
-    content: Option<Vec<u8>>,
-}
-
-#[derive(Debug, Clone)]
+/// Session ouverte sur la partition chiffrée, déverrouillée par son en-tête
 struct EncryptedSession {
     session_id: String,
     disk_num: u32,
     partition_num: u32,
-    partition_offset: u64, // Offset de la partition en secteurs
-    partition_size: u64,   // Taille de la partition en secteurs
-    files: HashMap<String, EncryptedFile>,
+    header: VaultHeader,
+    master_key: SecretKey,
 }
 
+/// Système de fichiers du coffre, stocké dans le volume déchiffré de la partition
+type PartitionFs = vaultfs::VaultFs<Box<dyn BlockDevice>>;
+
 // Stockage global des sessions chiffrées
 lazy_static::lazy_static! {
     static ref ENCRYPTED_SESSIONS: Mutex<HashMap<String, EncryptedSession>> = Mutex::new(HashMap::new());
 }
 
-/// Accès direct aux secteurs de la partition chiffrée
-fn read_partition_sectors(
-    disk_num: u32,
-    partition_offset: u64,
-    sector_count: u32,
-) -> std::result::Result<Vec<u8>, String> {
-    use std::fs::File;
-    use std::io::prelude::*;
-
-    let disk_path = format!("\\\\.\\PhysicalDrive{}", disk_num);
-    let mut file = File::open(&disk_path)
-        .map_err(|e| format!("Impossible d'ouvrir le disque {}: {}", disk_path, e))?;
-
-    // Allouer le buffer pour les secteurs
-    let sector_size = 512u64; // Taille standard d'un secteur
-    let buffer_size = (sector_count as usize) * (sector_size as usize);
-    let mut buffer = vec![0u8; buffer_size];
-
-    // Positionner le pointeur à l'offset de la partition
-    let byte_offset = partition_offset * sector_size;
-    file.seek(std::io::SeekFrom::Start(byte_offset))
-        .map_err(|e| format!("Impossible de se positionner sur la partition: {}", e))?;
-
-    // Lire les secteurs
-    file.read_exact(&mut buffer)
-        .map_err(|e| format!("Impossible de lire les secteurs: {}", e))?;
-
-    Ok(buffer)
-}
-
-/// Écrit des données dans les secteurs de la partition chiffrée
-fn write_partition_sectors(
-    disk_num: u32,
-    partition_offset: u64,
-    data: &[u8],
-) -> std::result::Result<(), String> {
-    use std::fs::OpenOptions;
-    use std::io::prelude::*;
-
-    let disk_path = format!("\\\\.\\PhysicalDrive{}", disk_num);
-    let mut file = OpenOptions::new()
+/// Accès direct aux secteurs d'une partition; Windows limite les accès à
+/// ses bornes réelles
+fn open_partition(disk_num: u32, partition_num: u32) -> std::result::Result<std::fs::File, String> {
+    let partition_path = format!("\\\\.\\Harddisk{}Partition{}", disk_num, partition_num);
+    std::fs::OpenOptions::new()
+        .read(true)
         .write(true)
-        .open(&disk_path)
-        .map_err(|e| {
-            format!(
-                "Impossible d'ouvrir le disque en écriture {}: {}",
-                disk_path, e
-            )
-        })?;
-
-    // Positionner le pointeur à l'offset de la partition
-    let byte_offset = partition_offset * 512; // Taille standard d'un secteur
-    file.seek(std::io::SeekFrom::Start(byte_offset))
-        .map_err(|e| format!("Impossible de se positionner sur la partition: {}", e))?;
-
-    // Écrire les données
-    file.write_all(data)
-        .map_err(|e| format!("Impossible d'écrire dans la partition: {}", e))?;
+        .open(&partition_path)
+        .map_err(|e| format!("Impossible d'ouvrir la partition {}: {}", partition_path, e))
+}
 
-    file.flush()
-        .map_err(|e| format!("Impossible de vider le buffer: {}", e))?;
+/// Taille réelle d'une partition, en octets, d'après Windows
+fn partition_size(disk_num: u32, partition_num: u32) -> std::result::Result<u64, String> {
+    let script = format!(
+        "(Get-Partition -DiskNumber {} -PartitionNumber {}).Size",
+        disk_num, partition_num
+    );
+    let output = std::process::Command::new("powershell")
+        .args(&["-Command", &script])
+        .output()
+        .map_err(|e| format!("Erreur lors de l'exécution de PowerShell: {}", e))?;
+    if !output.status.success() {
+        let error_msg = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "Erreur PowerShell (taille de partition): {}",
+            error_msg
+        ));
+    }
+    let size = String::from_utf8_lossy(&output.stdout);
+    size.trim()
+        .parse::<u64>()
+        .map_err(|_| format!("Taille de partition illisible: {}", size.trim()))
+}
 
-    Ok(())
+/// Volume déchiffré de la session
+fn session_volume(session: &EncryptedSession) -> std::result::Result<Box<dyn BlockDevice>, String> {
+    let storage = open_partition(session.disk_num, session.partition_num)?;
+    sector::open_volume(storage, &session.header, &session.master_key)
+        .map_err(|e| format!("Impossible d'ouvrir le volume chiffré: {}", e))
 }
 
-/// Simule le chargement des fichiers depuis la partition chiffrée (accès direct)
-fn load_real_files_from_partition(
-    session: &mut EncryptedSession,
-) -> std::result::Result<(), String> {
-    println!("Chargement des fichiers depuis la partition chiffrée (accès direct aux secteurs)");
-
-    // Lire les premiers secteurs de la partition pour analyser la structure
-    let sectors_data = read_partition_sectors(session.disk_num, session.partition_offset, 8)?;
-    println!("✅ {} octets lus depuis la partition", sectors_data.len());
-
-    // Analyser les données pour détecter un système de fichiers
-    let mut file_count = 0;
-
-    // Chercher des signatures de fichiers dans les secteurs
-    for (i, chunk) in sectors_data.chunks(512).enumerate() {
-        if chunk.len() >= 4 {
-            // Vérifier si c'est un en-tête de fichier (signature personnalisée)
-            if chunk[0] == 0x44 && chunk[1] == 0x56 && chunk[2] == 0x46 && chunk[3] == 0x54 {
-                // "DVFT" = DeepVault File Table
-                println!("En-tête de fichier trouvé dans le secteur {}", i);
-                file_count += 1;
-
-                // Lire les métadonnées du fichier
-                if chunk.len() >= 64 {
-                    let name_len = chunk[4] as usize;
-                    if name_len > 0 && name_len < 50 && chunk.len() >= 5 + name_len {
-                        let name = String::from_utf8_lossy(&chunk[5..5 + name_len]).to_string();
-                        let is_dir = chunk[5 + name_len] != 0;
-                        let file_size = u64::from_le_bytes([
-                            chunk[5 + name_len + 1],
-                            chunk[5 + name_len + 2],
-                            chunk[5 + name_len + 3],
-                            chunk[5 + name_len + 4],
-                            chunk[5 + name_len + 5],
-                            chunk[5 + name_len + 6],
-                            chunk[5 + name_len + 7],
-                            chunk[5 + name_len + 8],
-                        ]);
-
-                        let file_path = format!("/{}", name);
-
-                        session.files.insert(
-                            file_path.clone(),
-                            EncryptedFile {
-                                name: name.clone(),
-                                path: file_path,
-                                is_directory: is_dir,
-                                size: file_size,
-                                modified: std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs() as i64,
-                                content: if !is_dir && file_size > 0 {
-                                    Some(Vec::new())
-                                } else {
-                                    None
-                                },
-                            },
-                        );
-                    }
-                }
-            }
-        }
-    }
+/// Ouvre le système de fichiers du coffre de la session
+fn open_partition_fs(session: &EncryptedSession) -> std::result::Result<PartitionFs, String> {
+    VaultFs::open(session_volume(session)?)
+        .map_err(|e| format!("Système de fichiers du coffre illisible: {}", e))
+}
 
-    // Si aucun fichier n'a été trouvé, créer une structure de base
-    if file_count == 0 {
-        println!("Aucun fichier existant trouvé, création d'une structure de base");
-
-        // Créer un en-tête de partition vide
-        let mut partition_header = vec![0u8; 512];
-        partition_header[0] = 0x44; // 'D'
-        partition_header[1] = 0x56; // 'V'
-        partition_header[2] = 0x50; // 'P'
-        partition_header[3] = 0x54; // 'T' = DeepVault Partition Table
-
-        // Écrire l'en-tête sur la partition
-        write_partition_sectors(
-            session.disk_num,
-            session.partition_offset,
-            &partition_header,
-        )?;
-        println!("✅ En-tête de partition créé");
+/// Vérifie le système de fichiers du volume déchiffré; renvoie `false` si
+/// le volume n'a pas encore été formaté
+fn load_partition_fs(session: &EncryptedSession) -> std::result::Result<bool, String> {
+    println!("Chargement du système de fichiers du volume chiffré");
+
+    let mut device = session_volume(session)?;
+    let found = VaultFs::probe(&mut device)
+        .map_err(|e| format!("Impossible de lire le volume chiffré: {}", e))?;
+    if !found {
+        println!("Aucun système de fichiers trouvé, le volume doit être formaté par l'utilisateur");
+        return Ok(false);
     }
 
+    let fs = VaultFs::open(device)
+        .map_err(|e| format!("Système de fichiers du coffre illisible: {}", e))?;
+    let stats = fs.statfs();
     println!(
-        "✅ {} fichiers chargés depuis la partition (accès direct aux secteurs)",
-        session.files.len()
+        "✅ Système de fichiers prêt: {} blocs libres sur {}",
+        stats.free_blocks, stats.total_blocks
     );
-    Ok(())
+    Ok(true)
 }
 
 #[tauri::command]
@@ -200,12 +105,9 @@ async fn get_usb_devices() -> std::result::Result<Vec<UsbDevice>, String> {
 async fn configure_device(
     _device_path: String,
     _config_type: String,
-    password: String,
+    password: SecretString,
 ) -> std::result::Result<String, String> {
-    // Simple password validation
-    if password.len() < 8 {
-        return Err("Le mot de passe doit contenir au moins 8 caractères".to_string());
-    }
+    utils::validate_password(password.expose_secret()).map_err(|e| e.to_string())?;
 
     // TODO: Implement device configuration
     Ok("Configuration en cours...".to_string())
@@ -215,7 +117,7 @@ async fn configure_device(
 async fn mount_volume(
     device_path: String,
     volume_name: String,
-    password: String,
+    password: SecretString,
 ) -> std::result::Result<String, String> {
     let device_path = PathBuf::from(device_path);
     let mount_manager = MountManager::new(device_path);
@@ -412,7 +314,7 @@ async fn partition_device(
     publicSize: u64,
     encryptedSize: u64,
     publicLabel: String,
-    password: String,
+    password: SecretString,
 ) -> std::result::Result<String, String> {
     println!("=== DÉBUT DU PARTITIONNEMENT ===");
     println!("Chemin du périphérique: {}", devicePath);
@@ -446,15 +348,12 @@ async fn partition_device(
          format fs={} quick label=\"{}\"\n\
          assign letter={}\n\
          create partition primary\n\
-         format fs=ntfs quick label=\"ENCRYPTED\"\n\
-         assign letter={}\n\
          list partition\n",
         disk_number,
         public_size_mb,
         filesystem,
         publicLabel,
-        devicePath.chars().next().unwrap_or('E'),
-        get_next_drive_letter(&devicePath)
+        devicePath.chars().next().unwrap_or('E')
     );
 
     println!("Script diskpart généré:");
@@ -497,24 +396,43 @@ async fn partition_device(
         return Err(format!("Erreur diskpart: {}", error_msg));
     }
 
-    // Stocker le mot de passe de manière sécurisée
-    println!("Stockage du mot de passe...");
-    let password_hash = store_password_hash(&password)?;
-    println!("Hash du mot de passe stocké: {}", password_hash);
-
-    // Masquer la partition chiffrée
-    println!("Masquage de la partition chiffrée...");
-    let hide_result = hide_encrypted_partition(&devicePath, &password).await;
-    match hide_result {
-        Ok(_) => println!("Partition chiffrée masquée avec succès"),
-        Err(e) => println!(
-            "Avertissement: Impossible de masquer la partition chiffrée: {}",
-            e
-        ),
-    }
+    // Créer l'en-tête du coffre sur la deuxième partition, laissée sans
+    // système de fichiers ni lettre de lecteur
+    println!("Création de l'en-tête du coffre...");
+    let encrypted_partition = 2;
+    let data_size = partition_size(disk_number, encrypted_partition)?
+        .checked_sub(DEFAULT_DATA_OFFSET)
+        .filter(|&size| size > 0)
+        .ok_or("La partition chiffrée est trop petite")?;
+    let security = AppConfig::load(&AppConfig::default_config_path())
+        .map(|config| config.security)
+        .unwrap_or_default();
+    let kdf = security.default_kdf().map_err(|e| e.to_string())?;
+    let (mut header, master_key) =
+        VaultHeader::create(&password, &kdf, DEFAULT_DATA_OFFSET, data_size)
+            .map_err(|e| format!("Impossible de créer l'en-tête du coffre: {}", e))?;
+    header
+        .set_attempt_policy(&master_key, security.attempt_policy)
+        .map_err(|e| format!("Politique de tentatives invalide: {}", e))?;
+    let mut storage = open_partition(disk_number, encrypted_partition)?;
+    // Remplir la zone de données d'aléa, pour qu'un volume caché ne s'y
+    // distingue pas de l'espace libre
+    println!("Remplissage de la partition chiffrée avec des données aléatoires...");
+    block::fill_random(&mut storage, DEFAULT_DATA_OFFSET, data_size)
+        .map_err(|e| format!("Impossible de remplir la partition chiffrée: {}", e))?;
+    header
+        .enable_random_fill(&master_key)
+        .map_err(|e| format!("Impossible de créer l'en-tête du coffre: {}", e))?;
+    header
+        .initialize(&mut storage)
+        .map_err(|e| format!("Impossible d'écrire l'en-tête du coffre: {}", e))?;
+    println!(
+        "En-tête du coffre écrit ({} MB de données)",
+        data_size / (1024 * 1024)
+    );
 
     Ok(format!(
-        "Partitionnement terminé avec succès!\n- Partition publique: {} MB ({}) - Label: {}\n- Partition chiffrée: {} MB (NTFS) - Masquée\n- Disque: {}",
+        "Partitionnement terminé avec succès!\n- Partition publique: {} MB ({}) - Label: {}\n- Partition chiffrée: {} MB (coffre) - Sans lettre de lecteur\n- Disque: {}",
         publicSize / (1024 * 1024),
         filesystem.to_uppercase(),
         publicLabel,
@@ -554,17 +472,6 @@ fn get_disk_number_from_path(device_path: &str) -> std::result::Result<u32, Stri
     Ok(1)
 }
 
-/// Obtenir la prochaine lettre de lecteur disponible
-fn get_next_drive_letter(current_path: &str) -> char {
-    let current_char = current_path.chars().next().unwrap_or('E');
-    let next_char = ((current_char as u8) + 1) as char;
-    if next_char <= 'Z' {
-        next_char
-    } else {
-        'F' // Retourner à F si on dépasse Z
-    }
-}
-
 #[tauri::command]
 async fn list_disks() -> std::result::Result<String, String> {
     // Utiliser diskpart pour lister tous les disques
@@ -648,82 +555,41 @@ fn extract_disk_number(line: &str) -> Option<u32> {
     None
 }
 
-/// Masquer la partition chiffrée en supprimant sa lettre de lecteur
-async fn hide_encrypted_partition(
-    device_path: &str,
-    _password: &str,
-) -> std::result::Result<(), String> {
-    let encrypted_letter = get_next_drive_letter(device_path);
-
-    // Créer un script diskpart pour supprimer la lettre de lecteur de la partition chiffrée
-    let hide_script = format!(
-        "select disk 1\n\
-         select partition 2\n\
-         remove letter={}\n\
-         list partition\n",
-        encrypted_letter
-    );
-
-    let script_path = std::env::temp_dir().join("deepvault_hide.txt");
-    std::fs::write(&script_path, hide_script)
-        .map_err(|e| format!("Erreur lors de l'écriture du script de masquage: {}", e))?;
-
-    // Exécuter diskpart pour masquer la partition
-    let output = std::process::Command::new("diskpart")
-        .args(&["/s", script_path.to_str().unwrap()])
-        .output()
-        .map_err(|e| format!("Erreur lors de l'exécution du script de masquage: {}", e))?;
-
-    // Nettoyer le script temporaire
-    let _ = std::fs::remove_file(&script_path);
-
-    if !output.status.success() {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("Erreur lors du masquage: {}", error_msg));
-    }
-
-    Ok(())
-}
-
-/// Stocker le hash du mot de passe de manière sécurisée
-fn store_password_hash(password: &str) -> std::result::Result<String, String> {
-    use std::fs;
-    use std::path::Path;
-
-    // Créer un hash simple du mot de passe (dans une vraie implémentation, utiliser Argon2)
-    let hash = format!("{:x}", md5::compute(password));
-
-    // Stocker dans un fichier temporaire (dans une vraie implémentation, utiliser un stockage sécurisé)
-    let config_dir = std::env::temp_dir().join("deepvault");
-    fs::create_dir_all(&config_dir).map_err(|e| format!("Erreur création dossier: {}", e))?;
-
-    let password_file = config_dir.join("password_hash.txt");
-    fs::write(&password_file, &hash).map_err(|e| format!("Erreur écriture hash: {}", e))?;
-
-    Ok(hash)
+/// Dérivation du volume caché: les réglages par défaut de
+/// `deepvault-cli hidden create`, qui ne sont pas enregistrés sur le volume
+fn hidden_kdf() -> KdfParams {
+    KdfParams::Argon2id(KeyDerivation::default())
 }
 
-/// Vérifier le mot de passe
-fn verify_password(password: &str) -> std::result::Result<bool, String> {
-    use std::fs;
-
-    let config_dir = std::env::temp_dir().join("deepvault");
-    let password_file = config_dir.join("password_hash.txt");
-
-    if !password_file.exists() {
-        return Err("Aucun mot de passe configuré".to_string());
+/// Message d'erreur du déverrouillage, avec les tentatives restantes
+fn unlock_error(error: DeepVaultError) -> String {
+    match error {
+        DeepVaultError::RetryLater(seconds) => {
+            format!("❌ Trop de tentatives, réessayez dans {} s", seconds)
+        }
+        DeepVaultError::UnlockFailed {
+            remaining: Some(0), ..
+        } => {
+            "❌ Mot de passe incorrect, limite atteinte: les keyslots ont été détruits".to_string()
+        }
+        DeepVaultError::UnlockFailed {
+            remaining: Some(n), ..
+        } => {
+            format!("❌ Mot de passe incorrect ({} tentatives restantes)", n)
+        }
+        DeepVaultError::UnlockFailed {
+            remaining: None, ..
+        } => "❌ Mot de passe incorrect".to_string(),
+        e => format!("❌ Partition chiffrée non déverrouillée: {}", e),
     }
-
-    let stored_hash =
-        fs::read_to_string(&password_file).map_err(|e| format!("Erreur lecture hash: {}", e))?;
-
-    let input_hash = format!("{:x}", md5::compute(password));
-
-    Ok(stored_hash.trim() == input_hash.trim())
 }
 
 #[tauri::command]
-async fn access_encrypted_partition(password: String) -> std::result::Result<String, String> {
+async fn access_encrypted_partition(
+    password: SecretString,
+    hidden: Option<bool>,
+) -> std::result::Result<String, String> {
+    let hidden = hidden.unwrap_or(false);
     println!("=== ACCÈS À LA PARTITION CHIFFRÉE ===");
     println!(
         "Mot de passe fourni: {}",
@@ -734,17 +600,19 @@ async fn access_encrypted_partition(password: String) -> std::result::Result<Str
         }
     );
 
-    // Vérifier le mot de passe
-    println!("=== ÉTAPE 1: VÉRIFICATION DU MOT DE PASSE ===");
-    println!("Vérification du mot de passe...");
-    match verify_password(&password) {
-        Ok(true) => println!("✅ Mot de passe correct !"),
-        Ok(false) => return Err("❌ Mot de passe incorrect".to_string()),
-        Err(e) => return Err(format!("❌ Erreur de vérification: {}", e)),
+    // Le mot de passe est vérifié par l'en-tête du coffre, qui compte les
+    // échecs et applique sa politique de tentatives
+    let security = AppConfig::load(&AppConfig::default_config_path())
+        .map(|config| config.security)
+        .unwrap_or_default();
+    if hidden && !security.enable_hidden_volumes {
+        return Err(
+            "Les volumes cachés sont désactivés (security.enable_hidden_volumes)".to_string(),
+        );
     }
 
     // Trouver la partition chiffrée cachée
-    println!("=== ÉTAPE 2: RECHERCHE DE LA PARTITION CHIFFRÉE ===");
+    println!("=== ÉTAPE 1: RECHERCHE DE LA PARTITION CHIFFRÉE ===");
     println!("Recherche de la partition chiffrée...");
 
     // Utiliser PowerShell pour lister les disques
@@ -803,7 +671,7 @@ async fn access_encrypted_partition(password: String) -> std::result::Result<Str
     }
 
     // Utiliser PowerShell pour lister les partitions du disque sélectionné
-    println!("=== ÉTAPE 3: ANALYSE DES PARTITIONS ===");
+    println!("=== ÉTAPE 2: ANALYSE DES PARTITIONS ===");
     println!(
         "Exécution de PowerShell pour lister les partitions du disque {}...",
         disk_num
@@ -829,7 +697,7 @@ async fn access_encrypted_partition(password: String) -> std::result::Result<Str
     println!("Sortie PowerShell (partitions): {}", partition_str);
 
     // Analyser les partitions pour trouver celle sans lettre de lecteur
-    println!("=== ÉTAPE 3: ANALYSE DES PARTITIONS ===");
+    println!("=== ÉTAPE 2: ANALYSE DES PARTITIONS ===");
     println!("Recherche de la partition chiffrée (sans lettre de lecteur)...");
     let mut encrypted_partition = None;
     let mut partition_count = 0;
@@ -931,7 +799,7 @@ async fn access_encrypted_partition(password: String) -> std::result::Result<Str
     println!("✅ Partition chiffrée identifiée: {}", partition_num);
 
     // Accès direct à la partition (sans montage)
-    println!("=== ÉTAPE 4: ACCÈS DIRECT À LA PARTITION ===");
+    println!("=== ÉTAPE 3: ACCÈS DIRECT À LA PARTITION ===");
     println!(
         "Accès direct à la partition {} du disque {}...",
         partition_num, disk_num
@@ -947,33 +815,37 @@ async fn access_encrypted_partition(password: String) -> std::result::Result<Str
     );
 
     // Accès direct à la partition chiffrée (sans montage)
-    println!("=== ÉTAPE 5: ACCÈS DIRECT À LA PARTITION CHIFFRÉE ===");
+    println!("=== ÉTAPE 4: ACCÈS DIRECT À LA PARTITION CHIFFRÉE ===");
     println!(
         "Accès direct à la partition {} du disque {}...",
         partition_num, disk_num
     );
 
-    // Calculer l'offset et la taille de la partition en secteurs
-    // Pour l'instant, utiliser des valeurs par défaut
-    let partition_offset = 1048576 / 512; // 1MB en secteurs (valeur d'exemple)
-    let partition_size = 7_300_000_000 / 512; // 7.3GB en secteurs (valeur d'exemple)
-
-    println!("Offset de la partition: {} secteurs", partition_offset);
-    println!("Taille de la partition: {} secteurs", partition_size);
+    // Déverrouiller l'en-tête du coffre avec le mot de passe de la session;
+    // la session d'un volume caché porte son en-tête à la place de celui
+    // du volume externe
+    let mut storage = open_partition(disk_num, partition_num)?;
+    let credentials = Credentials::passphrase(&password);
+    let (header, master_key) = if hidden {
+        hidden::open(&mut storage, &credentials, &hidden_kdf())
+            .map_err(|_| "❌ Phrase de passe du volume caché incorrecte".to_string())?
+    } else {
+        VaultHeader::open(&mut storage, &credentials, &security).map_err(unlock_error)?
+    };
+    println!("✅ En-tête du coffre déverrouillé");
 
     // Créer une nouvelle session chiffrée
-    let mut session = EncryptedSession {
+    let session = EncryptedSession {
         session_id: session_id.clone(),
         disk_num,
         partition_num,
-        partition_offset,
-        partition_size,
-        files: HashMap::new(),
+        header,
+        master_key,
     };
 
-    // Charger les fichiers depuis la partition (accès direct)
-    println!("=== ÉTAPE 6: CHARGEMENT DES FICHIERS (ACCÈS DIRECT) ===");
-    load_real_files_from_partition(&mut session)?;
+    // Charger les fichiers depuis le volume déchiffré
+    println!("=== ÉTAPE 5: CHARGEMENT DES FICHIERS ===");
+    load_partition_fs(&session)?;
 
     // Stocker la session
     {
@@ -989,6 +861,39 @@ async fn access_encrypted_partition(password: String) -> std::result::Result<Str
     Ok(session_id)
 }
 
+/// Indique si le volume chiffré de la session contient un système de fichiers
+#[tauri::command]
+async fn encrypted_volume_formatted(session_id: String) -> std::result::Result<bool, String> {
+    let sessions = ENCRYPTED_SESSIONS.lock().unwrap();
+    let session = sessions.get(&session_id).ok_or("Session non trouvée")?;
+    load_partition_fs(session)
+}
+
+/// Crée un système de fichiers vide sur le volume déchiffré de la session,
+/// après confirmation explicite de l'utilisateur
+#[tauri::command]
+async fn format_encrypted_volume(
+    session_id: String,
+    confirm: bool,
+) -> std::result::Result<(), String> {
+    println!("=== FORMATAGE DU VOLUME CHIFFRÉ ===");
+    if !confirm {
+        return Err("Le formatage efface le volume et doit être confirmé".to_string());
+    }
+
+    let sessions = ENCRYPTED_SESSIONS.lock().unwrap();
+    let session = sessions.get(&session_id).ok_or("Session non trouvée")?;
+    let fs = VaultFs::format(session_volume(session)?)
+        .map_err(|e| format!("Impossible de créer le système de fichiers: {}", e))?;
+
+    let stats = fs.statfs();
+    println!(
+        "✅ Système de fichiers créé: {} blocs libres sur {}",
+        stats.free_blocks, stats.total_blocks
+    );
+    Ok(())
+}
+
 #[tauri::command]
 async fn close_encrypted_session(session_id: String) -> std::result::Result<String, String> {
     println!("=== FERMETURE DE LA SESSION CHIFFRÉE ===");
@@ -1017,31 +922,24 @@ async fn list_encrypted_files(
     // Récupérer la session
     let sessions = ENCRYPTED_SESSIONS.lock().unwrap();
     let session = sessions.get(&session_id).ok_or("Session non trouvée")?;
-
-    let mut files = Vec::new();
-
-    // Lister les fichiers dans le chemin demandé
-    for (file_path, file) in &session.files {
-        let should_include = if path == "/" {
-            // Pour la racine, inclure tous les fichiers qui sont directement dans la racine
-            !file_path.contains('/')
-                || (file_path.starts_with('/') && !file_path[1..].contains('/'))
-        } else {
-            // Pour les sous-répertoires, inclure les fichiers qui commencent par le chemin
-            file_path.starts_with(&format!("{}/", path))
-                && !file_path[path.len() + 1..].contains('/')
-        };
-
-        if should_include {
-            files.push(serde_json::json!({
-                "name": file.name,
-                "path": file.path,
-                "is_directory": file.is_directory,
-                "size": file.size,
-                "modified": file.modified
-            }));
-        }
-    }
+    let mut fs = open_partition_fs(session)?;
+
+    // Lister les entrées du répertoire demandé
+    let entries = fs
+        .read_dir(&path)
+        .map_err(|e| format!("Impossible de lister {}: {}", path, e))?;
+    let files: Vec<serde_json::Value> = entries
+        .into_iter()
+        .map(|entry| {
+            serde_json::json!({
+                "name": entry.name,
+                "path": format!("{}/{}", path.trim_end_matches('/'), entry.name),
+                "is_directory": entry.metadata.is_dir(),
+                "size": entry.metadata.size,
+                "modified": entry.metadata.modified
+            })
+        })
+        .collect();
 
     println!("✅ {} fichiers trouvés", files.len());
     Ok(files)
@@ -1055,36 +953,17 @@ async fn read_encrypted_file(
     println!("=== LECTURE DE FICHIER CHIFFRÉ ===");
     println!("Session: {}, Fichier: {}", session_id, file_path);
 
-    // Récupérer la session et le fichier
+    // Récupérer la session et lire le fichier depuis la partition
     let sessions = ENCRYPTED_SESSIONS.lock().unwrap();
     let session = sessions.get(&session_id).ok_or("Session non trouvée")?;
+    let mut fs = open_partition_fs(session)?;
 
-    let file = session.files.get(&file_path).ok_or("Fichier non trouvé")?;
-
-    if file.is_directory {
-        return Err("Impossible de lire un répertoire".to_string());
-    }
-
-    // Lire vraiment le contenu du fichier depuis la partition
-    let content = if let Some(data) = &file.content {
-        if data.is_empty() {
-            // Si le cache est vide, lire depuis la partition
-            let file_sector_offset = session.partition_offset + 1 + (session.files.len() as u64);
-            let sectors_data = read_partition_sectors(
-                session.disk_num,
-                file_sector_offset + 1,
-                ((file.size + 511) / 512) as u32,
-            )?;
-            String::from_utf8_lossy(&sectors_data[..file.size as usize]).to_string()
-        } else {
-            String::from_utf8_lossy(data).to_string()
-        }
-    } else {
-        "Contenu vide".to_string()
-    };
+    let content = fs
+        .read(&file_path)
+        .map_err(|e| format!("Impossible de lire {}: {}", file_path, e))?;
 
     println!("✅ Fichier lu avec succès");
-    Ok(content.to_string())
+    Ok(String::from_utf8_lossy(&content).to_string())
 }
 
 #[tauri::command]
@@ -1102,72 +981,14 @@ async fn write_encrypted_file(
     );
 
     // Récupérer la session et écrire le fichier sur la partition
-    let mut sessions = ENCRYPTED_SESSIONS.lock().unwrap();
-    let session = sessions.get_mut(&session_id).ok_or("Session non trouvée")?;
-
-    // Accès direct à la partition (pas de montage)
-
-    // Écrire vraiment le fichier sur la partition (accès direct aux secteurs)
-    println!(
-        "Écriture du fichier {} sur la partition (accès direct)",
-        file_path
-    );
-
-    // Créer l'en-tête du fichier
-    let mut file_header = vec![0u8; 512];
-    file_header[0] = 0x44; // 'D'
-    file_header[1] = 0x56; // 'V'
-    file_header[2] = 0x46; // 'F'
-    file_header[3] = 0x54; // 'T' = DeepVault File Table
-
-    let name_bytes = file_path.as_bytes();
-    let name_len = name_bytes.len().min(50);
-    file_header[4] = name_len as u8;
-    file_header[5..5 + name_len].copy_from_slice(&name_bytes[..name_len]);
-
-    file_header[5 + name_len] = 0; // Pas un répertoire
-    let size_bytes = content.len().to_le_bytes();
-    file_header[5 + name_len + 1..5 + name_len + 9].copy_from_slice(&size_bytes);
-
-    // Calculer l'offset où écrire le fichier (après les en-têtes)
-    let file_sector_offset = session.partition_offset + 1 + (session.files.len() as u64);
-
-    // Écrire l'en-tête du fichier
-    write_partition_sectors(session.disk_num, file_sector_offset, &file_header)?;
-
-    // Écrire le contenu du fichier
-    if !content.is_empty() {
-        let content_sectors = (content.len() + 511) / 512; // Arrondir au secteur supérieur
-        let mut padded_content = content.as_bytes().to_vec();
-        padded_content.resize(content_sectors * 512, 0);
-
-        write_partition_sectors(session.disk_num, file_sector_offset + 1, &padded_content)?;
-    }
-
-    println!(
-        "✅ Fichier écrit sur la partition au secteur {}",
-        file_sector_offset
-    );
+    let sessions = ENCRYPTED_SESSIONS.lock().unwrap();
+    let session = sessions.get(&session_id).ok_or("Session non trouvée")?;
+    let mut fs = open_partition_fs(session)?;
 
-    let file_size = content.len() as u64;
-
-    // Mettre à jour le cache
-    session.files.insert(
-        file_path.clone(),
-        EncryptedFile {
-            name: file_path.split('/').last().unwrap_or("").to_string(),
-            path: file_path.clone(),
-            is_directory: false,
-            size: file_size,
-            modified: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64,
-            content: Some(content.as_bytes().to_vec()),
-        },
-    );
+    fs.write(&file_path, content.as_bytes())
+        .map_err(|e| format!("Impossible d'écrire {}: {}", file_path, e))?;
 
-    println!("✅ Fichier écrit avec succès ({} octets)", file_size);
+    println!("✅ Fichier écrit avec succès ({} octets)", content.len());
     Ok(())
 }
 
@@ -1180,57 +1001,13 @@ async fn delete_encrypted_file(
     println!("Session: {}, Fichier: {}", session_id, file_path);
 
     // Récupérer la session et supprimer le fichier de la partition
-    let mut sessions = ENCRYPTED_SESSIONS.lock().unwrap();
-    let session = sessions.get_mut(&session_id).ok_or("Session non trouvée")?;
-
-    // Accès direct à la partition (pas de montage)
-
-    // Simuler l'opération sur la partition (accès direct aux secteurs)
-    println!("Opération sur la partition (accès direct)");
-
-    // Vérifier si le fichier existe dans le cache et récupérer ses propriétés
-    let is_directory = if let Some(file) = session.files.get(&file_path) {
-        file.is_directory
-    } else {
-        return Err("Fichier non trouvé".to_string());
-    };
-
-    // Supprimer vraiment le fichier de la partition (accès direct aux secteurs)
-    println!(
-        "Suppression du fichier {} de la partition (accès direct)",
-        file_path
-    );
-
-    // Marquer le fichier comme supprimé en écrasant son en-tête
-    let mut empty_header = vec![0u8; 512];
-    // Laisser un en-tête vide pour indiquer que l'espace est libre
-
-    // Trouver l'offset du fichier à supprimer
-    let file_sector_offset = session.partition_offset + 1 + (session.files.len() as u64);
-
-    // Écraser l'en-tête du fichier
-    write_partition_sectors(session.disk_num, file_sector_offset, &empty_header)?;
-
-    println!(
-        "✅ Fichier supprimé de la partition au secteur {}",
-        file_sector_offset
-    );
-
-    // Supprimer du cache
-    session.files.remove(&file_path);
-
-    // Si c'est un répertoire, supprimer aussi tous les fichiers qu'il contient du cache
-    if is_directory {
-        let prefix = if file_path == "/" {
-            "/".to_string()
-        } else {
-            format!("{}/", file_path)
-        };
+    let sessions = ENCRYPTED_SESSIONS.lock().unwrap();
+    let session = sessions.get(&session_id).ok_or("Session non trouvée")?;
+    let mut fs = open_partition_fs(session)?;
 
-        session
-            .files
-            .retain(|path, _| !path.starts_with(&prefix) || path == &file_path);
-    }
+    // Un répertoire est supprimé avec tout son contenu
+    fs.remove_all(&file_path)
+        .map_err(|e| format!("Impossible de supprimer {}: {}", file_path, e))?;
 
     println!("✅ Fichier supprimé avec succès");
     Ok(())
@@ -1245,59 +1022,12 @@ async fn create_encrypted_directory(
     println!("Session: {}, Dossier: {}", session_id, dir_path);
 
     // Récupérer la session et créer le répertoire sur la partition
-    let mut sessions = ENCRYPTED_SESSIONS.lock().unwrap();
-    let session = sessions.get_mut(&session_id).ok_or("Session non trouvée")?;
-
-    // Accès direct à la partition (pas de montage)
-
-    // Créer vraiment le répertoire sur la partition (accès direct aux secteurs)
-    println!(
-        "Création du répertoire {} sur la partition (accès direct)",
-        dir_path
-    );
-
-    // Créer l'en-tête du répertoire
-    let mut dir_header = vec![0u8; 512];
-    dir_header[0] = 0x44; // 'D'
-    dir_header[1] = 0x56; // 'V'
-    dir_header[2] = 0x46; // 'F'
-    dir_header[3] = 0x54; // 'T' = DeepVault File Table
-
-    let name_bytes = dir_path.as_bytes();
-    let name_len = name_bytes.len().min(50);
-    dir_header[4] = name_len as u8;
-    dir_header[5..5 + name_len].copy_from_slice(&name_bytes[..name_len]);
-
-    dir_header[5 + name_len] = 1; // C'est un répertoire
-    let size_bytes = 0u64.to_le_bytes(); // Taille 0 pour un répertoire
-    dir_header[5 + name_len + 1..5 + name_len + 9].copy_from_slice(&size_bytes);
-
-    // Calculer l'offset où écrire le répertoire
-    let dir_sector_offset = session.partition_offset + 1 + (session.files.len() as u64);
-
-    // Écrire l'en-tête du répertoire
-    write_partition_sectors(session.disk_num, dir_sector_offset, &dir_header)?;
-
-    println!(
-        "✅ Répertoire créé sur la partition au secteur {}",
-        dir_sector_offset
-    );
+    let sessions = ENCRYPTED_SESSIONS.lock().unwrap();
+    let session = sessions.get(&session_id).ok_or("Session non trouvée")?;
+    let mut fs = open_partition_fs(session)?;
 
-    // Mettre à jour le cache
-    session.files.insert(
-        dir_path.clone(),
-        EncryptedFile {
-            name: dir_path.split('/').last().unwrap_or("").to_string(),
-            path: dir_path.clone(),
-            is_directory: true,
-            size: 0,
-            modified: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64,
-            content: None,
-        },
-    );
+    fs.create_dir(&dir_path)
+        .map_err(|e| format!("Impossible de créer {}: {}", dir_path, e))?;
 
     println!("✅ Dossier créé avec succès");
     Ok(())
@@ -1318,71 +1048,14 @@ async fn upload_encrypted_file(
     );
 
     // Récupérer la session et uploader le fichier sur la partition
-    let mut sessions = ENCRYPTED_SESSIONS.lock().unwrap();
-    let session = sessions.get_mut(&session_id).ok_or("Session non trouvée")?;
-
-    // Accès direct à la partition (pas de montage)
-
-    // Uploader vraiment le fichier sur la partition (accès direct aux secteurs)
-    println!(
-        "Upload du fichier {} sur la partition (accès direct)",
-        file_path
-    );
-
-    // Créer l'en-tête du fichier
-    let mut file_header = vec![0u8; 512];
-    file_header[0] = 0x44; // 'D'
-    file_header[1] = 0x56; // 'V'
-    file_header[2] = 0x46; // 'F'
-    file_header[3] = 0x54; // 'T' = DeepVault File Table
-
-    let name_bytes = file_path.as_bytes();
-    let name_len = name_bytes.len().min(50);
-    file_header[4] = name_len as u8;
-    file_header[5..5 + name_len].copy_from_slice(&name_bytes[..name_len]);
-
-    file_header[5 + name_len] = 0; // Pas un répertoire
-    let file_size = content.len() as u64;
-    let size_bytes = file_size.to_le_bytes();
-    file_header[5 + name_len + 1..5 + name_len + 9].copy_from_slice(&size_bytes);
-
-    // Calculer l'offset où écrire le fichier
-    let file_sector_offset = session.partition_offset + 1 + (session.files.len() as u64);
-
-    // Écrire l'en-tête du fichier
-    write_partition_sectors(session.disk_num, file_sector_offset, &file_header)?;
-
-    // Écrire le contenu du fichier
-    if !content.is_empty() {
-        let content_sectors = (content.len() + 511) / 512; // Arrondir au secteur supérieur
-        let mut padded_content = content.clone();
-        padded_content.resize(content_sectors * 512, 0);
-
-        write_partition_sectors(session.disk_num, file_sector_offset + 1, &padded_content)?;
-    }
-
-    println!(
-        "✅ Fichier uploadé sur la partition au secteur {}",
-        file_sector_offset
-    );
+    let sessions = ENCRYPTED_SESSIONS.lock().unwrap();
+    let session = sessions.get(&session_id).ok_or("Session non trouvée")?;
+    let mut fs = open_partition_fs(session)?;
 
-    // Mettre à jour le cache
-    session.files.insert(
-        file_path.clone(),
-        EncryptedFile {
-            name: file_path.split('/').last().unwrap_or("").to_string(),
-            path: file_path.clone(),
-            is_directory: false,
-            size: file_size,
-            modified: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64,
-            content: Some(content),
-        },
-    );
+    fs.write(&file_path, &content)
+        .map_err(|e| format!("Impossible d'uploader {}: {}", file_path, e))?;
 
-    println!("✅ Fichier uploadé avec succès ({} octets)", file_size);
+    println!("✅ Fichier uploadé avec succès ({} octets)", content.len());
     Ok(())
 }
 
@@ -1833,6 +1506,8 @@ fn main() {
             list_disks,
             list_hidden_partitions,
             access_encrypted_partition,
+            encrypted_volume_formatted,
+            format_encrypted_volume,
             close_encrypted_session,
             list_encrypted_files,
             read_encrypted_file,
@@ -0,0 +1,97 @@
+//! Limits on failed unlock attempts
+//!
+//! A volume counts its consecutive failed unlocks. After a failure the
+//! next attempt is refused for a back-off delay that doubles with every
+//! further failure, and an optional policy destroys the keyslots once a
+//! number of failures is reached. A successful unlock resets the count.
+//!
+//! The policy is covered by the header MAC and the count by a hash chain
+//! rooted in the master key (see [`crate::header`]), so changing either on
+//! disk is detected at the next successful unlock. Like any software limit
+//! this only applies to unlocks through DeepVault, not to an attacker
+//! working on a copy of the volume or restoring an earlier one.
+
+use crate::{DeepVaultError, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Longest back-off accepted in a policy (one day)
+pub const MAX_DELAY_LIMIT: u32 = 24 * 60 * 60;
+
+/// Failed-attempt policy of a volume
+///
+/// The default policy imposes no delay and never destroys keyslots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct AttemptPolicy {
+    /// Consecutive failures after which the keyslots are destroyed, 0 to
+    /// never destroy them
+    pub wipe_after: u32,
+    /// Back-off after the first failure, in seconds
+    pub base_delay: u32,
+    /// Longest back-off, in seconds
+    pub max_delay: u32,
+}
+
+impl AttemptPolicy {
+    /// Check that the delays are consistent
+    pub fn validate(&self) -> Result<()> {
+        if self.max_delay < self.base_delay {
+            return Err(DeepVaultError::Config(
+                "The maximum back-off cannot be shorter than the first one".to_string(),
+            ));
+        }
+        if self.max_delay > MAX_DELAY_LIMIT {
+            return Err(DeepVaultError::Config(format!(
+                "The back-off cannot exceed {} seconds",
+                MAX_DELAY_LIMIT
+            )));
+        }
+        Ok(())
+    }
+
+    /// Back-off imposed after `failures` consecutive failures
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+        let factor = 1u64 << (failures - 1).min(32);
+        let delay = (self.base_delay as u64).saturating_mul(factor);
+        Duration::from_secs(delay.min(self.max_delay as u64))
+    }
+
+    /// Attempts left before the keyslots are destroyed, `None` without a
+    /// wipe policy
+    pub fn remaining(&self, failures: u32) -> Option<u32> {
+        (self.wipe_after > 0).then(|| self.wipe_after.saturating_sub(failures))
+    }
+}
+
+/// Consecutive failed unlocks of a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct AttemptCounter {
+    pub failures: u32,
+    /// Time of the last failure (Unix seconds)
+    pub last_failure: u64,
+}
+
+impl AttemptCounter {
+    /// Time left before the next attempt is allowed at `now` (Unix seconds)
+    pub fn retry_after(&self, policy: &AttemptPolicy, now: u64) -> Option<Duration> {
+        let delay = policy.delay(self.failures);
+        let retry_at = self.last_failure.saturating_add(delay.as_secs());
+        // A clock set back never extends the wait beyond one full delay
+        let wait = Duration::from_secs(retry_at.saturating_sub(now)).min(delay);
+        (!wait.is_zero()).then_some(wait)
+    }
+
+    /// Count a failure at `now`
+    pub fn record_failure(&mut self, now: u64) {
+        self.failures = self.failures.saturating_add(1);
+        self.last_failure = now;
+    }
+
+    /// Forget past failures after a successful unlock
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,134 @@
+//! Header backup and restore
+//!
+//! A backup is a verbatim copy of the metadata region of a volume, from
+//! offset 0 up to the start of the data area. It starts with the vault
+//! header, so it is protected by the same keyslots and MAC: a backup is
+//! no easier to attack than the drive itself, but it keeps the old
+//! passphrases valid after they have been changed on the drive.
+//!
+//! Restoring leaves the hidden header slot of the volume as it is, so a
+//! backup taken before a hidden volume was created does not destroy it.
+
+use crate::block::Storage;
+use crate::header::{VaultHeader, HEADER_SIZE, OFF_UUID};
+use crate::hidden::{HIDDEN_HEADER_OFFSET, HIDDEN_HEADER_SIZE};
+use crate::keyfile::Credentials;
+use crate::{DeepVaultError, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Largest metadata region accepted in a backup (16 MiB)
+pub const MAX_BACKUP_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Save the header region of `device` into a new file `out_file`
+///
+/// Fails if `out_file` already exists, so an older backup is never lost.
+pub fn backup_header(device: &Path, out_file: &Path) -> Result<()> {
+    let mut storage = std::fs::File::open(device)?;
+    let region = read_header_region(&mut storage)?;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(out_file).map_err(|e| {
+        DeepVaultError::InvalidInput(format!("Cannot create backup {}: {}", out_file.display(), e))
+    })?;
+    file.write_all(&region)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Restore the header region saved in `file` onto `device`
+///
+/// See [`restore_header_region`] for the checks made before writing.
+pub fn restore_header(file: &Path, device: &Path, credentials: &Credentials, force: bool) -> Result<VaultHeader> {
+    let backup = std::fs::read(file)?;
+    let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(device)?;
+    restore_header_region(&backup, &mut storage, credentials, force)
+}
+
+/// Read the header region of a volume
+pub fn read_header_region<S: Read + Seek>(storage: &mut S) -> Result<Vec<u8>> {
+    storage.seek(SeekFrom::Start(0))?;
+    let header = VaultHeader::read_from(storage)?;
+    if header.data_offset > MAX_BACKUP_SIZE {
+        return Err(DeepVaultError::InvalidHeader(format!(
+            "Header region of {} bytes is too large to back up",
+            header.data_offset
+        )));
+    }
+
+    let mut region = vec![0u8; header.data_offset as usize];
+    storage.seek(SeekFrom::Start(0))?;
+    storage.read_exact(&mut region)?;
+    Ok(region)
+}
+
+/// Write a backed-up header region back onto a volume
+///
+/// Nothing is written unless:
+/// - the backup is a well-formed header region;
+/// - `credentials` unlock the backup and its MAC verifies;
+/// - the volume is large enough for the data area of the backup;
+/// - the UUID on the volume matches the backup. If the volume no longer
+///   carries a readable header, the UUID cannot be checked and `force`
+///   must be set.
+///
+/// The hidden header slot is not written. Returns the restored header.
+pub fn restore_header_region<S: Storage>(
+    backup: &[u8],
+    storage: &mut S,
+    credentials: &Credentials,
+    force: bool,
+) -> Result<VaultHeader> {
+    let header = VaultHeader::from_bytes(backup)?;
+    if backup.len() as u64 != header.data_offset {
+        return Err(DeepVaultError::InvalidHeader(format!(
+            "Backup is {} bytes but its header region is {} bytes",
+            backup.len(),
+            header.data_offset
+        )));
+    }
+    header.unlock_with_credentials(credentials)?;
+
+    let device_size = storage.seek(SeekFrom::End(0))?;
+    if device_size < header.data_offset.saturating_add(header.data_size) {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "Device is {} bytes but the backed-up volume needs {}",
+            device_size,
+            header.data_offset + header.data_size
+        )));
+    }
+
+    let mut current = vec![0u8; HEADER_SIZE];
+    storage.seek(SeekFrom::Start(0))?;
+    storage.read_exact(&mut current)?;
+    if VaultHeader::is_vault_header(&current) {
+        let uuid = &current[OFF_UUID..OFF_UUID + 16];
+        if uuid != header.uuid.as_bytes() {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "Backup belongs to vault {} but the device holds vault {}",
+                header.uuid,
+                uuid::Uuid::from_slice(uuid).unwrap_or_default()
+            )));
+        }
+    } else if !force {
+        return Err(DeepVaultError::InvalidInput(
+            "The device has no readable header to check the vault UUID against".to_string(),
+        ));
+    }
+
+    // Skip the hidden header slot, which may hold a newer hidden volume
+    let slot = HIDDEN_HEADER_OFFSET as usize..HIDDEN_HEADER_OFFSET as usize + HIDDEN_HEADER_SIZE;
+    storage.seek(SeekFrom::Start(0))?;
+    if backup.len() >= slot.end {
+        storage.write_all(&backup[..slot.start])?;
+        storage.seek(SeekFrom::Start(slot.end as u64))?;
+        storage.write_all(&backup[slot.end..])?;
+    } else {
+        storage.write_all(backup)?;
+    }
+    storage.sync()?;
+    Ok(header)
+}
@@ -1,7 +1,7 @@
 //! DeepVault CLI application
 
 use deepvault_core::*;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -30,13 +30,33 @@ enum Commands {
         /// Device path
         device: PathBuf,
         /// Volume name
-        #[arg(short, long)]
+        #[arg(short, long, default_value = "encrypted")]
         volume: String,
+        /// Mount through FUSE on this directory, without root (Linux)
+        #[arg(long)]
+        fuse: Option<PathBuf>,
+        /// Refuse writes to the volume
+        #[arg(long)]
+        read_only: bool,
+        /// Refuse writes to the hidden volume inside, asking for its passphrase
+        #[arg(long, requires = "fuse")]
+        protect_hidden: bool,
+        /// Mount the hidden volume inside instead, asking for its passphrase
+        #[arg(long, requires = "fuse", conflicts_with_all = ["protect_hidden", "keyfile"])]
+        hidden: bool,
+        #[command(flatten)]
+        hidden_kdf: HiddenKdf,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
     },
     /// Unmount a volume
     Unmount {
-        /// Volume name
+        /// Volume name, or mount point with --fuse
         volume: String,
+        /// Unmount a FUSE mount point
+        #[arg(long)]
+        fuse: bool,
     },
     /// Wipe a device
     Wipe {
@@ -46,6 +66,366 @@ enum Commands {
         #[arg(long)]
         header_only: bool,
     },
+    /// Calibrate key derivation for this machine
+    Benchmark {
+        /// Target unlock time in milliseconds
+        #[arg(short, long, default_value_t = 2000)]
+        time: u64,
+        /// Memory ceiling in MB (default: 1 GB or half of the RAM)
+        #[arg(short, long)]
+        memory: Option<u32>,
+        /// Maximum number of lanes
+        #[arg(short, long)]
+        parallelism: Option<u32>,
+        /// Argon2 variant
+        #[arg(short, long, default_value = "Argon2id")]
+        algorithm: String,
+        /// Store the result as the default for new vaults
+        #[arg(long)]
+        save: bool,
+    },
+    /// Add a recovery key to a vault and print its recovery sheet
+    Recovery {
+        /// Device path
+        device: PathBuf,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+        /// Write the recovery sheet to this file (.svg or text)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Split access to a vault between custodians
+    Shares {
+        #[command(subcommand)]
+        action: SharesAction,
+    },
+    /// Back up or restore a vault header
+    Header {
+        #[command(subcommand)]
+        action: HeaderAction,
+    },
+    /// Manage X25519 identities for administrator access
+    Identity {
+        #[command(subcommand)]
+        action: IdentityAction,
+    },
+    /// Wrap a vault to a recipient or unlock it with an identity
+    Recipient {
+        #[command(subcommand)]
+        action: RecipientAction,
+    },
+    /// Grow or shrink a vault or a container
+    Resize {
+        /// Device or container path
+        device: PathBuf,
+        /// New size of the data area in MB
+        #[arg(short, long)]
+        size: u64,
+        /// Shrink even if the file system inside cannot be checked
+        #[arg(long)]
+        force: bool,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+    /// Set the failed-unlock policy of a vault
+    Policy {
+        /// Device path
+        device: PathBuf,
+        /// Destroy the keyslots after this many consecutive failures (0 = never)
+        #[arg(long)]
+        wipe_after: Option<u32>,
+        /// Back-off after the first failure, in seconds
+        #[arg(long)]
+        delay: Option<u32>,
+        /// Longest back-off, in seconds
+        #[arg(long)]
+        max_delay: Option<u32>,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+    /// Enroll a duress passphrase that destroys the keyslots when typed
+    Duress {
+        /// Device path
+        device: PathBuf,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+    /// Unlock a vault with an ed25519 key held in ssh-agent
+    Ssh {
+        #[command(subcommand)]
+        action: SshAction,
+    },
+    /// Nest a hidden volume in a vault
+    Hidden {
+        #[command(subcommand)]
+        action: HiddenAction,
+    },
+    /// Detect tampering with per-sector integrity tags
+    Integrity {
+        #[command(subcommand)]
+        action: IntegrityAction,
+    },
+    /// Create and open encrypted container files
+    Container {
+        #[command(subcommand)]
+        action: ContainerAction,
+    },
+    /// Generate a passphrase or check the strength of one
+    Passphrase {
+        #[command(subcommand)]
+        action: PassphraseAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum HiddenAction {
+    /// Create a hidden volume at the end of a vault
+    Create {
+        /// Device path
+        device: PathBuf,
+        /// Size of the hidden volume in MB
+        #[arg(short, long)]
+        size: u64,
+        /// Keyfile used to unlock the outer vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+        /// Record that the data area of the vault was already filled with
+        /// random bytes by other means
+        #[arg(long)]
+        assume_random: bool,
+        #[command(flatten)]
+        kdf: HiddenKdf,
+    },
+    /// Check that a passphrase opens the hidden volume of a vault
+    Unlock {
+        /// Device path
+        device: PathBuf,
+        #[command(flatten)]
+        kdf: HiddenKdf,
+    },
+}
+
+/// Argon2id settings of a hidden volume, which are not recorded on the
+/// volume and must be given again to open it
+#[derive(Args)]
+struct HiddenKdf {
+    /// Argon2id memory of the hidden volume in MB
+    #[arg(long, default_value_t = 64)]
+    kdf_memory: u32,
+    /// Argon2id passes of the hidden volume
+    #[arg(long, default_value_t = 3)]
+    kdf_iterations: u32,
+    /// Argon2id lanes of the hidden volume
+    #[arg(long, default_value_t = 1)]
+    kdf_parallelism: u32,
+}
+
+impl HiddenKdf {
+    fn params(&self) -> Result<KdfParams> {
+        let params = KeyDerivation {
+            memory: self.kdf_memory.checked_mul(1024).ok_or_else(|| {
+                DeepVaultError::InvalidInput(format!("KDF memory too large: {} MB", self.kdf_memory))
+            })?,
+            iterations: self.kdf_iterations,
+            parallelism: self.kdf_parallelism,
+        };
+        params.validate()?;
+        Ok(KdfParams::Argon2id(params))
+    }
+}
+
+#[derive(Subcommand)]
+enum ContainerAction {
+    /// Create a container file
+    Create {
+        /// Container path, usually ending in .dvc
+        path: PathBuf,
+        /// Size of the data area in MB
+        #[arg(short, long)]
+        size: u64,
+        /// Authenticate every sector
+        #[arg(long)]
+        integrity: bool,
+        /// Fill the data area with random bytes
+        #[arg(long)]
+        fill_random: bool,
+        /// Grow the file as data is written
+        #[arg(long)]
+        sparse: bool,
+        /// Keyfile protecting the container, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+    /// Open a container and show its details
+    Info {
+        /// Container path
+        path: PathBuf,
+        /// Keyfile used to unlock the container, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+    /// Shrink the file of a sparse container to the blocks in use
+    Compact {
+        /// Container path
+        path: PathBuf,
+        /// Keyfile used to unlock the container, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IntegrityAction {
+    /// Switch a vault to integrity mode, erasing its data
+    Enable {
+        /// Device path
+        device: PathBuf,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+        /// Confirm that the data of the vault may be lost
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Check every sector of a vault and list the modified ones
+    Check {
+        /// Device path
+        device: PathBuf,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PassphraseAction {
+    /// Generate a random passphrase from the embedded word list
+    Generate {
+        /// Number of words
+        #[arg(short, long, default_value_t = utils::DEFAULT_PASSPHRASE_WORDS)]
+        words: usize,
+        /// Separator between words
+        #[arg(short, long, default_value = "-")]
+        separator: String,
+    },
+    /// Estimate the strength of a passphrase
+    Check,
+}
+
+#[derive(Subcommand)]
+enum SshAction {
+    /// Enroll a keyslot unlocked by an SSH agent key
+    Add {
+        /// Device path
+        device: PathBuf,
+        /// Public key file (default: first ed25519 key of the agent)
+        #[arg(long)]
+        key: Option<PathBuf>,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+    /// Check that the SSH agent unlocks a vault
+    Unlock {
+        /// Device path
+        device: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum IdentityAction {
+    /// Generate a new identity file
+    Generate {
+        /// Identity file to create
+        file: PathBuf,
+    },
+    /// Print the recipient matching an identity file
+    Recipient {
+        /// Identity file
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum RecipientAction {
+    /// Enroll a keyslot wrapped to a recipient
+    Add {
+        /// Device path
+        device: PathBuf,
+        /// Recipient (dvr1...) or file holding one
+        recipient: String,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+    /// Unlock a vault with an identity file
+    Unlock {
+        /// Device path
+        device: PathBuf,
+        /// Identity file
+        #[arg(short, long)]
+        identity: PathBuf,
+        /// Enroll a new passphrase once unlocked
+        #[arg(long)]
+        add_passphrase: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SharesAction {
+    /// Enroll an M-of-N keyslot and write one share file per custodian
+    Split {
+        /// Device path
+        device: PathBuf,
+        /// Number of shares required to unlock
+        #[arg(short = 'm', long)]
+        threshold: u8,
+        /// Number of shares to create
+        #[arg(short = 'n', long)]
+        count: u8,
+        /// Directory receiving the share files
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+        /// Keyfile used to unlock the vault, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+    },
+    /// Check that shares unlock a vault
+    Verify {
+        /// Device path
+        device: PathBuf,
+        /// Share files
+        #[arg(required = true)]
+        shares: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum HeaderAction {
+    /// Save the header region of a device to a file
+    Backup {
+        /// Device path
+        device: PathBuf,
+        /// Backup file to create
+        file: PathBuf,
+    },
+    /// Write a header backup back onto a device
+    Restore {
+        /// Backup file
+        file: PathBuf,
+        /// Device path
+        device: PathBuf,
+        /// Keyfile protecting the backup, may be repeated
+        #[arg(short, long)]
+        keyfile: Vec<PathBuf>,
+        /// Restore even if the device has no readable header
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 #[tokio::main]
@@ -61,15 +441,70 @@ async fn main() -> Result<()> {
         Commands::Configure { device, config_type } => {
             configure_device(device, config_type).await?;
         }
-        Commands::Mount { device, volume } => {
+        Commands::Mount { device, volume, fuse: Some(mountpoint), read_only, hidden: true, hidden_kdf, .. } => {
+            mount_hidden_fuse(device, volume, mountpoint, read_only, hidden_kdf).await?;
+        }
+        Commands::Mount { device, volume, fuse: Some(mountpoint), read_only, protect_hidden, hidden_kdf, keyfile, .. } => {
+            let hidden_kdf = protect_hidden.then_some(hidden_kdf);
+            mount_fuse(device, volume, mountpoint, read_only, hidden_kdf, keyfile).await?;
+        }
+        Commands::Mount { device, volume, .. } => {
             mount_volume(device, volume).await?;
         }
-        Commands::Unmount { volume } => {
+        Commands::Unmount { volume, fuse: true } => {
+            #[cfg(target_os = "linux")]
+            fuse::unmount(std::path::Path::new(&volume))?;
+            #[cfg(not(target_os = "linux"))]
+            return Err(DeepVaultError::NotImplemented(format!("FUSE mounts are only supported on Linux ({})", volume)));
+        }
+        Commands::Unmount { volume, .. } => {
             unmount_volume(volume).await?;
         }
         Commands::Wipe { device, header_only } => {
             wipe_device(device, header_only).await?;
         }
+        Commands::Benchmark { time, memory, parallelism, algorithm, save } => {
+            benchmark_kdf(time, memory, parallelism, algorithm, save).await?;
+        }
+        Commands::Recovery { device, keyfile, output } => {
+            add_recovery_key(device, keyfile, output).await?;
+        }
+        Commands::Shares { action } => {
+            manage_shares(action).await?;
+        }
+        Commands::Header { action } => {
+            manage_header(action).await?;
+        }
+        Commands::Identity { action } => {
+            manage_identity(action).await?;
+        }
+        Commands::Recipient { action } => {
+            manage_recipient(action).await?;
+        }
+        Commands::Resize { device, size, force, keyfile } => {
+            resize_volume(device, size, force, keyfile).await?;
+        }
+        Commands::Policy { device, wipe_after, delay, max_delay, keyfile } => {
+            set_attempt_policy(device, wipe_after, delay, max_delay, keyfile).await?;
+        }
+        Commands::Duress { device, keyfile } => {
+            add_duress_passphrase(device, keyfile).await?;
+        }
+        Commands::Ssh { action } => {
+            manage_ssh(action).await?;
+        }
+        Commands::Hidden { action } => {
+            manage_hidden(action).await?;
+        }
+        Commands::Integrity { action } => {
+            manage_integrity(action).await?;
+        }
+        Commands::Container { action } => {
+            manage_container(action).await?;
+        }
+        Commands::Passphrase { action } => {
+            manage_passphrase(action).await?;
+        }
     }
     
     Ok(())
@@ -109,6 +544,14 @@ async fn list_devices() -> Result<()> {
 
 async fn configure_device(device: PathBuf, config_type: Option<String>) -> Result<()> {
     println!("Configuration du périphérique: {}", device.display());
+    if let Some(config_type) = &config_type {
+        println!("Type de configuration: {}", config_type);
+    }
+    
+    if config_type.as_deref() == Some("container") {
+        println!("Utilisez `deepvault-cli container create` pour créer un conteneur.");
+        return Ok(());
+    }
     
     if !crate::utils::is_admin() {
         return Err(DeepVaultError::Permission("Administrator privileges required".to_string()));
@@ -129,6 +572,89 @@ async fn mount_volume(device: PathBuf, volume: String) -> Result<()> {
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
+async fn mount_fuse(
+    device: PathBuf,
+    volume: String,
+    mountpoint: PathBuf,
+    read_only: bool,
+    hidden_kdf: Option<HiddenKdf>,
+    keyfiles: Vec<PathBuf>,
+) -> Result<()> {
+    let config = AppConfig::load(&AppConfig::default_config_path())?;
+    let credentials = read_credentials(&keyfiles)?;
+    let options = fuse::FuseOptions { read_only };
+    let hidden = match hidden_kdf {
+        Some(kdf) => {
+            let passphrase = SecretString::new(rpassword::prompt_password("Phrase de passe du volume caché à protéger: ")?);
+            Some((Credentials::passphrase(&passphrase), kdf.params()?))
+        }
+        None => None,
+    };
+    let protect = hidden.as_ref().map(|(credentials, kdf)| ProtectHidden { credentials, kdf });
+    println!("Montage du volume {} sur {} (FUSE)", volume, mountpoint.display());
+    println!(
+        "L'image déchiffrée est {}. Démontez avec `deepvault-cli unmount {} --fuse`.",
+        mountpoint.join(fuse::IMAGE_NAME).display(),
+        mountpoint.display()
+    );
+    MountManager::new(device).mount_fuse(&credentials, &config.security, &mountpoint, &options, protect.as_ref())?;
+    println!("Volume démonté.");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+async fn mount_hidden_fuse(
+    device: PathBuf,
+    volume: String,
+    mountpoint: PathBuf,
+    read_only: bool,
+    hidden_kdf: HiddenKdf,
+) -> Result<()> {
+    let config = AppConfig::load(&AppConfig::default_config_path())?;
+    if !config.security.enable_hidden_volumes {
+        return Err(DeepVaultError::Config(
+            "Hidden volumes are disabled (security.enable_hidden_volumes)".to_string(),
+        ));
+    }
+    let kdf = hidden_kdf.params()?;
+    let passphrase = SecretString::new(rpassword::prompt_password("Phrase de passe du volume caché: ")?);
+    let credentials = Credentials::passphrase(&passphrase);
+    let options = fuse::FuseOptions { read_only };
+    println!("Montage du volume caché {} sur {} (FUSE)", volume, mountpoint.display());
+    println!(
+        "L'image déchiffrée est {}. Démontez avec `deepvault-cli unmount {} --fuse`.",
+        mountpoint.join(fuse::IMAGE_NAME).display(),
+        mountpoint.display()
+    );
+    MountManager::new(device).mount_hidden_fuse(&credentials, &kdf, &mountpoint, &options)?;
+    println!("Volume démonté.");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn mount_hidden_fuse(
+    _device: PathBuf,
+    _volume: String,
+    _mountpoint: PathBuf,
+    _read_only: bool,
+    _hidden_kdf: HiddenKdf,
+) -> Result<()> {
+    Err(DeepVaultError::NotImplemented("FUSE mounts are only supported on Linux".to_string()))
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn mount_fuse(
+    _device: PathBuf,
+    _volume: String,
+    _mountpoint: PathBuf,
+    _read_only: bool,
+    _hidden_kdf: Option<HiddenKdf>,
+    _keyfiles: Vec<PathBuf>,
+) -> Result<()> {
+    Err(DeepVaultError::NotImplemented("FUSE mounts are only supported on Linux".to_string()))
+}
+
 async fn unmount_volume(volume: String) -> Result<()> {
     println!("Démontage du volume: {}", volume);
     
@@ -156,3 +682,562 @@ async fn wipe_device(device: PathBuf, header_only: bool) -> Result<()> {
     
     Ok(())
 }
+
+async fn benchmark_kdf(
+    time: u64,
+    memory: Option<u32>,
+    parallelism: Option<u32>,
+    algorithm: String,
+    save: bool,
+) -> Result<()> {
+    let algorithm: KdfAlgorithm = algorithm.parse()?;
+    let mut target = kdf::CalibrationTarget {
+        unlock_time: std::time::Duration::from_millis(time),
+        ..Default::default()
+    };
+    if let Some(memory) = memory {
+        target.max_memory = memory.saturating_mul(1024);
+    }
+    if let Some(parallelism) = parallelism {
+        target.max_parallelism = parallelism;
+    }
+
+    println!("DeepVault - Calibration de la dérivation de clé");
+    println!("===============================================");
+    println!("Algorithme: {}", algorithm);
+    println!("Temps visé: {} ms", time);
+    println!("Mémoire maximale: {}", crate::utils::format_bytes(target.max_memory as u64 * 1024));
+    println!("Parallélisme maximal: {}", target.max_parallelism);
+    println!();
+    println!("Mesure en cours...");
+
+    let calibration = tokio::task::spawn_blocking(move || kdf::calibrate(algorithm, &target))
+        .await
+        .map_err(|e| DeepVaultError::System(format!("Benchmark task failed: {}", e)))??;
+
+    let params = calibration
+        .kdf
+        .argon2()
+        .cloned()
+        .ok_or_else(|| DeepVaultError::Crypto("Calibration returned non-Argon2 parameters".to_string()))?;
+    println!();
+    println!("Mémoire: {}", crate::utils::format_bytes(params.memory as u64 * 1024));
+    println!("Itérations: {}", params.iterations);
+    println!("Parallélisme: {}", params.parallelism);
+    println!("Temps de déverrouillage mesuré: {} ms", calibration.duration.as_millis());
+
+    if save {
+        let config_path = AppConfig::default_config_path();
+        let mut config = AppConfig::load(&config_path)?;
+        config.security.default_algorithm = algorithm.name().to_string();
+        config.security.default_kdf_params = params;
+        config.validate()?;
+        config.save(&config_path)?;
+        println!();
+        println!("Paramètres enregistrés dans {}", config_path.display());
+    }
+
+    Ok(())
+}
+
+async fn manage_header(action: HeaderAction) -> Result<()> {
+    match action {
+        HeaderAction::Backup { device, file } => {
+            println!("Sauvegarde du header de {} vers {}", device.display(), file.display());
+            backup_header(&device, &file)?;
+            println!("Sauvegarde terminée. Conservez ce fichier en lieu sûr.");
+        }
+        HeaderAction::Restore { file, device, keyfile, force } => {
+            println!("Restauration du header de {} sur {}", file.display(), device.display());
+            let credentials = read_credentials(&keyfile)?;
+            let header = restore_header(&file, &device, &credentials, force)?;
+            println!("Header du volume {} restauré.", header.uuid);
+        }
+    }
+    Ok(())
+}
+
+/// Prompt for a passphrase and add the given keyfiles
+fn read_credentials(keyfiles: &[PathBuf]) -> Result<Credentials> {
+    let passphrase = SecretString::new(rpassword::prompt_password("Phrase de passe: ")?);
+    let mut credentials = Credentials::new();
+    if !passphrase.is_empty() || keyfiles.is_empty() {
+        credentials = credentials.with_passphrase(&passphrase);
+    }
+    for keyfile in keyfiles {
+        credentials = credentials.with_keyfile(keyfile)?;
+    }
+    Ok(credentials)
+}
+
+/// Prompt twice for a new passphrase
+fn read_new_passphrase(prompt: &str) -> Result<SecretString> {
+    let passphrase = SecretString::new(rpassword::prompt_password(prompt)?);
+    let strength = strength::estimate(passphrase.expose_secret());
+    if !strength.is_acceptable() {
+        print_strength(&strength);
+        return Err(DeepVaultError::InvalidInput(
+            "Passphrase too weak, try `deepvault-cli passphrase generate`".to_string(),
+        ));
+    }
+    let confirmation = SecretString::new(rpassword::prompt_password("Confirmation: ")?);
+    if passphrase != confirmation {
+        return Err(DeepVaultError::InvalidInput("Passphrases do not match".to_string()));
+    }
+    Ok(passphrase)
+}
+
+/// Convert a size given in MB on the command line to bytes
+fn megabytes(size: u64) -> Result<u64> {
+    size.checked_mul(1024 * 1024)
+        .ok_or_else(|| DeepVaultError::InvalidInput(format!("Size too large: {} MB", size)))
+}
+
+async fn manage_hidden(action: HiddenAction) -> Result<()> {
+    let config = AppConfig::load(&AppConfig::default_config_path())?;
+    if !config.security.enable_hidden_volumes {
+        return Err(DeepVaultError::Config(
+            "Hidden volumes are disabled (security.enable_hidden_volumes)".to_string(),
+        ));
+    }
+    match action {
+        HiddenAction::Create { device, size, keyfile, assume_random, kdf: kdf_args } => {
+            let kdf = kdf_args.params()?;
+            let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+            let credentials = read_credentials(&keyfile)?;
+            let (mut outer, outer_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+            if assume_random && !outer.random_fill() {
+                outer.enable_random_fill(&outer_key)?;
+                outer.store(&mut storage)?;
+            }
+
+            let passphrase = read_new_passphrase("Phrase de passe du volume caché: ")?;
+            let hidden_credentials = Credentials::passphrase(&passphrase);
+            if outer.unlock_with_credentials(&hidden_credentials).is_ok() {
+                return Err(DeepVaultError::InvalidInput(
+                    "The hidden passphrase must differ from the outer one".to_string(),
+                ));
+            }
+            let (hidden, _) = hidden::create(&mut storage, &outer, &hidden_credentials, &kdf, megabytes(size)?)?;
+            println!(
+                "Volume caché de {} créé à l'offset {}.",
+                utils::format_bytes(hidden.data_size),
+                hidden.data_offset
+            );
+            println!(
+                "Réglages de dérivation, à redonner pour l'ouvrir: --kdf-memory {} --kdf-iterations {} --kdf-parallelism {}",
+                kdf_args.kdf_memory, kdf_args.kdf_iterations, kdf_args.kdf_parallelism
+            );
+        }
+        HiddenAction::Unlock { device, kdf } => {
+            let kdf = kdf.params()?;
+            let mut storage = std::fs::File::open(&device)?;
+            let passphrase = SecretString::new(rpassword::prompt_password("Phrase de passe du volume caché: ")?);
+            let (hidden, _) = hidden::open_with_password(&mut storage, &passphrase, &kdf)?;
+            println!(
+                "Volume caché {} ouvert: {} à l'offset {}.",
+                hidden.uuid,
+                utils::format_bytes(hidden.data_size),
+                hidden.data_offset
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn manage_integrity(action: IntegrityAction) -> Result<()> {
+    match action {
+        IntegrityAction::Enable { device, keyfile, yes } => {
+            if !yes {
+                return Err(DeepVaultError::InvalidInput(
+                    "Enabling integrity erases the data of the vault, confirm with --yes".to_string(),
+                ));
+            }
+            let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+            let config = AppConfig::load(&AppConfig::default_config_path())?;
+            let credentials = read_credentials(&keyfile)?;
+            let (mut header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+            if header.integrity() {
+                println!("Le volume {} est déjà en mode intégrité.", header.uuid);
+                return Ok(());
+            }
+
+            header.enable_integrity(&master_key)?;
+            let data_area = FileDevice::new(&mut storage, header.data_offset, header.data_size);
+            let tagged = AuthenticatedDevice::format(data_area, &master_key)?;
+            println!(
+                "Mode intégrité activé: {} utilisables sur {}.",
+                utils::format_bytes(tagged.size()),
+                utils::format_bytes(header.data_size)
+            );
+            header.store(&mut storage)?;
+        }
+        IntegrityAction::Check { device, keyfile } => {
+            let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+            let config = AppConfig::load(&AppConfig::default_config_path())?;
+            let credentials = read_credentials(&keyfile)?;
+            let (header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+            if !header.integrity() {
+                return Err(DeepVaultError::InvalidInput("The volume does not use integrity tags".to_string()));
+            }
+
+            let data_area = FileDevice::new(&mut storage, header.data_offset, header.data_size);
+            let mut tagged = AuthenticatedDevice::new(data_area, &master_key)?;
+            let modified = tagged.scan()?;
+            if modified.is_empty() {
+                println!("Les {} secteurs sont intacts.", tagged.sector_count());
+            } else {
+                println!("{} secteurs modifiés:", modified.len());
+                for sector in &modified {
+                    println!("  - secteur {}", sector);
+                }
+                return Err(DeepVaultError::IntegrityViolation { sector: modified[0] });
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn manage_container(action: ContainerAction) -> Result<()> {
+    let config = AppConfig::load(&AppConfig::default_config_path())?;
+    match action {
+        ContainerAction::Create { path, size, integrity, fill_random, sparse, keyfile } => {
+            let passphrase = if keyfile.is_empty() {
+                Some(read_new_passphrase("Phrase de passe du conteneur: ")?)
+            } else {
+                None
+            };
+            let mut credentials = Credentials::new();
+            if let Some(passphrase) = &passphrase {
+                credentials = credentials.with_passphrase(passphrase);
+            }
+            for keyfile in &keyfile {
+                credentials = credentials.with_keyfile(keyfile)?;
+            }
+            let options = ContainerOptions {
+                size: megabytes(size)?,
+                kdf: config.security.default_kdf()?,
+                integrity,
+                fill_random,
+                sparse,
+                attempt_policy: config.security.attempt_policy,
+            };
+            let container = Container::create(&path, &credentials, &options)?;
+            println!(
+                "Conteneur {} créé: {} utilisables.",
+                path.display(),
+                utils::format_bytes(container.size())
+            );
+            container.close()?;
+        }
+        ContainerAction::Info { path, keyfile } => {
+            let credentials = read_credentials(&keyfile)?;
+            let container = Container::open(&path, &credentials, &config.security)?;
+            let header = container.header();
+            println!("Conteneur: {}", container.path().display());
+            println!("UUID: {}", header.uuid);
+            println!("Taille: {}", utils::format_bytes(container.size()));
+            println!("Intégrité: {}", if header.integrity() { "Oui" } else { "Non" });
+            println!("Dynamique: {}", if header.sparse() { "Oui" } else { "Non" });
+            println!("Rempli d'aléa: {}", if header.random_fill() { "Oui" } else { "Non" });
+            println!("Taille du fichier: {}", utils::format_bytes(std::fs::metadata(&path)?.len()));
+            container.close()?;
+        }
+        ContainerAction::Compact { path, keyfile } => {
+            let credentials = read_credentials(&keyfile)?;
+            let mut container = Container::open(&path, &credentials, &config.security)?;
+            let reclaimed = container.compact()?;
+            println!("{} libérés.", utils::format_bytes(reclaimed));
+            container.close()?;
+        }
+    }
+    Ok(())
+}
+
+fn print_strength(strength: &PasswordStrength) {
+    println!("Score: {}/4 ({:.0} bits)", strength.score, strength.entropy_bits);
+    println!("Temps de cassage estimé: {}", format_crack_time(strength.crack_time));
+    for advice in &strength.feedback {
+        println!("  - {}", advice);
+    }
+}
+
+fn format_crack_time(time: std::time::Duration) -> String {
+    const UNITS: &[(u64, &str)] = &[
+        (100 * 365 * 24 * 3600, "siècles"),
+        (365 * 24 * 3600, "ans"),
+        (24 * 3600, "jours"),
+        (3600, "heures"),
+        (60, "minutes"),
+        (1, "secondes"),
+    ];
+    let seconds = time.as_secs();
+    match UNITS.iter().find(|(unit, _)| seconds >= *unit) {
+        Some((unit, name)) if seconds / unit >= 1_000_000 => format!("plus d'un million de {}", name),
+        Some((unit, name)) => format!("{} {}", seconds / unit, name),
+        None => "moins d'une seconde".to_string(),
+    }
+}
+
+async fn manage_passphrase(action: PassphraseAction) -> Result<()> {
+    match action {
+        PassphraseAction::Generate { words, separator } => {
+            let passphrase = utils::generate_passphrase(words, &separator)?;
+            println!("{}", passphrase.expose_secret());
+            print_strength(&strength::estimate(passphrase.expose_secret()));
+        }
+        PassphraseAction::Check => {
+            let passphrase = SecretString::new(rpassword::prompt_password("Phrase de passe: ")?);
+            let strength = strength::estimate(passphrase.expose_secret());
+            print_strength(&strength);
+            if strength.is_acceptable() {
+                println!("Phrase de passe acceptée.");
+            } else {
+                println!("Phrase de passe trop faible.");
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn add_recovery_key(device: PathBuf, keyfiles: Vec<PathBuf>, output: Option<PathBuf>) -> Result<()> {
+    println!("Ajout d'une clé de récupération sur {}", device.display());
+
+    let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+    let config = AppConfig::load(&AppConfig::default_config_path())?;
+    let credentials = read_credentials(&keyfiles)?;
+    let (mut header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+
+    let kdf = config.security.default_kdf()?;
+    let (keyslot, recovery_key) = header.add_recovery_key(&master_key, &kdf)?;
+
+    // The sheet is saved before the header, so an enrolled key is never lost
+    let sheet = RecoverySheet::new(&header, keyslot, &recovery_key);
+    match output {
+        Some(path) => {
+            let is_svg = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+            let contents = if is_svg { sheet.to_svg() } else { sheet.to_text() };
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .and_then(|mut file| std::io::Write::write_all(&mut file, contents.expose_secret().as_bytes()))?;
+            println!("Feuille de récupération écrite dans {}", path.display());
+        }
+        None => println!("\n{}", sheet.to_text().expose_secret()),
+    }
+    header.store(&mut storage)?;
+    println!("Clé de récupération ajoutée dans le keyslot {}.", keyslot);
+
+    Ok(())
+}
+
+async fn manage_shares(action: SharesAction) -> Result<()> {
+    match action {
+        SharesAction::Split { device, threshold, count, output, keyfile } => {
+            println!("Partage de l'accès à {} ({} parts sur {})", device.display(), threshold, count);
+
+            let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+            let config = AppConfig::load(&AppConfig::default_config_path())?;
+            let credentials = read_credentials(&keyfile)?;
+            let (mut header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+
+            let kdf = config.security.default_kdf()?;
+            let (keyslot, shares) = header.add_shared_keyslot(&master_key, threshold, count, &kdf)?;
+
+            // Shares are saved before the header, so an enrolled keyslot is never orphaned
+            for share in &shares {
+                let path = output.join(format!("{}-part-{}.dvs", header.uuid, share.x));
+                share.save(&path)?;
+                println!();
+                println!("Part {}/{}: {}", share.x, share.count, path.display());
+                println!("{}", share.to_code().expose_secret());
+            }
+            header.store(&mut storage)?;
+
+            println!();
+            println!("Keyslot partagé {} ajouté. Remettez chaque part à un dépositaire différent.", keyslot);
+        }
+        SharesAction::Verify { device, shares } => {
+            let mut storage = std::fs::File::open(&device)?;
+            let header = VaultHeader::load(&mut storage)?;
+            let shares = shares
+                .iter()
+                .map(|path| Share::load(path))
+                .collect::<Result<Vec<_>>>()?;
+            header.unlock_with_shares(&shares)?;
+            println!("Les {} parts déverrouillent le volume {}.", shares.len(), header.uuid);
+        }
+    }
+    Ok(())
+}
+
+async fn manage_identity(action: IdentityAction) -> Result<()> {
+    match action {
+        IdentityAction::Generate { file } => {
+            let identity = Identity::generate();
+            identity.save(&file)?;
+            println!("Identité écrite dans {}", file.display());
+            println!("Destinataire: {}", identity.to_recipient());
+        }
+        IdentityAction::Recipient { file } => {
+            println!("{}", Identity::load(&file)?.to_recipient());
+        }
+    }
+    Ok(())
+}
+
+async fn manage_recipient(action: RecipientAction) -> Result<()> {
+    match action {
+        RecipientAction::Add { device, recipient, keyfile } => {
+            let path = PathBuf::from(&recipient);
+            let recipient = if path.is_file() { Recipient::load(&path)? } else { recipient.parse()? };
+            println!("Ajout du destinataire {} sur {}", recipient, device.display());
+
+            let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+            let config = AppConfig::load(&AppConfig::default_config_path())?;
+            let credentials = read_credentials(&keyfile)?;
+            let (mut header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+            let keyslot = header.add_recipient_keyslot(&master_key, &recipient)?;
+            header.store(&mut storage)?;
+            println!("Keyslot destinataire {} ajouté.", keyslot);
+        }
+        RecipientAction::Unlock { device, identity, add_passphrase } => {
+            let identity = Identity::load(&identity)?;
+            let mut storage = std::fs::OpenOptions::new()
+                .read(true)
+                .write(add_passphrase)
+                .open(&device)?;
+            let mut header = VaultHeader::load(&mut storage)?;
+            let master_key = header.unlock_with_identity(&identity)?;
+            println!("L'identité {} déverrouille le volume {}.", identity.to_recipient(), header.uuid);
+
+            if add_passphrase {
+                let passphrase = read_new_passphrase("Nouvelle phrase de passe: ")?;
+                let kdf = AppConfig::load(&AppConfig::default_config_path())?.security.default_kdf()?;
+                let keyslot = header.add_keyslot(&master_key, &passphrase, &kdf)?;
+                header.store(&mut storage)?;
+                println!("Nouvelle phrase de passe ajoutée dans le keyslot {}.", keyslot);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn manage_ssh(action: SshAction) -> Result<()> {
+    let mut agent = SshAgent::connect()?;
+    match action {
+        SshAction::Add { device, key, keyfile } => {
+            let ssh_key = match key {
+                Some(path) => SshPublicKey::load(&path)?,
+                None => agent
+                    .identities()?
+                    .into_iter()
+                    .map(|(key, _)| key)
+                    .next()
+                    .ok_or_else(|| DeepVaultError::Config("The SSH agent holds no ed25519 key".to_string()))?,
+            };
+            println!("Ajout de la clé SSH {} sur {}", ssh_key.fingerprint(), device.display());
+
+            let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+            let config = AppConfig::load(&AppConfig::default_config_path())?;
+            let credentials = read_credentials(&keyfile)?;
+            let (mut header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+            let keyslot = header.add_ssh_agent_keyslot(&master_key, &ssh_key, &mut agent)?;
+            header.store(&mut storage)?;
+            println!("Keyslot SSH {} ajouté.", keyslot);
+        }
+        SshAction::Unlock { device } => {
+            let mut storage = std::fs::File::open(&device)?;
+            let header = VaultHeader::load(&mut storage)?;
+            header.unlock_with_ssh_agent(&mut agent)?;
+            println!("L'agent SSH déverrouille le volume {}.", header.uuid);
+        }
+    }
+    Ok(())
+}
+
+async fn add_duress_passphrase(device: PathBuf, keyfiles: Vec<PathBuf>) -> Result<()> {
+    let config = AppConfig::load(&AppConfig::default_config_path())?;
+    if !config.security.enable_duress_passphrase {
+        return Err(DeepVaultError::Config(
+            "Duress passphrases are disabled, set security.enable_duress_passphrase first".to_string(),
+        ));
+    }
+    println!("Ajout d'une phrase de passe de contrainte sur {}", device.display());
+    println!("ATTENTION: la saisir détruit définitivement tous les keyslots du volume.");
+
+    let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+    let credentials = read_credentials(&keyfiles)?;
+    let (mut header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+    let passphrase = read_new_passphrase("Phrase de passe de contrainte: ")?;
+    let keyslot = header.add_duress_keyslot(&master_key, &passphrase, &config.security.default_kdf()?)?;
+    header.store(&mut storage)?;
+    println!("Phrase de passe de contrainte ajoutée dans le keyslot {}.", keyslot);
+
+    Ok(())
+}
+
+async fn resize_volume(device: PathBuf, size: u64, force: bool, keyfiles: Vec<PathBuf>) -> Result<()> {
+    let config = AppConfig::load(&AppConfig::default_config_path())?;
+    let credentials = read_credentials(&keyfiles)?;
+    let new_size = megabytes(size)?;
+
+    // Containers and images grow with their file, partitions are fixed
+    if std::fs::metadata(&device)?.is_file() {
+        let mut container = Container::open(&device, &credentials, &config.security)?;
+        let old_size = container.size();
+        container.resize(new_size, force)?;
+        println!(
+            "Conteneur redimensionné: {} -> {}.",
+            utils::format_bytes(old_size),
+            utils::format_bytes(container.size())
+        );
+        container.close()?;
+    } else {
+        let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+        let (mut header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+        let old_size = header.data_size;
+        resize::resize(&mut storage, &mut header, &master_key, new_size, force)?;
+        println!(
+            "Volume redimensionné: {} -> {}.",
+            utils::format_bytes(old_size),
+            utils::format_bytes(header.data_size)
+        );
+    }
+    println!("Pensez à agrandir ou réduire le système de fichiers du volume avec ses propres outils.");
+    Ok(())
+}
+
+async fn set_attempt_policy(
+    device: PathBuf,
+    wipe_after: Option<u32>,
+    delay: Option<u32>,
+    max_delay: Option<u32>,
+    keyfiles: Vec<PathBuf>,
+) -> Result<()> {
+    let config = AppConfig::load(&AppConfig::default_config_path())?;
+    let defaults = config.security.attempt_policy;
+    let policy = AttemptPolicy {
+        wipe_after: wipe_after.unwrap_or(defaults.wipe_after),
+        base_delay: delay.unwrap_or(defaults.base_delay),
+        max_delay: max_delay.unwrap_or(defaults.max_delay),
+    };
+    policy.validate()?;
+    println!("Politique d'échecs de {}", device.display());
+
+    let mut storage = std::fs::OpenOptions::new().read(true).write(true).open(&device)?;
+    let credentials = read_credentials(&keyfiles)?;
+    let (mut header, master_key) = VaultHeader::open(&mut storage, &credentials, &config.security)?;
+    header.set_attempt_policy(&master_key, policy)?;
+    header.store(&mut storage)?;
+
+    println!("Délai après un échec: {} s, doublé à chaque échec, au plus {} s", policy.base_delay, policy.max_delay);
+    if policy.wipe_after > 0 {
+        println!("Les keyslots seront détruits après {} échecs consécutifs.", policy.wipe_after);
+    } else {
+        println!("Les keyslots ne sont jamais détruits après des échecs.");
+    }
+
+    Ok(())
+}
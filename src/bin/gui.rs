@@ -1,7 +1,6 @@
 //! DeepVault GUI application (Tauri)
 
 use deepvault_core::*;
-use std::path::PathBuf;
 
 // Note: This is a placeholder for the Tauri GUI
 // The actual Tauri implementation would be in src-tauri/
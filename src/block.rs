@@ -0,0 +1,267 @@
+//! Block device abstraction for DeepVault
+//!
+//! Everything that stores vault data (raw partitions, image files,
+//! containers, encryption layers) is exposed as a [`BlockDevice`] of fixed
+//! 512-byte sectors, so layers can be stacked freely.
+
+use crate::{DeepVaultError, Result};
+use rand::RngCore;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+/// Size of a sector in bytes
+pub const SECTOR_SIZE: usize = 512;
+
+/// Chunk size used by [`fill_random`]
+const FILL_CHUNK: usize = 1024 * 1024;
+
+/// Random-access storage addressed in sectors
+pub trait BlockDevice {
+    /// Number of sectors on the device
+    fn sector_count(&self) -> u64;
+
+    /// Read whole sectors starting at `first` into `buf`
+    ///
+    /// `buf.len()` must be a multiple of [`SECTOR_SIZE`].
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Write whole sectors starting at `first` from `buf`
+    ///
+    /// `buf.len()` must be a multiple of [`SECTOR_SIZE`].
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()>;
+
+    /// Flush pending writes to stable storage
+    fn flush(&mut self) -> Result<()>;
+
+    /// Tell the device that `count` sectors from `first` no longer hold
+    /// data, so it may release them
+    ///
+    /// Discarded sectors read back as zeros or as their previous contents.
+    fn discard(&mut self, first: u64, count: u64) -> Result<()> {
+        let _ = (first, count);
+        Ok(())
+    }
+
+    /// Size of the device in bytes
+    fn size(&self) -> u64 {
+        self.sector_count() * SECTOR_SIZE as u64
+    }
+
+    /// Read bytes at an arbitrary offset
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        check_range(self.size(), offset, buf.len())?;
+
+        let mut done = 0;
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let index = pos / SECTOR_SIZE as u64;
+            let skip = (pos % SECTOR_SIZE as u64) as usize;
+            let len = (SECTOR_SIZE - skip).min(buf.len() - done);
+
+            self.read_sectors(index, &mut sector)?;
+            buf[done..done + len].copy_from_slice(&sector[skip..skip + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Write bytes at an arbitrary offset
+    ///
+    /// Partially covered sectors are read, modified and written back.
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        check_range(self.size(), offset, buf.len())?;
+
+        let mut done = 0;
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let index = pos / SECTOR_SIZE as u64;
+            let skip = (pos % SECTOR_SIZE as u64) as usize;
+            let len = (SECTOR_SIZE - skip).min(buf.len() - done);
+
+            if len < SECTOR_SIZE {
+                self.read_sectors(index, &mut sector)?;
+            }
+            sector[skip..skip + len].copy_from_slice(&buf[done..done + len]);
+            self.write_sectors(index, &sector)?;
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn sector_count(&self) -> u64 {
+        (**self).sector_count()
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_sectors(first, buf)
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        (**self).write_sectors(first, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn discard(&mut self, first: u64, count: u64) -> Result<()> {
+        (**self).discard(first, count)
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for Box<D> {
+    fn sector_count(&self) -> u64 {
+        (**self).sector_count()
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_sectors(first, buf)
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        (**self).write_sectors(first, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn discard(&mut self, first: u64, count: u64) -> Result<()> {
+        (**self).discard(first, count)
+    }
+}
+
+/// Check that a sector request is aligned and inside the device
+pub fn check_sectors(sector_count: u64, first: u64, len: usize) -> Result<()> {
+    if !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "Buffer length {} is not a multiple of the sector size",
+            len
+        )));
+    }
+    let count = (len / SECTOR_SIZE) as u64;
+    if first.checked_add(count).is_none_or(|end| end > sector_count) {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "Sectors {}..{} are outside the device ({} sectors)",
+            first,
+            first.saturating_add(count),
+            sector_count
+        )));
+    }
+    Ok(())
+}
+
+fn check_range(size: u64, offset: u64, len: usize) -> Result<()> {
+    if offset.checked_add(len as u64).is_none_or(|end| end > size) {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "Range {}+{} is outside the device ({} bytes)",
+            offset, len, size
+        )));
+    }
+    Ok(())
+}
+
+/// Seekable storage that can be made durable
+pub trait Storage: Read + Write + Seek {
+    /// Flush written data to stable storage
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Storage for std::fs::File {
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.sync_data()
+    }
+}
+
+impl Storage for Cursor<Vec<u8>> {}
+
+impl Storage for Cursor<&mut Vec<u8>> {}
+
+impl<S: Storage + ?Sized> Storage for &mut S {
+    fn sync(&mut self) -> std::io::Result<()> {
+        (**self).sync()
+    }
+}
+
+/// Overwrite `len` bytes of `storage` from `offset` with random bytes
+pub fn fill_random<S: Storage + ?Sized>(storage: &mut S, offset: u64, len: u64) -> Result<()> {
+    let mut chunk = vec![0u8; FILL_CHUNK];
+    storage.seek(SeekFrom::Start(offset))?;
+    let mut done = 0;
+    while done < len {
+        let n = (len - done).min(FILL_CHUNK as u64) as usize;
+        rand::rngs::OsRng.fill_bytes(&mut chunk[..n]);
+        storage.write_all(&chunk[..n])?;
+        done += n as u64;
+    }
+    Ok(())
+}
+
+/// Block device backed by a region of a file, disk or in-memory buffer
+pub struct FileDevice<F> {
+    inner: F,
+    offset: u64,
+    sector_count: u64,
+}
+
+impl<F: Storage> FileDevice<F> {
+    /// Expose `size` bytes of `inner` starting at `offset`
+    pub fn new(inner: F, offset: u64, size: u64) -> Self {
+        Self {
+            inner,
+            offset,
+            sector_count: size / SECTOR_SIZE as u64,
+        }
+    }
+
+    /// Get a reference to the underlying storage
+    pub fn get_ref(&self) -> &F {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying storage
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    /// Consume the device and return the underlying storage
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    fn seek_to(&mut self, sector: u64) -> Result<()> {
+        self.inner
+            .seek(SeekFrom::Start(self.offset + sector * SECTOR_SIZE as u64))?;
+        Ok(())
+    }
+}
+
+impl<F: Storage> BlockDevice for FileDevice<F> {
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        check_sectors(self.sector_count, first, buf.len())?;
+        self.seek_to(first)?;
+        self.inner.read_exact(buf)?;
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        check_sectors(self.sector_count, first, buf.len())?;
+        self.seek_to(first)?;
+        self.inner.write_all(buf)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.sync()?;
+        Ok(())
+    }
+}
@@ -1,11 +1,13 @@
 //! Configuration management for DeepVault
 
+use crate::attempts::AttemptPolicy;
+use crate::kdf::{KdfAlgorithm, KdfParams, Pbkdf2Params, ScryptParams};
 use crate::{DeepVaultError, Result};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
 /// Application configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AppConfig {
     pub security: SecurityConfig,
     pub ui: UiConfig,
@@ -17,7 +19,17 @@ pub struct AppConfig {
 pub struct SecurityConfig {
     pub default_algorithm: String,
     pub default_kdf_params: crate::crypto::KeyDerivation,
+    #[serde(default)]
+    pub default_scrypt_params: ScryptParams,
+    #[serde(default)]
+    pub default_pbkdf2_params: Pbkdf2Params,
     pub enable_hidden_volumes: bool,
+    /// Honour duress keyslots: their passphrase destroys every keyslot
+    #[serde(default)]
+    pub enable_duress_passphrase: bool,
+    /// Failed-attempt policy written into the header of new volumes
+    #[serde(default = "default_attempt_policy")]
+    pub attempt_policy: AttemptPolicy,
     pub auto_unmount_timeout: u64, // seconds
     pub secure_wipe_passes: u32,
 }
@@ -39,28 +51,44 @@ pub struct PathConfig {
     pub temp_dir: PathBuf,
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self {
-            security: SecurityConfig::default(),
-            ui: UiConfig::default(),
-            paths: PathConfig::default(),
-        }
-    }
-}
-
 impl Default for SecurityConfig {
     fn default() -> Self {
         Self {
             default_algorithm: "Argon2id".to_string(),
             default_kdf_params: crate::crypto::KeyDerivation::default(),
+            default_scrypt_params: ScryptParams::default(),
+            default_pbkdf2_params: Pbkdf2Params::default(),
             enable_hidden_volumes: true,
+            enable_duress_passphrase: false,
+            attempt_policy: default_attempt_policy(),
             auto_unmount_timeout: 300, // 5 minutes
             secure_wipe_passes: 3,
         }
     }
 }
 
+/// One second after the first failure, doubling up to five minutes
+fn default_attempt_policy() -> AttemptPolicy {
+    AttemptPolicy {
+        wipe_after: 0,
+        base_delay: 1,
+        max_delay: 300,
+    }
+}
+
+impl SecurityConfig {
+    /// Key derivation function used for new keyslots
+    pub fn default_kdf(&self) -> Result<KdfParams> {
+        let algorithm: KdfAlgorithm = self.default_algorithm.parse()?;
+        Ok(KdfParams::for_algorithm(
+            algorithm,
+            &self.default_kdf_params,
+            &self.default_scrypt_params,
+            &self.default_pbkdf2_params,
+        ))
+    }
+}
+
 impl Default for UiConfig {
     fn default() -> Self {
         Self {
@@ -135,10 +163,15 @@ impl AppConfig {
             return Err(DeepVaultError::Config("Secure wipe passes cannot be zero".to_string()));
         }
 
-        if !["Argon2id", "Argon2i", "Argon2d"].contains(&self.security.default_algorithm.as_str()) {
+        if self.security.default_algorithm.parse::<KdfAlgorithm>().is_err() {
             return Err(DeepVaultError::Config("Invalid default algorithm".to_string()));
         }
 
+        self.security.default_kdf_params.validate()?;
+        self.security.default_scrypt_params.validate()?;
+        self.security.default_pbkdf2_params.validate()?;
+        self.security.attempt_policy.validate()?;
+
         Ok(())
     }
 }
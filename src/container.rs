@@ -0,0 +1,316 @@
+//! Encrypted container files
+//!
+//! A container is an ordinary file, by convention with the `.dvc`
+//! extension, holding a complete DeepVault volume: header region, then
+//! data area. It can live on the public partition of the stick or in any
+//! directory, which gives encrypted storage without repartitioning.
+//!
+//! An open container holds an exclusive lock on its file, so the same
+//! container cannot be opened twice at once.
+//!
+//! [`Container::resize`] grows or shrinks the data area together with
+//! the file, see [`crate::resize`].
+//!
+//! Sparse containers start small and grow as blocks are first written,
+//! see [`crate::sparse`]; [`Container::compact`] gives the space of the
+//! blocks freed inside back to the host file system.
+
+use crate::attempts::AttemptPolicy;
+use crate::block::{fill_random, BlockDevice, FileDevice};
+use crate::config::SecurityConfig;
+use crate::header::{VaultHeader, DEFAULT_DATA_OFFSET, HEADER_SIZE};
+use crate::integrity::AuthenticatedDevice;
+use crate::kdf::KdfParams;
+use crate::keyfile::Credentials;
+use crate::secret::SecretKey;
+use crate::resize;
+use crate::sector;
+use crate::sparse::SparseVolume;
+use crate::{DeepVaultError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Usual extension of container files
+pub const CONTAINER_EXTENSION: &str = "dvc";
+/// Smallest data area accepted for a container (1 MiB)
+pub const MIN_CONTAINER_SIZE: u64 = 1024 * 1024;
+/// Largest file a FAT32 partition can hold
+pub const FAT32_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024 - 1;
+
+/// Settings of a new container
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerOptions {
+    /// Size of the data area in bytes, a multiple of 512
+    pub size: u64,
+    /// Key derivation for the first keyslot
+    pub kdf: KdfParams,
+    /// Authenticate every sector, see [`crate::integrity`]
+    pub integrity: bool,
+    /// Fill the data area with random bytes, so that the amount of data
+    /// written later cannot be told from the file (slower)
+    pub fill_random: bool,
+    /// Allocate the data area as it is written; the file size then gives
+    /// away roughly how much data the container holds
+    pub sparse: bool,
+    /// Failed-attempt policy stored in the header
+    #[serde(default)]
+    pub attempt_policy: AttemptPolicy,
+}
+
+impl Default for ContainerOptions {
+    fn default() -> Self {
+        Self {
+            size: 64 * 1024 * 1024,
+            kdf: KdfParams::default(),
+            integrity: false,
+            fill_random: false,
+            sparse: false,
+            attempt_policy: AttemptPolicy::default(),
+        }
+    }
+}
+
+/// An open container, usable as a block device
+pub struct Container {
+    path: PathBuf,
+    header: VaultHeader,
+    master_key: SecretKey,
+    file: File,
+    volume: Box<dyn BlockDevice>,
+}
+
+impl Container {
+    /// Create a new container file at `path`, protected by `credentials`
+    ///
+    /// Fails if the file already exists. On error, the partial file is
+    /// removed.
+    pub fn create(path: &Path, credentials: &Credentials, options: &ContainerOptions) -> Result<Self> {
+        if options.size < MIN_CONTAINER_SIZE || !options.size.is_multiple_of(512) {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "A container holds at least {} bytes, in whole 512-byte sectors",
+                MIN_CONTAINER_SIZE
+            )));
+        }
+        if options.size.checked_add(DEFAULT_DATA_OFFSET).is_none() {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "A container of {} bytes is too large",
+                options.size
+            )));
+        }
+        if options.sparse && (options.integrity || options.fill_random) {
+            return Err(DeepVaultError::InvalidInput(
+                "Sparse containers cannot use integrity tags or be filled with random data".to_string(),
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| DeepVaultError::InvalidInput(format!("Cannot create container {}: {}", path.display(), e)))?;
+        lock(&file, path)?;
+
+        match Self::format(file, credentials, options) {
+            Ok((file, header, master_key)) => Self::from_parts(path, file, header, master_key),
+            Err(e) => {
+                let _ = std::fs::remove_file(path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Open an existing container, applying `security` and the
+    /// failed-attempt policy of the container like [`VaultHeader::open`]
+    pub fn open(path: &Path, credentials: &Credentials, security: &SecurityConfig) -> Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        lock(&file, path)?;
+        let (header, master_key) = VaultHeader::open(&mut file, credentials, security)?;
+        if !header.sparse() && file.metadata()?.len() < header.data_offset + header.data_size {
+            return Err(DeepVaultError::InvalidHeader(format!(
+                "Container {} is truncated",
+                path.display()
+            )));
+        }
+        Self::from_parts(path, file, header, master_key)
+    }
+
+    /// Flush every write and release the container
+    pub fn close(mut self) -> Result<()> {
+        self.volume.flush()
+    }
+
+    /// Resize the data area to `new_size` bytes, growing or truncating
+    /// the file
+    ///
+    /// A container created with [`ContainerOptions::fill_random`] gets
+    /// random bytes in the grown part as well.
+    ///
+    /// The file system inside must be extended after growing, and shrunk
+    /// before shrinking: unless `force` is set, shrinking is refused when
+    /// the file system is not recognized or does not fit.
+    pub fn resize(&mut self, new_size: u64, force: bool) -> Result<()> {
+        if new_size < MIN_CONTAINER_SIZE {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "A container holds at least {} bytes",
+                MIN_CONTAINER_SIZE
+            )));
+        }
+        let new_len = self.header.data_offset.checked_add(new_size).ok_or_else(|| {
+            DeepVaultError::InvalidInput(format!("A container of {} bytes is too large", new_size))
+        })?;
+        self.volume.flush()?;
+        let old_len = self.header.data_offset + self.header.data_size;
+        let mut header = self.header.clone();
+
+        if self.header.sparse() {
+            resize::resize(&mut self.file, &mut header, &self.master_key, new_size, force)?;
+            self.volume = sector::open_volume(self.file.try_clone()?, &header, &self.master_key)?;
+            self.header = header;
+            return Ok(());
+        }
+
+        // The file is always at least as large as the data area in the header
+        if new_len > old_len {
+            set_file_len(&self.file, new_len)?;
+        }
+        let grown = if new_len > old_len && self.header.random_fill() {
+            fill_random(&mut self.file, old_len, new_len - old_len)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = grown.and_then(|_| resize::resize(&mut self.file, &mut header, &self.master_key, new_size, force)) {
+            if new_len > old_len {
+                let _ = self.file.set_len(old_len);
+            }
+            return Err(e);
+        }
+        self.file.sync_all()?;
+        if new_len < old_len {
+            self.file.set_len(new_len)?;
+        }
+
+        self.volume = sector::open_volume(self.file.try_clone()?, &header, &self.master_key)?;
+        self.header = header;
+        Ok(())
+    }
+
+    /// Give the space of the blocks freed inside a sparse container back
+    /// to the host file system
+    ///
+    /// Blocks discarded by the file system inside, or zeroed by it, are
+    /// released and the file is truncated. Returns the bytes reclaimed.
+    pub fn compact(&mut self) -> Result<u64> {
+        if !self.header.sparse() {
+            return Err(DeepVaultError::InvalidInput("Only sparse containers can be compacted".to_string()));
+        }
+        self.volume.flush()?;
+        let old_len = self.file.metadata()?.len();
+        let new_len = SparseVolume::open(&mut self.file, &self.header, &self.master_key)?.compact()?;
+        if new_len < old_len {
+            self.file.set_len(new_len)?;
+            self.file.sync_all()?;
+        }
+        self.volume = sector::open_volume(self.file.try_clone()?, &self.header, &self.master_key)?;
+        Ok(old_len.saturating_sub(new_len))
+    }
+
+    /// Path of the container file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Header of the container
+    pub fn header(&self) -> &VaultHeader {
+        &self.header
+    }
+
+    fn from_parts(path: &Path, file: File, header: VaultHeader, master_key: SecretKey) -> Result<Self> {
+        let volume = sector::open_volume(file.try_clone()?, &header, &master_key)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            header,
+            master_key,
+            file,
+            volume,
+        })
+    }
+
+    /// Lay out a new volume in `file`; the header is written last, so an
+    /// interrupted creation never leaves a container that opens
+    fn format(
+        mut file: File,
+        credentials: &Credentials,
+        options: &ContainerOptions,
+    ) -> Result<(File, VaultHeader, SecretKey)> {
+        let (mut header, master_key) =
+            VaultHeader::create_with_credentials(credentials, &options.kdf, DEFAULT_DATA_OFFSET, options.size)?;
+        header.set_attempt_policy(&master_key, options.attempt_policy)?;
+        if !options.sparse {
+            set_file_len(&file, DEFAULT_DATA_OFFSET + options.size)?;
+        }
+
+        // Random header region, which also hides any future hidden header
+        fill_random(&mut file, HEADER_SIZE as u64, DEFAULT_DATA_OFFSET - HEADER_SIZE as u64)?;
+        if options.fill_random {
+            fill_random(&mut file, DEFAULT_DATA_OFFSET, options.size)?;
+            header.enable_random_fill(&master_key)?;
+        }
+        if options.sparse {
+            header.enable_sparse(&master_key)?;
+            SparseVolume::format(&mut file, &header, &master_key)?;
+        }
+        if options.integrity {
+            header.enable_integrity(&master_key)?;
+            AuthenticatedDevice::format(FileDevice::new(&mut file, header.data_offset, header.data_size), &master_key)?;
+        }
+        header.initialize(&mut file)?;
+        Ok((file, header, master_key))
+    }
+}
+
+impl BlockDevice for Container {
+    fn sector_count(&self) -> u64 {
+        self.volume.sector_count()
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        self.volume.read_sectors(first, buf)
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        self.volume.write_sectors(first, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.volume.flush()
+    }
+
+    fn discard(&mut self, first: u64, count: u64) -> Result<()> {
+        self.volume.discard(first, count)
+    }
+}
+
+/// Take the exclusive lock of an open container
+fn lock(file: &File, path: &Path) -> Result<()> {
+    file.try_lock().map_err(|e| match e {
+        std::fs::TryLockError::WouldBlock => {
+            DeepVaultError::InvalidInput(format!("Container {} is already open", path.display()))
+        }
+        std::fs::TryLockError::Error(e) => e.into(),
+    })
+}
+
+/// Set the length of a container file, explaining the FAT32 limit
+fn set_file_len(file: &File, len: u64) -> Result<()> {
+    file.set_len(len).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::FileTooLarge {
+            DeepVaultError::InvalidInput(format!(
+                "The file system refuses a file this large (FAT32 stops at {} bytes)",
+                FAT32_MAX_FILE_SIZE
+            ))
+        } else {
+            e.into()
+        }
+    })
+}
@@ -1,11 +1,21 @@
 //! Cryptographic operations for DeepVault
 
+use crate::kdf::{Calibration, CalibrationTarget, KdfAlgorithm, KdfParams, Pbkdf2Params, ScryptParams};
+use crate::secret::{SecretKey, SecretString};
 use crate::{DeepVaultError, Result};
-use argon2::{Argon2, PasswordHasher};
-use argon2::password_hash::{rand_core::OsRng, SaltString};
+use subtle::ConstantTimeEq;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use base64::Engine;
+
+/// Length of derived and data encryption keys (AES-256)
+pub const KEY_LEN: usize = 32;
+/// Length of the AES-GCM nonce prepended to every ciphertext
+pub const NONCE_LEN: usize = 12;
+/// Length of the AES-GCM authentication tag appended to every ciphertext
+pub const TAG_LEN: usize = 16;
+/// Length of salts used for key derivation
+pub const SALT_LEN: usize = 32;
 
 /// Cryptographic configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,10 +24,47 @@ pub struct CryptoConfig {
     pub kdf_params: KeyDerivation,
     pub salt: Vec<u8>,
     pub header_file: String,
+    #[serde(default)]
+    pub scrypt_params: ScryptParams,
+    #[serde(default)]
+    pub pbkdf2_params: Pbkdf2Params,
+}
+
+impl CryptoConfig {
+    /// Configuration deriving keys with `kdf` and `salt`
+    pub fn for_kdf(kdf: &KdfParams, salt: &[u8]) -> Self {
+        let mut config = Self {
+            algorithm: kdf.algorithm().name().to_string(),
+            kdf_params: KeyDerivation::default(),
+            salt: salt.to_vec(),
+            header_file: ".dv_meta".to_string(),
+            scrypt_params: ScryptParams::default(),
+            pbkdf2_params: Pbkdf2Params::default(),
+        };
+        match kdf {
+            KdfParams::Argon2id(params) | KdfParams::Argon2i(params) | KdfParams::Argon2d(params) => {
+                config.kdf_params = params.clone()
+            }
+            KdfParams::Scrypt(params) => config.scrypt_params = params.clone(),
+            KdfParams::Pbkdf2Sha512(params) => config.pbkdf2_params = params.clone(),
+        }
+        config
+    }
+
+    /// Key derivation function selected by `algorithm`
+    pub fn kdf(&self) -> Result<KdfParams> {
+        let algorithm: KdfAlgorithm = self.algorithm.parse()?;
+        Ok(KdfParams::for_algorithm(
+            algorithm,
+            &self.kdf_params,
+            &self.scrypt_params,
+            &self.pbkdf2_params,
+        ))
+    }
 }
 
 /// Key derivation parameters
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct KeyDerivation {
     pub memory: u32,      // Memory in KB
     pub iterations: u32,  // Iterations
@@ -44,12 +91,7 @@ impl CryptoManager {
     pub fn new() -> Result<Self> {
         let salt = Self::generate_salt()?;
         Ok(Self {
-            config: CryptoConfig {
-                algorithm: "Argon2id".to_string(),
-                kdf_params: KeyDerivation::default(),
-                salt,
-                header_file: ".dv_meta".to_string(),
-            },
+            config: CryptoConfig::for_kdf(&KdfParams::default(), &salt),
         })
     }
 
@@ -59,73 +101,163 @@ impl CryptoManager {
     }
 
     /// Generate a random salt
-    fn generate_salt() -> Result<Vec<u8>> {
-        let mut salt_bytes = vec![0u8; 16]; // 128 bits
+    pub fn generate_salt() -> Result<Vec<u8>> {
+        let mut salt_bytes = vec![0u8; SALT_LEN];
         use rand::Rng;
         let mut rng = rand::thread_rng();
         rng.fill(&mut salt_bytes[..]);
         Ok(salt_bytes)
     }
 
-    /// Derive key from password using Argon2id
-    pub fn derive_key(&self, password: &str) -> Result<Vec<u8>> {
-        // Create salt string from raw bytes
-        let salt_string = SaltString::from_b64(&base64::engine::general_purpose::STANDARD.encode(&self.config.salt))
-            .map_err(|e| DeepVaultError::Crypto(format!("Invalid salt: {}", e)))?;
-
-        let argon2 = Argon2::new(
-            argon2::Algorithm::Argon2id,
-            argon2::Version::V0x13,
-            argon2::Params::new(
-                self.config.kdf_params.memory,
-                self.config.kdf_params.iterations,
-                self.config.kdf_params.parallelism,
-                Some(32), // Output length
-            )
-            .map_err(|e| DeepVaultError::Crypto(format!("Invalid Argon2 params: {}", e)))?,
-        );
-
-        let password_hash = argon2
-            .hash_password(password.as_bytes(), &salt_string)
-            .map_err(|e| DeepVaultError::Crypto(format!("Key derivation failed: {}", e)))?;
-
-        Ok(password_hash.hash.unwrap().as_bytes().to_vec())
-    }
-
-    /// Verify password against derived key
-    pub fn verify_password(&self, password: &str, derived_key: &[u8]) -> Result<bool> {
-        let salt_string = SaltString::from_b64(&base64::engine::general_purpose::STANDARD.encode(&self.config.salt))
-            .map_err(|e| DeepVaultError::Crypto(format!("Invalid salt: {}", e)))?;
-
-        let argon2 = Argon2::new(
-            argon2::Algorithm::Argon2id,
-            argon2::Version::V0x13,
-            argon2::Params::new(
-                self.config.kdf_params.memory,
-                self.config.kdf_params.iterations,
-                self.config.kdf_params.parallelism,
-                Some(32),
-            )
-            .map_err(|e| DeepVaultError::Crypto(format!("Invalid Argon2 params: {}", e)))?,
-        );
-
-        let password_hash = argon2
-            .hash_password(password.as_bytes(), &salt_string)
-            .map_err(|e| DeepVaultError::Crypto(format!("Key derivation failed: {}", e)))?;
-
-        Ok(password_hash.hash.unwrap().as_bytes() == derived_key)
-    }
-
-    /// Generate header metadata
-    pub fn generate_header_metadata(&self) -> Result<HashMap<String, serde_json::Value>> {
-        let mut metadata = HashMap::new();
-        metadata.insert("algorithm".to_string(), serde_json::Value::String(self.config.algorithm.clone()));
-        metadata.insert("kdf_params".to_string(), serde_json::to_value(&self.config.kdf_params)?);
-        metadata.insert("salt".to_string(), serde_json::Value::String(hex::encode(&self.config.salt)));
-        metadata.insert("version".to_string(), serde_json::Value::String(crate::VERSION.to_string()));
-        metadata.insert("created_at".to_string(), serde_json::Value::String(chrono::Utc::now().to_rfc3339()));
-
-        Ok(metadata)
+    /// Generate a random 256-bit key
+    pub fn generate_key() -> SecretKey {
+        use rand::RngCore;
+        let mut key = SecretKey::new(vec![0u8; KEY_LEN]);
+        OsRng.fill_bytes(key.expose_secret_mut());
+        key
+    }
+
+    /// Derive an independent subkey from a master key with HKDF-SHA256
+    ///
+    /// `info` separates the uses of the master key (header MAC, sector
+    /// encryption, ...) so that no two purposes share key material.
+    pub fn derive_subkey(master_key: &SecretKey, info: &[u8], len: usize) -> Result<SecretKey> {
+        let hkdf = hkdf::Hkdf::<sha2::Sha256>::new(None, master_key.expose_secret());
+        let mut subkey = SecretKey::new(vec![0u8; len]);
+        hkdf.expand(info, subkey.expose_secret_mut())
+            .map_err(|e| DeepVaultError::Crypto(format!("Subkey derivation failed: {}", e)))?;
+        Ok(subkey)
+    }
+
+    /// Derive key from password with the configured algorithm
+    pub fn derive_key(&self, password: &SecretString) -> Result<SecretKey> {
+        self.derive_key_from_bytes(password.expose_secret().as_bytes())
+    }
+
+    /// Derive key from a passphrase and/or keyfiles with the configured algorithm
+    pub fn derive_key_from_credentials(&self, credentials: &crate::keyfile::Credentials) -> Result<SecretKey> {
+        self.derive_key_from_bytes(&credentials.kdf_input()?)
+    }
+
+    pub(crate) fn derive_key_from_bytes(&self, secret: &[u8]) -> Result<SecretKey> {
+        let mut key = SecretKey::new(vec![0u8; KEY_LEN]);
+        self.config.kdf()?.derive(secret, &self.config.salt, key.expose_secret_mut())?;
+        Ok(key)
+    }
+
+    /// Verify password against derived key, in constant time
+    pub fn verify_password(&self, password: &SecretString, derived_key: &SecretKey) -> Result<bool> {
+        Ok(self.derive_key(password)?.ct_eq(derived_key).into())
+    }
+
+    /// Encrypt data with AES-256-GCM
+    ///
+    /// The output is `nonce || ciphertext || tag`. A fresh random nonce is
+    /// drawn for every call, so the same key must not be used for more than
+    /// 2^32 messages.
+    pub fn encrypt(&self, key: &SecretKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_with_aad(key, plaintext, &[])
+    }
+
+    /// Encrypt data with AES-256-GCM, authenticating additional data
+    ///
+    /// The associated data is not stored in the output; the same bytes must
+    /// be supplied to [`CryptoManager::decrypt_with_aad`].
+    pub fn encrypt_with_aad(&self, key: &SecretKey, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let cipher = Self::cipher(key)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .map_err(|_| DeepVaultError::Crypto("Encryption failed".to_string()))?;
+
+        let mut output = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        output.extend_from_slice(&nonce);
+        output.extend_from_slice(&ciphertext);
+        Ok(output)
+    }
+
+    /// Decrypt data produced by [`CryptoManager::encrypt`]
+    pub fn decrypt(&self, key: &SecretKey, data: &[u8]) -> Result<Vec<u8>> {
+        self.decrypt_with_aad(key, data, &[])
+    }
+
+    /// Decrypt data produced by [`CryptoManager::encrypt_with_aad`]
+    ///
+    /// Fails with [`DeepVaultError::Crypto`] if the key is wrong or if the
+    /// ciphertext, tag or associated data were modified.
+    pub fn decrypt_with_aad(&self, key: &SecretKey, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_LEN + TAG_LEN {
+            return Err(DeepVaultError::Crypto("Ciphertext too short".to_string()));
+        }
+
+        let cipher = Self::cipher(key)?;
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+
+        cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| DeepVaultError::Crypto("Authentication failed: wrong key or corrupted data".to_string()))
+    }
+
+    /// Build an AES-256-GCM cipher from raw key bytes
+    fn cipher(key: &SecretKey) -> Result<Aes256Gcm> {
+        if key.len() != KEY_LEN {
+            return Err(DeepVaultError::Crypto(format!(
+                "Invalid key length: expected {} bytes, got {}",
+                KEY_LEN,
+                key.len()
+            )));
+        }
+        Ok(Aes256Gcm::new_from_slice(key.expose_secret()).expect("key length checked above"))
+    }
+
+    /// Create a new vault header protecting a fresh random master key
+    ///
+    /// The key encryption key is derived from `password` with this manager's
+    /// KDF parameters and a newly generated salt. Returns the header and the
+    /// master key it protects.
+    pub fn create_header(
+        &self,
+        password: &SecretString,
+        data_offset: u64,
+        data_size: u64,
+    ) -> Result<(crate::header::VaultHeader, SecretKey)> {
+        crate::header::VaultHeader::create(password, &self.config.kdf()?, data_offset, data_size)
+    }
+
+    /// Split access to a vault between custodians
+    ///
+    /// Enrolls a keyslot that any `threshold` of the `count` returned
+    /// shares can open, using this manager's KDF.
+    pub fn split_master_key(
+        &self,
+        header: &mut crate::header::VaultHeader,
+        master_key: &SecretKey,
+        threshold: u8,
+        count: u8,
+    ) -> Result<(usize, Vec<crate::shamir::Share>)> {
+        header.add_shared_keyslot(master_key, threshold, count, &self.config.kdf()?)
+    }
+
+    /// Recover a vault master key from custodian shares
+    pub fn unlock_with_shares(
+        &self,
+        header: &crate::header::VaultHeader,
+        shares: &[crate::shamir::Share],
+    ) -> Result<SecretKey> {
+        header.unlock_with_shares(shares)
+    }
+
+    /// Calibrate the configured Argon2 variant for this machine
+    ///
+    /// The calibrated parameters replace the configured ones, so headers
+    /// created afterwards use them.
+    pub fn calibrate_kdf(&mut self, target: &CalibrationTarget) -> Result<Calibration> {
+        let calibration = crate::kdf::calibrate(self.config.kdf()?.algorithm(), target)?;
+        if let Some(params) = calibration.kdf.argon2() {
+            self.config.kdf_params = params.clone();
+        }
+        Ok(calibration)
     }
 
     /// Get current configuration
@@ -1,9 +1,13 @@
 //! USB device detection and management
 
-use crate::{DeepVaultError, Result};
+#[cfg(target_os = "windows")]
+use crate::DeepVaultError;
+use crate::Result;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+#[cfg(target_os = "windows")]
 use std::process::Command;
+#[cfg(target_os = "windows")]
 use std::str::FromStr;
 use sysinfo::{DiskExt, System, SystemExt};
 
@@ -11,6 +11,9 @@ pub enum DeepVaultError {
     #[error("Cryptographic error: {0}")]
     Crypto(String),
 
+    #[error("Invalid vault header: {0}")]
+    InvalidHeader(String),
+
     #[error("Partition error: {0}")]
     Partition(String),
 
@@ -32,6 +35,18 @@ pub enum DeepVaultError {
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
+    #[error(
+        "Wrong password or corrupted header ({failures} consecutive failures{})",
+        .remaining.map(|n| format!(", {} attempts left", n)).unwrap_or_default()
+    )]
+    UnlockFailed { failures: u32, remaining: Option<u32> },
+
+    #[error("Too many failed attempts, retry in {0} seconds")]
+    RetryLater(u64),
+
+    #[error("Integrity check failed for sector {sector}: the data was modified")]
+    IntegrityViolation { sector: u64 },
+
     #[error("Invalid input: {0}")]
     InvalidInput(String),
 
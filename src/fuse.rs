@@ -0,0 +1,537 @@
+//! Userspace mounts through FUSE (Linux)
+//!
+//! Mounting a volume with dm-crypt needs root. Through FUSE, the volume is
+//! decrypted by this process instead, running as the user: the setuid
+//! `fusermount3` (or `fusermount`) helper shipped with FUSE mounts
+//! `/dev/fuse` and hands the descriptor back, and [`FuseMount::serve`]
+//! answers the kernel requests until the mount point is unmounted. Without
+//! the helper, the mount is done directly, which only works as root.
+//!
+//! The mount point holds a single file, [`IMAGE_NAME`], which is the
+//! decrypted data area. Its file system can then be opened without root by
+//! tools that work on disk images (`fuse2fs`, `mtools`, `7z`...).
+//!
+//! Only the requests needed for one regular file are implemented, see
+//! [`ImageFs::handle`]; the kernel protocol is spoken directly, as
+//! described in `linux/fuse.h`.
+
+use crate::block::BlockDevice;
+use crate::{DeepVaultError, Result};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Name of the decrypted image in the mount point
+pub const IMAGE_NAME: &str = "volume.img";
+/// Largest write accepted in one request
+pub const MAX_WRITE: usize = 128 * 1024;
+
+/// Helpers tried, in order, to mount without root
+const HELPERS: &[&str] = &["fusermount3", "fusermount"];
+
+const ROOT_INO: u64 = 1;
+const IMAGE_INO: u64 = 2;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+/// Size of `fuse_init_out` before protocol 7.23
+const COMPAT_22_INIT_OUT_SIZE: usize = 24;
+const FUSE_BIG_WRITES: u32 = 1 << 5;
+/// `fuse_setattr_in.valid` bit for a size change
+const FATTR_SIZE: u32 = 1 << 3;
+
+const IN_HEADER_LEN: usize = 40;
+const OUT_HEADER_LEN: usize = 16;
+const ATTR_LEN: usize = 88;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_SETATTR: u32 = 4;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_WRITE: u32 = 16;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FSYNC: u32 = 20;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_FSYNCDIR: u32 = 30;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+/// Settings of a FUSE mount
+#[derive(Debug, Clone, Default)]
+pub struct FuseOptions {
+    /// Refuse every write to the volume
+    pub read_only: bool,
+}
+
+/// Exposes a block device as a single image file to the FUSE kernel module
+pub struct ImageFs<D> {
+    device: D,
+    read_only: bool,
+    uid: u32,
+    gid: u32,
+    time: u64,
+    destroyed: bool,
+}
+
+impl<D: BlockDevice> ImageFs<D> {
+    /// Serve `device`, owned by the current user
+    pub fn new(device: D, options: &FuseOptions) -> Self {
+        // SAFETY: getuid and getgid cannot fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Self {
+            device,
+            read_only: options.read_only,
+            uid,
+            gid,
+            time: chrono::Utc::now().timestamp().max(0) as u64,
+            destroyed: false,
+        }
+    }
+
+    /// Whether the kernel ended the session
+    pub fn destroyed(&self) -> bool {
+        self.destroyed
+    }
+
+    /// Consume the file system and return the device
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Answer one request read from `/dev/fuse`
+    ///
+    /// Returns the reply to write back, or `None` for requests that take
+    /// no reply. Unsupported requests fail with `ENOSYS`.
+    pub fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        if request.len() < IN_HEADER_LEN {
+            return None;
+        }
+        let opcode = u32_at(request, 4);
+        let unique = u64_at(request, 8);
+        let node = u64_at(request, 16);
+        let body = &request[IN_HEADER_LEN..request.len().min(u32_at(request, 0) as usize)];
+
+        let result = match opcode {
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+            FUSE_INIT => self.init(body),
+            FUSE_LOOKUP => self.lookup(node, body),
+            FUSE_GETATTR => self.attr(node).map(|attr| attr_out(&attr)),
+            FUSE_SETATTR => self.setattr(node, body),
+            FUSE_OPEN => self.open(node, body),
+            FUSE_READ => self.read(node, body),
+            FUSE_WRITE => self.write(node, body),
+            FUSE_STATFS => Ok(self.statfs()),
+            FUSE_OPENDIR if node == ROOT_INO => Ok(vec![0u8; 16]),
+            FUSE_OPENDIR => Err(libc::ENOTDIR),
+            FUSE_READDIR => self.readdir(node, body),
+            FUSE_FSYNC | FUSE_FLUSH => self.device.flush().map(|_| Vec::new()).map_err(io_error),
+            FUSE_RELEASE | FUSE_RELEASEDIR | FUSE_FSYNCDIR | FUSE_ACCESS => Ok(Vec::new()),
+            FUSE_DESTROY => {
+                self.destroyed = true;
+                self.device.flush().map(|_| Vec::new()).map_err(io_error)
+            }
+            _ => Err(libc::ENOSYS),
+        };
+        Some(match result {
+            Ok(payload) => reply(unique, 0, &payload),
+            Err(errno) => reply(unique, -errno, &[]),
+        })
+    }
+
+    fn init(&mut self, body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+        if body.len() < 16 || u32_at(body, 0) != FUSE_KERNEL_VERSION {
+            return Err(libc::EPROTO);
+        }
+        let minor = u32_at(body, 4).min(FUSE_KERNEL_MINOR_VERSION);
+        let mut out = vec![0u8; 64];
+        put_u32(&mut out, 0, FUSE_KERNEL_VERSION);
+        put_u32(&mut out, 4, minor);
+        put_u32(&mut out, 8, u32_at(body, 8));
+        put_u32(&mut out, 12, u32_at(body, 12) & FUSE_BIG_WRITES);
+        put_u16(&mut out, 16, 16);
+        put_u16(&mut out, 18, 12);
+        put_u32(&mut out, 20, MAX_WRITE as u32);
+        put_u32(&mut out, 24, 1);
+        if minor < 23 {
+            out.truncate(COMPAT_22_INIT_OUT_SIZE);
+        }
+        Ok(out)
+    }
+
+    fn lookup(&self, parent: u64, body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+        let name = body.split(|&b| b == 0).next().unwrap_or_default();
+        if parent != ROOT_INO || name != IMAGE_NAME.as_bytes() {
+            return Err(libc::ENOENT);
+        }
+        let mut out = vec![0u8; 40];
+        put_u64(&mut out, 0, IMAGE_INO);
+        put_u64(&mut out, 16, 1);
+        put_u64(&mut out, 24, 1);
+        out.extend_from_slice(&self.attr(IMAGE_INO)?);
+        Ok(out)
+    }
+
+    fn attr(&self, node: u64) -> std::result::Result<[u8; ATTR_LEN], i32> {
+        let write_bits = if self.read_only { 0 } else { 0o200 };
+        let (mode, size, nlink) = match node {
+            ROOT_INO => (libc::S_IFDIR | 0o555, 0, 2),
+            IMAGE_INO => (libc::S_IFREG | 0o400 | write_bits, self.device.size(), 1),
+            _ => return Err(libc::ENOENT),
+        };
+        let mut attr = [0u8; ATTR_LEN];
+        put_u64(&mut attr, 0, node);
+        put_u64(&mut attr, 8, size);
+        put_u64(&mut attr, 16, size.div_ceil(512));
+        for offset in [24, 32, 40] {
+            put_u64(&mut attr, offset, self.time);
+        }
+        put_u32(&mut attr, 60, mode);
+        put_u32(&mut attr, 64, nlink);
+        put_u32(&mut attr, 68, self.uid);
+        put_u32(&mut attr, 72, self.gid);
+        put_u32(&mut attr, 80, 4096);
+        Ok(attr)
+    }
+
+    /// Attribute changes are accepted and ignored, except for the size
+    fn setattr(&self, node: u64, body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+        let attr = self.attr(node)?;
+        if body.len() >= 24 && u32_at(body, 0) & FATTR_SIZE != 0 && u64_at(body, 16) != u64_at(&attr, 8) {
+            return Err(libc::EPERM);
+        }
+        Ok(attr_out(&attr))
+    }
+
+    fn open(&self, node: u64, body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+        match node {
+            IMAGE_INO => {}
+            ROOT_INO => return Err(libc::EISDIR),
+            _ => return Err(libc::ENOENT),
+        }
+        let flags = body.first_chunk::<4>().map(|b| i32::from_le_bytes(*b)).unwrap_or(0);
+        if self.read_only && flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EROFS);
+        }
+        Ok(vec![0u8; 16])
+    }
+
+    fn read(&mut self, node: u64, body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+        if node != IMAGE_INO {
+            return Err(libc::EISDIR);
+        }
+        if body.len() < 20 {
+            return Err(libc::EINVAL);
+        }
+        let offset = u64_at(body, 8);
+        let size = self.device.size();
+        let len = (u32_at(body, 16) as u64).min(size.saturating_sub(offset));
+        let mut data = vec![0u8; len as usize];
+        self.device.read_at(offset, &mut data).map_err(io_error)?;
+        Ok(data)
+    }
+
+    fn write(&mut self, node: u64, body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+        if node != IMAGE_INO {
+            return Err(libc::EISDIR);
+        }
+        if self.read_only {
+            return Err(libc::EROFS);
+        }
+        if body.len() < 40 {
+            return Err(libc::EINVAL);
+        }
+        let offset = u64_at(body, 8);
+        let len = u32_at(body, 16) as usize;
+        let data = body.get(40..40 + len).ok_or(libc::EINVAL)?;
+        if offset.checked_add(len as u64).is_none_or(|end| end > self.device.size()) {
+            return Err(libc::ENOSPC);
+        }
+        self.device.write_at(offset, data).map_err(io_error)?;
+        let mut out = vec![0u8; 8];
+        put_u32(&mut out, 0, len as u32);
+        Ok(out)
+    }
+
+    fn statfs(&self) -> Vec<u8> {
+        let mut out = vec![0u8; 80];
+        put_u64(&mut out, 0, self.device.size() / 512);
+        put_u64(&mut out, 24, 2);
+        put_u32(&mut out, 40, 512);
+        put_u32(&mut out, 44, 255);
+        put_u32(&mut out, 48, 512);
+        out
+    }
+
+    fn readdir(&self, node: u64, body: &[u8]) -> std::result::Result<Vec<u8>, i32> {
+        if node != ROOT_INO {
+            return Err(libc::ENOTDIR);
+        }
+        if body.len() < 20 {
+            return Err(libc::EINVAL);
+        }
+        let offset = u64_at(body, 8);
+        let size = u32_at(body, 16) as usize;
+        let entries: [(u64, &str, u32); 3] = [
+            (ROOT_INO, ".", libc::DT_DIR as u32),
+            (ROOT_INO, "..", libc::DT_DIR as u32),
+            (IMAGE_INO, IMAGE_NAME, libc::DT_REG as u32),
+        ];
+        let mut out = Vec::new();
+        for (index, (ino, name, kind)) in entries.iter().enumerate().skip(offset as usize) {
+            let len = (24 + name.len()).next_multiple_of(8);
+            if out.len() + len > size {
+                break;
+            }
+            let mut dirent = vec![0u8; len];
+            put_u64(&mut dirent, 0, *ino);
+            put_u64(&mut dirent, 8, index as u64 + 1);
+            put_u32(&mut dirent, 16, name.len() as u32);
+            put_u32(&mut dirent, 20, *kind);
+            dirent[24..24 + name.len()].copy_from_slice(name.as_bytes());
+            out.extend_from_slice(&dirent);
+        }
+        Ok(out)
+    }
+}
+
+/// A mounted FUSE session
+pub struct FuseMount {
+    device: File,
+    mountpoint: PathBuf,
+}
+
+impl FuseMount {
+    /// Mount a DeepVault file system on `mountpoint`
+    ///
+    /// Uses the FUSE helper when installed, so no privileges are needed.
+    pub fn mount(mountpoint: &Path, options: &FuseOptions) -> Result<Self> {
+        let mountpoint = mountpoint
+            .canonicalize()
+            .map_err(|e| DeepVaultError::Mount(format!("Invalid mount point {}: {}", mountpoint.display(), e)))?;
+        let mut mount_options = "nosuid,nodev,fsname=deepvault,subtype=deepvault".to_string();
+        if options.read_only {
+            mount_options.push_str(",ro");
+        }
+
+        let fd = match mount_with_helper(&mountpoint, &mount_options)? {
+            Some(fd) => fd,
+            None => mount_directly(&mountpoint, options)?,
+        };
+        Ok(Self {
+            device: File::from(fd),
+            mountpoint,
+        })
+    }
+
+    /// Mount point of the session
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Answer the kernel requests until the mount point is unmounted
+    pub fn serve<D: BlockDevice>(&mut self, fs: &mut ImageFs<D>) -> Result<()> {
+        let mut buf = vec![0u8; MAX_WRITE + 4096];
+        while !fs.destroyed() {
+            let len = match self.device.read(&mut buf) {
+                Ok(len) => len,
+                // The file system was unmounted
+                Err(e) if e.raw_os_error() == Some(libc::ENODEV) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted || e.raw_os_error() == Some(libc::ENOENT) => {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(reply) = fs.handle(&buf[..len]) {
+                match self.device.write_all(&reply) {
+                    // The request was interrupted meanwhile
+                    Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {}
+                    result => result?,
+                }
+            }
+        }
+        fs.device.flush()
+    }
+}
+
+/// Unmount a FUSE mount point, with the helper or directly as root
+pub fn unmount(mountpoint: &Path) -> Result<()> {
+    for helper in HELPERS {
+        match Command::new(helper).arg("-u").arg(mountpoint).output() {
+            Ok(output) if output.status.success() => return Ok(()),
+            Ok(output) => {
+                return Err(DeepVaultError::Mount(format!(
+                    "Failed to unmount {}: {}",
+                    mountpoint.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(DeepVaultError::Mount(format!("Failed to run {}: {}", helper, e))),
+        }
+    }
+    let path = c_path(mountpoint)?;
+    // SAFETY: `path` is a valid NUL-terminated string
+    if unsafe { libc::umount2(path.as_ptr(), 0) } != 0 {
+        return Err(DeepVaultError::Mount(format!(
+            "Failed to unmount {}: {}",
+            mountpoint.display(),
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
+
+/// Have the setuid helper mount `/dev/fuse` and pass the descriptor back
+/// over a socket; `None` if no helper is installed
+fn mount_with_helper(mountpoint: &Path, options: &str) -> Result<Option<OwnedFd>> {
+    for helper in HELPERS {
+        let (ours, theirs) = UnixStream::pair()?;
+        // The helper must inherit its end of the socket
+        // SAFETY: plain fcntl on a descriptor we own
+        if unsafe { libc::fcntl(theirs.as_raw_fd(), libc::F_SETFD, 0) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let output = Command::new(helper)
+            .arg("-o")
+            .arg(options)
+            .arg("--")
+            .arg(mountpoint)
+            .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+            .output();
+        drop(theirs);
+        match output {
+            Ok(output) if output.status.success() => return receive_fd(&ours).map(Some),
+            Ok(output) => {
+                return Err(DeepVaultError::Mount(format!(
+                    "{} failed: {}",
+                    helper,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(DeepVaultError::Mount(format!("Failed to run {}: {}", helper, e))),
+        }
+    }
+    Ok(None)
+}
+
+/// Mount with the mount system call, which needs root
+fn mount_directly(mountpoint: &Path, options: &FuseOptions) -> Result<OwnedFd> {
+    let device = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/fuse")
+        .map_err(|e| DeepVaultError::Mount(format!("FUSE is not available: {}", e)))?;
+    // SAFETY: getuid and getgid cannot fail
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let data = format!("fd={},rootmode=40000,user_id={},group_id={}", device.as_raw_fd(), uid, gid);
+    let mut flags = libc::MS_NOSUID | libc::MS_NODEV;
+    if options.read_only {
+        flags |= libc::MS_RDONLY;
+    }
+
+    let source = c_path(Path::new("deepvault"))?;
+    let target = c_path(mountpoint)?;
+    let fstype = c_path(Path::new("fuse.deepvault"))?;
+    let data = std::ffi::CString::new(data).expect("no NUL in mount options");
+    // SAFETY: every pointer is a valid NUL-terminated string
+    let status = unsafe {
+        libc::mount(source.as_ptr(), target.as_ptr(), fstype.as_ptr(), flags, data.as_ptr().cast())
+    };
+    if status != 0 {
+        return Err(DeepVaultError::Mount(format!(
+            "No FUSE helper (fusermount3) installed and direct mount failed: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(device.into())
+}
+
+/// Receive the descriptor sent with SCM_RIGHTS by the helper
+fn receive_fd(socket: &UnixStream) -> Result<OwnedFd> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: byte.len(),
+    };
+    let mut control = [0u64; 8];
+    // SAFETY: msghdr is plain data, all-zero is a valid value
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr().cast();
+    message.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    // SAFETY: the buffers referenced by `message` outlive the call
+    if unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, 0) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: the control buffer was filled by recvmsg
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+        if header.is_null() || (*header).cmsg_level != libc::SOL_SOCKET || (*header).cmsg_type != libc::SCM_RIGHTS {
+            return Err(DeepVaultError::Mount("The FUSE helper did not pass a descriptor".to_string()));
+        }
+        let fd = std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const RawFd);
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+fn c_path(path: &Path) -> Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| DeepVaultError::InvalidInput(format!("Invalid path {}", path.display())))
+}
+
+fn reply(unique: u64, error: i32, payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; OUT_HEADER_LEN];
+    put_u32(&mut out, 0, (OUT_HEADER_LEN + payload.len()) as u32);
+    put_u32(&mut out, 4, error as u32);
+    put_u64(&mut out, 8, unique);
+    out.extend_from_slice(payload);
+    out
+}
+
+fn attr_out(attr: &[u8; ATTR_LEN]) -> Vec<u8> {
+    let mut out = vec![0u8; 16];
+    put_u64(&mut out, 0, 1);
+    out.extend_from_slice(attr);
+    out
+}
+
+fn io_error(e: DeepVaultError) -> i32 {
+    log::error!("FUSE request failed: {}", e);
+    libc::EIO
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn put_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
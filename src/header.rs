@@ -0,0 +1,990 @@
+//! On-disk vault header format for DeepVault
+//!
+//! Every DeepVault volume starts with a fixed-size binary header. All
+//! integers are little-endian.
+//!
+//! | Offset | Size | Field                                        |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 4    | Magic `DVLT`                                 |
+//! | 4      | 2    | Major format version                         |
+//! | 6      | 2    | Minor format version                         |
+//! | 8      | 4    | Header size in bytes                         |
+//! | 12     | 4    | Incompatible feature flags                   |
+//! | 16     | 16   | Vault UUID                                   |
+//! | 32     | 8    | Data area offset                             |
+//! | 40     | 8    | Data area size                               |
+//! | 48     | 8    | Creation time (Unix seconds)                 |
+//! | 56     | 4    | Compatible feature flags                     |
+//! | 64     | 2048 | Keyslot table, see [`crate::keyslot`]        |
+//! | 2112   | 4    | Wipe after N failed unlocks (0 = never)      |
+//! | 2116   | 4    | First back-off delay (seconds)               |
+//! | 2120   | 4    | Longest back-off delay (seconds)             |
+//! | 2124   | 4    | Consecutive failed unlocks                   |
+//! | 2128   | 8    | Time of the last failed unlock (Unix seconds)|
+//! | 2136   | 32   | Current link of the failure chain            |
+//! | 2168   | 32   | Tag of the last failure                      |
+//! | 2200   | 1864 | Reserved, zero                               |
+//! | 4064   | 32   | HMAC-SHA256 of bytes `0..4064`, with the     |
+//! |        |      | failure fields (`2124..2200`) zeroed         |
+//!
+//! The failure fields are written without the master key, so the MAC
+//! cannot cover them. They are authenticated by a hash chain instead: the
+//! first link is derived from the master key and the UUID, each failure
+//! replaces the link with its hash and stores a tag binding the previous
+//! link to the new count and time. Lowering the count or moving the last
+//! failure would take a preimage of the current link, so a successful
+//! unlock detects it, see [`VaultHeader::verify_attempts`].
+//!
+//! Forward-compatibility rules:
+//! - a different major version is never opened;
+//! - a newer minor version only adds fields in the reserved area, so it is
+//!   opened read-only: it can be unlocked (the reserved bytes are kept
+//!   verbatim for the MAC) but not rewritten by this version;
+//! - any unknown bit in the incompatible flags refuses the header, while
+//!   unknown compatible flags are kept and ignored.
+
+use crate::attempts::{AttemptCounter, AttemptPolicy};
+use crate::block::{Storage, SECTOR_SIZE};
+use crate::config::SecurityConfig;
+use crate::crypto::{CryptoManager, KEY_LEN};
+use crate::hidden;
+use crate::kdf::KdfParams;
+use crate::keyfile::Credentials;
+use crate::keyslot::{Keyslot, KeyslotInfo, KeyslotKind, KEYSLOT_SIZE, MAX_KEYSLOTS};
+use crate::recipient::{Identity, Recipient};
+use crate::recovery::RecoveryKey;
+use crate::shamir::{self, Share};
+use crate::secret::{SecretKey, SecretString};
+use crate::ssh_agent::{SshAgent, SshPublicKey};
+use crate::{utils, DeepVaultError, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use std::io::{Read, Seek, SeekFrom, Write};
+use uuid::Uuid;
+
+/// Magic bytes identifying a DeepVault header
+pub const MAGIC: &[u8; 4] = b"DVLT";
+/// Major format version written by this implementation
+pub const VERSION_MAJOR: u16 = 1;
+/// Minor format version written by this implementation
+pub const VERSION_MINOR: u16 = 0;
+/// Size of the on-disk header
+pub const HEADER_SIZE: usize = 4096;
+/// Default offset of the data area, leaving room for the header region
+pub const DEFAULT_DATA_OFFSET: u64 = 1024 * 1024;
+/// Length of the header MAC
+pub const MAC_LEN: usize = 32;
+
+/// Incompatible flag: the data area carries per-sector integrity tags,
+/// see [`crate::integrity`]
+pub const INCOMPAT_INTEGRITY: u32 = 1 << 0;
+/// Incompatible flag: the data area is allocated on demand through an
+/// allocation map, see [`crate::sparse`]
+pub const INCOMPAT_SPARSE: u32 = 1 << 1;
+
+/// Incompatible feature flags understood by this implementation
+const KNOWN_INCOMPAT_FLAGS: u32 = INCOMPAT_INTEGRITY | INCOMPAT_SPARSE;
+
+/// Compatible flag: the data area was filled with random bytes when
+/// created, and must be again wherever it grows
+pub const COMPAT_RANDOM_FILL: u32 = 1 << 0;
+
+const OFF_VERSION_MAJOR: usize = 4;
+const OFF_VERSION_MINOR: usize = 6;
+const OFF_HEADER_SIZE: usize = 8;
+const OFF_INCOMPAT_FLAGS: usize = 12;
+pub(crate) const OFF_UUID: usize = 16;
+const OFF_DATA_OFFSET: usize = 32;
+const OFF_DATA_SIZE: usize = 40;
+const OFF_CREATED_AT: usize = 48;
+const OFF_COMPAT_FLAGS: usize = 56;
+const OFF_KEYSLOTS: usize = 64;
+const OFF_ATTEMPT_POLICY: usize = OFF_KEYSLOTS + MAX_KEYSLOTS * KEYSLOT_SIZE;
+const OFF_FAILURES: usize = OFF_ATTEMPT_POLICY + 12;
+const OFF_LAST_FAILURE: usize = OFF_FAILURES + 4;
+const OFF_ATTEMPT_LINK: usize = OFF_LAST_FAILURE + 8;
+const OFF_ATTEMPT_TAG: usize = OFF_ATTEMPT_LINK + 32;
+const OFF_RESERVED: usize = OFF_ATTEMPT_TAG + 32;
+const OFF_MAC: usize = HEADER_SIZE - MAC_LEN;
+
+/// HKDF context for the header MAC key
+const MAC_KEY_INFO: &[u8] = b"deepvault header mac v1";
+/// Domain separator for the marker wrapped by duress keyslots
+const DURESS_DOMAIN: &[u8] = b"deepvault duress v1";
+/// HKDF context for the key of the first link of the failure chain
+const ATTEMPT_KEY_INFO: &[u8] = b"deepvault attempt chain v1";
+/// Domain separators for the links and tags of the failure chain
+const ATTEMPT_LINK_DOMAIN: &[u8] = b"deepvault attempt link v1";
+const ATTEMPT_TAG_DOMAIN: &[u8] = b"deepvault attempt tag v1";
+/// Longest failure chain walked to verify the count
+const MAX_CHAINED_FAILURES: u32 = 1 << 20;
+
+/// Outcome of trying a secret against the keyslots of one kind
+enum Attempt {
+    Unlocked(usize, SecretKey),
+    Duress,
+    Failed,
+}
+
+/// Versioned vault header
+#[derive(Debug, Clone, PartialEq)]
+pub struct VaultHeader {
+    pub version_major: u16,
+    pub version_minor: u16,
+    pub uuid: Uuid,
+    pub data_offset: u64,
+    pub data_size: u64,
+    pub created_at: DateTime<Utc>,
+    incompat_flags: u32,
+    compat_flags: u32,
+    keyslots: Vec<Option<Keyslot>>,
+    attempt_policy: AttemptPolicy,
+    attempts: AttemptCounter,
+    attempt_link: [u8; 32],
+    attempt_tag: [u8; 32],
+    reserved: Vec<u8>,
+    mac: [u8; MAC_LEN],
+}
+
+impl VaultHeader {
+    /// Create a header protecting a fresh random master key
+    ///
+    /// `password` is enrolled in keyslot 0. Returns the header and the
+    /// master key, which never changes for the lifetime of the volume. The
+    /// header is written to the new volume with [`VaultHeader::initialize`].
+    pub fn create(
+        password: &SecretString,
+        kdf: &KdfParams,
+        data_offset: u64,
+        data_size: u64,
+    ) -> Result<(Self, SecretKey)> {
+        Self::create_with_credentials(&Credentials::passphrase(password), kdf, data_offset, data_size)
+    }
+
+    /// Create a header whose first keyslot is protected by `credentials`
+    pub fn create_with_credentials(
+        credentials: &Credentials,
+        kdf: &KdfParams,
+        data_offset: u64,
+        data_size: u64,
+    ) -> Result<(Self, SecretKey)> {
+        if data_offset < HEADER_SIZE as u64 {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "Data area must start after the header ({} bytes)",
+                HEADER_SIZE
+            )));
+        }
+
+        let master_key = CryptoManager::generate_key();
+        let mut header = Self {
+            version_major: VERSION_MAJOR,
+            version_minor: VERSION_MINOR,
+            uuid: Uuid::new_v4(),
+            data_offset,
+            data_size,
+            created_at: Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap(),
+            incompat_flags: 0,
+            compat_flags: 0,
+            keyslots: vec![None; MAX_KEYSLOTS],
+            attempt_policy: AttemptPolicy::default(),
+            attempts: AttemptCounter::default(),
+            attempt_link: [0u8; 32],
+            attempt_tag: [0u8; 32],
+            reserved: vec![0u8; OFF_MAC - OFF_RESERVED],
+            mac: [0u8; MAC_LEN],
+        };
+        header.attempt_link = header.first_attempt_link(&master_key)?;
+
+        header.add_keyslot_with_credentials(&master_key, credentials, kdf)?;
+        Ok((header, master_key))
+    }
+
+    /// Check whether a buffer starts with a DeepVault header magic
+    pub fn is_vault_header(bytes: &[u8]) -> bool {
+        bytes.len() >= MAGIC.len() && &bytes[..MAGIC.len()] == MAGIC
+    }
+
+    /// Parse a header from its on-disk representation
+    ///
+    /// This only checks the structure of the header; the MAC can only be
+    /// verified once the master key is known, see [`VaultHeader::unlock`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(DeepVaultError::InvalidHeader(format!(
+                "Header too short: {} bytes",
+                bytes.len()
+            )));
+        }
+        if !Self::is_vault_header(bytes) {
+            return Err(DeepVaultError::InvalidHeader("Not a DeepVault volume".to_string()));
+        }
+
+        let version_major = read_u16(bytes, OFF_VERSION_MAJOR);
+        let version_minor = read_u16(bytes, OFF_VERSION_MINOR);
+        if version_major != VERSION_MAJOR {
+            return Err(DeepVaultError::InvalidHeader(format!(
+                "Unsupported header version {}.{}",
+                version_major, version_minor
+            )));
+        }
+
+        let header_size = read_u32(bytes, OFF_HEADER_SIZE);
+        if header_size as usize != HEADER_SIZE {
+            return Err(DeepVaultError::InvalidHeader(format!(
+                "Unexpected header size {}",
+                header_size
+            )));
+        }
+
+        let incompat_flags = read_u32(bytes, OFF_INCOMPAT_FLAGS);
+        if incompat_flags & !KNOWN_INCOMPAT_FLAGS != 0 {
+            return Err(DeepVaultError::InvalidHeader(format!(
+                "Unsupported features: {:#x}",
+                incompat_flags & !KNOWN_INCOMPAT_FLAGS
+            )));
+        }
+
+        let created_at = Utc
+            .timestamp_opt(read_u64(bytes, OFF_CREATED_AT) as i64, 0)
+            .single()
+            .ok_or_else(|| DeepVaultError::InvalidHeader("Invalid creation time".to_string()))?;
+
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&bytes[OFF_UUID..OFF_UUID + 16]);
+        let keyslots = (0..MAX_KEYSLOTS)
+            .map(|i| Keyslot::from_bytes(&bytes[Self::keyslot_range(i)]))
+            .collect::<Result<Vec<_>>>()?;
+        let mut mac = [0u8; MAC_LEN];
+        mac.copy_from_slice(&bytes[OFF_MAC..OFF_MAC + MAC_LEN]);
+        let mut attempt_link = [0u8; 32];
+        attempt_link.copy_from_slice(&bytes[OFF_ATTEMPT_LINK..OFF_ATTEMPT_TAG]);
+        let mut attempt_tag = [0u8; 32];
+        attempt_tag.copy_from_slice(&bytes[OFF_ATTEMPT_TAG..OFF_RESERVED]);
+
+        Ok(Self {
+            version_major,
+            version_minor,
+            uuid: Uuid::from_bytes(uuid),
+            data_offset: read_u64(bytes, OFF_DATA_OFFSET),
+            data_size: read_u64(bytes, OFF_DATA_SIZE),
+            created_at,
+            incompat_flags,
+            compat_flags: read_u32(bytes, OFF_COMPAT_FLAGS),
+            keyslots,
+            attempt_policy: AttemptPolicy {
+                wipe_after: read_u32(bytes, OFF_ATTEMPT_POLICY),
+                base_delay: read_u32(bytes, OFF_ATTEMPT_POLICY + 4),
+                max_delay: read_u32(bytes, OFF_ATTEMPT_POLICY + 8),
+            },
+            attempts: AttemptCounter {
+                failures: read_u32(bytes, OFF_FAILURES),
+                last_failure: read_u64(bytes, OFF_LAST_FAILURE),
+            },
+            attempt_link,
+            attempt_tag,
+            reserved: bytes[OFF_RESERVED..OFF_MAC].to_vec(),
+            mac,
+        })
+    }
+
+    /// Serialize the header to its on-disk representation
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.unsealed_bytes();
+        bytes[OFF_MAC..OFF_MAC + MAC_LEN].copy_from_slice(&self.mac);
+        bytes
+    }
+
+    /// Read and parse a header from a reader
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut bytes = vec![0u8; HEADER_SIZE];
+        reader.read_exact(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Serialize and write the header to a writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Read the header at the start of a volume
+    pub fn load<S: Read + Seek>(storage: &mut S) -> Result<Self> {
+        storage.seek(SeekFrom::Start(0))?;
+        Self::read_from(storage)
+    }
+
+    /// Write the header at the start of a volume and make it durable
+    pub fn store<S: Storage>(&self, storage: &mut S) -> Result<()> {
+        storage.seek(SeekFrom::Start(0))?;
+        self.write_to(storage)?;
+        storage.sync()?;
+        Ok(())
+    }
+
+    /// Write the header of a new volume and make it durable
+    ///
+    /// Unlike [`VaultHeader::store`], this also fills the hidden header
+    /// slot with random bytes, see [`hidden::write_decoy`]. Only use it
+    /// when creating a volume: on an existing one it destroys any hidden
+    /// volume.
+    pub fn initialize<S: Storage>(&self, storage: &mut S) -> Result<()> {
+        if self.data_offset >= hidden::HIDDEN_HEADER_OFFSET + hidden::HIDDEN_HEADER_SIZE as u64 {
+            hidden::write_decoy(storage)?;
+        }
+        self.store(storage)
+    }
+
+    /// Load the header of a volume and unlock it, applying `security` and
+    /// the failed-attempt policy of the volume
+    ///
+    /// This is the path to use whenever a user types a passphrase for a
+    /// volume:
+    /// - while a back-off delay runs, the attempt is refused with
+    ///   [`DeepVaultError::RetryLater`] before any key derivation;
+    /// - a failure is counted on the volume and reported with
+    ///   [`DeepVaultError::UnlockFailed`]. Reaching the wipe threshold of
+    ///   the policy destroys every keyslot;
+    /// - when duress passphrases are enabled and `credentials` open a
+    ///   duress keyslot, every keyslot is destroyed and the attempt fails
+    ///   like any other;
+    /// - a success resets the failure count. A count altered on disk since
+    ///   the last failure is reported in the log.
+    pub fn open<S: Storage>(
+        storage: &mut S,
+        credentials: &Credentials,
+        security: &SecurityConfig,
+    ) -> Result<(Self, SecretKey)> {
+        let mut header = Self::load(storage)?;
+        let now = utils::now();
+        if let Some(wait) = header.attempts.retry_after(&header.attempt_policy, now) {
+            return Err(DeepVaultError::RetryLater(wait.as_secs()));
+        }
+
+        match header.attempt(KeyslotKind::Password, &credentials.kdf_input()?)? {
+            Attempt::Unlocked(_, master_key) => {
+                let verified = header.verify_attempts(&master_key);
+                if let Err(e) = &verified {
+                    log::warn!("{}", e);
+                }
+                if verified.is_err() || header.attempts.failures > 0 {
+                    header.reset_attempts(&master_key)?;
+                    header.store(storage)?;
+                }
+                return Ok((header, master_key));
+            }
+            Attempt::Duress if security.enable_duress_passphrase => header.destroy_keyslots(),
+            Attempt::Duress | Attempt::Failed => {}
+        }
+
+        header.record_failure(now);
+        let remaining = header.attempt_policy.remaining(header.attempts.failures);
+        if remaining == Some(0) {
+            header.destroy_keyslots();
+        }
+        header.store(storage)?;
+        Err(DeepVaultError::UnlockFailed {
+            failures: header.attempts.failures,
+            remaining,
+        })
+    }
+
+    /// Recover the master key with a password and verify the header MAC
+    ///
+    /// Every password keyslot is tried in turn.
+    pub fn unlock(&self, password: &SecretString) -> Result<SecretKey> {
+        self.unlock_with_credentials(&Credentials::passphrase(password))
+    }
+
+    /// Recover the master key with a passphrase and/or keyfiles
+    pub fn unlock_with_credentials(&self, credentials: &Credentials) -> Result<SecretKey> {
+        self.unlock_with_index(credentials).map(|(_, master_key)| master_key)
+    }
+
+    /// Recover the master key from a specific keyslot
+    pub fn unlock_keyslot(&self, index: usize, credentials: &Credentials) -> Result<SecretKey> {
+        let slot = self.keyslot(index)?;
+        let master_key = slot
+            .unwrap_key(credentials, &self.keyslot_aad(index))
+            .map_err(|_| wrong_password())?;
+        if self.is_duress_marker(index, &master_key) {
+            return Err(wrong_password());
+        }
+        self.verify_mac(&master_key)?;
+        Ok(master_key)
+    }
+
+    /// List the active keyslots
+    pub fn list_keyslots(&self) -> Vec<KeyslotInfo> {
+        self.keyslots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                slot.as_ref().map(|slot| KeyslotInfo {
+                    index,
+                    kind: slot.kind,
+                    kdf: slot.kdf.clone(),
+                    recipient: slot.recipient.map(|recipient| recipient.to_string()),
+                    ssh_key: slot.ssh_key.map(|ssh_key| ssh_key.fingerprint()),
+                })
+            })
+            .collect()
+    }
+
+    /// Enroll a new passphrase in the first free keyslot
+    ///
+    /// Requires the master key, obtained from [`VaultHeader::unlock`].
+    /// Returns the index of the new keyslot.
+    pub fn add_keyslot(
+        &mut self,
+        master_key: &SecretKey,
+        password: &SecretString,
+        kdf: &KdfParams,
+    ) -> Result<usize> {
+        self.add_keyslot_with_credentials(master_key, &Credentials::passphrase(password), kdf)
+    }
+
+    /// Enroll a passphrase and/or keyfiles in the first free keyslot
+    pub fn add_keyslot_with_credentials(
+        &mut self,
+        master_key: &SecretKey,
+        credentials: &Credentials,
+        kdf: &KdfParams,
+    ) -> Result<usize> {
+        self.enroll(master_key, |aad| Keyslot::new_password(master_key, credentials, kdf, aad))
+    }
+
+    /// Generate a recovery key and enroll it in the first free keyslot
+    ///
+    /// Returns the index of the new keyslot and the recovery key, which is
+    /// not stored anywhere else and must be handed to the user.
+    pub fn add_recovery_key(&mut self, master_key: &SecretKey, kdf: &KdfParams) -> Result<(usize, RecoveryKey)> {
+        let recovery_key = RecoveryKey::generate();
+        let index = self.enroll(master_key, |aad| {
+            Keyslot::new(KeyslotKind::Recovery, master_key, recovery_key.secret(), kdf, aad)
+        })?;
+        Ok((index, recovery_key))
+    }
+
+    /// Recover the master key with a recovery key
+    pub fn unlock_with_recovery_key(&self, recovery_key: &RecoveryKey) -> Result<SecretKey> {
+        self.unlock_kind(KeyslotKind::Recovery, recovery_key.secret())
+            .map(|(_, master_key)| master_key)
+    }
+
+    /// Enroll a keyslot opened by `threshold` of `count` shares
+    ///
+    /// Returns the index of the new keyslot and the shares, one per
+    /// custodian. The shared secret itself is never stored.
+    pub fn add_shared_keyslot(
+        &mut self,
+        master_key: &SecretKey,
+        threshold: u8,
+        count: u8,
+        kdf: &KdfParams,
+    ) -> Result<(usize, Vec<Share>)> {
+        shamir::check_parameters(threshold, count)?;
+        let secret = CryptoManager::generate_key();
+        let index = self.enroll(master_key, |aad| {
+            Keyslot::new(KeyslotKind::Shamir, master_key, secret.expose_secret(), kdf, aad)
+        })?;
+        Ok((index, shamir::split(&secret, threshold, count, self.uuid, index)?))
+    }
+
+    /// Recover the master key by recombining shares
+    pub fn unlock_with_shares(&self, shares: &[Share]) -> Result<SecretKey> {
+        let secret = shamir::combine(shares)?;
+        let share = &shares[0];
+        if share.uuid != self.uuid {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "Shares belong to vault {}, not {}",
+                share.uuid, self.uuid
+            )));
+        }
+        let slot = self.keyslot(share.keyslot)?;
+        if slot.kind != KeyslotKind::Shamir {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "Keyslot {} is not a shared keyslot",
+                share.keyslot
+            )));
+        }
+        let master_key = slot
+            .unwrap_with_secret(secret.expose_secret(), &self.keyslot_aad(share.keyslot))
+            .map_err(|_| DeepVaultError::Crypto("Shares do not match this keyslot".to_string()))?;
+        self.verify_mac(&master_key)?;
+        Ok(master_key)
+    }
+
+    /// Enroll a keyslot wrapped to an X25519 recipient
+    ///
+    /// Only the matching identity can open it; the recipient alone cannot.
+    pub fn add_recipient_keyslot(&mut self, master_key: &SecretKey, recipient: &Recipient) -> Result<usize> {
+        self.enroll(master_key, |aad| Keyslot::new_recipient(master_key, recipient, aad))
+    }
+
+    /// Recover the master key with an X25519 identity
+    pub fn unlock_with_identity(&self, identity: &Identity) -> Result<SecretKey> {
+        let recipient = identity.to_recipient();
+        for (index, slot) in self.keyslots.iter().enumerate() {
+            let Some(slot) = slot.as_ref().filter(|slot| slot.recipient == Some(recipient)) else { continue };
+            if let Ok(master_key) = slot.unwrap_with_identity(identity, &self.keyslot_aad(index)) {
+                self.verify_mac(&master_key)?;
+                return Ok(master_key);
+            }
+        }
+        Err(DeepVaultError::Crypto("No keyslot is wrapped to this identity".to_string()))
+    }
+
+    /// Enroll a keyslot unlocked by `ssh_key` through an SSH agent
+    pub fn add_ssh_agent_keyslot<S: Read + Write>(
+        &mut self,
+        master_key: &SecretKey,
+        ssh_key: &SshPublicKey,
+        agent: &mut SshAgent<S>,
+    ) -> Result<usize> {
+        self.enroll(master_key, |aad| Keyslot::new_ssh_agent(master_key, ssh_key, agent, aad))
+    }
+
+    /// Recover the master key with any key the SSH agent holds
+    pub fn unlock_with_ssh_agent<S: Read + Write>(&self, agent: &mut SshAgent<S>) -> Result<SecretKey> {
+        let held: Vec<SshPublicKey> = agent.identities()?.into_iter().map(|(key, _)| key).collect();
+        for (index, slot) in self.keyslots.iter().enumerate() {
+            let Some(slot) = slot.as_ref().filter(|slot| slot.ssh_key.is_some_and(|key| held.contains(&key))) else {
+                continue;
+            };
+            if let Ok(master_key) = slot.unwrap_with_ssh_agent(agent, &self.keyslot_aad(index)) {
+                self.verify_mac(&master_key)?;
+                return Ok(master_key);
+            }
+        }
+        Err(DeepVaultError::Crypto("No keyslot matches a key held by the SSH agent".to_string()))
+    }
+
+    /// Enroll a duress passphrase in the first free keyslot
+    ///
+    /// The keyslot is a password keyslot like any other on disk, but it
+    /// wraps a marker instead of the master key. Typing its passphrase
+    /// into [`VaultHeader::open`] destroys the keyslots of the volume.
+    /// Header backups and copies of the volume are not affected.
+    pub fn add_duress_keyslot(
+        &mut self,
+        master_key: &SecretKey,
+        password: &SecretString,
+        kdf: &KdfParams,
+    ) -> Result<usize> {
+        if self.unlock(password).is_ok() {
+            return Err(DeepVaultError::InvalidInput(
+                "The duress passphrase must differ from every real passphrase".to_string(),
+            ));
+        }
+        let secret = Credentials::passphrase(password).kdf_input()?;
+        self.enroll(master_key, |aad| {
+            Keyslot::new(KeyslotKind::Password, &duress_marker(aad), &secret, kdf, aad)
+        })
+    }
+
+    /// Overwrite the wrapped keys and salts of every keyslot, and the MAC,
+    /// with random data
+    ///
+    /// The header keeps its layout, so it still parses and every later
+    /// unlock fails like a wrong passphrase. The master key can no longer
+    /// be recovered from this header.
+    pub fn destroy_keyslots(&mut self) {
+        use rand::RngCore;
+        for slot in self.keyslots.iter_mut().flatten() {
+            slot.scramble();
+        }
+        rand::rngs::OsRng.fill_bytes(&mut self.mac);
+    }
+
+    /// Failed-attempt policy of the volume
+    pub fn attempt_policy(&self) -> AttemptPolicy {
+        self.attempt_policy
+    }
+
+    /// Consecutive failed unlocks recorded on the volume
+    pub fn failed_attempts(&self) -> AttemptCounter {
+        self.attempts
+    }
+
+    /// Check the failure count and time against the failure chain
+    ///
+    /// Fails when they were changed on disk other than by recording a
+    /// failure. Restoring an earlier copy of the whole header cannot be
+    /// told apart from an earlier state of the volume.
+    pub fn verify_attempts(&self, master_key: &SecretKey) -> Result<()> {
+        let tampered = || DeepVaultError::InvalidHeader("The failed-unlock count was altered on disk".to_string());
+        let failures = self.attempts.failures;
+        if failures > MAX_CHAINED_FAILURES {
+            return Err(tampered());
+        }
+        let mut link = self.first_attempt_link(master_key)?;
+        let mut tag = [0u8; 32];
+        if failures == 0 {
+            if self.attempts.last_failure != 0 {
+                return Err(tampered());
+            }
+        } else {
+            for _ in 1..failures {
+                link = next_attempt_link(&link);
+            }
+            tag = attempt_tag(&link, &self.attempts);
+            link = next_attempt_link(&link);
+        }
+        if !bool::from(link.ct_eq(&self.attempt_link) & tag.ct_eq(&self.attempt_tag)) {
+            return Err(tampered());
+        }
+        Ok(())
+    }
+
+    /// Count a failed unlock at `now`, extending the failure chain
+    fn record_failure(&mut self, now: u64) {
+        self.attempts.record_failure(now);
+        self.attempt_tag = attempt_tag(&self.attempt_link, &self.attempts);
+        self.attempt_link = next_attempt_link(&self.attempt_link);
+    }
+
+    /// Forget past failures and restart the failure chain
+    fn reset_attempts(&mut self, master_key: &SecretKey) -> Result<()> {
+        self.attempts.reset();
+        self.attempt_link = self.first_attempt_link(master_key)?;
+        self.attempt_tag = [0u8; 32];
+        Ok(())
+    }
+
+    /// First link of the failure chain, which only the master key gives
+    fn first_attempt_link(&self, master_key: &SecretKey) -> Result<[u8; 32]> {
+        let key = CryptoManager::derive_subkey(master_key, ATTEMPT_KEY_INFO, KEY_LEN)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.expose_secret())
+            .map_err(|e| DeepVaultError::Crypto(format!("Invalid MAC key: {}", e)))?;
+        mac.update(self.uuid.as_bytes());
+        Ok(mac.finalize().into_bytes().into())
+    }
+
+    /// Replace the failed-attempt policy and reseal the header
+    pub fn set_attempt_policy(&mut self, master_key: &SecretKey, policy: AttemptPolicy) -> Result<()> {
+        policy.validate()?;
+        let previous = std::mem::replace(&mut self.attempt_policy, policy);
+        if let Err(e) = self.seal(master_key) {
+            self.attempt_policy = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Whether the data area carries per-sector integrity tags
+    pub fn integrity(&self) -> bool {
+        self.incompat_flags & INCOMPAT_INTEGRITY != 0
+    }
+
+    /// Switch the volume to integrity mode and reseal the header
+    ///
+    /// The data area changes layout: it must then be formatted with
+    /// [`crate::integrity::AuthenticatedDevice::format`], and whatever it
+    /// held before is lost.
+    pub fn enable_integrity(&mut self, master_key: &SecretKey) -> Result<()> {
+        self.set_incompat_flag(master_key, INCOMPAT_INTEGRITY)
+    }
+
+    /// Whether the data area is allocated on demand
+    pub fn sparse(&self) -> bool {
+        self.incompat_flags & INCOMPAT_SPARSE != 0
+    }
+
+    /// Switch the volume to the sparse layout and reseal the header
+    ///
+    /// The data area must then be formatted with
+    /// [`crate::sparse::SparseVolume::format`].
+    pub fn enable_sparse(&mut self, master_key: &SecretKey) -> Result<()> {
+        self.set_incompat_flag(master_key, INCOMPAT_SPARSE)
+    }
+
+    /// Whether the data area is kept filled with random bytes
+    pub fn random_fill(&self) -> bool {
+        self.compat_flags & COMPAT_RANDOM_FILL != 0
+    }
+
+    /// Record that the data area was filled with random bytes, so that
+    /// growing it fills the new part too, and reseal the header
+    pub fn enable_random_fill(&mut self, master_key: &SecretKey) -> Result<()> {
+        self.verify_mac(master_key)?;
+        let previous = self.compat_flags;
+        self.compat_flags |= COMPAT_RANDOM_FILL;
+        if let Err(e) = self.seal(master_key) {
+            self.compat_flags = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn set_incompat_flag(&mut self, master_key: &SecretKey, flag: u32) -> Result<()> {
+        self.verify_mac(master_key)?;
+        let previous = self.incompat_flags;
+        self.incompat_flags |= flag;
+        if let Err(e) = self.seal(master_key) {
+            self.incompat_flags = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Change the size of the data area and reseal the header
+    ///
+    /// Only the header changes; see [`crate::resize`] to resize a volume.
+    pub fn set_data_size(&mut self, master_key: &SecretKey, data_size: u64) -> Result<()> {
+        if data_size == 0 || !data_size.is_multiple_of(SECTOR_SIZE as u64) {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "Data area size {} is not a positive number of sectors",
+                data_size
+            )));
+        }
+        self.verify_mac(master_key)?;
+        let previous = std::mem::replace(&mut self.data_size, data_size);
+        if let Err(e) = self.seal(master_key) {
+            self.data_size = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Store the keyslot built by `make` in the first free slot and reseal
+    fn enroll(&mut self, master_key: &SecretKey, make: impl FnOnce(&[u8]) -> Result<Keyslot>) -> Result<usize> {
+        let index = self
+            .keyslots
+            .iter()
+            .position(Option::is_none)
+            .ok_or_else(|| DeepVaultError::InvalidInput("All keyslots are in use".to_string()))?;
+
+        let slot = make(&self.keyslot_aad(index))?;
+        self.keyslots[index] = Some(slot);
+        if let Err(e) = self.seal(master_key) {
+            self.keyslots[index] = None;
+            return Err(e);
+        }
+        Ok(index)
+    }
+
+    /// Revoke a keyslot
+    ///
+    /// The last active keyslot cannot be removed, since the volume would
+    /// become impossible to unlock.
+    pub fn remove_keyslot(&mut self, master_key: &SecretKey, index: usize) -> Result<()> {
+        self.keyslot(index)?;
+        if self.list_keyslots().len() == 1 {
+            return Err(DeepVaultError::InvalidInput("Cannot remove the last keyslot".to_string()));
+        }
+
+        let removed = self.keyslots[index].take();
+        if let Err(e) = self.seal(master_key) {
+            self.keyslots[index] = removed;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Replace the passphrase of the keyslot unlocked by `old_password`
+    ///
+    /// The keyslot keeps its index and KDF parameters but gets a new salt.
+    /// Returns the index of the changed keyslot.
+    pub fn change_passphrase(&mut self, old_password: &SecretString, new_password: &SecretString) -> Result<usize> {
+        self.change_credentials(&Credentials::passphrase(old_password), &Credentials::passphrase(new_password))
+    }
+
+    /// Replace the credentials of the keyslot unlocked by `old`
+    ///
+    /// This can also add or drop keyfiles for that keyslot.
+    pub fn change_credentials(&mut self, old: &Credentials, new: &Credentials) -> Result<usize> {
+        let (index, master_key) = self.unlock_with_index(old)?;
+        let kdf = self
+            .keyslot(index)?
+            .kdf
+            .clone()
+            .ok_or_else(|| DeepVaultError::InvalidHeader(format!("Keyslot {} has no KDF", index)))?;
+
+        let slot = Keyslot::new_password(&master_key, new, &kdf, &self.keyslot_aad(index))?;
+        let previous = self.keyslots[index].replace(slot);
+        if let Err(e) = self.seal(&master_key) {
+            self.keyslots[index] = previous;
+            return Err(e);
+        }
+        Ok(index)
+    }
+
+    /// Try every password keyslot, returning the matching index and master key
+    fn unlock_with_index(&self, credentials: &Credentials) -> Result<(usize, SecretKey)> {
+        self.unlock_kind(KeyslotKind::Password, &credentials.kdf_input()?)
+    }
+
+    /// Try every active keyslot of `kind` with a raw secret
+    ///
+    /// A duress keyslot fails like a wrong secret.
+    fn unlock_kind(&self, kind: KeyslotKind, secret: &[u8]) -> Result<(usize, SecretKey)> {
+        match self.attempt(kind, secret)? {
+            Attempt::Unlocked(index, master_key) => Ok((index, master_key)),
+            Attempt::Duress | Attempt::Failed => Err(wrong_password()),
+        }
+    }
+
+    /// Try every active keyslot of `kind` with a raw secret, telling duress
+    /// keyslots apart
+    fn attempt(&self, kind: KeyslotKind, secret: &[u8]) -> Result<Attempt> {
+        for (index, slot) in self.keyslots.iter().enumerate() {
+            let Some(slot) = slot.as_ref().filter(|slot| slot.kind == kind) else { continue };
+            if let Ok(master_key) = slot.unwrap_with_secret(secret, &self.keyslot_aad(index)) {
+                if self.is_duress_marker(index, &master_key) {
+                    return Ok(Attempt::Duress);
+                }
+                self.verify_mac(&master_key)?;
+                return Ok(Attempt::Unlocked(index, master_key));
+            }
+        }
+        Ok(Attempt::Failed)
+    }
+
+    /// Whether `key` is the marker wrapped by a duress keyslot at `index`
+    fn is_duress_marker(&self, index: usize, key: &SecretKey) -> bool {
+        duress_marker(&self.keyslot_aad(index)).ct_eq(key).into()
+    }
+
+    /// Active keyslot at `index`
+    fn keyslot(&self, index: usize) -> Result<&Keyslot> {
+        self.keyslots
+            .get(index)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| DeepVaultError::InvalidInput(format!("Keyslot {} is not in use", index)))
+    }
+
+    /// Associated data binding a wrapped key to this vault and slot index
+    fn keyslot_aad(&self, index: usize) -> Vec<u8> {
+        let mut aad = self.uuid.as_bytes().to_vec();
+        aad.push(index as u8);
+        aad
+    }
+
+    fn keyslot_range(index: usize) -> std::ops::Range<usize> {
+        let start = OFF_KEYSLOTS + index * KEYSLOT_SIZE;
+        start..start + KEYSLOT_SIZE
+    }
+
+    /// Verify the header MAC with the master key
+    pub fn verify_mac(&self, master_key: &SecretKey) -> Result<()> {
+        let mut mac = Self::mac_for(master_key)?;
+        mac.update(&self.mac_input());
+        mac.verify_slice(&self.mac)
+            .map_err(|_| DeepVaultError::InvalidHeader("Header MAC mismatch".to_string()))
+    }
+
+    /// Recompute the header MAC after modifying fields
+    ///
+    /// Fails if the header was written by a newer minor version, whose
+    /// extra fields this implementation would silently drop.
+    pub fn seal(&mut self, master_key: &SecretKey) -> Result<()> {
+        if self.version_minor > VERSION_MINOR {
+            return Err(DeepVaultError::InvalidHeader(format!(
+                "Header version {}.{} is read-only for this version of DeepVault",
+                self.version_major, self.version_minor
+            )));
+        }
+
+        let mut mac = Self::mac_for(master_key)?;
+        mac.update(&self.mac_input());
+        self.mac.copy_from_slice(&mac.finalize().into_bytes());
+        Ok(())
+    }
+
+    /// Serialize every field except the MAC
+    fn unsealed_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; HEADER_SIZE];
+        bytes[..MAGIC.len()].copy_from_slice(MAGIC);
+        write_u16(&mut bytes, OFF_VERSION_MAJOR, self.version_major);
+        write_u16(&mut bytes, OFF_VERSION_MINOR, self.version_minor);
+        write_u32(&mut bytes, OFF_HEADER_SIZE, HEADER_SIZE as u32);
+        write_u32(&mut bytes, OFF_INCOMPAT_FLAGS, self.incompat_flags);
+        bytes[OFF_UUID..OFF_UUID + 16].copy_from_slice(self.uuid.as_bytes());
+        write_u64(&mut bytes, OFF_DATA_OFFSET, self.data_offset);
+        write_u64(&mut bytes, OFF_DATA_SIZE, self.data_size);
+        write_u64(&mut bytes, OFF_CREATED_AT, self.created_at.timestamp() as u64);
+        write_u32(&mut bytes, OFF_COMPAT_FLAGS, self.compat_flags);
+        for (index, slot) in self.keyslots.iter().enumerate() {
+            if let Some(slot) = slot {
+                slot.write_bytes(&mut bytes[Self::keyslot_range(index)]);
+            }
+        }
+        write_u32(&mut bytes, OFF_ATTEMPT_POLICY, self.attempt_policy.wipe_after);
+        write_u32(&mut bytes, OFF_ATTEMPT_POLICY + 4, self.attempt_policy.base_delay);
+        write_u32(&mut bytes, OFF_ATTEMPT_POLICY + 8, self.attempt_policy.max_delay);
+        write_u32(&mut bytes, OFF_FAILURES, self.attempts.failures);
+        write_u64(&mut bytes, OFF_LAST_FAILURE, self.attempts.last_failure);
+        bytes[OFF_ATTEMPT_LINK..OFF_ATTEMPT_TAG].copy_from_slice(&self.attempt_link);
+        bytes[OFF_ATTEMPT_TAG..OFF_RESERVED].copy_from_slice(&self.attempt_tag);
+        bytes[OFF_RESERVED..OFF_MAC].copy_from_slice(&self.reserved);
+        bytes
+    }
+
+    /// Bytes covered by the MAC: everything before it, except the failure
+    /// fields, which are updated without the master key
+    fn mac_input(&self) -> Vec<u8> {
+        let mut bytes = self.unsealed_bytes();
+        bytes[OFF_FAILURES..OFF_RESERVED].fill(0);
+        bytes.truncate(OFF_MAC);
+        bytes
+    }
+
+    fn mac_for(master_key: &SecretKey) -> Result<Hmac<Sha256>> {
+        let mac_key = CryptoManager::derive_subkey(master_key, MAC_KEY_INFO, KEY_LEN)?;
+        Hmac::<Sha256>::new_from_slice(mac_key.expose_secret())
+            .map_err(|e| DeepVaultError::Crypto(format!("Invalid MAC key: {}", e)))
+    }
+}
+
+/// Error returned for every failed unlock, whatever the cause
+fn wrong_password() -> DeepVaultError {
+    DeepVaultError::Crypto("Wrong password or corrupted header".to_string())
+}
+
+/// Link of the failure chain following `link`
+fn next_attempt_link(link: &[u8; 32]) -> [u8; 32] {
+    Sha256::new().chain_update(ATTEMPT_LINK_DOMAIN).chain_update(link).finalize().into()
+}
+
+/// Tag binding the link before a failure to the count and time it left
+fn attempt_tag(link: &[u8; 32], attempts: &AttemptCounter) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(ATTEMPT_TAG_DOMAIN)
+        .chain_update(link)
+        .chain_update(attempts.failures.to_le_bytes())
+        .chain_update(attempts.last_failure.to_le_bytes())
+        .finalize()
+        .into()
+}
+
+/// Value wrapped by a duress keyslot in place of the master key
+fn duress_marker(aad: &[u8]) -> SecretKey {
+    let digest = Sha256::new().chain_update(DURESS_DOMAIN).chain_update(aad).finalize();
+    SecretKey::from_slice(&digest)
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+pub(crate) fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,254 @@
+//! Hidden volumes nested in an outer volume
+//!
+//! A hidden volume occupies the end of the data area of an ordinary
+//! (outer) volume. Its header sits in a fixed slot of the outer header
+//! region, which [`VaultHeader::initialize`] fills with random bytes on
+//! every new volume, so without the hidden passphrase the slot cannot be
+//! told apart from unused space:
+//!
+//! | Offset | Size | Field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 32   | KDF salt                                       |
+//! | 32     | 4156 | AES-256-GCM nonce, ciphertext of the master    |
+//! |        |      | key followed by a [`VaultHeader`], and tag     |
+//! | 4188   | 4004 | Random padding                                 |
+//!
+//! The key encrypting the slot is derived from the hidden passphrase with
+//! the KDF given by the caller. The KDF settings cannot be recorded in
+//! the clear without giving the slot away, so the same settings must be
+//! supplied to open the volume.
+//!
+//! Writing to the outer volume would overwrite the hidden one, which the
+//! outer volume knows nothing about. [`ProtectedVolume`] mounts the outer
+//! volume with the hidden range fenced off: a write landing in it is
+//! refused, and so is every later write, as the outer file system is then
+//! in an inconsistent state. [`crate::mount::MountManager::open_volume`]
+//! applies it when given the hidden credentials.
+
+use crate::block::{check_sectors, BlockDevice, FileDevice, Storage, SECTOR_SIZE};
+use crate::crypto::{CryptoConfig, CryptoManager, KEY_LEN, NONCE_LEN, SALT_LEN, TAG_LEN};
+use crate::header::{VaultHeader, HEADER_SIZE};
+use crate::kdf::KdfParams;
+use crate::keyfile::Credentials;
+use crate::secret::{SecretKey, SecretString};
+use crate::sector::EncryptedVolume;
+use crate::{DeepVaultError, Result};
+use rand::RngCore;
+use std::io::SeekFrom;
+use std::ops::Range;
+use zeroize::Zeroizing;
+
+/// Offset of the hidden header slot from the start of the volume
+pub const HIDDEN_HEADER_OFFSET: u64 = 64 * 1024;
+/// Size of the hidden header slot
+pub const HIDDEN_HEADER_SIZE: usize = 8 * 1024;
+/// Smallest hidden volume accepted
+pub const MIN_HIDDEN_SIZE: u64 = 64 * 1024;
+
+/// Domain of the associated data of the slot
+const SLOT_AAD: &[u8] = b"deepvault hidden header v1";
+/// Length of the encrypted part of the slot
+const ENVELOPE_LEN: usize = NONCE_LEN + KEY_LEN + HEADER_SIZE + TAG_LEN;
+
+/// Fill the hidden header slot with random bytes
+///
+/// Done by [`VaultHeader::initialize`] for every new volume, so that a
+/// slot holding a hidden header looks like any other.
+pub fn write_decoy<S: Storage>(storage: &mut S) -> Result<()> {
+    let mut slot = vec![0u8; HIDDEN_HEADER_SIZE];
+    rand::rngs::OsRng.fill_bytes(&mut slot);
+    write_slot(storage, &slot)
+}
+
+/// Create a hidden volume of `size` bytes at the end of the outer data area
+///
+/// The outer volume must have room for the slot in its header region,
+/// must neither use integrity tags nor be sparse, and its data area must
+/// have been filled with random bytes ([`VaultHeader::random_fill`]):
+/// otherwise the hidden data would stand out from the untouched space
+/// around it. Returns the hidden header and master key; the hidden data
+/// area keeps its current (random) contents.
+///
+/// The hidden header keeps the default attempt policy: failed attempts
+/// cannot be counted in the slot without showing that it is in use.
+pub fn create<S: Storage>(
+    storage: &mut S,
+    outer: &VaultHeader,
+    credentials: &Credentials,
+    kdf: &KdfParams,
+    size: u64,
+) -> Result<(VaultHeader, SecretKey)> {
+    if outer.data_offset < HIDDEN_HEADER_OFFSET + HIDDEN_HEADER_SIZE as u64 {
+        return Err(DeepVaultError::InvalidInput(
+            "The outer header region is too small for a hidden volume".to_string(),
+        ));
+    }
+    if outer.integrity() || outer.sparse() {
+        return Err(DeepVaultError::InvalidInput(
+            "Hidden volumes cannot be nested in a volume with integrity tags or a sparse volume".to_string(),
+        ));
+    }
+    if !outer.random_fill() {
+        return Err(DeepVaultError::InvalidInput(
+            "The outer data area was not filled with random bytes, a hidden volume would be detectable".to_string(),
+        ));
+    }
+    let size = size - size % SECTOR_SIZE as u64;
+    if size < MIN_HIDDEN_SIZE || size >= outer.data_size {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "The hidden volume must be at least {} bytes and smaller than the outer data area",
+            MIN_HIDDEN_SIZE
+        )));
+    }
+
+    let data_offset = outer.data_offset + outer.data_size - size;
+    let (header, master_key) = VaultHeader::create_with_credentials(credentials, kdf, data_offset, size)?;
+    seal(storage, &header, &master_key, credentials, kdf)?;
+    Ok((header, master_key))
+}
+
+/// Open the hidden volume of `storage` with its passphrase and/or keyfiles
+///
+/// Fails the same way whether the credentials are wrong or there is no
+/// hidden volume at all.
+pub fn open<S: Storage>(storage: &mut S, credentials: &Credentials, kdf: &KdfParams) -> Result<(VaultHeader, SecretKey)> {
+    let mut slot = vec![0u8; HIDDEN_HEADER_SIZE];
+    storage.seek(SeekFrom::Start(HIDDEN_HEADER_OFFSET))?;
+    storage.read_exact(&mut slot)?;
+
+    let manager = CryptoManager::with_config(CryptoConfig::for_kdf(kdf, &slot[..SALT_LEN]));
+    let key = manager.derive_key_from_credentials(credentials)?;
+    let plaintext = Zeroizing::new(
+        manager
+            .decrypt_with_aad(&key, &slot[SALT_LEN..SALT_LEN + ENVELOPE_LEN], SLOT_AAD)
+            .map_err(|_| no_hidden_volume())?,
+    );
+    let master_key = SecretKey::from_slice(&plaintext[..KEY_LEN]);
+    let header = VaultHeader::from_bytes(&plaintext[KEY_LEN..])?;
+    header.verify_mac(&master_key)?;
+    Ok((header, master_key))
+}
+
+/// Open the hidden volume with a passphrase
+pub fn open_with_password<S: Storage>(
+    storage: &mut S,
+    password: &SecretString,
+    kdf: &KdfParams,
+) -> Result<(VaultHeader, SecretKey)> {
+    open(storage, &Credentials::passphrase(password), kdf)
+}
+
+/// Rewrite the hidden header slot, for instance after changing the hidden
+/// header, with a fresh salt and nonce
+pub fn seal<S: Storage>(
+    storage: &mut S,
+    header: &VaultHeader,
+    master_key: &SecretKey,
+    credentials: &Credentials,
+    kdf: &KdfParams,
+) -> Result<()> {
+    header.verify_mac(master_key)?;
+    let mut slot = vec![0u8; HIDDEN_HEADER_SIZE];
+    rand::rngs::OsRng.fill_bytes(&mut slot);
+
+    let manager = CryptoManager::with_config(CryptoConfig::for_kdf(kdf, &slot[..SALT_LEN]));
+    let key = manager.derive_key_from_credentials(credentials)?;
+    let mut plaintext = Zeroizing::new(master_key.expose_secret().to_vec());
+    plaintext.extend_from_slice(&header.to_bytes());
+    let envelope = manager.encrypt_with_aad(&key, &plaintext, SLOT_AAD)?;
+    slot[SALT_LEN..SALT_LEN + ENVELOPE_LEN].copy_from_slice(&envelope);
+    write_slot(storage, &slot)
+}
+
+/// Sectors of the outer data area holding the hidden volume
+pub fn hidden_sectors(outer: &VaultHeader, hidden: &VaultHeader) -> Result<Range<u64>> {
+    let outer_end = outer.data_offset + outer.data_size;
+    if hidden.data_offset < outer.data_offset || hidden.data_offset + hidden.data_size > outer_end {
+        return Err(DeepVaultError::InvalidInput(
+            "The hidden volume does not belong to this outer volume".to_string(),
+        ));
+    }
+    let start = (hidden.data_offset - outer.data_offset) / SECTOR_SIZE as u64;
+    let end = (hidden.data_offset + hidden.data_size - outer.data_offset).div_ceil(SECTOR_SIZE as u64);
+    Ok(start..end)
+}
+
+/// Open the outer data area with the sectors of `hidden` fenced off
+pub fn open_protected<F: Storage>(
+    storage: F,
+    outer: &VaultHeader,
+    outer_key: &SecretKey,
+    hidden: &VaultHeader,
+) -> Result<ProtectedVolume<EncryptedVolume<FileDevice<F>>>> {
+    let protected = hidden_sectors(outer, hidden)?;
+    Ok(ProtectedVolume::new(EncryptedVolume::open(storage, outer, outer_key)?, protected))
+}
+
+fn write_slot<S: Storage>(storage: &mut S, slot: &[u8]) -> Result<()> {
+    storage.seek(SeekFrom::Start(HIDDEN_HEADER_OFFSET))?;
+    storage.write_all(slot)?;
+    storage.sync()?;
+    Ok(())
+}
+
+fn no_hidden_volume() -> DeepVaultError {
+    DeepVaultError::Crypto("Wrong password or no hidden volume".to_string())
+}
+
+/// Outer volume mounted with its hidden volume fenced off
+pub struct ProtectedVolume<D> {
+    inner: D,
+    protected: Range<u64>,
+    tripped: bool,
+}
+
+impl<D: BlockDevice> ProtectedVolume<D> {
+    /// Refuse writes to the `protected` sectors of `inner`
+    pub fn new(inner: D, protected: Range<u64>) -> Self {
+        Self {
+            inner,
+            protected,
+            tripped: false,
+        }
+    }
+
+    /// Whether a write to the hidden volume was refused
+    ///
+    /// Once set, the outer volume is read-only until it is reopened.
+    pub fn tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// Consume the volume and return the underlying device
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for ProtectedVolume<D> {
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_sectors(first, buf)
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        check_sectors(self.sector_count(), first, buf.len())?;
+        let end = first + (buf.len() / SECTOR_SIZE) as u64;
+        if first < self.protected.end && self.protected.start < end {
+            self.tripped = true;
+        }
+        if self.tripped {
+            return Err(DeepVaultError::Permission(
+                "Write refused to protect the hidden volume; the outer volume is now read-only".to_string(),
+            ));
+        }
+        self.inner.write_sectors(first, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,222 @@
+//! Per-sector integrity tags
+//!
+//! In integrity mode the data area interleaves tag sectors with data
+//! sectors. Each group is one tag sector followed by 16 data sectors, and
+//! the tag sector holds the HMAC-SHA256 of each of them in order:
+//!
+//! | Raw sector | Contents                      |
+//! |------------|-------------------------------|
+//! | 0          | Tags of data sectors 0..16    |
+//! | 1..=16     | Data sectors 0..16            |
+//! | 17         | Tags of data sectors 16..32   |
+//! | ...        | ...                           |
+//!
+//! A tag covers the sector number and the stored (encrypted) sector, so a
+//! modified, moved or swapped sector fails to read with
+//! [`DeepVaultError::IntegrityViolation`]. Tags cannot tell an older
+//! version of a sector from the current one: someone who kept a copy of
+//! the drive can still roll single sectors back to an earlier state.
+//!
+//! Data is written before its tags, so a sector interrupted by a crash
+//! also fails its check until it is written again.
+
+use crate::block::{check_sectors, BlockDevice, SECTOR_SIZE};
+use crate::crypto::{CryptoManager, KEY_LEN};
+use crate::secret::SecretKey;
+use crate::{DeepVaultError, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Length of a sector tag
+pub const TAG_LEN: usize = 32;
+/// Data sectors covered by one tag sector
+pub const SECTORS_PER_GROUP: u64 = (SECTOR_SIZE / TAG_LEN) as u64;
+
+/// Raw sectors per group: the tag sector and its data sectors
+const GROUP_LEN: u64 = SECTORS_PER_GROUP + 1;
+/// HKDF context for the sector tag key
+const TAG_KEY_INFO: &[u8] = b"deepvault sector tags v1";
+
+/// Data sectors available in `raw_sectors` sectors of tagged storage
+pub fn data_sectors(raw_sectors: u64) -> u64 {
+    let full_groups = raw_sectors / GROUP_LEN;
+    let rest = raw_sectors % GROUP_LEN;
+    full_groups * SECTORS_PER_GROUP + rest.saturating_sub(1)
+}
+
+/// Block device that authenticates every sector of another device
+pub struct AuthenticatedDevice<D> {
+    inner: D,
+    mac: Hmac<Sha256>,
+    sector_count: u64,
+}
+
+impl<D: BlockDevice> AuthenticatedDevice<D> {
+    /// Authenticate the sectors of `inner`, already formatted with tags,
+    /// with a key derived from the master key
+    pub fn new(inner: D, master_key: &SecretKey) -> Result<Self> {
+        let tag_key = CryptoManager::derive_subkey(master_key, TAG_KEY_INFO, KEY_LEN)?;
+        let mac = Hmac::<Sha256>::new_from_slice(tag_key.expose_secret())
+            .map_err(|e| DeepVaultError::Crypto(format!("Invalid tag key: {}", e)))?;
+        let sector_count = Self::data_sectors(inner.sector_count());
+        Ok(Self {
+            inner,
+            mac,
+            sector_count,
+        })
+    }
+
+    /// Tag the current contents of every sector of `inner`
+    ///
+    /// Run once on a new data area; whatever it held becomes valid data.
+    pub fn format(inner: D, master_key: &SecretKey) -> Result<Self> {
+        Self::format_from(inner, master_key, 0)
+    }
+
+    /// Tag the current contents of the sectors from `first` on, keeping
+    /// the tags of the sectors before it
+    ///
+    /// Used after the data area grew, with `first` the previous number of
+    /// data sectors.
+    pub fn format_from(inner: D, master_key: &SecretKey, first: u64) -> Result<Self> {
+        let mut device = Self::new(inner, master_key)?;
+        let mut tags = vec![0u8; SECTOR_SIZE];
+        let mut group = vec![0u8; SECTORS_PER_GROUP as usize * SECTOR_SIZE];
+        for (start, count) in Self::runs(first, device.sector_count.saturating_sub(first)) {
+            if count < SECTORS_PER_GROUP {
+                device.inner.read_sectors(Self::tag_sector(start), &mut tags)?;
+            }
+            let data = &mut group[..count as usize * SECTOR_SIZE];
+            device.inner.read_sectors(Self::raw_sector(start), data)?;
+            for (i, sector) in data.chunks_exact(SECTOR_SIZE).enumerate() {
+                let index = start + i as u64;
+                let slot = (index % SECTORS_PER_GROUP) as usize * TAG_LEN;
+                tags[slot..slot + TAG_LEN].copy_from_slice(&device.tag(index, sector));
+            }
+            device.inner.write_sectors(Self::tag_sector(start), &tags)?;
+        }
+        device.inner.flush()?;
+        Ok(device)
+    }
+
+    /// Data sectors available on a device of `raw_sectors` sectors
+    pub fn data_sectors(raw_sectors: u64) -> u64 {
+        data_sectors(raw_sectors)
+    }
+
+    /// List the sectors whose tags do not match
+    pub fn scan(&mut self) -> Result<Vec<u64>> {
+        let mut failed = Vec::new();
+        let mut buf = vec![0u8; SECTOR_SIZE];
+        for index in 0..self.sector_count {
+            match self.read_sectors(index, &mut buf) {
+                Ok(()) => {}
+                Err(DeepVaultError::IntegrityViolation { sector }) => failed.push(sector),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Get a reference to the underlying device
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying device
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Consume the device and return the underlying device
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn tag(&self, sector: u64, data: &[u8]) -> [u8; TAG_LEN] {
+        let mut mac = self.mac.clone();
+        mac.update(&sector.to_le_bytes());
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+
+    /// Raw sector holding the tags of the group of `sector`
+    fn tag_sector(sector: u64) -> u64 {
+        sector / SECTORS_PER_GROUP * GROUP_LEN
+    }
+
+    /// Raw sector holding data sector `sector`
+    fn raw_sector(sector: u64) -> u64 {
+        Self::tag_sector(sector) + 1 + sector % SECTORS_PER_GROUP
+    }
+
+    /// Split a request into runs that stay within one group
+    fn runs(first: u64, count: u64) -> impl Iterator<Item = (u64, u64)> {
+        let end = first + count;
+        let mut next = first;
+        std::iter::from_fn(move || {
+            if next >= end {
+                return None;
+            }
+            let run = (SECTORS_PER_GROUP - next % SECTORS_PER_GROUP).min(end - next);
+            let start = next;
+            next += run;
+            Some((start, run))
+        })
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for AuthenticatedDevice<D> {
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        check_sectors(self.sector_count, first, buf.len())?;
+        let mut tags = vec![0u8; SECTOR_SIZE];
+        let mut done = 0;
+        for (start, count) in Self::runs(first, (buf.len() / SECTOR_SIZE) as u64) {
+            let data = &mut buf[done..done + count as usize * SECTOR_SIZE];
+            self.inner.read_sectors(Self::tag_sector(start), &mut tags)?;
+            self.inner.read_sectors(Self::raw_sector(start), data)?;
+            for (i, sector) in data.chunks_exact(SECTOR_SIZE).enumerate() {
+                let index = start + i as u64;
+                let slot = (index % SECTORS_PER_GROUP) as usize * TAG_LEN;
+                let mut mac = self.mac.clone();
+                mac.update(&index.to_le_bytes());
+                mac.update(sector);
+                if mac.verify_slice(&tags[slot..slot + TAG_LEN]).is_err() {
+                    data.fill(0);
+                    return Err(DeepVaultError::IntegrityViolation { sector: index });
+                }
+            }
+            done += data.len();
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        check_sectors(self.sector_count, first, buf.len())?;
+        let mut tags = vec![0u8; SECTOR_SIZE];
+        let mut done = 0;
+        for (start, count) in Self::runs(first, (buf.len() / SECTOR_SIZE) as u64) {
+            let data = &buf[done..done + count as usize * SECTOR_SIZE];
+            if count < SECTORS_PER_GROUP {
+                self.inner.read_sectors(Self::tag_sector(start), &mut tags)?;
+            }
+            for (i, sector) in data.chunks_exact(SECTOR_SIZE).enumerate() {
+                let index = start + i as u64;
+                let slot = (index % SECTORS_PER_GROUP) as usize * TAG_LEN;
+                tags[slot..slot + TAG_LEN].copy_from_slice(&self.tag(index, sector));
+            }
+            self.inner.write_sectors(Self::raw_sector(start), data)?;
+            self.inner.write_sectors(Self::tag_sector(start), &tags)?;
+            done += data.len();
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
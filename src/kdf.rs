@@ -0,0 +1,466 @@
+//! Key derivation functions for DeepVault
+//!
+//! Argon2id is the default. Argon2i, Argon2d, scrypt and PBKDF2-SHA512 are
+//! available for interoperability with other tools. The selected function
+//! and its parameters are stored in each keyslot, so every keyslot can be
+//! opened regardless of the current configuration.
+
+use crate::crypto::KeyDerivation;
+use crate::{DeepVaultError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Upper bound on KDF memory, to refuse headers that would exhaust RAM
+pub const MAX_KDF_MEMORY_KIB: u64 = 4 * 1024 * 1024;
+/// Upper bound on Argon2 passes, so a crafted header cannot stall unlocking
+pub const MAX_ARGON2_ITERATIONS: u32 = 64;
+/// Upper bound on scrypt parallelism, which runs sequentially when unlocking
+pub const MAX_SCRYPT_PARALLELISM: u32 = 16;
+/// Minimum PBKDF2 iteration count accepted
+pub const MIN_PBKDF2_ITERATIONS: u32 = 1000;
+/// Upper bound on PBKDF2 iterations, for the same reason
+pub const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+/// Default unlock time targeted by calibration
+pub const DEFAULT_CALIBRATION_TIME: Duration = Duration::from_secs(2);
+/// Default memory ceiling for calibration (1 GiB)
+pub const DEFAULT_CALIBRATION_MEMORY_KIB: u32 = 1024 * 1024;
+/// Default upper bound on calibrated lanes
+pub const DEFAULT_CALIBRATION_PARALLELISM: u32 = 4;
+
+/// Memory used for the first calibration measurement (1 MiB)
+const CALIBRATION_START_MEMORY_KIB: u32 = 1024;
+/// Largest factor by which memory grows between two measurements
+const CALIBRATION_MAX_GROWTH: f64 = 8.0;
+
+/// Key derivation function selector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KdfAlgorithm {
+    Argon2id,
+    Argon2i,
+    Argon2d,
+    Scrypt,
+    Pbkdf2Sha512,
+}
+
+impl KdfAlgorithm {
+    /// All supported algorithms
+    pub const ALL: [KdfAlgorithm; 5] = [
+        KdfAlgorithm::Argon2id,
+        KdfAlgorithm::Argon2i,
+        KdfAlgorithm::Argon2d,
+        KdfAlgorithm::Scrypt,
+        KdfAlgorithm::Pbkdf2Sha512,
+    ];
+
+    /// Name used in configuration files
+    pub fn name(self) -> &'static str {
+        match self {
+            KdfAlgorithm::Argon2id => "Argon2id",
+            KdfAlgorithm::Argon2i => "Argon2i",
+            KdfAlgorithm::Argon2d => "Argon2d",
+            KdfAlgorithm::Scrypt => "scrypt",
+            KdfAlgorithm::Pbkdf2Sha512 => "PBKDF2-SHA512",
+        }
+    }
+
+    /// Identifier stored in keyslots
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            KdfAlgorithm::Argon2id => 1,
+            KdfAlgorithm::Argon2i => 2,
+            KdfAlgorithm::Argon2d => 3,
+            KdfAlgorithm::Scrypt => 4,
+            KdfAlgorithm::Pbkdf2Sha512 => 5,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.id() == id)
+            .ok_or_else(|| DeepVaultError::InvalidHeader(format!("Unknown KDF identifier {}", id)))
+    }
+}
+
+impl fmt::Display for KdfAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for KdfAlgorithm {
+    type Err = DeepVaultError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| DeepVaultError::Config(format!("Unknown key derivation algorithm: {}", s)))
+    }
+}
+
+/// scrypt parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScryptParams {
+    pub log_n: u8, // CPU/memory cost, as a power of two
+    pub r: u32,    // Block size
+    pub p: u32,    // Parallelism
+}
+
+impl Default for ScryptParams {
+    fn default() -> Self {
+        Self {
+            log_n: 17, // 128MB with r = 8
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+/// PBKDF2-HMAC-SHA512 parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pbkdf2Params {
+    pub iterations: u32,
+}
+
+impl Default for Pbkdf2Params {
+    fn default() -> Self {
+        Self {
+            iterations: 210_000, // OWASP recommendation for SHA-512
+        }
+    }
+}
+
+/// A key derivation function together with its parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum KdfParams {
+    Argon2id(KeyDerivation),
+    Argon2i(KeyDerivation),
+    Argon2d(KeyDerivation),
+    Scrypt(ScryptParams),
+    Pbkdf2Sha512(Pbkdf2Params),
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams::Argon2id(KeyDerivation::default())
+    }
+}
+
+impl From<KeyDerivation> for KdfParams {
+    fn from(params: KeyDerivation) -> Self {
+        KdfParams::Argon2id(params)
+    }
+}
+
+impl KdfParams {
+    /// Build parameters for `algorithm` from the per-algorithm settings
+    pub fn for_algorithm(
+        algorithm: KdfAlgorithm,
+        argon2: &KeyDerivation,
+        scrypt: &ScryptParams,
+        pbkdf2: &Pbkdf2Params,
+    ) -> Self {
+        match algorithm {
+            KdfAlgorithm::Argon2id => KdfParams::Argon2id(argon2.clone()),
+            KdfAlgorithm::Argon2i => KdfParams::Argon2i(argon2.clone()),
+            KdfAlgorithm::Argon2d => KdfParams::Argon2d(argon2.clone()),
+            KdfAlgorithm::Scrypt => KdfParams::Scrypt(scrypt.clone()),
+            KdfAlgorithm::Pbkdf2Sha512 => KdfParams::Pbkdf2Sha512(pbkdf2.clone()),
+        }
+    }
+
+    /// Selected algorithm
+    pub fn algorithm(&self) -> KdfAlgorithm {
+        match self {
+            KdfParams::Argon2id(_) => KdfAlgorithm::Argon2id,
+            KdfParams::Argon2i(_) => KdfAlgorithm::Argon2i,
+            KdfParams::Argon2d(_) => KdfAlgorithm::Argon2d,
+            KdfParams::Scrypt(_) => KdfAlgorithm::Scrypt,
+            KdfParams::Pbkdf2Sha512(_) => KdfAlgorithm::Pbkdf2Sha512,
+        }
+    }
+
+    /// Argon2 parameters, if this is one of the Argon2 variants
+    pub fn argon2(&self) -> Option<&KeyDerivation> {
+        match self {
+            KdfParams::Argon2id(params) | KdfParams::Argon2i(params) | KdfParams::Argon2d(params) => Some(params),
+            _ => None,
+        }
+    }
+
+    /// Check that the parameters are usable and within resource limits
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            KdfParams::Argon2id(params) | KdfParams::Argon2i(params) | KdfParams::Argon2d(params) => {
+                params.validate()
+            }
+            KdfParams::Scrypt(params) => params.validate(),
+            KdfParams::Pbkdf2Sha512(params) => params.validate(),
+        }
+    }
+
+    /// Derive `output.len()` bytes from `secret` and `salt`
+    pub fn derive(&self, secret: &[u8], salt: &[u8], output: &mut [u8]) -> Result<()> {
+        self.validate()?;
+        match self {
+            KdfParams::Argon2id(params) => argon2_derive(argon2::Algorithm::Argon2id, params, secret, salt, output),
+            KdfParams::Argon2i(params) => argon2_derive(argon2::Algorithm::Argon2i, params, secret, salt, output),
+            KdfParams::Argon2d(params) => argon2_derive(argon2::Algorithm::Argon2d, params, secret, salt, output),
+            KdfParams::Scrypt(params) => {
+                let scrypt_params = scrypt::Params::new(params.log_n, params.r, params.p, output.len())
+                    .map_err(|e| DeepVaultError::Crypto(format!("Invalid scrypt params: {}", e)))?;
+                scrypt::scrypt(secret, salt, &scrypt_params, output)
+                    .map_err(|e| DeepVaultError::Crypto(format!("Key derivation failed: {}", e)))
+            }
+            KdfParams::Pbkdf2Sha512(params) => {
+                pbkdf2::pbkdf2_hmac::<sha2::Sha512>(secret, salt, params.iterations, output);
+                Ok(())
+            }
+        }
+    }
+
+    /// Encode as the identifier and three parameter words stored on disk
+    pub(crate) fn encode(&self) -> (u8, [u32; 3]) {
+        let words = match self {
+            KdfParams::Argon2id(params) | KdfParams::Argon2i(params) | KdfParams::Argon2d(params) => {
+                [params.memory, params.iterations, params.parallelism]
+            }
+            KdfParams::Scrypt(params) => [params.log_n as u32, params.r, params.p],
+            KdfParams::Pbkdf2Sha512(params) => [params.iterations, 0, 0],
+        };
+        (self.algorithm().id(), words)
+    }
+
+    /// Decode parameters read from disk and check their limits
+    pub(crate) fn decode(id: u8, words: [u32; 3]) -> Result<Self> {
+        let argon2 = || KeyDerivation {
+            memory: words[0],
+            iterations: words[1],
+            parallelism: words[2],
+        };
+        let params = match KdfAlgorithm::from_id(id)? {
+            KdfAlgorithm::Argon2id => KdfParams::Argon2id(argon2()),
+            KdfAlgorithm::Argon2i => KdfParams::Argon2i(argon2()),
+            KdfAlgorithm::Argon2d => KdfParams::Argon2d(argon2()),
+            KdfAlgorithm::Scrypt => KdfParams::Scrypt(ScryptParams {
+                log_n: u8::try_from(words[0])
+                    .map_err(|_| DeepVaultError::InvalidHeader("Invalid scrypt cost".to_string()))?,
+                r: words[1],
+                p: words[2],
+            }),
+            KdfAlgorithm::Pbkdf2Sha512 => KdfParams::Pbkdf2Sha512(Pbkdf2Params { iterations: words[0] }),
+        };
+        params
+            .validate()
+            .map_err(|e| DeepVaultError::InvalidHeader(format!("Invalid KDF parameters: {}", e)))?;
+        Ok(params)
+    }
+}
+
+impl KeyDerivation {
+    /// Check Argon2 parameters
+    pub fn validate(&self) -> Result<()> {
+        if self.iterations == 0 {
+            return Err(DeepVaultError::Config("Argon2 iterations cannot be zero".to_string()));
+        }
+        if self.iterations > MAX_ARGON2_ITERATIONS {
+            return Err(DeepVaultError::Config(format!(
+                "Argon2 iterations cannot exceed {}",
+                MAX_ARGON2_ITERATIONS
+            )));
+        }
+        if self.parallelism == 0 || self.parallelism > 255 {
+            return Err(DeepVaultError::Config("Argon2 parallelism must be between 1 and 255".to_string()));
+        }
+        if self.memory < 8 * self.parallelism {
+            return Err(DeepVaultError::Config(
+                "Argon2 memory must be at least 8 KB per lane".to_string(),
+            ));
+        }
+        if self.memory as u64 > MAX_KDF_MEMORY_KIB {
+            return Err(DeepVaultError::Config("Argon2 memory exceeds 4 GB".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl ScryptParams {
+    /// Check scrypt parameters
+    pub fn validate(&self) -> Result<()> {
+        if self.log_n == 0 || self.log_n >= 64 {
+            return Err(DeepVaultError::Config("scrypt log_n must be between 1 and 63".to_string()));
+        }
+        if self.r == 0 || self.p == 0 {
+            return Err(DeepVaultError::Config("scrypt r and p cannot be zero".to_string()));
+        }
+        if self.p > MAX_SCRYPT_PARALLELISM {
+            return Err(DeepVaultError::Config(format!(
+                "scrypt p cannot exceed {}",
+                MAX_SCRYPT_PARALLELISM
+            )));
+        }
+        // The scrypt specification requires r * p < 2^30
+        if self.r as u64 * self.p as u64 >= 1 << 30 {
+            return Err(DeepVaultError::Config("scrypt r * p must be below 2^30".to_string()));
+        }
+        // scrypt uses 128 * r * N bytes of memory
+        let memory_bytes = (128u128 * self.r as u128) << self.log_n;
+        if memory_bytes / 1024 > MAX_KDF_MEMORY_KIB as u128 {
+            return Err(DeepVaultError::Config("scrypt memory exceeds 4 GB".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Pbkdf2Params {
+    /// Check PBKDF2 parameters
+    pub fn validate(&self) -> Result<()> {
+        if self.iterations < MIN_PBKDF2_ITERATIONS {
+            return Err(DeepVaultError::Config(format!(
+                "PBKDF2 iterations must be at least {}",
+                MIN_PBKDF2_ITERATIONS
+            )));
+        }
+        if self.iterations > MAX_PBKDF2_ITERATIONS {
+            return Err(DeepVaultError::Config(format!(
+                "PBKDF2 iterations cannot exceed {}",
+                MAX_PBKDF2_ITERATIONS
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn argon2_derive(
+    algorithm: argon2::Algorithm,
+    params: &KeyDerivation,
+    secret: &[u8],
+    salt: &[u8],
+    output: &mut [u8],
+) -> Result<()> {
+    let argon2 = argon2::Argon2::new(
+        algorithm,
+        argon2::Version::V0x13,
+        argon2::Params::new(params.memory, params.iterations, params.parallelism, Some(output.len()))
+            .map_err(|e| DeepVaultError::Crypto(format!("Invalid Argon2 params: {}", e)))?,
+    );
+    argon2
+        .hash_password_into(secret, salt, output)
+        .map_err(|e| DeepVaultError::Crypto(format!("Key derivation failed: {}", e)))
+}
+
+/// Limits for [`calibrate`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationTarget {
+    /// Desired time for one key derivation on this machine
+    pub unlock_time: Duration,
+    /// Memory ceiling in KB
+    pub max_memory: u32,
+    /// Maximum number of lanes
+    pub max_parallelism: u32,
+}
+
+impl Default for CalibrationTarget {
+    fn default() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get()) as u32;
+        Self {
+            unlock_time: DEFAULT_CALIBRATION_TIME,
+            max_memory: DEFAULT_CALIBRATION_MEMORY_KIB.min(half_physical_memory_kib()),
+            max_parallelism: cpus.clamp(1, DEFAULT_CALIBRATION_PARALLELISM),
+        }
+    }
+}
+
+/// Outcome of a calibration run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    /// Selected function and parameters
+    pub kdf: KdfParams,
+    /// Measured time of one derivation with `kdf`
+    pub duration: Duration,
+}
+
+/// Benchmark Argon2 on this machine and pick parameters for `target`
+///
+/// Memory is raised first, up to the ceiling, since it is what makes
+/// attacks on GPUs and ASICs expensive. Iterations are only added once the
+/// ceiling is reached and the target time has not been met.
+pub fn calibrate(algorithm: KdfAlgorithm, target: &CalibrationTarget) -> Result<Calibration> {
+    if target.unlock_time.is_zero() {
+        return Err(DeepVaultError::InvalidInput("Calibration time cannot be zero".to_string()));
+    }
+    let parallelism = target.max_parallelism.clamp(1, 255);
+    let ceiling = target.max_memory.min(MAX_KDF_MEMORY_KIB as u32);
+    if ceiling < 8 * parallelism {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "Calibration memory ceiling must be at least {} KB",
+            8 * parallelism
+        )));
+    }
+
+    let make = |memory: u32, iterations: u32| {
+        let params = KeyDerivation {
+            memory,
+            iterations,
+            parallelism,
+        };
+        match algorithm {
+            KdfAlgorithm::Argon2id => Ok(KdfParams::Argon2id(params)),
+            KdfAlgorithm::Argon2i => Ok(KdfParams::Argon2i(params)),
+            KdfAlgorithm::Argon2d => Ok(KdfParams::Argon2d(params)),
+            other => Err(DeepVaultError::InvalidInput(format!("Calibration is not supported for {}", other))),
+        }
+    };
+    let target_secs = target.unlock_time.as_secs_f64();
+
+    // Grow memory with a single pass until the target or the ceiling is hit
+    let mut memory = CALIBRATION_START_MEMORY_KIB.clamp(8 * parallelism, ceiling);
+    let mut duration = measure(&make(memory, 1)?)?;
+    while duration < target.unlock_time && memory < ceiling {
+        let factor = (target_secs / duration.as_secs_f64().max(1e-6)).min(CALIBRATION_MAX_GROWTH);
+        memory = ((memory as f64 * factor) as u64).clamp(memory as u64 + 1, ceiling as u64) as u32;
+        duration = measure(&make(memory, 1)?)?;
+    }
+
+    // Then spend the remaining time on extra passes
+    let mut iterations = 1;
+    if duration < target.unlock_time {
+        iterations = (target_secs / duration.as_secs_f64().max(1e-6))
+            .floor()
+            .clamp(1.0, MAX_ARGON2_ITERATIONS as f64) as u32;
+        if iterations > 1 {
+            duration = measure(&make(memory, iterations)?)?;
+        }
+    }
+
+    Ok(Calibration {
+        kdf: make(memory, iterations)?,
+        duration,
+    })
+}
+
+/// Time one derivation with `kdf`
+fn measure(kdf: &KdfParams) -> Result<Duration> {
+    let salt = [0u8; crate::crypto::SALT_LEN];
+    let mut output = [0u8; crate::crypto::KEY_LEN];
+    let start = Instant::now();
+    kdf.derive(b"deepvault calibration", &salt, &mut output)?;
+    Ok(start.elapsed())
+}
+
+/// Half of the physical memory in KB, so calibration never makes a
+/// machine swap
+fn half_physical_memory_kib() -> u32 {
+    use sysinfo::{System, SystemExt};
+
+    let mut system = System::new();
+    system.refresh_memory();
+    match system.total_memory() / 2 / 1024 {
+        0 => DEFAULT_CALIBRATION_MEMORY_KIB,
+        kib => kib.min(u32::MAX as u64) as u32,
+    }
+}
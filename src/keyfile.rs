@@ -0,0 +1,158 @@
+//! Keyfiles and combined unlock credentials
+//!
+//! A keyslot can be protected by a passphrase, one or more keyfiles, or
+//! both. The credentials are turned into a single KDF input:
+//!
+//! - passphrase only: the UTF-8 passphrase bytes, so plain passphrase
+//!   keyslots are unaffected by keyfile support;
+//! - with keyfiles: `"DVKF1" || u32 len(passphrase) || passphrase ||
+//!   u32 n || SHA-256(keyfile_1) || ... || SHA-256(keyfile_n)`, with the
+//!   keyfile digests sorted so that the order in which keyfiles are given
+//!   does not matter. Lengths are little-endian.
+//!
+//! The result always goes through the keyslot KDF, so keyfiles get the same
+//! brute-force protection as passphrases.
+
+use crate::secret::SecretString;
+use crate::{DeepVaultError, Result};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Domain separator for credentials that include keyfiles
+const KEYFILE_DOMAIN: &[u8] = b"DVKF1";
+/// Size of keyfiles created by [`generate_keyfile`]
+pub const KEYFILE_SIZE: usize = 64;
+
+/// Passphrase and keyfiles presented to unlock a keyslot
+#[derive(Clone, Default)]
+pub struct Credentials {
+    passphrase: Option<SecretString>,
+    keyfile_digests: Vec<[u8; 32]>,
+}
+
+impl Credentials {
+    /// Create empty credentials
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Credentials made of a passphrase only
+    pub fn passphrase(passphrase: &SecretString) -> Self {
+        Self::new().with_passphrase(passphrase)
+    }
+
+    /// Set the passphrase
+    pub fn with_passphrase(mut self, passphrase: &SecretString) -> Self {
+        self.passphrase = Some(passphrase.clone());
+        self
+    }
+
+    /// Add a keyfile read from disk
+    pub fn with_keyfile(self, path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path).map_err(|e| {
+            DeepVaultError::InvalidInput(format!("Cannot read keyfile {}: {}", path.display(), e))
+        })?;
+        self.with_keyfile_reader(file)
+    }
+
+    /// Add a keyfile from any reader, such as stdin
+    pub fn with_keyfile_reader<R: Read>(mut self, mut reader: R) -> Result<Self> {
+        let mut hasher = Sha256::new();
+        let mut buffer = Zeroizing::new([0u8; 8192]);
+        let mut total = 0usize;
+        loop {
+            let n = reader.read(buffer.as_mut())?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+            total += n;
+        }
+
+        if total == 0 {
+            return Err(DeepVaultError::InvalidInput("Keyfile is empty".to_string()));
+        }
+
+        self.keyfile_digests.push(hasher.finalize().into());
+        Ok(self)
+    }
+
+    /// Add a keyfile whose contents are already in memory
+    pub fn with_keyfile_bytes(self, contents: &[u8]) -> Result<Self> {
+        self.with_keyfile_reader(contents)
+    }
+
+    /// Number of keyfiles
+    pub fn keyfile_count(&self) -> usize {
+        self.keyfile_digests.len()
+    }
+
+    /// Whether a passphrase is set
+    pub fn has_passphrase(&self) -> bool {
+        self.passphrase.is_some()
+    }
+
+    /// Combine the credentials into the KDF input
+    pub(crate) fn kdf_input(&self) -> Result<Zeroizing<Vec<u8>>> {
+        let passphrase = self.passphrase.as_ref().map_or("", SecretString::expose_secret);
+        if self.keyfile_digests.is_empty() {
+            if self.passphrase.is_none() {
+                return Err(DeepVaultError::InvalidInput(
+                    "A passphrase or at least one keyfile is required".to_string(),
+                ));
+            }
+            return Ok(Zeroizing::new(passphrase.as_bytes().to_vec()));
+        }
+
+        let mut digests = Zeroizing::new(self.keyfile_digests.clone());
+        digests.sort_unstable();
+
+        let mut input = Zeroizing::new(Vec::with_capacity(KEYFILE_DOMAIN.len() + 8 + passphrase.len() + 32 * digests.len()));
+        input.extend_from_slice(KEYFILE_DOMAIN);
+        input.extend_from_slice(&(passphrase.len() as u32).to_le_bytes());
+        input.extend_from_slice(passphrase.as_bytes());
+        input.extend_from_slice(&(digests.len() as u32).to_le_bytes());
+        for digest in digests.iter() {
+            input.extend_from_slice(digest);
+        }
+        Ok(input)
+    }
+}
+
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        self.keyfile_digests.zeroize();
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("passphrase", &self.passphrase.as_ref().map(|_| "<redacted>"))
+            .field("keyfiles", &self.keyfile_digests.len())
+            .finish()
+    }
+}
+
+/// Create a new random keyfile
+///
+/// Fails if `path` already exists, so an existing keyfile is never
+/// overwritten by mistake.
+pub fn generate_keyfile(path: &Path) -> Result<()> {
+    use rand::RngCore;
+    use std::io::Write;
+
+    let mut contents = Zeroizing::new([0u8; KEYFILE_SIZE]);
+    rand::rngs::OsRng.fill_bytes(contents.as_mut());
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| DeepVaultError::InvalidInput(format!("Cannot create keyfile {}: {}", path.display(), e)))?;
+    file.write_all(contents.as_ref())?;
+    file.sync_all()?;
+    Ok(())
+}
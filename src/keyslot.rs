@@ -0,0 +1,315 @@
+//! Keyslots protecting the vault master key
+//!
+//! The data area of a vault is encrypted with a random master key that never
+//! changes. Each keyslot stores an independent copy of that key, wrapped with
+//! a key encryption key derived from one credential. Adding, revoking or
+//! changing a passphrase therefore only rewrites a keyslot.
+//!
+//! Keyslot layout (little-endian, [`KEYSLOT_SIZE`] bytes):
+//!
+//! | Offset | Size | Field                                   |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 1    | Keyslot kind (0 = empty, 1 = password,  |
+//! |        |      | 2 = recovery key, 3 = Shamir shares,    |
+//! |        |      | 4 = X25519 recipient, 5 = SSH agent)    |
+//! | 1      | 1    | KDF identifier, zero for recipient and  |
+//! |        |      | SSH agent keyslots                      |
+//! | 4      | 12   | KDF parameters (three `u32`)            |
+//! | 16     | 32   | Salt; ephemeral X25519 public key for   |
+//! |        |      | recipients                              |
+//! | 48     | 60   | Wrapped master key (AES-256-GCM)        |
+//! | 108    | 32   | Recipient or SSH ed25519 public key,    |
+//! |        |      | zero otherwise                          |
+//! | 140    | 116  | Reserved, zero                          |
+
+use crate::crypto::{CryptoConfig, CryptoManager, KEY_LEN, NONCE_LEN, SALT_LEN, TAG_LEN};
+use crate::header::{read_u32, write_u32};
+use crate::kdf::KdfParams;
+use crate::keyfile::Credentials;
+use crate::recipient::{Identity, Recipient};
+use crate::secret::SecretKey;
+use crate::ssh_agent::{SshAgent, SshPublicKey};
+use crate::{DeepVaultError, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Size of one on-disk keyslot
+pub const KEYSLOT_SIZE: usize = 256;
+/// Number of keyslots in a vault header
+pub const MAX_KEYSLOTS: usize = 8;
+/// Length of a wrapped master key (nonce, ciphertext and tag)
+pub const WRAPPED_KEY_LEN: usize = NONCE_LEN + KEY_LEN + TAG_LEN;
+
+const OFF_KIND: usize = 0;
+const OFF_KDF_ID: usize = 1;
+const OFF_KDF_PARAMS: usize = 4;
+const OFF_SALT: usize = 16;
+const OFF_WRAPPED_KEY: usize = 48;
+const OFF_PUBLIC_KEY: usize = 108;
+
+const KIND_EMPTY: u8 = 0;
+const KIND_PASSWORD: u8 = 1;
+const KIND_RECOVERY: u8 = 2;
+const KIND_SHAMIR: u8 = 3;
+const KIND_RECIPIENT: u8 = 4;
+const KIND_SSH_AGENT: u8 = 5;
+
+/// Kind of credential protecting a keyslot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyslotKind {
+    /// Passphrase and/or keyfiles run through the keyslot KDF
+    Password,
+    /// Randomly generated recovery key, see [`crate::recovery`]
+    Recovery,
+    /// Secret split into M-of-N shares, see [`crate::shamir`]
+    Shamir,
+    /// Master key wrapped to an X25519 public key, see [`crate::recipient`]
+    Recipient,
+    /// Ed25519 key held in ssh-agent, see [`crate::ssh_agent`]
+    SshAgent,
+}
+
+/// A single keyslot
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyslot {
+    pub kind: KeyslotKind,
+    /// KDF run on the credential, `None` for public-key keyslots
+    pub kdf: Option<KdfParams>,
+    /// Random salt, or the ephemeral public key of a recipient keyslot
+    pub salt: [u8; SALT_LEN],
+    pub recipient: Option<Recipient>,
+    pub ssh_key: Option<SshPublicKey>,
+    wrapped_key: [u8; WRAPPED_KEY_LEN],
+}
+
+/// Public description of a keyslot, without any key material
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyslotInfo {
+    pub index: usize,
+    pub kind: KeyslotKind,
+    pub kdf: Option<KdfParams>,
+    /// Recipient string of a recipient keyslot
+    pub recipient: Option<String>,
+    /// Fingerprint of the key of an SSH agent keyslot
+    pub ssh_key: Option<String>,
+}
+
+impl Keyslot {
+    /// Wrap `master_key` with a key derived from `credentials`
+    ///
+    /// `aad` binds the wrapped key to its vault and slot position.
+    pub fn new_password(
+        master_key: &SecretKey,
+        credentials: &Credentials,
+        kdf: &KdfParams,
+        aad: &[u8],
+    ) -> Result<Self> {
+        Self::new(KeyslotKind::Password, master_key, &credentials.kdf_input()?, kdf, aad)
+    }
+
+    /// Wrap `master_key` with a key derived from a raw secret
+    pub fn new(
+        kind: KeyslotKind,
+        master_key: &SecretKey,
+        secret: &[u8],
+        kdf: &KdfParams,
+        aad: &[u8],
+    ) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&CryptoManager::generate_salt()?);
+
+        let mut slot = Self {
+            kind,
+            kdf: Some(kdf.clone()),
+            salt,
+            recipient: None,
+            ssh_key: None,
+            wrapped_key: [0u8; WRAPPED_KEY_LEN],
+        };
+
+        let manager = slot.key_manager()?;
+        let kek = manager.derive_key_from_bytes(secret)?;
+        let wrapped = manager.encrypt_with_aad(&kek, master_key.expose_secret(), aad)?;
+        slot.wrapped_key.copy_from_slice(&wrapped);
+        Ok(slot)
+    }
+
+    /// Wrap `master_key` to an X25519 recipient
+    pub fn new_recipient(master_key: &SecretKey, recipient: &Recipient, aad: &[u8]) -> Result<Self> {
+        let (ephemeral_public, kek) = recipient.key_agreement()?;
+        let wrapped = Self::wrapping_manager().encrypt_with_aad(&kek, master_key.expose_secret(), aad)?;
+
+        let mut slot = Self {
+            kind: KeyslotKind::Recipient,
+            kdf: None,
+            salt: ephemeral_public,
+            recipient: Some(*recipient),
+            ssh_key: None,
+            wrapped_key: [0u8; WRAPPED_KEY_LEN],
+        };
+        slot.wrapped_key.copy_from_slice(&wrapped);
+        Ok(slot)
+    }
+
+    /// Wrap `master_key` with a key derived from an agent signature
+    ///
+    /// The challenge is signed twice, so that an agent giving randomized
+    /// signatures is caught now rather than when unlocking.
+    pub fn new_ssh_agent<S: Read + Write>(
+        master_key: &SecretKey,
+        ssh_key: &SshPublicKey,
+        agent: &mut SshAgent<S>,
+        aad: &[u8],
+    ) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&CryptoManager::generate_salt()?);
+        let kek = agent.wrapping_key(ssh_key, &salt, aad)?;
+        if agent.wrapping_key(ssh_key, &salt, aad)? != kek {
+            return Err(DeepVaultError::Crypto(format!(
+                "The SSH agent signs {} non-deterministically, it cannot protect a keyslot",
+                ssh_key.fingerprint()
+            )));
+        }
+        let wrapped = Self::wrapping_manager().encrypt_with_aad(&kek, master_key.expose_secret(), aad)?;
+
+        let mut slot = Self {
+            kind: KeyslotKind::SshAgent,
+            kdf: None,
+            salt,
+            recipient: None,
+            ssh_key: Some(*ssh_key),
+            wrapped_key: [0u8; WRAPPED_KEY_LEN],
+        };
+        slot.wrapped_key.copy_from_slice(&wrapped);
+        Ok(slot)
+    }
+
+    /// Recover the master key from this keyslot
+    pub fn unwrap_key(&self, credentials: &Credentials, aad: &[u8]) -> Result<SecretKey> {
+        self.unwrap_with_secret(&credentials.kdf_input()?, aad)
+    }
+
+    /// Recover the master key with the raw secret this keyslot was made with
+    pub fn unwrap_with_secret(&self, secret: &[u8], aad: &[u8]) -> Result<SecretKey> {
+        let manager = self.key_manager()?;
+        let kek = manager.derive_key_from_bytes(secret)?;
+        manager.decrypt_with_aad(&kek, &self.wrapped_key, aad).map(SecretKey::new)
+    }
+
+    /// Recover the master key from a recipient keyslot with its identity
+    pub fn unwrap_with_identity(&self, identity: &Identity, aad: &[u8]) -> Result<SecretKey> {
+        if self.kind != KeyslotKind::Recipient {
+            return Err(DeepVaultError::InvalidInput("Not a recipient keyslot".to_string()));
+        }
+        let kek = identity.key_agreement(&self.salt)?;
+        Self::wrapping_manager()
+            .decrypt_with_aad(&kek, &self.wrapped_key, aad)
+            .map(SecretKey::new)
+    }
+
+    /// Recover the master key from an SSH agent keyslot
+    pub fn unwrap_with_ssh_agent<S: Read + Write>(&self, agent: &mut SshAgent<S>, aad: &[u8]) -> Result<SecretKey> {
+        let ssh_key = self
+            .ssh_key
+            .as_ref()
+            .ok_or_else(|| DeepVaultError::InvalidInput("Not an SSH agent keyslot".to_string()))?;
+        let kek = agent.wrapping_key(ssh_key, &self.salt, aad)?;
+        Self::wrapping_manager()
+            .decrypt_with_aad(&kek, &self.wrapped_key, aad)
+            .map(SecretKey::new)
+    }
+
+    /// Replace the salt and wrapped key with random data
+    pub(crate) fn scramble(&mut self) {
+        use rand::RngCore;
+        rand::rngs::OsRng.fill_bytes(&mut self.salt);
+        rand::rngs::OsRng.fill_bytes(&mut self.wrapped_key);
+    }
+
+    /// Parse a keyslot, returning `None` for an empty slot
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Option<Self>> {
+        let kind = match bytes[OFF_KIND] {
+            KIND_EMPTY => return Ok(None),
+            KIND_PASSWORD => KeyslotKind::Password,
+            KIND_RECOVERY => KeyslotKind::Recovery,
+            KIND_SHAMIR => KeyslotKind::Shamir,
+            KIND_RECIPIENT => KeyslotKind::Recipient,
+            KIND_SSH_AGENT => KeyslotKind::SshAgent,
+            other => {
+                return Err(DeepVaultError::InvalidHeader(format!("Unknown keyslot kind {}", other)))
+            }
+        };
+
+        let mut public_key = [0u8; 32];
+        public_key.copy_from_slice(&bytes[OFF_PUBLIC_KEY..OFF_PUBLIC_KEY + 32]);
+        let (kdf, recipient, ssh_key) = match kind {
+            KeyslotKind::Recipient => (None, Some(Recipient::from_bytes(public_key)), None),
+            KeyslotKind::SshAgent => (None, None, Some(SshPublicKey::from_bytes(public_key))),
+            _ => {
+                let kdf = KdfParams::decode(
+                    bytes[OFF_KDF_ID],
+                    [
+                        read_u32(bytes, OFF_KDF_PARAMS),
+                        read_u32(bytes, OFF_KDF_PARAMS + 4),
+                        read_u32(bytes, OFF_KDF_PARAMS + 8),
+                    ],
+                )?;
+                (Some(kdf), None, None)
+            }
+        };
+
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&bytes[OFF_SALT..OFF_SALT + SALT_LEN]);
+        let mut wrapped_key = [0u8; WRAPPED_KEY_LEN];
+        wrapped_key.copy_from_slice(&bytes[OFF_WRAPPED_KEY..OFF_WRAPPED_KEY + WRAPPED_KEY_LEN]);
+
+        Ok(Some(Self {
+            kind,
+            kdf,
+            salt,
+            recipient,
+            ssh_key,
+            wrapped_key,
+        }))
+    }
+
+    /// Serialize the keyslot into a [`KEYSLOT_SIZE`] buffer
+    pub(crate) fn write_bytes(&self, bytes: &mut [u8]) {
+        bytes[OFF_KIND] = match self.kind {
+            KeyslotKind::Password => KIND_PASSWORD,
+            KeyslotKind::Recovery => KIND_RECOVERY,
+            KeyslotKind::Shamir => KIND_SHAMIR,
+            KeyslotKind::Recipient => KIND_RECIPIENT,
+            KeyslotKind::SshAgent => KIND_SSH_AGENT,
+        };
+        if let Some(kdf) = &self.kdf {
+            let (kdf_id, kdf_words) = kdf.encode();
+            bytes[OFF_KDF_ID] = kdf_id;
+            for (i, word) in kdf_words.iter().enumerate() {
+                write_u32(bytes, OFF_KDF_PARAMS + 4 * i, *word);
+            }
+        }
+        bytes[OFF_SALT..OFF_SALT + SALT_LEN].copy_from_slice(&self.salt);
+        bytes[OFF_WRAPPED_KEY..OFF_WRAPPED_KEY + WRAPPED_KEY_LEN].copy_from_slice(&self.wrapped_key);
+        if let Some(recipient) = &self.recipient {
+            bytes[OFF_PUBLIC_KEY..OFF_PUBLIC_KEY + 32].copy_from_slice(recipient.as_bytes());
+        }
+        if let Some(ssh_key) = &self.ssh_key {
+            bytes[OFF_PUBLIC_KEY..OFF_PUBLIC_KEY + 32].copy_from_slice(ssh_key.as_bytes());
+        }
+    }
+
+    /// Crypto manager configured with this keyslot's KDF parameters
+    fn key_manager(&self) -> Result<CryptoManager> {
+        let kdf = self
+            .kdf
+            .as_ref()
+            .ok_or_else(|| DeepVaultError::InvalidInput("Keyslot is not protected by a KDF".to_string()))?;
+        Ok(CryptoManager::with_config(CryptoConfig::for_kdf(kdf, &self.salt)))
+    }
+
+    /// Crypto manager used only to wrap keys, for keyslots without a KDF
+    fn wrapping_manager() -> CryptoManager {
+        CryptoManager::with_config(CryptoConfig::for_kdf(&KdfParams::default(), &[]))
+    }
+}
@@ -4,22 +4,65 @@
 //! Windows (VeraCrypt), and macOS (APFS/cryptsetup).
 
 pub mod device;
+pub mod attempts;
+pub mod block;
+pub mod container;
 pub mod crypto;
+#[cfg(target_os = "linux")]
+pub mod fuse;
+pub mod kdf;
+pub mod header;
+pub mod hidden;
+pub mod integrity;
+pub mod backup;
+pub mod keyslot;
+pub mod keyfile;
+pub mod recipient;
+pub mod recovery;
+pub mod resize;
+pub mod secret;
+pub mod sector;
+pub mod shamir;
+pub mod sparse;
+pub mod ssh_agent;
+pub mod stream;
+pub mod strength;
 pub mod partition;
 pub mod mount;
 pub mod wipe;
 pub mod config;
 pub mod error;
 pub mod utils;
+pub mod vaultfs;
 
 pub use error::{DeepVaultError, Result};
 
 /// Re-export commonly used types
 pub use device::{UsbDevice, DeviceManager};
+pub use attempts::{AttemptCounter, AttemptPolicy};
+pub use container::{Container, ContainerOptions};
 pub use crypto::{CryptoConfig, KeyDerivation};
+pub use kdf::{Calibration, CalibrationTarget, KdfAlgorithm, KdfParams};
+pub use header::VaultHeader;
+pub use hidden::ProtectedVolume;
+pub use integrity::AuthenticatedDevice;
+pub use backup::{backup_header, restore_header};
+pub use keyslot::{KeyslotInfo, KeyslotKind};
+pub use keyfile::Credentials;
+pub use recipient::{Identity, Recipient};
+pub use recovery::{RecoveryKey, RecoverySheet};
+pub use secret::{SecretKey, SecretString};
+pub use block::{BlockDevice, FileDevice};
+pub use sector::{EncryptedVolume, SectorCipher};
+pub use shamir::Share;
+pub use sparse::SparseVolume;
+pub use ssh_agent::{SshAgent, SshPublicKey};
+pub use stream::{StreamReader, StreamWriter};
+pub use strength::PasswordStrength;
 pub use partition::{PartitionManager, PartitionType};
-pub use mount::{MountManager, MountStatus};
+pub use mount::{MountManager, MountStatus, ProtectHidden};
 pub use config::{AppConfig, SecurityConfig};
+pub use vaultfs::VaultFs;
 
 /// Version information
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -1,8 +1,19 @@
 //! Mount management for DeepVault
 
+use crate::block::BlockDevice;
+use crate::config::SecurityConfig;
+use crate::container::Container;
+#[cfg(target_os = "linux")]
+use crate::fuse::{FuseMount, FuseOptions, ImageFs};
+use crate::header::VaultHeader;
+use crate::hidden::{self, ProtectedVolume};
+use crate::kdf::KdfParams;
+use crate::keyfile::Credentials;
+use crate::secret::{SecretKey, SecretString};
+use crate::sector::{self, EncryptedVolume};
 use crate::{DeepVaultError, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Mount status
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -12,6 +23,14 @@ pub enum MountStatus {
     Error(String),
 }
 
+/// Hidden volume to fence off while its outer volume is mounted
+pub struct ProtectHidden<'a> {
+    /// Passphrase and/or keyfiles of the hidden volume
+    pub credentials: &'a Credentials,
+    /// Key derivation the hidden volume was created with
+    pub kdf: &'a KdfParams,
+}
+
 /// Mount manager for encrypted volumes
 pub struct MountManager {
     device_path: PathBuf,
@@ -27,7 +46,7 @@ impl MountManager {
     pub async fn mount_volume(
         &self,
         volume_name: &str,
-        password: &str,
+        password: &SecretString,
     ) -> Result<MountStatus> {
         log::info!("Mounting volume: {}", volume_name);
         
@@ -45,6 +64,117 @@ impl MountManager {
         Err(DeepVaultError::NotImplemented("Mounting not supported on this platform".to_string()))
     }
 
+    /// Unlock the DeepVault header of the device, counting failed attempts
+    ///
+    /// See [`VaultHeader::open`] for the limits applied.
+    pub fn unlock_vault(&self, credentials: &Credentials, security: &SecurityConfig) -> Result<(VaultHeader, SecretKey)> {
+        let mut storage = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.device_path)?;
+        VaultHeader::open(&mut storage, credentials, security)
+    }
+
+    /// Unlock the container or partition and open its data area
+    ///
+    /// With `protect`, writes to the hidden volume inside are refused, see
+    /// [`ProtectedVolume`].
+    pub fn open_volume(
+        &self,
+        credentials: &Credentials,
+        security: &SecurityConfig,
+        read_only: bool,
+        protect: Option<&ProtectHidden>,
+    ) -> Result<Box<dyn BlockDevice>> {
+        let (outer, mut device): (VaultHeader, Box<dyn BlockDevice>) = if std::fs::metadata(&self.device_path)?.is_file() {
+            let container = Container::open(&self.device_path, credentials, security)?;
+            (container.header().clone(), Box::new(container))
+        } else {
+            // Unlocking records failed attempts in the header, so it needs
+            // write access even for a read-only mount
+            let mut storage = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&self.device_path)?;
+            let (header, master_key) = VaultHeader::open(&mut storage, credentials, security)?;
+            if read_only {
+                storage = std::fs::OpenOptions::new().read(true).open(&self.device_path)?;
+            }
+            let volume = sector::open_volume(storage, &header, &master_key)?;
+            (header, volume)
+        };
+        if let Some(protect) = protect {
+            let mut storage = std::fs::File::open(&self.device_path)?;
+            let (hidden, _) = hidden::open(&mut storage, protect.credentials, protect.kdf)?;
+            let sectors = hidden::hidden_sectors(&outer, &hidden)?;
+            log::info!("Protecting hidden sectors {}..{}", sectors.start, sectors.end);
+            device = Box::new(ProtectedVolume::new(device, sectors));
+        }
+        Ok(device)
+    }
+
+    /// Open the data area of the hidden volume nested in the container or
+    /// partition
+    ///
+    /// `kdf` is the key derivation the hidden volume was created with, see
+    /// [`hidden::open`]. Failed attempts are not counted, as that would
+    /// show that the hidden header slot is in use.
+    pub fn open_hidden_volume(
+        &self,
+        credentials: &Credentials,
+        kdf: &KdfParams,
+        read_only: bool,
+    ) -> Result<Box<dyn BlockDevice>> {
+        let mut storage = std::fs::OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .open(&self.device_path)?;
+        let (header, master_key) = hidden::open(&mut storage, credentials, kdf)?;
+        Ok(Box::new(EncryptedVolume::open(storage, &header, &master_key)?))
+    }
+
+    /// Decrypt the container or partition in userspace and expose it
+    /// through FUSE on `mountpoint`, without root
+    ///
+    /// Blocks until the mount point is unmounted, see [`crate::fuse`] and
+    /// [`MountManager::open_volume`].
+    #[cfg(target_os = "linux")]
+    pub fn mount_fuse(
+        &self,
+        credentials: &Credentials,
+        security: &SecurityConfig,
+        mountpoint: &Path,
+        options: &FuseOptions,
+        protect: Option<&ProtectHidden>,
+    ) -> Result<()> {
+        let device = self.open_volume(credentials, security, options.read_only, protect)?;
+        self.serve_fuse(device, mountpoint, options)
+    }
+
+    /// Decrypt the hidden volume nested in the container or partition and
+    /// expose it through FUSE on `mountpoint`
+    ///
+    /// Blocks until the mount point is unmounted, see
+    /// [`MountManager::open_hidden_volume`].
+    #[cfg(target_os = "linux")]
+    pub fn mount_hidden_fuse(
+        &self,
+        credentials: &Credentials,
+        kdf: &KdfParams,
+        mountpoint: &Path,
+        options: &FuseOptions,
+    ) -> Result<()> {
+        let device = self.open_hidden_volume(credentials, kdf, options.read_only)?;
+        self.serve_fuse(device, mountpoint, options)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn serve_fuse(&self, device: Box<dyn BlockDevice>, mountpoint: &Path, options: &FuseOptions) -> Result<()> {
+        let mut session = FuseMount::mount(mountpoint, options)?;
+        log::info!("Serving {} on {}", self.device_path.display(), session.mountpoint().display());
+        session.serve(&mut ImageFs::new(device, options))
+    }
+
     /// Unmount a volume
     pub async fn unmount_volume(&self, volume_name: &str) -> Result<MountStatus> {
         log::info!("Unmounting volume: {}", volume_name);
@@ -64,25 +194,38 @@ impl MountManager {
     }
 
     /// Get mount status of a volume
-    pub async fn get_mount_status(&self, volume_name: &str) -> Result<MountStatus> {
+    pub async fn get_mount_status(&self, _volume_name: &str) -> Result<MountStatus> {
         // Check if volume is currently mounted
         // This would query the system for mount information
         Ok(MountStatus::Unmounted)
     }
 
     #[cfg(target_os = "linux")]
-    async fn mount_linux(&self, volume_name: &str, password: &str) -> Result<MountStatus> {
+    async fn mount_linux(&self, volume_name: &str, password: &SecretString) -> Result<MountStatus> {
+        use std::io::Write;
         use std::process::Command;
         
-        // Use cryptsetup to open LUKS volume
-        let output = Command::new("cryptsetup")
+        // Use cryptsetup to open LUKS volume, passing the key on stdin
+        let mut child = Command::new("cryptsetup")
             .arg("open")
             .arg(&self.device_path)
             .arg(volume_name)
             .arg("--key-file")
             .arg("-")
             .stdin(std::process::Stdio::piped())
-            .output()
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| DeepVaultError::Mount(format!("Failed to open LUKS volume: {}", e)))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(password.expose_secret().as_bytes())
+                .map_err(|e| DeepVaultError::Mount(format!("Failed to pass key to cryptsetup: {}", e)))?;
+        }
+
+        let output = child
+            .wait_with_output()
             .map_err(|e| DeepVaultError::Mount(format!("Failed to open LUKS volume: {}", e)))?;
 
         if !output.status.success() {
@@ -114,7 +257,7 @@ impl MountManager {
     }
 
     #[cfg(target_os = "windows")]
-    async fn mount_windows(&self, volume_name: &str, password: &str) -> Result<MountStatus> {
+    async fn mount_windows(&self, volume_name: &str, password: &SecretString) -> Result<MountStatus> {
         use std::process::Command;
         
         // Use VeraCrypt CLI to mount volume
@@ -124,7 +267,7 @@ impl MountManager {
             .arg("/letter")
             .arg("Z")
             .arg("/password")
-            .arg(password)
+            .arg(password.expose_secret())
             .arg("/quit")
             .output()
             .map_err(|e| DeepVaultError::Mount(format!("Failed to mount VeraCrypt volume: {}", e)))?;
@@ -140,7 +283,7 @@ impl MountManager {
     }
 
     #[cfg(target_os = "macos")]
-    async fn mount_macos(&self, volume_name: &str, password: &str) -> Result<MountStatus> {
+    async fn mount_macos(&self, volume_name: &str, password: &SecretString) -> Result<MountStatus> {
         use std::process::Command;
         
         // Use diskutil to mount encrypted volume
@@ -149,7 +292,7 @@ impl MountManager {
             .arg("unlockVolume")
             .arg(volume_name)
             .arg("-passphrase")
-            .arg(password)
+            .arg(password.expose_secret())
             .output()
             .map_err(|e| DeepVaultError::Mount(format!("Failed to unlock volume: {}", e)))?;
 
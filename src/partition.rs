@@ -2,7 +2,7 @@
 
 use crate::{DeepVaultError, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Partition types supported by DeepVault
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -165,7 +165,7 @@ impl PartitionManager {
     }
 
     /// Get partition information
-    pub async fn get_partition_info(&self, partition_path: &PathBuf) -> Result<PartitionInfo> {
+    pub async fn get_partition_info(&self, partition_path: &Path) -> Result<PartitionInfo> {
         // This would query the system for partition information
         // For now, return a placeholder
         Ok(PartitionInfo {
@@ -174,7 +174,7 @@ impl PartitionManager {
                 .unwrap_or_default()
                 .to_string_lossy()
                 .to_string(),
-            path: partition_path.clone(),
+            path: partition_path.to_path_buf(),
             size: 0, // Would be queried from system
             filesystem: None,
             is_mounted: false,
@@ -0,0 +1,262 @@
+//! Public-key recipients for administrator access
+//!
+//! An administrator keeps an X25519 identity (private key) and hands out
+//! the matching recipient (public key). Anyone holding the recipient can
+//! enroll a keyslot that wraps the vault master key to it, without being
+//! able to open that keyslot themselves; only the identity can.
+//!
+//! Wrapping follows age: an ephemeral X25519 key agreement with the
+//! recipient, then HKDF-SHA256 over the shared secret, salted with both
+//! public keys, gives the key encryption key.
+//!
+//! Text forms carry a 4-byte checksum, the start of
+//! `SHA-256(domain || key)`:
+//! - recipient: `dvr1` followed by lowercase base32;
+//! - identity: `DVI1-` followed by uppercase base32. Identity files hold
+//!   the identity on its own line, with `#` comment lines around it.
+
+use crate::crypto::KEY_LEN;
+use crate::secret::{SecretKey, SecretString};
+use crate::{DeepVaultError, Result};
+use data_encoding::BASE32_NOPAD;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// Prefix of a recipient string
+pub const RECIPIENT_PREFIX: &str = "dvr1";
+/// Prefix of an identity string
+pub const IDENTITY_PREFIX: &str = "DVI1-";
+
+const CHECKSUM_LEN: usize = 4;
+const RECIPIENT_DOMAIN: &[u8] = b"deepvault recipient v1";
+const IDENTITY_DOMAIN: &[u8] = b"deepvault identity v1";
+/// HKDF context for key encryption keys
+const WRAP_KEY_INFO: &[u8] = b"deepvault x25519 v1";
+
+/// X25519 public key that keyslots can be wrapped to
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Recipient {
+    public: PublicKey,
+}
+
+impl Recipient {
+    /// Recipient from raw public key bytes
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self {
+            public: PublicKey::from(bytes),
+        }
+    }
+
+    /// Raw public key bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        self.public.as_bytes()
+    }
+
+    /// Parse a `dvr1...` recipient string
+    pub fn parse(text: &str) -> Result<Self> {
+        let encoded = text
+            .trim()
+            .strip_prefix(RECIPIENT_PREFIX)
+            .ok_or_else(|| DeepVaultError::InvalidInput("Not a DeepVault recipient".to_string()))?;
+        let key = decode_checked(&encoded.to_ascii_uppercase(), RECIPIENT_DOMAIN)
+            .map_err(|_| DeepVaultError::InvalidInput("Invalid recipient, check for typing mistakes".to_string()))?;
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&key);
+        Ok(Self::from_bytes(bytes))
+    }
+
+    /// Read the first recipient found in a file
+    ///
+    /// Identity files are refused, so a private key is never mistaken for
+    /// a public one and copied around.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = Zeroizing::new(std::fs::read_to_string(path)?);
+        let line = key_lines(&text)
+            .next()
+            .ok_or_else(|| DeepVaultError::InvalidInput(format!("No recipient found in {}", path.display())))?;
+        if line.starts_with(IDENTITY_PREFIX) {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "{} holds a private identity, not a recipient",
+                path.display()
+            )));
+        }
+        Self::parse(line)
+    }
+
+    /// Agree on a fresh key encryption key with the holder of this recipient
+    ///
+    /// Returns the ephemeral public key to store alongside the wrapped key,
+    /// and the key encryption key.
+    pub(crate) fn key_agreement(&self) -> Result<([u8; 32], SecretKey)> {
+        let ephemeral = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&self.public);
+        if !shared.was_contributory() {
+            return Err(DeepVaultError::InvalidInput("Invalid recipient public key".to_string()));
+        }
+        let kek = wrapping_key(shared.as_bytes(), ephemeral_public.as_bytes(), self.as_bytes())?;
+        Ok((ephemeral_public.to_bytes(), kek))
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded = encode_checked(self.as_bytes(), RECIPIENT_DOMAIN);
+        write!(f, "{}{}", RECIPIENT_PREFIX, encoded.to_ascii_lowercase())
+    }
+}
+
+impl fmt::Debug for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Recipient({})", self)
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = DeepVaultError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+/// X25519 private key opening the keyslots wrapped to its recipient
+#[derive(Clone)]
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    /// Generate a new random identity
+    pub fn generate() -> Self {
+        Self {
+            secret: StaticSecret::random_from_rng(rand::rngs::OsRng),
+        }
+    }
+
+    /// The public recipient matching this identity
+    pub fn to_recipient(&self) -> Recipient {
+        Recipient {
+            public: PublicKey::from(&self.secret),
+        }
+    }
+
+    /// Parse a `DVI1-...` identity string
+    pub fn parse(text: &str) -> Result<Self> {
+        let encoded = text
+            .trim()
+            .strip_prefix(IDENTITY_PREFIX)
+            .ok_or_else(|| DeepVaultError::InvalidInput("Not a DeepVault identity".to_string()))?;
+        let key = decode_checked(encoded, IDENTITY_DOMAIN)
+            .map_err(|_| DeepVaultError::InvalidInput("Invalid identity, check for typing mistakes".to_string()))?;
+        let mut bytes = Zeroizing::new([0u8; 32]);
+        bytes.copy_from_slice(&key);
+        Ok(Self {
+            secret: StaticSecret::from(*bytes),
+        })
+    }
+
+    /// The identity in its text form
+    pub fn to_text(&self) -> SecretString {
+        let encoded = Zeroizing::new(encode_checked(self.secret.as_bytes(), IDENTITY_DOMAIN));
+        SecretString::new(format!("{}{}", IDENTITY_PREFIX, encoded.as_str()))
+    }
+
+    /// Write the identity to a new file, readable by its owner only
+    pub fn save(&self, path: &Path) -> Result<()> {
+        use std::io::Write;
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(path)
+            .map_err(|e| DeepVaultError::InvalidInput(format!("Cannot create identity {}: {}", path.display(), e)))?;
+        writeln!(file, "# DeepVault identity")?;
+        writeln!(file, "# created: {}", chrono::Utc::now().to_rfc3339())?;
+        writeln!(file, "# recipient: {}", self.to_recipient())?;
+        writeln!(file, "{}", self.to_text().expose_secret())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Read an identity file written by [`Identity::save`]
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = Zeroizing::new(std::fs::read_to_string(path)?);
+        let line = key_lines(&text)
+            .next()
+            .ok_or_else(|| DeepVaultError::InvalidInput(format!("No identity found in {}", path.display())))?;
+        Self::parse(line)
+    }
+
+    /// Recover the key encryption key agreed by [`Recipient::key_agreement`]
+    pub(crate) fn key_agreement(&self, ephemeral_public: &[u8; 32]) -> Result<SecretKey> {
+        let ephemeral_public = PublicKey::from(*ephemeral_public);
+        let shared = self.secret.diffie_hellman(&ephemeral_public);
+        if !shared.was_contributory() {
+            return Err(DeepVaultError::Crypto("Invalid ephemeral public key".to_string()));
+        }
+        wrapping_key(shared.as_bytes(), ephemeral_public.as_bytes(), self.to_recipient().as_bytes())
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Identity(<redacted>, {})", self.to_recipient())
+    }
+}
+
+/// HKDF-SHA256 from the shared secret, salted with both public keys
+fn wrapping_key(shared: &[u8; 32], ephemeral_public: &[u8; 32], recipient: &[u8; 32]) -> Result<SecretKey> {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral_public);
+    salt[32..].copy_from_slice(recipient);
+    let hkdf = hkdf::Hkdf::<Sha256>::new(Some(&salt), shared);
+    let mut kek = SecretKey::new(vec![0u8; KEY_LEN]);
+    hkdf.expand(WRAP_KEY_INFO, kek.expose_secret_mut())
+        .map_err(|e| DeepVaultError::Crypto(format!("Key derivation failed: {}", e)))?;
+    Ok(kek)
+}
+
+/// Non-empty lines of a key file that are not comments
+fn key_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn checksum(key: &[u8], domain: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let digest = Sha256::new().chain_update(domain).chain_update(key).finalize();
+    let mut checksum = [0u8; CHECKSUM_LEN];
+    checksum.copy_from_slice(&digest[..CHECKSUM_LEN]);
+    checksum
+}
+
+/// Base32 of `key` followed by its checksum
+fn encode_checked(key: &[u8], domain: &[u8]) -> String {
+    let mut bytes = Zeroizing::new(key.to_vec());
+    bytes.extend_from_slice(&checksum(key, domain));
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// Decode uppercase base32 and verify its checksum, returning the key
+fn decode_checked(encoded: &str, domain: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let mut bytes = Zeroizing::new(
+        BASE32_NOPAD
+            .decode(encoded.as_bytes())
+            .map_err(|_| DeepVaultError::InvalidInput("Invalid base32".to_string()))?,
+    );
+    if bytes.len() != 32 + CHECKSUM_LEN {
+        return Err(DeepVaultError::InvalidInput("Invalid key length".to_string()));
+    }
+    if bytes[32..] != checksum(&bytes[..32], domain) {
+        return Err(DeepVaultError::InvalidInput("Checksum mismatch".to_string()));
+    }
+    bytes.truncate(32);
+    Ok(bytes)
+}
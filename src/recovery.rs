@@ -0,0 +1,224 @@
+//! Recovery keys for emergency access
+//!
+//! A recovery key is 200 random bits enrolled in its own keyslot, to be
+//! used when every passphrase is lost. It is written as eight groups of six
+//! base32 characters (RFC 4648 alphabet) encoding the 25 key bytes
+//! followed by a 5-byte checksum, the start of
+//! `SHA-256("deepvault recovery v1" || key)`. The checksum catches typing
+//! mistakes before the slow KDF runs.
+
+use crate::header::VaultHeader;
+use crate::secret::{SecretKey, SecretString};
+use crate::{DeepVaultError, Result};
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+/// Length of the random part of a recovery key
+pub const RECOVERY_KEY_LEN: usize = 25;
+/// Length of the checksum appended to the key
+const CHECKSUM_LEN: usize = 5;
+/// Characters per printed group
+const GROUP_LEN: usize = 6;
+/// Domain separator for the checksum
+const CHECKSUM_DOMAIN: &[u8] = b"deepvault recovery v1";
+/// Buffer size for rendered sheets, larger than any sheet
+const SHEET_CAPACITY: usize = 4096;
+
+/// A recovery key
+#[derive(Clone, PartialEq, Eq)]
+pub struct RecoveryKey {
+    key: SecretKey,
+}
+
+impl RecoveryKey {
+    /// Generate a new random recovery key
+    pub fn generate() -> Self {
+        use rand::RngCore;
+        let mut key = SecretKey::new(vec![0u8; RECOVERY_KEY_LEN]);
+        rand::rngs::OsRng.fill_bytes(key.expose_secret_mut());
+        Self { key }
+    }
+
+    /// Parse a recovery key typed by the user
+    ///
+    /// Case, spaces and dashes are ignored, and the digits 0, 1 and 8 are
+    /// read as the letters O, I and B they are easily mistaken for.
+    pub fn parse(text: &str) -> Result<Self> {
+        let normalized: Zeroizing<String> = Zeroizing::new(
+            text.chars()
+                .filter(|c| !c.is_whitespace() && *c != '-')
+                .map(|c| match c.to_ascii_uppercase() {
+                    '0' => 'O',
+                    '1' => 'I',
+                    '8' => 'B',
+                    other => other,
+                })
+                .collect(),
+        );
+        let decoded = Zeroizing::new(
+            BASE32_NOPAD
+                .decode(normalized.as_bytes())
+                .map_err(|_| DeepVaultError::InvalidInput("Invalid recovery key".to_string()))?,
+        );
+        if decoded.len() != RECOVERY_KEY_LEN + CHECKSUM_LEN {
+            return Err(DeepVaultError::InvalidInput("Invalid recovery key length".to_string()));
+        }
+
+        let (key, checksum) = decoded.split_at(RECOVERY_KEY_LEN);
+        if checksum != Self::checksum(key) {
+            return Err(DeepVaultError::InvalidInput(
+                "Recovery key checksum mismatch, check for typing mistakes".to_string(),
+            ));
+        }
+        Ok(Self {
+            key: SecretKey::from_slice(key),
+        })
+    }
+
+    /// The key in its printed form, in dash-separated groups
+    pub fn to_formatted(&self) -> SecretString {
+        let mut bytes = Zeroizing::new(self.key.expose_secret().to_vec());
+        bytes.extend_from_slice(&Self::checksum(self.key.expose_secret()));
+        let encoded = Zeroizing::new(BASE32_NOPAD.encode(&bytes));
+
+        let groups: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(GROUP_LEN)
+            .map(|group| std::str::from_utf8(group).expect("base32 is ASCII"))
+            .collect();
+        SecretString::new(groups.join("-"))
+    }
+
+    /// Raw key bytes, used as the keyslot KDF input
+    pub(crate) fn secret(&self) -> &[u8] {
+        self.key.expose_secret()
+    }
+
+    fn checksum(key: &[u8]) -> [u8; CHECKSUM_LEN] {
+        let digest = Sha256::new().chain_update(CHECKSUM_DOMAIN).chain_update(key).finalize();
+        let mut checksum = [0u8; CHECKSUM_LEN];
+        checksum.copy_from_slice(&digest[..CHECKSUM_LEN]);
+        checksum
+    }
+}
+
+impl FromStr for RecoveryKey {
+    type Err = DeepVaultError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Debug for RecoveryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RecoveryKey(<redacted>)")
+    }
+}
+
+/// Printable sheet holding a recovery key
+pub struct RecoverySheet {
+    pub uuid: Uuid,
+    pub keyslot: usize,
+    pub created_at: DateTime<Utc>,
+    key: SecretString,
+}
+
+impl RecoverySheet {
+    /// Prepare a sheet for the recovery key enrolled in `keyslot`
+    pub fn new(header: &VaultHeader, keyslot: usize, recovery_key: &RecoveryKey) -> Self {
+        Self {
+            uuid: header.uuid,
+            keyslot,
+            created_at: Utc::now(),
+            key: recovery_key.to_formatted(),
+        }
+    }
+
+    /// Render the sheet as plain text
+    pub fn to_text(&self) -> SecretString {
+        // Preallocated so that no partial copy of the key is left behind
+        let mut text = String::with_capacity(SHEET_CAPACITY);
+        let _ = writeln!(text, "DeepVault - Feuille de récupération");
+        let _ = writeln!(text, "===================================\n");
+        let _ = writeln!(text, "Volume: {}", self.uuid);
+        let _ = writeln!(text, "Keyslot: {}", self.keyslot);
+        let _ = writeln!(text, "Créée le: {}\n", self.created_at.format("%Y-%m-%d %H:%M UTC"));
+        let _ = writeln!(text, "Clé de récupération:\n");
+        for line in self.key_lines().iter() {
+            let _ = writeln!(text, "    {}", line);
+        }
+        text.push('\n');
+        for line in NOTICE {
+            let _ = writeln!(text, "{}", line);
+        }
+        SecretString::new(text)
+    }
+
+    /// Render the sheet as an A4 SVG document
+    pub fn to_svg(&self) -> SecretString {
+        let mut svg = String::with_capacity(SHEET_CAPACITY);
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="210mm" height="297mm" viewBox="0 0 210 297">"#
+        );
+        let _ = writeln!(svg, r#"<rect width="210" height="297" fill="white"/>"#);
+        let _ = writeln!(
+            svg,
+            r#"<text x="20" y="30" font-family="sans-serif" font-size="9" font-weight="bold">DeepVault - Feuille de récupération</text>"#
+        );
+        let details = [
+            format!("Volume: {}", self.uuid),
+            format!("Keyslot: {}", self.keyslot),
+            format!("Créée le: {}", self.created_at.format("%Y-%m-%d %H:%M UTC")),
+        ];
+        for (i, line) in details.iter().enumerate() {
+            let _ = writeln!(
+                svg,
+                r#"<text x="20" y="{}" font-family="sans-serif" font-size="4.5">{}</text>"#,
+                45 + 7 * i,
+                line
+            );
+        }
+        let _ = writeln!(
+            svg,
+            r#"<rect x="15" y="75" width="180" height="36" fill="none" stroke="black" stroke-width="0.5"/>"#
+        );
+        for (i, line) in self.key_lines().iter().enumerate() {
+            let _ = writeln!(
+                svg,
+                r#"<text x="105" y="{}" text-anchor="middle" font-family="monospace" font-size="7">{}</text>"#,
+                90 + 12 * i,
+                line
+            );
+        }
+        for (i, line) in NOTICE.iter().enumerate() {
+            let _ = writeln!(
+                svg,
+                r#"<text x="20" y="{}" font-family="sans-serif" font-size="4">{}</text>"#,
+                125 + 7 * i,
+                line
+            );
+        }
+        let _ = writeln!(svg, "</svg>");
+        SecretString::new(svg)
+    }
+
+    /// The key split over two lines of four groups
+    fn key_lines(&self) -> Zeroizing<Vec<String>> {
+        let groups: Vec<&str> = self.key.expose_secret().split('-').collect();
+        Zeroizing::new(groups.chunks(4).map(|line| line.join("-")).collect())
+    }
+}
+
+/// Instructions printed under the key
+const NOTICE: [&str; 3] = [
+    "Cette clé ouvre le volume sans phrase de passe.",
+    "Conservez cette feuille dans un coffre, ne la photographiez pas",
+    "et ne la stockez jamais sur le volume lui-même.",
+];
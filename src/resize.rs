@@ -0,0 +1,165 @@
+//! Growing and shrinking volumes
+//!
+//! Resizing changes the size of the data area recorded in the header; the
+//! data area always keeps its offset, so existing sectors stay where they
+//! are. The file system inside the volume is not touched: after growing,
+//! it must be extended with its own tools, and before shrinking, it must
+//! already have been reduced to fit.
+//!
+//! To avoid cutting off data, a volume is only shrunk when the file
+//! system in it is recognized (ext2/3/4, FAT, exFAT or NTFS) and its
+//! recorded size fits in the new data area.
+//!
+//! Sparse volumes only store their allocated blocks: their storage does
+//! not need to grow, but they cannot grow beyond the capacity of their
+//! allocation map, see [`crate::sparse`].
+//!
+//! A hidden volume sits at the end of the outer data area and cannot be
+//! detected without its passphrase: shrinking the outer volume destroys
+//! it, and growing moves the end of the outer volume away from it.
+
+use crate::block::{BlockDevice, FileDevice, Storage, SECTOR_SIZE};
+use crate::header::VaultHeader;
+use crate::integrity::{self, AuthenticatedDevice};
+use crate::secret::SecretKey;
+use crate::sector;
+use crate::sparse::SparseVolume;
+use crate::{DeepVaultError, Result};
+use std::io::SeekFrom;
+
+/// Bytes read to recognize a file system
+const PROBE_LEN: usize = 4096;
+
+/// Offset of the ext2/3/4 superblock
+const EXT_SUPERBLOCK: usize = 1024;
+/// ext2/3/4 superblock magic
+const EXT_MAGIC: u16 = 0xEF53;
+/// ext4 incompatible feature: 64-bit block numbers
+const EXT_INCOMPAT_64BIT: u32 = 0x80;
+
+/// Resize the data area of the volume in `storage` to `new_size` bytes
+///
+/// `storage` must already be large enough for the new data area, unless
+/// the volume is sparse. In integrity mode, the new sectors are tagged
+/// before the header is updated. Shrinking is refused unless the file
+/// system fits, which `force` skips.
+pub fn resize<S: Storage>(
+    storage: &mut S,
+    header: &mut VaultHeader,
+    master_key: &SecretKey,
+    new_size: u64,
+    force: bool,
+) -> Result<()> {
+    header.verify_mac(master_key)?;
+    if new_size == 0 || !new_size.is_multiple_of(SECTOR_SIZE as u64) {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "New size {} is not a positive number of sectors",
+            new_size
+        )));
+    }
+    let new_end = header.data_offset.checked_add(new_size).ok_or_else(|| {
+        DeepVaultError::InvalidInput(format!("New size {} is too large", new_size))
+    })?;
+    let capacity = storage.seek(SeekFrom::End(0))?;
+    if !header.sparse() && new_end > capacity {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "The storage holds {} bytes, too few for a {}-byte data area",
+            capacity, new_size
+        )));
+    }
+
+    if new_size < header.data_size && !force {
+        check_fits(storage, header, master_key, new_size)?;
+    }
+    if header.sparse() {
+        SparseVolume::open(&mut *storage, header, master_key)?.set_size(new_size)?;
+    } else if new_size > header.data_size && header.integrity() {
+        let old_sectors = integrity::data_sectors(header.data_size / SECTOR_SIZE as u64);
+        let data_area = FileDevice::new(&mut *storage, header.data_offset, new_size);
+        AuthenticatedDevice::format_from(data_area, master_key, old_sectors)?;
+    }
+
+    header.set_data_size(master_key, new_size)?;
+    header.store(storage)
+}
+
+/// Usable size of a data area of `data_size` bytes
+pub fn usable_size(header: &VaultHeader, data_size: u64) -> u64 {
+    if header.integrity() {
+        integrity::data_sectors(data_size / SECTOR_SIZE as u64) * SECTOR_SIZE as u64
+    } else {
+        data_size
+    }
+}
+
+/// Size of the file system at the start of `volume`, if it is recognized
+pub fn filesystem_size<D: BlockDevice + ?Sized>(volume: &mut D) -> Result<Option<u64>> {
+    let mut probe = vec![0u8; PROBE_LEN];
+    let len = PROBE_LEN.min(volume.size() as usize);
+    volume.read_at(0, &mut probe[..len])?;
+    Ok(ext_size(&probe).or_else(|| boot_sector_size(&probe)))
+}
+
+/// Refuse to shrink below the file system of the volume
+fn check_fits<S: Storage>(storage: &mut S, header: &VaultHeader, master_key: &SecretKey, new_size: u64) -> Result<()> {
+    let mut volume = sector::open_volume(&mut *storage, header, master_key)?;
+    let fs_size = filesystem_size(volume.as_mut())?.ok_or_else(|| {
+        DeepVaultError::InvalidInput(
+            "No known file system in the volume, cannot check that the data fits after shrinking".to_string(),
+        )
+    })?;
+    let usable = usable_size(header, new_size);
+    if fs_size > usable {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "The file system takes {} bytes, more than the {} bytes left after shrinking; shrink it first",
+            fs_size, usable
+        )));
+    }
+    Ok(())
+}
+
+fn ext_size(probe: &[u8]) -> Option<u64> {
+    let sb = probe.get(EXT_SUPERBLOCK..EXT_SUPERBLOCK + 1024)?;
+    if u16::from_le_bytes([sb[56], sb[57]]) != EXT_MAGIC {
+        return None;
+    }
+    let mut blocks = u32_at(sb, 4) as u64;
+    if u32_at(sb, 96) & EXT_INCOMPAT_64BIT != 0 {
+        blocks |= (u32_at(sb, 0x150) as u64) << 32;
+    }
+    let block_size = 1024u64.checked_shl(u32_at(sb, 24))?;
+    blocks.checked_mul(block_size)
+}
+
+/// FAT, exFAT and NTFS, which all start with a boot sector
+fn boot_sector_size(probe: &[u8]) -> Option<u64> {
+    let bs = probe.get(..SECTOR_SIZE)?;
+    if bs[510..512] != [0x55, 0xAA] {
+        return None;
+    }
+    match &bs[3..11] {
+        b"EXFAT   " => u64_at(bs, 72).checked_shl(bs[108] as u32),
+        b"NTFS    " => {
+            // The backup boot sector follows the last counted sector
+            let bytes_per_sector = u16::from_le_bytes([bs[11], bs[12]]) as u64;
+            u64_at(bs, 40).checked_add(1)?.checked_mul(bytes_per_sector)
+        }
+        _ if &bs[54..57] == b"FAT" || &bs[82..85] == b"FAT" => {
+            let bytes_per_sector = u16::from_le_bytes([bs[11], bs[12]]) as u64;
+            let total = match u16::from_le_bytes([bs[19], bs[20]]) {
+                0 => u32_at(bs, 32) as u64,
+                small => small as u64,
+            };
+            Some(total * bytes_per_sector)
+        }
+        _ => None,
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
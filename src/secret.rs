@@ -0,0 +1,238 @@
+//! Secret values that are wiped from memory after use
+//!
+//! [`SecretString`] holds passphrases and [`SecretKey`] holds key material.
+//! Both are zeroized when dropped, never print their contents through
+//! `Debug`, and compare in constant time. Their heap buffer can also be
+//! locked in RAM (`mlock`, `VirtualLock` on Windows) so that it is never
+//! written to swap; see [`set_memory_locking`].
+
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+/// Whether new secrets lock their memory
+static LOCK_BY_DEFAULT: AtomicBool = AtomicBool::new(false);
+
+/// Lock the memory of every secret created from now on
+///
+/// Locking is best effort: it silently does nothing when the process
+/// exceeds its locked memory limit (`RLIMIT_MEMLOCK`).
+pub fn set_memory_locking(enabled: bool) {
+    LOCK_BY_DEFAULT.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether new secrets lock their memory
+pub fn memory_locking() -> bool {
+    LOCK_BY_DEFAULT.load(Ordering::Relaxed)
+}
+
+/// Lock the pages holding `bytes`, if possible
+fn lock_pages(bytes: &[u8]) -> Option<region::LockGuard> {
+    if bytes.is_empty() {
+        return None;
+    }
+    region::lock(bytes.as_ptr(), bytes.len()).ok()
+}
+
+/// A passphrase that is wiped from memory when dropped
+pub struct SecretString {
+    inner: String,
+    lock: Option<region::LockGuard>,
+}
+
+impl SecretString {
+    /// Take ownership of `secret`
+    ///
+    /// The buffer is never reallocated afterwards, so no copy is left
+    /// behind in memory.
+    pub fn new(secret: String) -> Self {
+        let mut secret = Self { inner: secret, lock: None };
+        if memory_locking() {
+            secret.lock_memory();
+        }
+        secret
+    }
+
+    /// Access the passphrase
+    pub fn expose_secret(&self) -> &str {
+        &self.inner
+    }
+
+    /// Length in bytes
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Whether the passphrase is empty
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Lock the passphrase in RAM, returning whether it is locked
+    pub fn lock_memory(&mut self) -> bool {
+        if self.lock.is_none() {
+            self.lock = lock_pages(self.inner.as_bytes());
+        }
+        self.lock.is_some()
+    }
+
+    /// Whether the passphrase is locked in RAM
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        // The lock guard is dropped after the contents are wiped
+        self.inner.zeroize();
+    }
+}
+
+impl Clone for SecretString {
+    fn clone(&self) -> Self {
+        let mut clone = Self::new(self.inner.clone());
+        if self.is_locked() {
+            clone.lock_memory();
+        }
+        clone
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self::new(secret.to_string())
+    }
+}
+
+impl ConstantTimeEq for SecretString {
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        self.inner.as_bytes().ct_eq(other.inner.as_bytes())
+    }
+}
+
+impl PartialEq for SecretString {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for SecretString {}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(<redacted>)")
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// Key material that is wiped from memory when dropped
+pub struct SecretKey {
+    inner: Vec<u8>,
+    lock: Option<region::LockGuard>,
+}
+
+impl SecretKey {
+    /// Take ownership of `key`
+    pub fn new(key: Vec<u8>) -> Self {
+        let mut key = Self { inner: key, lock: None };
+        if memory_locking() {
+            key.lock_memory();
+        }
+        key
+    }
+
+    /// Copy key material from a slice
+    pub fn from_slice(key: &[u8]) -> Self {
+        Self::new(key.to_vec())
+    }
+
+    /// Access the key bytes
+    pub fn expose_secret(&self) -> &[u8] {
+        &self.inner
+    }
+
+    /// Mutable access to the key bytes, to fill a key in place
+    pub fn expose_secret_mut(&mut self) -> &mut [u8] {
+        &mut self.inner
+    }
+
+    /// Length in bytes
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Whether the key is empty
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Lock the key in RAM, returning whether it is locked
+    pub fn lock_memory(&mut self) -> bool {
+        if self.lock.is_none() {
+            self.lock = lock_pages(&self.inner);
+        }
+        self.lock.is_some()
+    }
+
+    /// Whether the key is locked in RAM
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        // The lock guard is dropped after the contents are wiped
+        self.inner.zeroize();
+    }
+}
+
+impl Clone for SecretKey {
+    fn clone(&self) -> Self {
+        let mut clone = Self::from_slice(&self.inner);
+        if self.is_locked() {
+            clone.lock_memory();
+        }
+        clone
+    }
+}
+
+impl From<Vec<u8>> for SecretKey {
+    fn from(key: Vec<u8>) -> Self {
+        Self::new(key)
+    }
+}
+
+impl ConstantTimeEq for SecretKey {
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        self.inner.ct_eq(&other.inner)
+    }
+}
+
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for SecretKey {}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey(<redacted>, {} bytes)", self.inner.len())
+    }
+}
@@ -0,0 +1,223 @@
+//! Sector-level encryption for DeepVault volumes
+//!
+//! Sectors are encrypted with XTS-AES-256 (IEEE 1619), using the sector
+//! number inside the data area as the tweak. Every sector can therefore be
+//! read or written on its own, and identical plaintext sectors at different
+//! positions produce unrelated ciphertext.
+//!
+//! Volumes in integrity mode also authenticate every encrypted sector,
+//! see [`crate::integrity`].
+
+use crate::block::{check_sectors, BlockDevice, FileDevice, Storage, SECTOR_SIZE};
+use crate::crypto::CryptoManager;
+use crate::header::VaultHeader;
+use crate::integrity::AuthenticatedDevice;
+use crate::secret::SecretKey;
+use crate::sparse::SparseVolume;
+use crate::{DeepVaultError, Result};
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes256;
+
+/// Length of an XTS-AES-256 key (data key and tweak key)
+pub const XTS_KEY_LEN: usize = 64;
+
+/// HKDF context for the sector encryption key
+const XTS_KEY_INFO: &[u8] = b"deepvault xts-aes-256 v1";
+
+/// AES block size
+const BLOCK_LEN: usize = 16;
+
+/// XTS-AES-256 cipher for whole sectors
+pub struct SectorCipher {
+    data: Aes256,
+    tweak: Aes256,
+}
+
+impl SectorCipher {
+    /// Create a cipher from a raw 64-byte XTS key
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.len() != XTS_KEY_LEN {
+            return Err(DeepVaultError::Crypto(format!(
+                "Invalid XTS key length: expected {} bytes, got {}",
+                XTS_KEY_LEN,
+                key.len()
+            )));
+        }
+        let (data_key, tweak_key) = key.split_at(XTS_KEY_LEN / 2);
+        if data_key == tweak_key {
+            return Err(DeepVaultError::Crypto("XTS data and tweak keys must differ".to_string()));
+        }
+
+        Ok(Self {
+            data: Aes256::new(GenericArray::from_slice(data_key)),
+            tweak: Aes256::new(GenericArray::from_slice(tweak_key)),
+        })
+    }
+
+    /// Create a cipher keyed from the vault master key
+    pub fn from_master_key(master_key: &SecretKey) -> Result<Self> {
+        Self::new(CryptoManager::derive_subkey(master_key, XTS_KEY_INFO, XTS_KEY_LEN)?.expose_secret())
+    }
+
+    /// Encrypt one sector in place
+    ///
+    /// The buffer length must be a non-zero multiple of 16 bytes.
+    pub fn encrypt_sector(&self, sector: u64, buf: &mut [u8]) {
+        self.process(sector, buf, true);
+    }
+
+    /// Decrypt one sector in place
+    pub fn decrypt_sector(&self, sector: u64, buf: &mut [u8]) {
+        self.process(sector, buf, false);
+    }
+
+    fn process(&self, sector: u64, buf: &mut [u8], encrypt: bool) {
+        assert!(
+            !buf.is_empty() && buf.len().is_multiple_of(BLOCK_LEN),
+            "XTS sector length must be a multiple of the AES block size"
+        );
+
+        let mut tweak = GenericArray::clone_from_slice(&(sector as u128).to_le_bytes());
+        self.tweak.encrypt_block(&mut tweak);
+        let mut t = u128::from_le_bytes(tweak.into());
+
+        for chunk in buf.chunks_exact_mut(BLOCK_LEN) {
+            let mask = t.to_le_bytes();
+            let block = GenericArray::from_mut_slice(chunk);
+            xor_in_place(block, &mask);
+            if encrypt {
+                self.data.encrypt_block(block);
+            } else {
+                self.data.decrypt_block(block);
+            }
+            xor_in_place(block, &mask);
+
+            // Multiply the tweak by the primitive element of GF(2^128)
+            let carry = t >> 127;
+            t = (t << 1) ^ (carry * 0x87);
+        }
+    }
+}
+
+fn xor_in_place(block: &mut [u8], mask: &[u8; BLOCK_LEN]) {
+    for (b, m) in block.iter_mut().zip(mask) {
+        *b ^= m;
+    }
+}
+
+/// Block device that transparently encrypts the sectors of another device
+pub struct EncryptedVolume<D> {
+    inner: D,
+    cipher: SectorCipher,
+}
+
+impl<D: BlockDevice> EncryptedVolume<D> {
+    /// Encrypt `inner` with a key derived from the master key
+    pub fn new(inner: D, master_key: &SecretKey) -> Result<Self> {
+        Ok(Self::with_cipher(inner, SectorCipher::from_master_key(master_key)?))
+    }
+
+    /// Encrypt `inner` with an existing cipher
+    pub fn with_cipher(inner: D, cipher: SectorCipher) -> Self {
+        Self { inner, cipher }
+    }
+
+    /// Get a reference to the underlying (ciphertext) device
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying (ciphertext) device
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Consume the volume and return the underlying device
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<F: Storage> EncryptedVolume<FileDevice<F>> {
+    /// Open the data area described by `header` on `storage`
+    ///
+    /// `storage` must contain the volume starting with its header, such as
+    /// a partition or an image file. Volumes in integrity mode are opened
+    /// with [`EncryptedVolume::open_authenticated`] instead.
+    pub fn open(storage: F, header: &VaultHeader, master_key: &SecretKey) -> Result<Self> {
+        header.verify_mac(master_key)?;
+        if header.integrity() {
+            return Err(DeepVaultError::InvalidInput(
+                "The volume uses integrity tags, open it with open_authenticated".to_string(),
+            ));
+        }
+        if header.sparse() {
+            return Err(DeepVaultError::InvalidInput(
+                "The volume is sparse, open it with SparseVolume::open".to_string(),
+            ));
+        }
+        Self::new(
+            FileDevice::new(storage, header.data_offset, header.data_size),
+            master_key,
+        )
+    }
+}
+
+impl<F: Storage> EncryptedVolume<AuthenticatedDevice<FileDevice<F>>> {
+    /// Open the data area of a volume in integrity mode
+    ///
+    /// Reads of sectors modified outside DeepVault fail with
+    /// [`DeepVaultError::IntegrityViolation`].
+    pub fn open_authenticated(storage: F, header: &VaultHeader, master_key: &SecretKey) -> Result<Self> {
+        header.verify_mac(master_key)?;
+        if !header.integrity() {
+            return Err(DeepVaultError::InvalidInput("The volume does not use integrity tags".to_string()));
+        }
+        let data_area = FileDevice::new(storage, header.data_offset, header.data_size);
+        Self::new(AuthenticatedDevice::new(data_area, master_key)?, master_key)
+    }
+}
+
+/// Open the data area of a volume, whatever its layout
+pub fn open_volume<'a, F: Storage + 'a>(
+    storage: F,
+    header: &VaultHeader,
+    master_key: &SecretKey,
+) -> Result<Box<dyn BlockDevice + 'a>> {
+    if header.sparse() {
+        Ok(Box::new(SparseVolume::open(storage, header, master_key)?))
+    } else if header.integrity() {
+        Ok(Box::new(EncryptedVolume::open_authenticated(storage, header, master_key)?))
+    } else {
+        Ok(Box::new(EncryptedVolume::open(storage, header, master_key)?))
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for EncryptedVolume<D> {
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        check_sectors(self.sector_count(), first, buf.len())?;
+        self.inner.read_sectors(first, buf)?;
+        for (i, sector) in buf.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            self.cipher.decrypt_sector(first + i as u64, sector);
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        check_sectors(self.sector_count(), first, buf.len())?;
+        let mut encrypted = buf.to_vec();
+        for (i, sector) in encrypted.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            self.cipher.encrypt_sector(first + i as u64, sector);
+        }
+        self.inner.write_sectors(first, &encrypted)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,284 @@
+//! Shamir secret sharing for M-of-N unlocking
+//!
+//! A random 256-bit secret is enrolled in a keyslot of its own and split
+//! into N shares over GF(2^8), any M of which rebuild it. No single
+//! custodian learns anything about the secret, and revoking the keyslot
+//! invalidates every share at once.
+//!
+//! Share layout (60 bytes, also printed as base32 groups):
+//!
+//! | Offset | Size | Field                                   |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 4    | Magic `DVS1`                            |
+//! | 4      | 16   | Vault UUID                              |
+//! | 20     | 1    | Keyslot index                           |
+//! | 21     | 1    | Threshold M                             |
+//! | 22     | 1    | Share count N                           |
+//! | 23     | 1    | Share number (x coordinate, 1..=N)      |
+//! | 24     | 32   | Share value                             |
+//! | 56     | 4    | Checksum, start of SHA-256 of bytes 0..56 |
+
+use crate::crypto::KEY_LEN;
+use crate::secret::{SecretKey, SecretString};
+use crate::{DeepVaultError, Result};
+use data_encoding::BASE32_NOPAD;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+/// Length of an encoded share
+pub const SHARE_LEN: usize = 60;
+
+const SHARE_MAGIC: &[u8; 4] = b"DVS1";
+const OFF_UUID: usize = 4;
+const OFF_KEYSLOT: usize = 20;
+const OFF_THRESHOLD: usize = 21;
+const OFF_COUNT: usize = 22;
+const OFF_X: usize = 23;
+const OFF_VALUE: usize = 24;
+const OFF_CHECKSUM: usize = OFF_VALUE + KEY_LEN;
+/// Characters per printed group
+const GROUP_LEN: usize = 6;
+
+/// One custodian's share of a vault secret
+#[derive(Clone, PartialEq, Eq)]
+pub struct Share {
+    pub uuid: Uuid,
+    pub keyslot: usize,
+    pub threshold: u8,
+    pub count: u8,
+    pub x: u8,
+    value: SecretKey,
+}
+
+impl Share {
+    /// Encode the share to its binary form
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(vec![0u8; SHARE_LEN]);
+        bytes[..4].copy_from_slice(SHARE_MAGIC);
+        bytes[OFF_UUID..OFF_UUID + 16].copy_from_slice(self.uuid.as_bytes());
+        bytes[OFF_KEYSLOT] = self.keyslot as u8;
+        bytes[OFF_THRESHOLD] = self.threshold;
+        bytes[OFF_COUNT] = self.count;
+        bytes[OFF_X] = self.x;
+        bytes[OFF_VALUE..OFF_CHECKSUM].copy_from_slice(self.value.expose_secret());
+        let checksum = Sha256::digest(&bytes[..OFF_CHECKSUM]);
+        bytes[OFF_CHECKSUM..].copy_from_slice(&checksum[..SHARE_LEN - OFF_CHECKSUM]);
+        bytes
+    }
+
+    /// Decode a share from its binary form
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != SHARE_LEN || &bytes[..4] != SHARE_MAGIC {
+            return Err(DeepVaultError::InvalidInput("Not a DeepVault share".to_string()));
+        }
+        let checksum = Sha256::digest(&bytes[..OFF_CHECKSUM]);
+        if bytes[OFF_CHECKSUM..] != checksum[..SHARE_LEN - OFF_CHECKSUM] {
+            return Err(DeepVaultError::InvalidInput(
+                "Share checksum mismatch, check for typing mistakes".to_string(),
+            ));
+        }
+
+        let (threshold, count, x) = (bytes[OFF_THRESHOLD], bytes[OFF_COUNT], bytes[OFF_X]);
+        if threshold == 0 || threshold > count || x == 0 || x > count {
+            return Err(DeepVaultError::InvalidInput("Invalid share parameters".to_string()));
+        }
+        Ok(Self {
+            uuid: Uuid::from_slice(&bytes[OFF_UUID..OFF_UUID + 16]).expect("16-byte UUID"),
+            keyslot: bytes[OFF_KEYSLOT] as usize,
+            threshold,
+            count,
+            x,
+            value: SecretKey::from_slice(&bytes[OFF_VALUE..OFF_CHECKSUM]),
+        })
+    }
+
+    /// The share as printable dash-separated base32 groups
+    pub fn to_code(&self) -> SecretString {
+        let encoded = Zeroizing::new(BASE32_NOPAD.encode(&self.to_bytes()));
+        let groups: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(GROUP_LEN)
+            .map(|group| std::str::from_utf8(group).expect("base32 is ASCII"))
+            .collect();
+        SecretString::new(groups.join("-"))
+    }
+
+    /// Parse a printed share code
+    ///
+    /// Case, spaces and dashes are ignored.
+    pub fn from_code(code: &str) -> Result<Self> {
+        let normalized: Zeroizing<String> = Zeroizing::new(
+            code.chars()
+                .filter(|c| !c.is_whitespace() && *c != '-')
+                .map(|c| c.to_ascii_uppercase())
+                .collect(),
+        );
+        let bytes = Zeroizing::new(
+            BASE32_NOPAD
+                .decode(normalized.as_bytes())
+                .map_err(|_| DeepVaultError::InvalidInput("Invalid share code".to_string()))?,
+        );
+        Self::from_bytes(&bytes)
+    }
+
+    /// Write the share to a new file
+    pub fn save(&self, path: &Path) -> Result<()> {
+        use std::io::Write;
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(path)
+            .map_err(|e| DeepVaultError::InvalidInput(format!("Cannot create share {}: {}", path.display(), e)))?;
+        file.write_all(&self.to_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Read a share from a file written by [`Share::save`]
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = Zeroizing::new(std::fs::read(path)?);
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl fmt::Debug for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Share")
+            .field("uuid", &self.uuid)
+            .field("keyslot", &self.keyslot)
+            .field("threshold", &self.threshold)
+            .field("count", &self.count)
+            .field("x", &self.x)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Split `secret` into `count` shares, any `threshold` of which rebuild it
+pub fn split(
+    secret: &SecretKey,
+    threshold: u8,
+    count: u8,
+    uuid: Uuid,
+    keyslot: usize,
+) -> Result<Vec<Share>> {
+    use rand::RngCore;
+
+    check_parameters(threshold, count)?;
+    if secret.len() != KEY_LEN {
+        return Err(DeepVaultError::InvalidInput(format!("Shared secrets must be {} bytes", KEY_LEN)));
+    }
+
+    // One random polynomial per secret byte, with the byte as constant term
+    let mut coefficients = Zeroizing::new(vec![0u8; KEY_LEN * (threshold as usize - 1)]);
+    rand::rngs::OsRng.fill_bytes(&mut coefficients);
+
+    let shares = (1..=count)
+        .map(|x| {
+            let mut value = SecretKey::new(vec![0u8; KEY_LEN]);
+            for (i, byte) in value.expose_secret_mut().iter_mut().enumerate() {
+                let polynomial = &coefficients[i * (threshold as usize - 1)..(i + 1) * (threshold as usize - 1)];
+                // Horner evaluation from the highest degree down
+                let mut y = 0u8;
+                for coefficient in polynomial.iter().rev() {
+                    y = gf_mul(y, x) ^ coefficient;
+                }
+                *byte = gf_mul(y, x) ^ secret.expose_secret()[i];
+            }
+            Share {
+                uuid,
+                keyslot,
+                threshold,
+                count,
+                x,
+                value,
+            }
+        })
+        .collect();
+    Ok(shares)
+}
+
+/// Check that `threshold` of `count` shares is a valid split
+pub fn check_parameters(threshold: u8, count: u8) -> Result<()> {
+    if threshold < 2 || threshold > count {
+        return Err(DeepVaultError::InvalidInput(
+            "The threshold must be at least 2 and at most the number of shares".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Rebuild the secret from at least `threshold` shares of the same split
+pub fn combine(shares: &[Share]) -> Result<SecretKey> {
+    let first = shares
+        .first()
+        .ok_or_else(|| DeepVaultError::InvalidInput("No shares given".to_string()))?;
+    for share in shares {
+        if (share.uuid, share.keyslot, share.threshold, share.count) != (first.uuid, first.keyslot, first.threshold, first.count) {
+            return Err(DeepVaultError::InvalidInput("Shares come from different splits".to_string()));
+        }
+    }
+    let mut xs: Vec<u8> = shares.iter().map(|share| share.x).collect();
+    xs.sort_unstable();
+    xs.dedup();
+    if xs.len() != shares.len() {
+        return Err(DeepVaultError::InvalidInput("The same share was given twice".to_string()));
+    }
+    if shares.len() < first.threshold as usize {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "{} of {} shares are required, {} given",
+            first.threshold,
+            first.count,
+            shares.len()
+        )));
+    }
+
+    // Lagrange interpolation at x = 0 over the first `threshold` shares
+    let shares = &shares[..first.threshold as usize];
+    let mut secret = SecretKey::new(vec![0u8; KEY_LEN]);
+    for (i, share) in shares.iter().enumerate() {
+        let mut basis = 1u8;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                basis = gf_mul(basis, gf_div(other.x, other.x ^ share.x));
+            }
+        }
+        for (byte, value) in secret.expose_secret_mut().iter_mut().zip(share.value.expose_secret()) {
+            *byte ^= gf_mul(basis, *value);
+        }
+    }
+    Ok(secret)
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1, without
+/// data-dependent branches
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// Division in GF(2^8), using a^254 = a^-1
+fn gf_div(a: u8, b: u8) -> u8 {
+    let mut inverse = 1u8;
+    let mut power = b;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            inverse = gf_mul(inverse, power);
+        }
+        power = gf_mul(power, power);
+        exponent >>= 1;
+    }
+    gf_mul(a, inverse)
+}
@@ -0,0 +1,420 @@
+//! Sparse volumes allocated on demand
+//!
+//! A sparse volume only stores the blocks that were written. The data area
+//! starts with an allocation map, followed by the physical blocks in the
+//! order they were allocated:
+//!
+//! | Offset                  | Size     | Field                              |
+//! |-------------------------|----------|------------------------------------|
+//! | 0                       | 512      | Map sector: magic `DVSP`, version, |
+//! |                         |          | block size, map capacity           |
+//! | 512                     | 4 × cap. | Map: physical slot + 1 of each     |
+//! |                         |          | 64 KiB virtual block, 0 = hole     |
+//! | map area (64 KiB units) | ...      | Physical blocks                    |
+//!
+//! The map is encrypted with XTS-AES-256 under its own key, so the layout
+//! of the data cannot be read without the master key. Physical blocks are
+//! encrypted like the sectors of an ordinary volume, with the virtual
+//! sector number as the tweak, so they can be moved without re-encryption.
+//!
+//! Holes read as zeros. The map capacity is fixed when the volume is
+//! formatted, which bounds how far the volume can grow later. Blocks freed
+//! inside the volume are only given back by [`SparseVolume::discard`] or
+//! by [`SparseVolume::compact`], which also releases blocks that read as
+//! zeros and packs the others at the start.
+
+use crate::block::{check_sectors, BlockDevice, Storage, SECTOR_SIZE};
+use crate::crypto::CryptoManager;
+use crate::header::VaultHeader;
+use crate::secret::SecretKey;
+use crate::sector::{SectorCipher, XTS_KEY_LEN};
+use crate::{DeepVaultError, Result};
+use std::collections::BTreeSet;
+use std::io::SeekFrom;
+
+/// Size of an allocation block
+pub const SPARSE_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Sectors per allocation block
+const SECTORS_PER_BLOCK: u64 = SPARSE_BLOCK_SIZE / SECTOR_SIZE as u64;
+/// Map entries per map sector
+const ENTRIES_PER_SECTOR: u64 = (SECTOR_SIZE / 4) as u64;
+/// Magic of the map sector
+const MAP_MAGIC: &[u8; 4] = b"DVSP";
+/// Version of the sparse layout
+const MAP_VERSION: u32 = 1;
+/// HKDF context for the allocation map key
+const MAP_KEY_INFO: &[u8] = b"deepvault allocation map v1";
+
+/// Block device storing only the written blocks of a volume
+pub struct SparseVolume<S> {
+    storage: S,
+    cipher: SectorCipher,
+    map_cipher: SectorCipher,
+    /// Physical slot + 1 of every virtual block, 0 for holes
+    map: Vec<u32>,
+    /// Unused physical slots below `slots`
+    free: BTreeSet<u32>,
+    /// Physical slots present in the storage
+    slots: u32,
+    map_offset: u64,
+    blocks_offset: u64,
+    sector_count: u64,
+}
+
+impl<S: Storage> SparseVolume<S> {
+    /// Write an empty allocation map for the sparse volume of `header`
+    ///
+    /// The map is sized for the current size of the volume, rounded up to
+    /// whole 64 KiB blocks of map.
+    pub fn format(storage: S, header: &VaultHeader, master_key: &SecretKey) -> Result<Self> {
+        check_header(header, master_key)?;
+        let blocks = header.data_size.div_ceil(SPARSE_BLOCK_SIZE);
+        let map_len = map_area_len(blocks);
+        let capacity = (map_len / SECTOR_SIZE as u64 - 1) * ENTRIES_PER_SECTOR;
+
+        let mut volume = Self::new(storage, header, master_key, capacity, 0)?;
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        sector[..4].copy_from_slice(MAP_MAGIC);
+        sector[4..8].copy_from_slice(&MAP_VERSION.to_le_bytes());
+        sector[8..16].copy_from_slice(&SPARSE_BLOCK_SIZE.to_le_bytes());
+        sector[16..24].copy_from_slice(&capacity.to_le_bytes());
+        volume.map_cipher.encrypt_sector(0, &mut sector);
+        volume.write_raw(volume.map_offset, &sector)?;
+        for index in 1..map_len / SECTOR_SIZE as u64 {
+            volume.write_map_sector(index)?;
+        }
+        volume.storage.sync()?;
+        Ok(volume)
+    }
+
+    /// Open the sparse volume described by `header` on `storage`
+    pub fn open(storage: S, header: &VaultHeader, master_key: &SecretKey) -> Result<Self> {
+        check_header(header, master_key)?;
+        let mut volume = Self::new(storage, header, master_key, 0, 0)?;
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        volume.read_raw(volume.map_offset, &mut sector)?;
+        volume.map_cipher.decrypt_sector(0, &mut sector);
+        if &sector[..4] != MAP_MAGIC || u32_at(&sector, 4) != MAP_VERSION {
+            return Err(DeepVaultError::InvalidHeader("Allocation map not found".to_string()));
+        }
+        if u64_at(&sector, 8) != SPARSE_BLOCK_SIZE {
+            return Err(DeepVaultError::InvalidHeader("Unsupported allocation block size".to_string()));
+        }
+        let capacity = u64_at(&sector, 16);
+        if capacity > u32::MAX as u64 || capacity * SPARSE_BLOCK_SIZE < header.data_size {
+            return Err(DeepVaultError::InvalidHeader("Allocation map too small for the volume".to_string()));
+        }
+
+        let storage_len = volume.storage.seek(SeekFrom::End(0))?;
+        let mut volume = Self::new(volume.storage, header, master_key, capacity, 0)?;
+        let slots = storage_len.saturating_sub(volume.blocks_offset) / SPARSE_BLOCK_SIZE;
+        volume.slots = u32::try_from(slots)
+            .map_err(|_| DeepVaultError::InvalidHeader("Too many allocated blocks".to_string()))?;
+
+        let map_sectors = capacity.div_ceil(ENTRIES_PER_SECTOR);
+        let mut raw = vec![0u8; (map_sectors as usize) * SECTOR_SIZE];
+        volume.read_raw(volume.map_offset + SECTOR_SIZE as u64, &mut raw)?;
+        let mut used = vec![false; volume.slots as usize];
+        for (i, sector) in raw.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            volume.map_cipher.decrypt_sector(i as u64 + 1, sector);
+            for (j, entry) in sector.chunks_exact(4).enumerate() {
+                let index = i * ENTRIES_PER_SECTOR as usize + j;
+                if index as u64 >= capacity {
+                    break;
+                }
+                let entry = u32::from_le_bytes(entry.try_into().unwrap());
+                if entry != 0 {
+                    let slot = entry as usize - 1;
+                    if slot >= used.len() || used[slot] {
+                        return Err(DeepVaultError::InvalidHeader("Corrupted allocation map".to_string()));
+                    }
+                    used[slot] = true;
+                }
+                volume.map[index] = entry;
+            }
+        }
+        volume.free = (0..volume.slots).filter(|&slot| !used[slot as usize]).collect();
+        Ok(volume)
+    }
+
+    /// Largest size the volume can grow to with its allocation map
+    pub fn max_size(&self) -> u64 {
+        self.map.len() as u64 * SPARSE_BLOCK_SIZE
+    }
+
+    /// Bytes of storage needed by the allocated blocks, once compacted
+    pub fn allocated_size(&self) -> u64 {
+        (self.slots as u64 - self.free.len() as u64) * SPARSE_BLOCK_SIZE
+    }
+
+    /// Length of the storage in use, from the start of the volume
+    pub fn storage_len(&self) -> u64 {
+        self.blocks_offset + self.slots as u64 * SPARSE_BLOCK_SIZE
+    }
+
+    /// Change the size of the volume, within [`SparseVolume::max_size`]
+    ///
+    /// Blocks beyond the new size are released. The header must be
+    /// updated by the caller.
+    pub fn set_size(&mut self, size: u64) -> Result<()> {
+        if size > self.max_size() {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "The allocation map of this sparse volume is limited to {} bytes",
+                self.max_size()
+            )));
+        }
+        let sector_count = size / SECTOR_SIZE as u64;
+        if sector_count < self.sector_count {
+            let first_block = sector_count.div_ceil(SECTORS_PER_BLOCK);
+            for block in first_block..self.map.len() as u64 {
+                self.release(block)?;
+            }
+            // Zero the tail of a partially kept block, so growing again
+            // reveals no stale data
+            let tail = sector_count % SECTORS_PER_BLOCK;
+            if tail != 0 && self.slot(sector_count / SECTORS_PER_BLOCK).is_some() {
+                let zeros = vec![0u8; ((SECTORS_PER_BLOCK - tail) as usize) * SECTOR_SIZE];
+                self.write_in_block(sector_count, &zeros)?;
+            }
+        }
+        self.sector_count = sector_count;
+        self.storage.sync()?;
+        Ok(())
+    }
+
+    /// Release the blocks that read as zeros and move the others to the
+    /// start of the block area
+    ///
+    /// Returns the length the storage can then be truncated to.
+    pub fn compact(&mut self) -> Result<u64> {
+        let mut block_buf = vec![0u8; SPARSE_BLOCK_SIZE as usize];
+        for block in 0..self.map.len() as u64 {
+            let Some(slot) = self.slot(block) else { continue };
+            self.read_raw(self.slot_offset(slot), &mut block_buf)?;
+            for (i, sector) in block_buf.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+                self.cipher.decrypt_sector(block * SECTORS_PER_BLOCK + i as u64, sector);
+            }
+            if block_buf.iter().all(|&b| b == 0) {
+                self.release(block)?;
+            }
+        }
+        self.storage.sync()?;
+
+        let used = self.slots - self.free.len() as u32;
+        let mut owners = vec![None; self.slots as usize];
+        for (block, &entry) in self.map.iter().enumerate() {
+            if entry != 0 {
+                owners[entry as usize - 1] = Some(block as u64);
+            }
+        }
+        for (slot, owner) in owners.iter().enumerate().skip(used as usize) {
+            let Some(block) = *owner else { continue };
+            let target = self.free.pop_first().expect("a free slot below the used count");
+            self.read_raw(self.slot_offset(slot as u32), &mut block_buf)?;
+            self.write_raw(self.slot_offset(target), &block_buf)?;
+            self.storage.sync()?;
+            self.set_entry(block, target + 1)?;
+        }
+        self.storage.sync()?;
+        self.free.clear();
+        self.slots = used;
+        Ok(self.storage_len())
+    }
+
+    /// Consume the volume and return the underlying storage
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    fn new(storage: S, header: &VaultHeader, master_key: &SecretKey, capacity: u64, slots: u32) -> Result<Self> {
+        let map_key = CryptoManager::derive_subkey(master_key, MAP_KEY_INFO, XTS_KEY_LEN)?;
+        Ok(Self {
+            storage,
+            cipher: SectorCipher::from_master_key(master_key)?,
+            map_cipher: SectorCipher::new(map_key.expose_secret())?,
+            map: vec![0; capacity as usize],
+            free: BTreeSet::new(),
+            slots,
+            map_offset: header.data_offset,
+            blocks_offset: header.data_offset + map_area_len(capacity),
+            sector_count: header.data_size / SECTOR_SIZE as u64,
+        })
+    }
+
+    /// Physical slot of a virtual block, if allocated
+    fn slot(&self, block: u64) -> Option<u32> {
+        self.map[block as usize].checked_sub(1)
+    }
+
+    fn slot_offset(&self, slot: u32) -> u64 {
+        self.blocks_offset + slot as u64 * SPARSE_BLOCK_SIZE
+    }
+
+    /// Turn a block back into a hole
+    fn release(&mut self, block: u64) -> Result<()> {
+        if let Some(slot) = self.slot(block) {
+            self.set_entry(block, 0)?;
+            self.free.insert(slot);
+        }
+        Ok(())
+    }
+
+    fn set_entry(&mut self, block: u64, entry: u32) -> Result<()> {
+        self.map[block as usize] = entry;
+        self.write_map_sector(1 + block / ENTRIES_PER_SECTOR)
+    }
+
+    fn write_map_sector(&mut self, index: u64) -> Result<()> {
+        let first = ((index - 1) * ENTRIES_PER_SECTOR) as usize;
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        for (i, entry) in self.map.iter().skip(first).take(ENTRIES_PER_SECTOR as usize).enumerate() {
+            sector[i * 4..i * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+        }
+        self.map_cipher.encrypt_sector(index, &mut sector);
+        self.write_raw(self.map_offset + index * SECTOR_SIZE as u64, &sector)
+    }
+
+    /// Write sectors that all belong to one block, allocating it if needed
+    ///
+    /// A new block is written in full before the map points to it.
+    fn write_in_block(&mut self, first: u64, data: &[u8]) -> Result<()> {
+        let block = first / SECTORS_PER_BLOCK;
+        let (slot, start, mut buf) = match self.slot(block) {
+            Some(slot) => (slot, first, data.to_vec()),
+            None => {
+                if data.iter().all(|&b| b == 0) {
+                    return Ok(());
+                }
+                let slot = self.free.pop_first().unwrap_or_else(|| {
+                    self.slots += 1;
+                    self.slots - 1
+                });
+                let mut full = vec![0u8; SPARSE_BLOCK_SIZE as usize];
+                let skip = ((first % SECTORS_PER_BLOCK) as usize) * SECTOR_SIZE;
+                full[skip..skip + data.len()].copy_from_slice(data);
+                (slot, block * SECTORS_PER_BLOCK, full)
+            }
+        };
+
+        for (i, sector) in buf.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            self.cipher.encrypt_sector(start + i as u64, sector);
+        }
+        let offset = self.slot_offset(slot) + (start % SECTORS_PER_BLOCK) * SECTOR_SIZE as u64;
+        self.write_raw(offset, &buf)?;
+        if self.slot(block).is_none() {
+            self.set_entry(block, slot + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Split a request into runs that stay within one block
+    fn runs(first: u64, count: u64) -> impl Iterator<Item = (u64, u64)> {
+        let end = first + count;
+        let mut next = first;
+        std::iter::from_fn(move || {
+            if next >= end {
+                return None;
+            }
+            let run = (SECTORS_PER_BLOCK - next % SECTORS_PER_BLOCK).min(end - next);
+            let start = next;
+            next += run;
+            Some((start, run))
+        })
+    }
+
+    fn read_raw(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.storage.seek(SeekFrom::Start(offset))?;
+        self.storage.read_exact(buf)?;
+        Ok(())
+    }
+
+    fn write_raw(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        self.storage.seek(SeekFrom::Start(offset))?;
+        self.storage.write_all(buf)?;
+        Ok(())
+    }
+}
+
+impl<S: Storage> BlockDevice for SparseVolume<S> {
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        check_sectors(self.sector_count, first, buf.len())?;
+        let mut done = 0;
+        for (start, count) in Self::runs(first, (buf.len() / SECTOR_SIZE) as u64) {
+            let data = &mut buf[done..done + count as usize * SECTOR_SIZE];
+            match self.slot(start / SECTORS_PER_BLOCK) {
+                None => data.fill(0),
+                Some(slot) => {
+                    let offset = self.slot_offset(slot) + (start % SECTORS_PER_BLOCK) * SECTOR_SIZE as u64;
+                    self.read_raw(offset, data)?;
+                    for (i, sector) in data.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+                        self.cipher.decrypt_sector(start + i as u64, sector);
+                    }
+                }
+            }
+            done += data.len();
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        check_sectors(self.sector_count, first, buf.len())?;
+        let mut done = 0;
+        for (start, count) in Self::runs(first, (buf.len() / SECTOR_SIZE) as u64) {
+            let len = count as usize * SECTOR_SIZE;
+            self.write_in_block(start, &buf[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.storage.sync()?;
+        Ok(())
+    }
+
+    /// Whole blocks become holes; the rest of the range is zeroed
+    fn discard(&mut self, first: u64, count: u64) -> Result<()> {
+        check_sectors(self.sector_count, first, (count as usize) * SECTOR_SIZE)?;
+        for (start, run) in Self::runs(first, count) {
+            let block = start / SECTORS_PER_BLOCK;
+            if run == SECTORS_PER_BLOCK {
+                self.release(block)?;
+            } else if self.slot(block).is_some() {
+                self.write_in_block(start, &vec![0u8; run as usize * SECTOR_SIZE])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bytes taken by the map sector and `capacity` entries, in whole blocks
+fn map_area_len(capacity: u64) -> u64 {
+    let sectors = 1 + capacity.div_ceil(ENTRIES_PER_SECTOR);
+    (sectors * SECTOR_SIZE as u64).div_ceil(SPARSE_BLOCK_SIZE) * SPARSE_BLOCK_SIZE
+}
+
+fn check_header(header: &VaultHeader, master_key: &SecretKey) -> Result<()> {
+    header.verify_mac(master_key)?;
+    if !header.sparse() {
+        return Err(DeepVaultError::InvalidInput("The volume is not sparse".to_string()));
+    }
+    if header.integrity() {
+        return Err(DeepVaultError::InvalidInput(
+            "Sparse volumes cannot use integrity tags".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,310 @@
+//! Keyslots unlocked by an ed25519 key held in ssh-agent
+//!
+//! Ed25519 signatures are deterministic: the same key always gives the
+//! same signature over the same message. A keyslot therefore stores a
+//! random salt and asks the agent to sign
+//! `"deepvault ssh-agent v1" || salt || uuid || slot index`; the key
+//! encryption key is HKDF-SHA256 of that signature. The private key never
+//! leaves the agent, and a user already authenticated to it unlocks the
+//! vault without typing anything.
+//!
+//! The agent is reached over the socket named by `SSH_AUTH_SOCK` (a named
+//! pipe on Windows), speaking the protocol of draft-miller-ssh-agent.
+
+use crate::crypto::KEY_LEN;
+use crate::secret::SecretKey;
+use crate::{DeepVaultError, Result};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// Environment variable naming the agent socket
+pub const SSH_AUTH_SOCK: &str = "SSH_AUTH_SOCK";
+/// Key type name of ed25519 keys in SSH
+pub const KEY_TYPE: &str = "ssh-ed25519";
+/// Length of an ed25519 signature
+pub const SIGNATURE_LEN: usize = 64;
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+/// Largest agent reply accepted
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+/// Domain separator for challenges and key encryption keys
+const CHALLENGE_DOMAIN: &[u8] = b"deepvault ssh-agent v1";
+
+/// An SSH ed25519 public key
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SshPublicKey {
+    key: [u8; 32],
+}
+
+impl SshPublicKey {
+    /// Key from raw ed25519 public key bytes
+    pub fn from_bytes(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Raw ed25519 public key bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Parse the SSH wire encoding of a public key
+    pub fn from_blob(blob: &[u8]) -> Result<Self> {
+        let mut reader = WireReader::new(blob);
+        let key_type = reader.read_string()?;
+        if key_type != KEY_TYPE.as_bytes() {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "Unsupported SSH key type {}, only {} keys can unlock a vault",
+                String::from_utf8_lossy(key_type),
+                KEY_TYPE
+            )));
+        }
+        let key = reader.read_string()?;
+        if key.len() != 32 || !reader.is_empty() {
+            return Err(DeepVaultError::InvalidInput("Malformed ed25519 public key".to_string()));
+        }
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(key);
+        Ok(Self::from_bytes(bytes))
+    }
+
+    /// SSH wire encoding of the key
+    pub fn to_blob(&self) -> Vec<u8> {
+        let mut blob = Vec::with_capacity(51);
+        write_string(&mut blob, KEY_TYPE.as_bytes());
+        write_string(&mut blob, &self.key);
+        blob
+    }
+
+    /// Parse an OpenSSH public key line, `ssh-ed25519 AAAA... [comment]`
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some(KEY_TYPE) {
+            return Err(DeepVaultError::InvalidInput(format!("Not an {} public key", KEY_TYPE)));
+        }
+        let blob = fields
+            .next()
+            .and_then(|field| base64::engine::general_purpose::STANDARD.decode(field).ok())
+            .ok_or_else(|| DeepVaultError::InvalidInput("Malformed SSH public key".to_string()))?;
+        Self::from_blob(&blob)
+    }
+
+    /// Read a public key file such as `~/.ssh/id_ed25519.pub`
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(std::fs::read_to_string(path)?.trim())
+    }
+
+    /// Fingerprint in the `ssh-keygen -l` format, `SHA256:...`
+    pub fn fingerprint(&self) -> String {
+        let digest = Sha256::digest(self.to_blob());
+        format!("SHA256:{}", base64::engine::general_purpose::STANDARD_NO_PAD.encode(digest))
+    }
+}
+
+impl fmt::Display for SshPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let blob = base64::engine::general_purpose::STANDARD.encode(self.to_blob());
+        write!(f, "{} {}", KEY_TYPE, blob)
+    }
+}
+
+impl fmt::Debug for SshPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SshPublicKey({})", self.fingerprint())
+    }
+}
+
+impl FromStr for SshPublicKey {
+    type Err = DeepVaultError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+/// Client for an SSH agent
+pub struct SshAgent<S: Read + Write> {
+    stream: S,
+}
+
+#[cfg(unix)]
+impl SshAgent<std::os::unix::net::UnixStream> {
+    /// Connect to the agent named by `SSH_AUTH_SOCK`
+    pub fn connect() -> Result<Self> {
+        let path = std::env::var_os(SSH_AUTH_SOCK)
+            .ok_or_else(|| DeepVaultError::Config(format!("{} is not set, no SSH agent is running", SSH_AUTH_SOCK)))?;
+        Self::connect_to(Path::new(&path))
+    }
+
+    /// Connect to the agent listening on `path`
+    pub fn connect_to(path: &Path) -> Result<Self> {
+        let stream = std::os::unix::net::UnixStream::connect(path)
+            .map_err(|e| DeepVaultError::System(format!("Cannot reach SSH agent at {}: {}", path.display(), e)))?;
+        Ok(Self::new(stream))
+    }
+}
+
+#[cfg(windows)]
+impl SshAgent<std::fs::File> {
+    /// Connect to the agent named by `SSH_AUTH_SOCK`, or to the pipe of
+    /// OpenSSH for Windows
+    pub fn connect() -> Result<Self> {
+        let path = std::env::var_os(SSH_AUTH_SOCK).unwrap_or_else(|| r"\\.\pipe\openssh-ssh-agent".into());
+        Self::connect_to(Path::new(&path))
+    }
+
+    /// Connect to the agent listening on the named pipe `path`
+    pub fn connect_to(path: &Path) -> Result<Self> {
+        let pipe = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| DeepVaultError::System(format!("Cannot reach SSH agent at {}: {}", path.display(), e)))?;
+        Ok(Self::new(pipe))
+    }
+}
+
+impl<S: Read + Write> SshAgent<S> {
+    /// Talk to an agent over an already connected stream
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// The ed25519 keys held by the agent, with their comments
+    ///
+    /// Keys of other types are skipped.
+    pub fn identities(&mut self) -> Result<Vec<(SshPublicKey, String)>> {
+        let reply = self.request(&[SSH_AGENTC_REQUEST_IDENTITIES])?;
+        let mut reader = WireReader::new(&reply);
+        if reader.read_u8()? != SSH_AGENT_IDENTITIES_ANSWER {
+            return Err(DeepVaultError::System("The SSH agent refused to list its keys".to_string()));
+        }
+
+        let count = reader.read_u32()?;
+        let mut identities = Vec::new();
+        for _ in 0..count {
+            let blob = reader.read_string()?;
+            let comment = String::from_utf8_lossy(reader.read_string()?).into_owned();
+            if let Ok(key) = SshPublicKey::from_blob(blob) {
+                identities.push((key, comment));
+            }
+        }
+        Ok(identities)
+    }
+
+    /// Ask the agent to sign `data` with `key`
+    pub fn sign(&mut self, key: &SshPublicKey, data: &[u8]) -> Result<Zeroizing<[u8; SIGNATURE_LEN]>> {
+        let mut message = vec![SSH_AGENTC_SIGN_REQUEST];
+        write_string(&mut message, &key.to_blob());
+        write_string(&mut message, data);
+        message.extend_from_slice(&0u32.to_be_bytes());
+
+        let reply = Zeroizing::new(self.request(&message)?);
+        let mut reader = WireReader::new(&reply);
+        match reader.read_u8()? {
+            SSH_AGENT_SIGN_RESPONSE => {}
+            SSH_AGENT_FAILURE => {
+                return Err(DeepVaultError::Crypto(format!(
+                    "The SSH agent refused to sign with {}",
+                    key.fingerprint()
+                )))
+            }
+            other => return Err(DeepVaultError::System(format!("Unexpected SSH agent reply {}", other))),
+        }
+
+        let mut signature = WireReader::new(reader.read_string()?);
+        if signature.read_string()? != KEY_TYPE.as_bytes() {
+            return Err(DeepVaultError::Crypto("The SSH agent returned a non-ed25519 signature".to_string()));
+        }
+        let bytes = signature.read_string()?;
+        if bytes.len() != SIGNATURE_LEN {
+            return Err(DeepVaultError::Crypto("Malformed ed25519 signature".to_string()));
+        }
+        let mut output = Zeroizing::new([0u8; SIGNATURE_LEN]);
+        output.copy_from_slice(bytes);
+        Ok(output)
+    }
+
+    /// Key encryption key of a keyslot, from the agent's signature
+    pub(crate) fn wrapping_key(&mut self, key: &SshPublicKey, salt: &[u8], aad: &[u8]) -> Result<SecretKey> {
+        let mut challenge = CHALLENGE_DOMAIN.to_vec();
+        challenge.extend_from_slice(salt);
+        challenge.extend_from_slice(aad);
+        let signature = self.sign(key, &challenge)?;
+
+        let hkdf = hkdf::Hkdf::<Sha256>::new(Some(salt), signature.as_slice());
+        let mut kek = SecretKey::new(vec![0u8; KEY_LEN]);
+        hkdf.expand(CHALLENGE_DOMAIN, kek.expose_secret_mut())
+            .map_err(|e| DeepVaultError::Crypto(format!("Key derivation failed: {}", e)))?;
+        Ok(kek)
+    }
+
+    /// Send one message and read the reply
+    fn request(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        self.stream.write_all(&(message.len() as u32).to_be_bytes())?;
+        self.stream.write_all(message)?;
+        self.stream.flush()?;
+
+        let mut len = [0u8; 4];
+        self.stream.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            return Err(DeepVaultError::System(format!("Invalid SSH agent reply of {} bytes", len)));
+        }
+        let mut reply = vec![0u8; len];
+        self.stream.read_exact(&mut reply)?;
+        Ok(reply)
+    }
+}
+
+/// Append an SSH `string`: a big-endian `u32` length and the bytes
+fn write_string(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(bytes);
+}
+
+/// Reader for SSH wire encoded values
+struct WireReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> WireReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(DeepVaultError::InvalidInput("Truncated SSH message".to_string()));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_string(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+}
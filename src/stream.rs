@@ -0,0 +1,303 @@
+//! Streaming authenticated encryption for large files
+//!
+//! Files are split into fixed-size segments, each sealed with AES-256-GCM
+//! (the STREAM construction). Memory use is bounded by one segment, so
+//! files of any size can be moved in and out of a vault.
+//!
+//! Stream layout (little-endian):
+//!
+//! | Offset | Size | Field                                   |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 4    | Magic `DVST`                            |
+//! | 4      | 1    | Format version                          |
+//! | 5      | 3    | Reserved, zero                          |
+//! | 8      | 4    | Plaintext segment size                  |
+//! | 12     | 16   | Random salt                             |
+//! | 28     | ...  | Segments: ciphertext and 16-byte tag    |
+//!
+//! Every segment except the last holds exactly one segment of plaintext.
+//! The stream key is derived from the caller's key and the whole header,
+//! so each stream gets its own key. Segment nonces are
+//! `0^3 || u64 BE counter || last flag`: reordering, dropping or
+//! appending segments, or truncating the stream, fails authentication.
+
+use crate::crypto::{CryptoManager, KEY_LEN, TAG_LEN};
+use crate::header::{read_u32, write_u32};
+use crate::secret::SecretKey;
+use crate::{DeepVaultError, Result};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+/// Magic bytes identifying an encrypted stream
+pub const STREAM_MAGIC: &[u8; 4] = b"DVST";
+/// Stream format version
+pub const STREAM_VERSION: u8 = 1;
+/// Size of the stream header
+pub const STREAM_HEADER_LEN: usize = 28;
+/// Default plaintext segment size (64 KiB)
+pub const DEFAULT_SEGMENT_SIZE: usize = 64 * 1024;
+/// Smallest accepted segment size
+pub const MIN_SEGMENT_SIZE: usize = 1024;
+/// Largest accepted segment size
+pub const MAX_SEGMENT_SIZE: usize = 16 * 1024 * 1024;
+
+/// HKDF context for stream keys
+const STREAM_KEY_INFO: &[u8] = b"deepvault stream v1";
+
+const OFF_VERSION: usize = 4;
+const OFF_SEGMENT_SIZE: usize = 8;
+const OFF_SALT: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// `Write` adaptor that encrypts everything written to it
+///
+/// [`StreamWriter::finish`] must be called once all data is written;
+/// a stream that is dropped without it is detected as truncated.
+pub struct StreamWriter<W: Write> {
+    inner: W,
+    cipher: Aes256Gcm,
+    segment_size: usize,
+    buffer: Zeroizing<Vec<u8>>,
+    counter: u64,
+}
+
+impl<W: Write> StreamWriter<W> {
+    /// Start an encrypted stream on `inner` with the default segment size
+    pub fn new(inner: W, key: &SecretKey) -> Result<Self> {
+        Self::with_segment_size(inner, key, DEFAULT_SEGMENT_SIZE)
+    }
+
+    /// Start an encrypted stream with a custom segment size
+    pub fn with_segment_size(mut inner: W, key: &SecretKey, segment_size: usize) -> Result<Self> {
+        check_segment_size(segment_size)?;
+
+        let mut header = [0u8; STREAM_HEADER_LEN];
+        header[..4].copy_from_slice(STREAM_MAGIC);
+        header[OFF_VERSION] = STREAM_VERSION;
+        write_u32(&mut header, OFF_SEGMENT_SIZE, segment_size as u32);
+        header[OFF_SALT..OFF_SALT + SALT_LEN].copy_from_slice(&CryptoManager::generate_salt()?[..SALT_LEN]);
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            cipher: stream_cipher(key, &header)?,
+            segment_size,
+            buffer: Zeroizing::new(Vec::with_capacity(segment_size)),
+            counter: 0,
+        })
+    }
+
+    /// Seal the final segment and return the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.seal_segment(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn seal_segment(&mut self, last: bool) -> Result<()> {
+        let nonce = segment_nonce(self.counter, last);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), self.buffer.as_slice())
+            .map_err(|_| DeepVaultError::Crypto("Encryption failed".to_string()))?;
+        self.inner.write_all(&ciphertext)?;
+        self.buffer.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| DeepVaultError::Crypto("Stream too long".to_string()))?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A full segment is only sealed once more data arrives, since the
+        // last segment must carry the final flag
+        if self.buffer.len() == self.segment_size {
+            self.seal_segment(false).map_err(into_io_error)?;
+        }
+        let len = buf.len().min(self.segment_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `Read` adaptor that decrypts and authenticates a stream
+///
+/// Reads fail with [`io::ErrorKind::InvalidData`] as soon as a segment
+/// does not authenticate; data returned before that point is genuine.
+pub struct StreamReader<R: Read> {
+    inner: R,
+    cipher: Aes256Gcm,
+    segment_size: usize,
+    counter: u64,
+    segment: Vec<u8>,
+    plaintext: Zeroizing<Vec<u8>>,
+    position: usize,
+    peeked: Option<u8>,
+    finished: bool,
+}
+
+impl<R: Read> StreamReader<R> {
+    /// Read the stream header from `inner`
+    pub fn new(mut inner: R, key: &SecretKey) -> Result<Self> {
+        let mut header = [0u8; STREAM_HEADER_LEN];
+        inner.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => DeepVaultError::InvalidInput("Not a DeepVault stream".to_string()),
+            _ => DeepVaultError::Io(e),
+        })?;
+        if &header[..4] != STREAM_MAGIC {
+            return Err(DeepVaultError::InvalidInput("Not a DeepVault stream".to_string()));
+        }
+        if header[OFF_VERSION] != STREAM_VERSION || header[OFF_VERSION + 1..OFF_SEGMENT_SIZE] != [0u8; 3] {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "Unsupported stream version {}",
+                header[OFF_VERSION]
+            )));
+        }
+        let segment_size = read_u32(&header, OFF_SEGMENT_SIZE) as usize;
+        check_segment_size(segment_size)?;
+
+        Ok(Self {
+            inner,
+            cipher: stream_cipher(key, &header)?,
+            segment_size,
+            counter: 0,
+            segment: Vec::with_capacity(segment_size + TAG_LEN),
+            plaintext: Zeroizing::new(Vec::with_capacity(segment_size)),
+            position: 0,
+            peeked: None,
+            finished: false,
+        })
+    }
+
+    /// Consume the reader and return the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read, authenticate and decrypt the next segment
+    fn next_segment(&mut self) -> Result<()> {
+        let full = self.segment_size + TAG_LEN;
+        self.segment.clear();
+        self.segment.resize(full, 0);
+        let mut start = 0;
+        if let Some(byte) = self.peeked.take() {
+            self.segment[0] = byte;
+            start = 1;
+        }
+        let len = self.fill_segment(start)?;
+        self.segment.truncate(len);
+
+        // A full segment is the last one only if nothing follows it
+        let last = if len < full {
+            true
+        } else {
+            let mut byte = [0u8; 1];
+            match read_some(&mut self.inner, &mut byte)? {
+                0 => true,
+                _ => {
+                    self.peeked = Some(byte[0]);
+                    false
+                }
+            }
+        };
+
+        if len < TAG_LEN {
+            return Err(DeepVaultError::Crypto("Encrypted stream is truncated".to_string()));
+        }
+        let nonce = segment_nonce(self.counter, last);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), self.segment.as_slice())
+            .map_err(|_| {
+                DeepVaultError::Crypto(
+                    "Stream segment failed authentication: truncated, reordered or modified".to_string(),
+                )
+            })?;
+        self.plaintext = Zeroizing::new(plaintext);
+        self.position = 0;
+        self.counter += 1;
+        self.finished = last;
+        Ok(())
+    }
+
+    /// Fill `self.segment` from `start`, returning the number of bytes held
+    fn fill_segment(&mut self, start: usize) -> Result<usize> {
+        let mut len = start;
+        while len < self.segment.len() {
+            match read_some(&mut self.inner, &mut self.segment[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl<R: Read> Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.next_segment().map_err(into_io_error)?;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+fn check_segment_size(segment_size: usize) -> Result<()> {
+    if !(MIN_SEGMENT_SIZE..=MAX_SEGMENT_SIZE).contains(&segment_size) {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "Segment size must be between {} and {} bytes",
+            MIN_SEGMENT_SIZE, MAX_SEGMENT_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Cipher keyed for one stream, bound to its header
+fn stream_cipher(key: &SecretKey, header: &[u8]) -> Result<Aes256Gcm> {
+    let mut info = STREAM_KEY_INFO.to_vec();
+    info.extend_from_slice(header);
+    let stream_key = CryptoManager::derive_subkey(key, &info, KEY_LEN)?;
+    Ok(Aes256Gcm::new_from_slice(stream_key.expose_secret()).expect("HKDF output has the AES-256 key length"))
+}
+
+fn segment_nonce(counter: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// Read once, retrying on interruption
+fn read_some<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+fn into_io_error(error: DeepVaultError) -> io::Error {
+    match error {
+        DeepVaultError::Io(e) => e,
+        other => io::Error::new(io::ErrorKind::InvalidData, other),
+    }
+}
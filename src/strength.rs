@@ -0,0 +1,408 @@
+//! Passphrase strength estimation
+//!
+//! A passphrase is split into the cheapest sequence of patterns an
+//! attacker would try first: common passwords and dictionary words (with
+//! capitals and leet substitutions), alphabet and keyboard sequences,
+//! repeats and years, with brute force for whatever is left. The entropy
+//! is the number of bits of that split, so `Password1!` scores as the
+//! handful of guesses it is, while a long lowercase passphrase does not
+//! need symbols to score well.
+//!
+//! Crack times assume an offline attacker making
+//! [`GUESSES_PER_SECOND`] guesses, as against a fast hash. The key
+//! derivation slows real attacks down further, so estimates err on the
+//! safe side.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Offline guessing rate assumed for crack times
+pub const GUESSES_PER_SECOND: f64 = 1e10;
+/// Lowest score accepted for a new passphrase
+pub const MIN_SCORE: u8 = 3;
+
+/// Entropy in bits needed for scores 1 to 4
+const SCORE_THRESHOLDS: [f64; 4] = [28.0, 36.0, 60.0, 80.0];
+/// Longest dictionary entry looked up
+const MAX_WORD_LEN: usize = 16;
+
+/// Most common passwords, most common first
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "password", "123456789", "12345678", "12345", "qwerty", "azerty", "1234567", "111111",
+    "123123", "motdepasse", "abc123", "1234567890", "password1", "iloveyou", "000000", "admin",
+    "welcome", "letmein", "monkey", "dragon", "football", "baseball", "soleil", "doudou", "loulou",
+    "chouchou", "bonjour", "marseille", "princess", "sunshine", "master", "shadow", "superman",
+    "michael", "jordan", "trustno1", "hello", "freedom", "whatever", "qazwsx", "secret", "login",
+    "passw0rd", "starwars", "liverpool", "chocolate", "nicolas", "camille", "julien", "thomas",
+    "pokemon", "batman", "charlie", "jessica", "ashley", "mustang", "access", "flower", "hunter",
+    "killer", "ginger", "pepper", "summer", "winter", "cookie", "internet", "computer", "orange",
+    "banane", "coucou", "jetaime", "amour", "toto", "test", "pass", "root", "toor", "changeme",
+    "default", "deepvault", "usb", "vault", "dieu", "paris", "france", "lyon", "toulouse",
+];
+
+/// Keyboard rows, QWERTY then AZERTY
+const KEYBOARD_ROWS: &[&str] = &[
+    "`1234567890-=",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+    "²&é\"'(-è_çà)=",
+    "azertyuiop^$",
+    "qsdfghjklmù*",
+    "<wxcvbn,;:!",
+];
+
+/// Result of [`estimate`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordStrength {
+    /// From 0 (guessed at once) to 4 (very strong)
+    pub score: u8,
+    /// Estimated entropy in bits
+    pub entropy_bits: f64,
+    /// Average time to find the passphrase offline
+    pub crack_time: Duration,
+    /// Advice on the weaknesses found, empty for acceptable passphrases
+    pub feedback: Vec<String>,
+}
+
+impl PasswordStrength {
+    /// Whether the passphrase is strong enough for a new keyslot
+    pub fn is_acceptable(&self) -> bool {
+        self.score >= MIN_SCORE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    CommonPassword,
+    Word,
+    Sequence,
+    Keyboard,
+    Repeat,
+    Year,
+    BruteForce,
+}
+
+/// A pattern covering `chars[start..end]`
+#[derive(Debug, Clone, Copy)]
+struct Match {
+    start: usize,
+    end: usize,
+    bits: f64,
+    pattern: Pattern,
+    capitalized: bool,
+    leet: bool,
+}
+
+impl Match {
+    fn new(start: usize, end: usize, bits: f64, pattern: Pattern) -> Self {
+        Self {
+            start,
+            end,
+            bits,
+            pattern,
+            capitalized: false,
+            leet: false,
+        }
+    }
+}
+
+/// Estimate the strength of a passphrase
+pub fn estimate(password: &str) -> PasswordStrength {
+    let chars: Vec<char> = password.chars().collect();
+    let (entropy_bits, matches) = cheapest_split(&chars);
+    let score = SCORE_THRESHOLDS.iter().filter(|&&bits| entropy_bits >= bits).count() as u8;
+    let average_guesses = (entropy_bits - 1.0).exp2();
+    let crack_time = Duration::try_from_secs_f64(average_guesses / GUESSES_PER_SECOND).unwrap_or(Duration::MAX);
+    PasswordStrength {
+        score,
+        entropy_bits,
+        crack_time,
+        feedback: feedback(&chars, &matches, score),
+    }
+}
+
+/// Lowest-entropy split of `chars` into patterns
+fn cheapest_split(chars: &[char]) -> (f64, Vec<Match>) {
+    let n = chars.len();
+    let mut by_end: Vec<Vec<Match>> = vec![Vec::new(); n + 1];
+    for found in find_matches(chars) {
+        by_end[found.end].push(found);
+    }
+
+    let mut best = vec![f64::INFINITY; n + 1];
+    let mut choice: Vec<Option<Match>> = vec![None; n + 1];
+    best[0] = 0.0;
+    for end in 1..=n {
+        let brute_force = Match::new(end - 1, end, brute_force_bits(chars, end - 1), Pattern::BruteForce);
+        for candidate in by_end[end].iter().chain(std::iter::once(&brute_force)) {
+            let total = best[candidate.start] + candidate.bits;
+            if total < best[end] {
+                best[end] = total;
+                choice[end] = Some(*candidate);
+            }
+        }
+    }
+
+    let mut matches = Vec::new();
+    let mut end = n;
+    while end > 0 {
+        let chosen = choice[end].expect("every position is reachable by brute force");
+        end = chosen.start;
+        matches.push(chosen);
+    }
+    matches.reverse();
+    (best[n], matches)
+}
+
+fn find_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    dictionary_matches(chars, &mut matches);
+    sequence_matches(chars, &mut matches);
+    keyboard_matches(chars, &mut matches);
+    repeat_matches(chars, &mut matches);
+    year_matches(chars, &mut matches);
+    matches
+}
+
+/// Rank of each dictionary entry, common passwords first
+fn dictionary() -> &'static HashMap<&'static str, usize> {
+    static DICTIONARY: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+    DICTIONARY.get_or_init(|| {
+        let mut ranks = HashMap::new();
+        for (rank, word) in COMMON_PASSWORDS.iter().enumerate() {
+            ranks.entry(*word).or_insert(rank + 1);
+        }
+        let words = crate::utils::wordlist();
+        for word in words {
+            ranks.entry(*word).or_insert(words.len());
+        }
+        ranks
+    })
+}
+
+/// Leet substitutions undone for dictionary lookups, with `1` read as `i`
+/// or as `l`
+fn unleet(c: char, one_as_l: bool) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '8' => 'b',
+        '(' => 'c',
+        '3' => 'e',
+        '6' | '9' => 'g',
+        '1' | '!' | '|' if one_as_l => 'l',
+        '1' | '!' | '|' => 'i',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '2' => 'z',
+        c => c,
+    }
+}
+
+fn dictionary_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let dictionary = dictionary();
+    let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    for start in 0..chars.len() {
+        for end in start + 3..=chars.len().min(start + MAX_WORD_LEN) {
+            let plain: String = lower[start..end].iter().collect();
+            let variants = [
+                plain.clone(),
+                lower[start..end].iter().map(|&c| unleet(c, false)).collect(),
+                lower[start..end].iter().map(|&c| unleet(c, true)).collect(),
+            ];
+            for variant in &variants {
+                let Some(&rank) = dictionary.get(variant.as_str()) else {
+                    continue;
+                };
+                let substitutions = variant.chars().zip(plain.chars()).filter(|(a, b)| a != b).count();
+                let capitals = capitalization_bits(&chars[start..end]);
+                let pattern = if rank <= COMMON_PASSWORDS.len() {
+                    Pattern::CommonPassword
+                } else {
+                    Pattern::Word
+                };
+                matches.push(Match {
+                    capitalized: capitals > 0.0,
+                    leet: substitutions > 0,
+                    ..Match::new(start, end, (rank as f64).log2() + capitals + substitutions as f64, pattern)
+                });
+            }
+        }
+    }
+}
+
+/// Extra bits for the capital letters of a dictionary match
+fn capitalization_bits(word: &[char]) -> f64 {
+    let upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let lower = word.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 0.0;
+    }
+    let first_only = word[0].is_uppercase() && upper == 1;
+    let last_only = word[word.len() - 1].is_uppercase() && upper == 1;
+    if lower == 0 || first_only || last_only {
+        return 1.0;
+    }
+    // Any placement of the smaller set of letters
+    let variations: f64 = (1..=upper.min(lower)).map(|k| binomial(upper + lower, k)).sum();
+    variations.log2()
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+/// Runs like `abcd`, `6543` or `XYZ`
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    for start in 0..chars.len() {
+        let first = chars[start];
+        let Some(alphabet) = sequence_alphabet(first) else {
+            continue;
+        };
+        let mut step = 0i32;
+        let mut end = start + 1;
+        while end < chars.len() && sequence_alphabet(chars[end]) == Some(alphabet) {
+            let delta = chars[end] as i32 - chars[end - 1] as i32;
+            if delta.abs() != 1 || (step != 0 && delta != step) {
+                break;
+            }
+            step = delta;
+            end += 1;
+            if end - start >= 3 {
+                let obvious_start = matches!(first, 'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9');
+                let start_bits = if obvious_start { 1.0 } else { (alphabet as f64).log2() };
+                let direction_bits = if step < 0 { 1.0 } else { 0.0 };
+                let bits = start_bits + ((end - start) as f64).log2() + direction_bits;
+                matches.push(Match::new(start, end, bits, Pattern::Sequence));
+            }
+        }
+    }
+}
+
+/// Size of the alphabet a sequence character belongs to
+fn sequence_alphabet(c: char) -> Option<usize> {
+    match c {
+        'a'..='z' | 'A'..='Z' => Some(26),
+        '0'..='9' => Some(10),
+        _ => None,
+    }
+}
+
+/// Runs along a keyboard row, like `qwerty` or `poiu`
+fn keyboard_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let keys: usize = KEYBOARD_ROWS.iter().map(|row| row.chars().count()).sum();
+    for row in KEYBOARD_ROWS {
+        let row: Vec<char> = row.chars().collect();
+        for reversed in [false, true] {
+            let row: Vec<char> = if reversed { row.iter().rev().copied().collect() } else { row.clone() };
+            for start in 0..chars.len() {
+                let first = chars[start].to_lowercase().next().unwrap_or(chars[start]);
+                let Some(position) = row.iter().position(|&key| key == first) else {
+                    continue;
+                };
+                let mut end = start + 1;
+                while end < chars.len()
+                    && row.get(position + end - start) == chars[end].to_lowercase().next().as_ref()
+                {
+                    end += 1;
+                    if end - start >= 4 {
+                        let bits = (keys as f64).log2() + ((end - start) as f64).log2() + reversed as u8 as f64;
+                        matches.push(Match::new(start, end, bits, Pattern::Keyboard));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Repeated characters or blocks, like `aaaa` or `abcabc`
+fn repeat_matches(chars: &[char], matches: &mut Vec<Match>) {
+    for start in 0..chars.len() {
+        // Shortest block that repeats from here
+        let repeat = (1..=(chars.len() - start) / 2).find_map(|len| {
+            let block = &chars[start..start + len];
+            let copies = chars[start..].chunks_exact(len).take_while(|chunk| *chunk == block).count();
+            (copies >= 2).then_some((len, copies))
+        });
+        let Some((len, copies)) = repeat else {
+            continue;
+        };
+        let (block_bits, _) = cheapest_split(&chars[start..start + len]);
+        for count in 2..=copies {
+            let bits = block_bits + (count as f64).log2();
+            matches.push(Match::new(start, start + len * count, bits, Pattern::Repeat));
+        }
+    }
+}
+
+/// Years from 1900 to 2099
+fn year_matches(chars: &[char], matches: &mut Vec<Match>) {
+    for start in 0..chars.len().saturating_sub(3) {
+        let digits: String = chars[start..start + 4].iter().collect();
+        if let Ok(year) = digits.parse::<u32>() {
+            if (1900..=2099).contains(&year) && digits.chars().all(|c| c.is_ascii_digit()) {
+                matches.push(Match::new(start, start + 4, 200f64.log2(), Pattern::Year));
+            }
+        }
+    }
+}
+
+/// Bits to brute-force `chars[index]`
+///
+/// A symbol already used earlier is taken for a separator and costs a
+/// single bit, so `correct horse battery staple` is not credited for its
+/// spaces.
+fn brute_force_bits(chars: &[char], index: usize) -> f64 {
+    let c = chars[index];
+    if !c.is_alphanumeric() && chars[..index].contains(&c) {
+        return 1.0;
+    }
+    cardinality(c).log2()
+}
+
+/// Brute-force alphabet size for one character
+fn cardinality(c: char) -> f64 {
+    match c {
+        'a'..='z' | 'A'..='Z' => 26.0,
+        '0'..='9' => 10.0,
+        ' '..='~' => 33.0,
+        _ => 100.0,
+    }
+}
+
+fn feedback(chars: &[char], matches: &[Match], score: u8) -> Vec<String> {
+    if score >= MIN_SCORE {
+        return Vec::new();
+    }
+    let mut feedback: Vec<String> = Vec::new();
+    let mut add = |message: &str| {
+        if !feedback.iter().any(|known| known == message) {
+            feedback.push(message.to_string());
+        }
+    };
+    if chars.is_empty() {
+        add("Enter a passphrase");
+    }
+    for found in matches {
+        match found.pattern {
+            Pattern::CommonPassword => add("This is one of the most common passwords"),
+            Pattern::Word if matches.len() == 1 => add("A single word is easy to guess"),
+            Pattern::Sequence => add("Sequences like abc or 6543 are easy to guess"),
+            Pattern::Keyboard => add("Keyboard patterns like qwerty are easy to guess"),
+            Pattern::Repeat => add("Repeated characters or blocks add little strength"),
+            Pattern::Year => add("Years are easy to guess"),
+            Pattern::Word | Pattern::BruteForce => {}
+        }
+        if found.capitalized {
+            add("Capital letters on words add little strength");
+        }
+        if found.leet {
+            add("Predictable substitutions like '@' for 'a' add little strength");
+        }
+    }
+    add("Use a longer passphrase, for instance several random words");
+    feedback
+}
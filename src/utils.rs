@@ -1,7 +1,10 @@
 //! Utility functions for DeepVault
 
+use crate::secret::SecretString;
+use crate::strength;
 use crate::{DeepVaultError, Result};
-use std::path::PathBuf;
+use std::path::Path;
+use std::sync::OnceLock;
 
 /// Check if running with administrator privileges
 pub fn is_admin() -> bool {
@@ -92,30 +95,42 @@ pub fn format_bytes(bytes: u64) -> String {
     }
 }
 
+/// Longest password accepted, only to bound the cost of the strength estimate
+pub const MAX_PASSWORD_BYTES: usize = 1024;
+
 /// Validate password strength
+///
+/// The password must reach [`strength::MIN_SCORE`]; the error carries the
+/// estimator's advice.
 pub fn validate_password(password: &str) -> Result<()> {
     if password.len() < 8 {
         return Err(DeepVaultError::InvalidInput("Password must be at least 8 characters long".to_string()));
     }
     
-    if password.len() > 128 {
-        return Err(DeepVaultError::InvalidInput("Password must be less than 128 characters long".to_string()));
+    if password.len() > MAX_PASSWORD_BYTES {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "Password must be at most {} bytes long",
+            MAX_PASSWORD_BYTES
+        )));
     }
     
-    let has_upper = password.chars().any(|c| c.is_uppercase());
-    let has_lower = password.chars().any(|c| c.is_lowercase());
-    let has_digit = password.chars().any(|c| c.is_numeric());
-    let has_special = password.chars().any(|c| "!@#$%^&*()_+-=[]{}|;:,.<>?".contains(c));
-    
-    if !has_upper || !has_lower || !has_digit || !has_special {
-        return Err(DeepVaultError::InvalidInput(
-            "Password must contain at least one uppercase letter, lowercase letter, digit, and special character".to_string()
-        ));
+    let strength = strength::estimate(password);
+    if !strength.is_acceptable() {
+        return Err(DeepVaultError::InvalidInput(format!(
+            "Password is too weak ({:.0} bits): {}",
+            strength.entropy_bits,
+            strength.feedback.join(". ")
+        )));
     }
     
     Ok(())
 }
 
+/// Current time in Unix seconds
+pub fn now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
 /// Generate a secure random string
 pub fn generate_random_string(length: usize) -> Result<String> {
     use rand::Rng;
@@ -133,8 +148,34 @@ pub fn generate_random_string(length: usize) -> Result<String> {
     Ok(password)
 }
 
+/// Words in a generated passphrase by default (66 bits)
+pub const DEFAULT_PASSPHRASE_WORDS: usize = 6;
+
+/// The embedded passphrase word list (the 2048-word BIP-39 English list)
+pub fn wordlist() -> &'static [&'static str] {
+    static WORDLIST: OnceLock<Vec<&'static str>> = OnceLock::new();
+    WORDLIST.get_or_init(|| include_str!("wordlist.txt").lines().collect())
+}
+
+/// Generate a diceware-style passphrase of random words
+///
+/// Each word adds 11 bits of entropy.
+pub fn generate_passphrase(words: usize, separator: &str) -> Result<SecretString> {
+    use rand::seq::SliceRandom;
+    
+    if words == 0 {
+        return Err(DeepVaultError::InvalidInput("A passphrase needs at least one word".to_string()));
+    }
+    let mut rng = rand::rngs::OsRng;
+    let chosen: Vec<&str> = (0..words)
+        .map(|_| *wordlist().choose(&mut rng).expect("the word list is not empty"))
+        .collect();
+    
+    Ok(SecretString::new(chosen.join(separator)))
+}
+
 /// Check if a path is a valid device path
-pub fn is_valid_device_path(path: &PathBuf) -> bool {
+pub fn is_valid_device_path(path: &Path) -> bool {
     #[cfg(target_os = "linux")]
     {
         path.to_string_lossy().starts_with("/dev/")
@@ -0,0 +1,1471 @@
+//! Vault file system
+//!
+//! A small file system that stores files and directories directly on any
+//! [`BlockDevice`], typically an open volume or container, so the vault
+//! can be used without mounting it through the operating system.
+//!
+//! The device is divided into 4 KiB blocks:
+//!
+//! | Block               | Field                                          |
+//! |---------------------|------------------------------------------------|
+//! | 0                   | Superblock: magic `DVFS`, version, block,      |
+//! |                     | inode and journal sizes, free block and inode  |
+//! |                     | counts, sequence of the last transaction       |
+//! | 1                   | Metadata journal, 1/32 of the device           |
+//! |                     | (64 blocks to 32 MiB)                          |
+//! | after the journal   | Free-space bitmap, one bit per block           |
+//! | after the bitmap    | Inode table, 256 bytes per inode               |
+//! | after the table     | Data blocks of files and directories           |
+//!
+//! An inode records the type, size and times of a file, and the extents
+//! (runs of consecutive blocks) holding its contents. The first 12 extents
+//! are kept in the inode, the others in a chain of extent blocks.
+//! Directories are files of entries: inode, type and a name of up to 255
+//! bytes. An entry never spans two blocks and a block ends at an entry
+//! with inode 0, so adding or removing an entry rewrites a single block.
+//! Inode 1 is the root directory.
+//!
+//! Each operation is a transaction: metadata changes are kept in memory
+//! and written together at the end, and the blocks it frees are only
+//! reused by later operations. A failed operation leaves the file system
+//! as it was, except for data overwritten in place in existing files.
+//!
+//! Transactions are written ahead to the journal: a descriptor (magic
+//! `DVJL`, sequence number, target blocks, SHA-256 of the record) followed
+//! by the new metadata blocks. Once the record is flushed, the blocks are
+//! written in place, then the superblock with the new sequence number.
+//! On open, a complete record newer than the superblock is replayed, so
+//! after a power loss every operation is either fully applied or not at
+//! all. New file contents are written to free blocks before the record,
+//! so [`VaultFs::write`] replaces a file atomically, and appending or
+//! truncating never changes the old contents; [`VaultFs::write_at`]
+//! overwrites existing bytes in place. The superblock is written alone,
+//! and its fields fit in its first sector, which the device writes
+//! atomically.
+
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::{utils, DeepVaultError, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+
+/// Size of a file system block
+pub const FS_BLOCK_SIZE: u64 = 4096;
+/// Longest file name, in bytes
+pub const MAX_NAME_LEN: usize = 255;
+
+const BLOCK: usize = FS_BLOCK_SIZE as usize;
+const SECTORS_PER_BLOCK: u64 = FS_BLOCK_SIZE / SECTOR_SIZE as u64;
+/// Magic of the superblock
+const MAGIC: &[u8; 4] = b"DVFS";
+/// Version of the file system layout
+const VERSION: u32 = 3;
+/// Smallest device that can be formatted, in blocks
+const MIN_BLOCKS: u64 = 128;
+/// Magic of a journal descriptor
+const JOURNAL_MAGIC: &[u8; 4] = b"DVJL";
+/// Bounds of the journal size, in blocks
+const MIN_JOURNAL_BLOCKS: u64 = 64;
+const MAX_JOURNAL_BLOCKS: u64 = 8192;
+/// Bytes before the target list in a journal descriptor
+const DESCRIPTOR_HEADER: usize = 48;
+const BITS_PER_BLOCK: u64 = FS_BLOCK_SIZE * 8;
+const INODE_SIZE: usize = 256;
+const INODES_PER_BLOCK: u64 = (BLOCK / INODE_SIZE) as u64;
+/// One inode is reserved for every 16 KiB of device
+const BYTES_PER_INODE: u64 = 16 * 1024;
+const ROOT_INODE: u32 = 1;
+const KIND_FILE: u8 = 1;
+const KIND_DIRECTORY: u8 = 2;
+/// Extents stored in the inode itself
+const INLINE_EXTENTS: usize = 12;
+const EXTENT_SIZE: usize = 12;
+/// Extents stored in each block of the extent chain
+const CHAIN_EXTENTS: usize = (BLOCK - 16) / EXTENT_SIZE;
+/// Bytes before the name in a directory entry
+const ENTRY_HEADER: usize = 6;
+/// Metadata blocks changed by removing one entry, besides the bitmap: its
+/// inode, the directory block and inode, and a directory extent block
+const REMOVAL_BLOCKS: usize = 4;
+/// Largest run of blocks read or written at once
+const MAX_IO_BLOCKS: u64 = 256;
+
+/// Type of a file system entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FileKind {
+    File,
+    Directory,
+}
+
+/// Information about a file or directory
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+    pub inode: u32,
+    pub kind: FileKind,
+    /// Size in bytes; for directories, the size of their entry blocks
+    pub size: u64,
+    /// Creation time in Unix seconds
+    pub created: u64,
+    /// Last modification time in Unix seconds
+    pub modified: u64,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Directory
+    }
+}
+
+/// An entry returned by [`VaultFs::read_dir`]
+#[derive(Debug, Clone, Serialize)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// Space usage of the file system
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FsStats {
+    pub block_size: u64,
+    /// Blocks available for file data and directories
+    pub total_blocks: u64,
+    pub free_blocks: u64,
+    pub total_inodes: u64,
+    pub free_inodes: u64,
+}
+
+/// Position of the metadata areas, derived from the block and inode counts
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    block_count: u64,
+    inode_count: u32,
+    journal_start: u64,
+    journal_blocks: u64,
+    bitmap_start: u64,
+    inode_start: u64,
+    data_start: u64,
+}
+
+impl Layout {
+    fn new(block_count: u64, inode_count: u32, journal_blocks: u64) -> Self {
+        let journal_start = 1;
+        let bitmap_start = journal_start + journal_blocks;
+        let inode_start = bitmap_start + block_count.div_ceil(BITS_PER_BLOCK);
+        let data_start = inode_start + inode_count as u64 / INODES_PER_BLOCK;
+        Self {
+            block_count,
+            inode_count,
+            journal_start,
+            journal_blocks,
+            bitmap_start,
+            inode_start,
+            data_start,
+        }
+    }
+
+    fn bitmap_blocks(&self) -> u64 {
+        self.inode_start - self.bitmap_start
+    }
+
+    fn inode_blocks(&self) -> u64 {
+        self.data_start - self.inode_start
+    }
+}
+
+/// A run of consecutive blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Extent {
+    start: u64,
+    len: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Inode {
+    kind: FileKind,
+    size: u64,
+    created: u64,
+    modified: u64,
+    /// Blocks of the contents, in file order
+    extents: Vec<Extent>,
+    /// Blocks of the extent chain
+    chain: Vec<u64>,
+}
+
+impl Inode {
+    fn new(kind: FileKind) -> Self {
+        let now = utils::now();
+        Self {
+            kind,
+            size: 0,
+            created: now,
+            modified: now,
+            extents: Vec::new(),
+            chain: Vec::new(),
+        }
+    }
+
+    fn blocks(&self) -> u64 {
+        self.extents.iter().map(|e| e.len).sum()
+    }
+
+    /// Physical block of the file block `logical`, and the number of
+    /// blocks that follow it contiguously
+    fn locate(&self, logical: u64) -> Result<(u64, u64)> {
+        let mut base = 0;
+        for extent in &self.extents {
+            if logical < base + extent.len {
+                let skip = logical - base;
+                return Ok((extent.start + skip, extent.len - skip));
+            }
+            base += extent.len;
+        }
+        Err(corrupted(format!("Block {} of a file is not allocated", logical)))
+    }
+
+    fn metadata(&self, inode: u32) -> Metadata {
+        Metadata {
+            inode,
+            kind: self.kind,
+            size: self.size,
+            created: self.created,
+            modified: self.modified,
+        }
+    }
+}
+
+/// Entry of a directory as stored on the device
+#[derive(Debug, Clone)]
+struct RawEntry {
+    inode: u32,
+    kind: FileKind,
+    name: String,
+}
+
+/// File system stored on a block device
+pub struct VaultFs<D> {
+    device: D,
+    layout: Layout,
+    free_blocks: u64,
+    free_inodes: u32,
+    bitmap: Vec<u8>,
+    /// Bitmap blocks changed by the current operation
+    dirty_bitmap: BTreeSet<u64>,
+    /// Metadata blocks changed by the current operation
+    dirty: BTreeMap<u64, Vec<u8>>,
+    /// Extents freed by the current operation, released when it is written
+    released: Vec<Extent>,
+    /// Sequence number of the last transaction written
+    sequence: u64,
+    next_block: u64,
+    next_inode: u32,
+}
+
+impl<D: BlockDevice> VaultFs<D> {
+    /// Create an empty file system on `device`, erasing its contents
+    pub fn format(device: D) -> Result<Self> {
+        let block_count = device.sector_count() / SECTORS_PER_BLOCK;
+        let inodes = (block_count * FS_BLOCK_SIZE / BYTES_PER_INODE)
+            .clamp(INODES_PER_BLOCK, u32::MAX as u64 / INODES_PER_BLOCK * INODES_PER_BLOCK);
+        let journal_blocks = (block_count / 32).clamp(MIN_JOURNAL_BLOCKS, MAX_JOURNAL_BLOCKS);
+        let layout = Layout::new(block_count, inodes.next_multiple_of(INODES_PER_BLOCK) as u32, journal_blocks);
+        if block_count < MIN_BLOCKS || layout.data_start + 1 >= block_count {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "A vault file system needs at least {} bytes",
+                MIN_BLOCKS * FS_BLOCK_SIZE
+            )));
+        }
+
+        let mut fs = Self {
+            device,
+            layout,
+            free_blocks: block_count,
+            free_inodes: layout.inode_count - ROOT_INODE - 1,
+            bitmap: vec![0; (layout.bitmap_blocks() as usize) * BLOCK],
+            dirty_bitmap: (0..layout.bitmap_blocks()).collect(),
+            dirty: BTreeMap::new(),
+            released: Vec::new(),
+            sequence: 0,
+            next_block: layout.data_start,
+            next_inode: ROOT_INODE + 1,
+        };
+        for block in 0..layout.data_start {
+            fs.set_used(block, true);
+        }
+        fs.free_blocks -= layout.data_start;
+
+        // An old journal record must not be replayed over the new layout
+        let zeros = vec![0u8; (MAX_IO_BLOCKS as usize) * BLOCK];
+        fs.write_raw(layout.journal_start, &zeros[..BLOCK])?;
+        let mut block = layout.inode_start;
+        while block < layout.data_start {
+            let count = (layout.data_start - block).min(MAX_IO_BLOCKS);
+            fs.write_raw(block, &zeros[..(count as usize) * BLOCK])?;
+            block += count;
+        }
+
+        let mut root = Inode::new(FileKind::Directory);
+        fs.store_inode(ROOT_INODE, &mut root)?;
+        fs.commit()?;
+        Ok(fs)
+    }
+
+    /// Open the file system stored on `device`, completing the last
+    /// transaction if it was interrupted
+    pub fn open(device: D) -> Result<Self> {
+        let mut fs = Self {
+            device,
+            layout: Layout::new(0, 0, 0),
+            free_blocks: 0,
+            free_inodes: 0,
+            bitmap: Vec::new(),
+            dirty_bitmap: BTreeSet::new(),
+            dirty: BTreeMap::new(),
+            released: Vec::new(),
+            sequence: 0,
+            next_block: 0,
+            next_inode: ROOT_INODE + 1,
+        };
+        fs.recover()?;
+        fs.next_block = fs.layout.data_start;
+        Ok(fs)
+    }
+
+    /// Whether `device` holds a vault file system
+    pub fn probe(device: &mut D) -> Result<bool> {
+        if device.sector_count() < SECTORS_PER_BLOCK {
+            return Ok(false);
+        }
+        let mut sb = vec![0u8; BLOCK];
+        device.read_sectors(0, &mut sb)?;
+        Ok(&sb[..4] == MAGIC)
+    }
+
+    /// Information about the file or directory at `path`
+    pub fn metadata(&mut self, path: &str) -> Result<Metadata> {
+        let ino = self.resolve(path)?;
+        Ok(self.load_inode(ino)?.metadata(ino))
+    }
+
+    /// Entries of the directory at `path`, in creation order
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>> {
+        let ino = self.resolve(path)?;
+        let (_, entries) = self.read_entries(ino)?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                let metadata = self.load_inode(entry.inode)?.metadata(entry.inode);
+                Ok(DirEntry {
+                    name: entry.name,
+                    metadata,
+                })
+            })
+            .collect()
+    }
+
+    /// Create an empty directory; its parent must exist
+    pub fn create_dir(&mut self, path: &str) -> Result<()> {
+        self.transaction(|fs| fs.create_node(path, FileKind::Directory).map(|_| ()))
+    }
+
+    /// Create an empty file; its parent must exist
+    pub fn create(&mut self, path: &str) -> Result<()> {
+        self.transaction(|fs| fs.create_node(path, FileKind::File).map(|_| ()))
+    }
+
+    /// Read the whole file at `path`
+    pub fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        let (_, inode) = self.file_inode(path)?;
+        let mut data = vec![0u8; inode.size as usize];
+        self.read_data(&inode, 0, &mut data)?;
+        Ok(data)
+    }
+
+    /// Read from the file at `path` starting at `offset`
+    ///
+    /// Returns the number of bytes read, less than `buf.len()` at the end
+    /// of the file.
+    pub fn read_at(&mut self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let (_, inode) = self.file_inode(path)?;
+        self.read_data(&inode, offset, buf)
+    }
+
+    /// Create or replace the file at `path` with `data`
+    ///
+    /// The old contents stay in place until the new ones are written, so
+    /// replacing a file needs free space for the new contents.
+    pub fn write(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        self.transaction(|fs| {
+            let (parent, name) = fs.resolve_parent(path)?;
+            let (ino, mut inode) = match fs.lookup(parent, &name)? {
+                Some(entry) => {
+                    let mut inode = fs.load_inode(entry.inode)?;
+                    if inode.kind == FileKind::Directory {
+                        return Err(fs_error(ErrorKind::IsADirectory, format!("{} is a directory", path)));
+                    }
+                    fs.set_block_count(&mut inode, 0)?;
+                    inode.size = 0;
+                    (entry.inode, inode)
+                }
+                None => fs.create_node(path, FileKind::File)?,
+            };
+            fs.write_data(&mut inode, 0, data)?;
+            inode.modified = utils::now();
+            fs.store_inode(ino, &mut inode)
+        })
+    }
+
+    /// Write `data` into the existing file at `path` at `offset`, growing
+    /// the file if needed
+    ///
+    /// A gap between the end of the file and `offset` reads as zeros.
+    pub fn write_at(&mut self, path: &str, offset: u64, data: &[u8]) -> Result<()> {
+        self.transaction(|fs| {
+            let (ino, mut inode) = fs.file_inode(path)?;
+            fs.write_data(&mut inode, offset, data)?;
+            inode.modified = utils::now();
+            fs.store_inode(ino, &mut inode)
+        })
+    }
+
+    /// Set the size of the file at `path`, zero-filling when it grows
+    pub fn truncate(&mut self, path: &str, size: u64) -> Result<()> {
+        self.transaction(|fs| {
+            let (ino, mut inode) = fs.file_inode(path)?;
+            fs.set_size(&mut inode, size)?;
+            inode.modified = utils::now();
+            fs.store_inode(ino, &mut inode)
+        })
+    }
+
+    /// Remove a file or an empty directory
+    pub fn remove(&mut self, path: &str) -> Result<()> {
+        self.transaction(|fs| fs.remove_node(path))
+    }
+
+    /// Remove a file, or a directory with everything in it
+    ///
+    /// A large tree is removed in several transactions, each small enough
+    /// for the journal, so an error or a power loss may leave part of it.
+    pub fn remove_all(&mut self, path: &str) -> Result<()> {
+        while !self.transaction(|fs| fs.remove_tree_part(path))? {}
+        Ok(())
+    }
+
+    /// Move `from` to `to`, replacing a file or an empty directory there
+    ///
+    /// A directory cannot be moved inside itself, and files and
+    /// directories cannot replace each other.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        self.transaction(|fs| fs.rename_node(from, to))
+    }
+
+    /// Space and inode usage
+    pub fn statfs(&self) -> FsStats {
+        FsStats {
+            block_size: FS_BLOCK_SIZE,
+            total_blocks: self.layout.block_count - self.layout.data_start,
+            free_blocks: self.free_blocks,
+            total_inodes: (self.layout.inode_count - ROOT_INODE) as u64,
+            free_inodes: self.free_inodes as u64,
+        }
+    }
+
+    /// Verify the metadata: every block and inode in use must be reached
+    /// once from the root, and the bitmap and free counts must match
+    pub fn check(&mut self) -> Result<()> {
+        let layout = self.layout;
+        let mut owned = vec![false; layout.block_count as usize];
+        owned[..layout.data_start as usize].fill(true);
+        let mut inodes = BTreeSet::new();
+        let mut pending = vec![(ROOT_INODE, FileKind::Directory)];
+        while let Some((ino, kind)) = pending.pop() {
+            if !inodes.insert(ino) {
+                return Err(corrupted(format!("Inode {} is linked twice", ino)));
+            }
+            let inode = self.load_inode(ino)?;
+            if inode.kind != kind {
+                return Err(corrupted(format!("Inode {} does not have the type of its entry", ino)));
+            }
+            let chain = inode.chain.iter().map(|&start| Extent { start, len: 1 });
+            for extent in inode.extents.iter().copied().chain(chain) {
+                for block in extent.start..extent.start + extent.len {
+                    if std::mem::replace(&mut owned[block as usize], true) {
+                        return Err(corrupted(format!("Block {} is used twice", block)));
+                    }
+                }
+            }
+            if kind == FileKind::Directory {
+                let (_, entries) = self.read_entries(ino)?;
+                pending.extend(entries.iter().map(|e| (e.inode, e.kind)));
+            }
+        }
+
+        for (block, &owned) in owned.iter().enumerate() {
+            if owned != self.is_used(block as u64) {
+                return Err(corrupted(format!("Block {} is wrongly marked in the bitmap", block)));
+            }
+        }
+        let free_blocks = owned.iter().filter(|&&owned| !owned).count() as u64;
+        let mut used_inodes = 0;
+        for index in 0..layout.inode_blocks() {
+            let data = self.read_meta(layout.inode_start + index)?;
+            used_inodes += data.chunks_exact(INODE_SIZE).filter(|raw| raw[0] != 0).count();
+        }
+        if used_inodes != inodes.len() {
+            return Err(corrupted(format!(
+                "{} inodes are in use but not linked",
+                used_inodes.abs_diff(inodes.len())
+            )));
+        }
+        let free_inodes = (layout.inode_count - ROOT_INODE) as usize - used_inodes;
+        if free_blocks != self.free_blocks || free_inodes != self.free_inodes as usize {
+            return Err(corrupted("The free counts do not match the bitmap and inode table".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Flush every write to the device
+    pub fn flush(&mut self) -> Result<()> {
+        self.device.flush()
+    }
+
+    /// Consume the file system and return the device
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Run `op` as one transaction, written at the end or dropped on error
+    fn transaction<T>(&mut self, op: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let result = op(self).and_then(|value| self.commit().map(|_| value));
+        if result.is_err() {
+            let _ = self.abort();
+        }
+        result
+    }
+
+    /// Write the changes of the current operation
+    fn commit(&mut self) -> Result<()> {
+        let released = std::mem::take(&mut self.released);
+        for extent in &released {
+            for block in extent.start..extent.start + extent.len {
+                self.set_used(block, false);
+            }
+            self.free_blocks += extent.len;
+        }
+        for index in std::mem::take(&mut self.dirty_bitmap) {
+            let start = (index as usize) * BLOCK;
+            let block = self.bitmap[start..start + BLOCK].to_vec();
+            self.dirty.insert(self.layout.bitmap_start + index, block);
+        }
+        self.sequence += 1;
+        let superblock = self.encode_superblock();
+        self.dirty.insert(0, superblock);
+
+        let blocks = std::mem::take(&mut self.dirty);
+        self.write_journal(&blocks)?;
+        self.checkpoint(&blocks)?;
+        for extent in released {
+            self.device
+                .discard(extent.start * SECTORS_PER_BLOCK, extent.len * SECTORS_PER_BLOCK)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the changes of a failed operation
+    fn abort(&mut self) -> Result<()> {
+        self.dirty.clear();
+        self.dirty_bitmap.clear();
+        self.released.clear();
+        self.recover()
+    }
+
+    /// Write the metadata blocks of a transaction to the journal
+    fn write_journal(&mut self, blocks: &BTreeMap<u64, Vec<u8>>) -> Result<()> {
+        let descriptor_blocks = descriptor_blocks(blocks.len());
+        if descriptor_blocks + blocks.len() as u64 > self.layout.journal_blocks {
+            return Err(DeepVaultError::InvalidInput(format!(
+                "The operation changes {} metadata blocks, more than the journal holds",
+                blocks.len()
+            )));
+        }
+        let mut record = vec![0u8; (descriptor_blocks as usize) * BLOCK];
+        record[..4].copy_from_slice(JOURNAL_MAGIC);
+        record[4..8].copy_from_slice(&(blocks.len() as u32).to_le_bytes());
+        record[8..16].copy_from_slice(&self.sequence.to_le_bytes());
+        for (i, block) in blocks.keys().enumerate() {
+            let at = DESCRIPTOR_HEADER + i * 8;
+            record[at..at + 8].copy_from_slice(&block.to_le_bytes());
+        }
+        for data in blocks.values() {
+            record.extend_from_slice(data);
+        }
+        let checksum = journal_checksum(&record);
+        record[16..48].copy_from_slice(&checksum);
+        // File data written outside the journal must be durable before the
+        // record that points to it
+        self.device.flush()?;
+        self.write_raw(self.layout.journal_start, &record)?;
+        self.device.flush()
+    }
+
+    /// Write journaled blocks in place, the superblock last
+    fn checkpoint(&mut self, blocks: &BTreeMap<u64, Vec<u8>>) -> Result<()> {
+        for (&block, data) in blocks.range(1..) {
+            self.write_raw(block, data)?;
+        }
+        self.device.flush()?;
+        if let Some(superblock) = blocks.get(&0) {
+            self.write_raw(0, superblock)?;
+            self.device.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Load the state from the device, completing an interrupted
+    /// transaction
+    fn recover(&mut self) -> Result<()> {
+        self.load_state()?;
+        if self.replay()? {
+            self.load_state()?;
+        }
+        Ok(())
+    }
+
+    /// Write in place the journal record of a transaction newer than the
+    /// superblock; a torn record is ignored, as none of its blocks were
+    /// written yet
+    fn replay(&mut self) -> Result<bool> {
+        let layout = self.layout;
+        let mut first = vec![0u8; BLOCK];
+        self.read_raw(layout.journal_start, &mut first)?;
+        if &first[..4] != JOURNAL_MAGIC || u64_at(&first, 8) <= self.sequence {
+            return Ok(false);
+        }
+        let count = u32_at(&first, 4) as usize;
+        let descriptor_blocks = descriptor_blocks(count);
+        if descriptor_blocks + count as u64 > layout.journal_blocks {
+            return Ok(false);
+        }
+        let mut record = vec![0u8; (descriptor_blocks as usize + count) * BLOCK];
+        self.read_raw(layout.journal_start, &mut record)?;
+        if journal_checksum(&record) != record[16..48] {
+            return Ok(false);
+        }
+
+        let payload = (descriptor_blocks as usize) * BLOCK;
+        let mut blocks = BTreeMap::new();
+        for i in 0..count {
+            let block = u64_at(&record, DESCRIPTOR_HEADER + i * 8);
+            if block >= layout.block_count || (block >= layout.journal_start && block < layout.bitmap_start) {
+                return Err(corrupted(format!("Invalid block {} in the journal", block)));
+            }
+            let at = payload + i * BLOCK;
+            blocks.insert(block, record[at..at + BLOCK].to_vec());
+        }
+        self.checkpoint(&blocks)?;
+        Ok(true)
+    }
+
+    /// Read the superblock and the bitmap from the device
+    fn load_state(&mut self) -> Result<()> {
+        let mut sb = vec![0u8; BLOCK];
+        self.read_raw(0, &mut sb)?;
+        if &sb[..4] != MAGIC {
+            return Err(DeepVaultError::InvalidHeader("No vault file system on the device".to_string()));
+        }
+        if u32_at(&sb, 4) != VERSION {
+            return Err(DeepVaultError::InvalidHeader(format!(
+                "Unsupported vault file system version {}",
+                u32_at(&sb, 4)
+            )));
+        }
+        if u32_at(&sb, 8) as u64 != FS_BLOCK_SIZE || u32_at(&sb, 12) as usize != INODE_SIZE {
+            return Err(corrupted("Unsupported block or inode size".to_string()));
+        }
+        let block_count = u64_at(&sb, 16);
+        let inode_count = u32_at(&sb, 24);
+        let journal_blocks = u64_at(&sb, 40);
+        let layout = Layout::new(block_count, inode_count, journal_blocks);
+        if block_count < MIN_BLOCKS
+            || !(MIN_JOURNAL_BLOCKS..=MAX_JOURNAL_BLOCKS).contains(&journal_blocks)
+            || block_count > self.device.sector_count() / SECTORS_PER_BLOCK
+            || !(inode_count as u64).is_multiple_of(INODES_PER_BLOCK)
+            || inode_count <= ROOT_INODE
+            || layout.data_start >= block_count
+        {
+            return Err(corrupted("Invalid superblock".to_string()));
+        }
+        let free_inodes = u32_at(&sb, 28);
+        let free_blocks = u64_at(&sb, 32);
+        if free_blocks > block_count - layout.data_start || free_inodes >= inode_count {
+            return Err(corrupted("Invalid free counts in the superblock".to_string()));
+        }
+
+        let mut bitmap = vec![0u8; (layout.bitmap_blocks() as usize) * BLOCK];
+        self.read_raw(layout.bitmap_start, &mut bitmap)?;
+        self.layout = layout;
+        self.free_blocks = free_blocks;
+        self.free_inodes = free_inodes;
+        self.sequence = u64_at(&sb, 48);
+        self.bitmap = bitmap;
+        Ok(())
+    }
+
+    fn encode_superblock(&self) -> Vec<u8> {
+        let mut sb = vec![0u8; BLOCK];
+        sb[..4].copy_from_slice(MAGIC);
+        sb[4..8].copy_from_slice(&VERSION.to_le_bytes());
+        sb[8..12].copy_from_slice(&(FS_BLOCK_SIZE as u32).to_le_bytes());
+        sb[12..16].copy_from_slice(&(INODE_SIZE as u32).to_le_bytes());
+        sb[16..24].copy_from_slice(&self.layout.block_count.to_le_bytes());
+        sb[24..28].copy_from_slice(&self.layout.inode_count.to_le_bytes());
+        sb[28..32].copy_from_slice(&self.free_inodes.to_le_bytes());
+        sb[32..40].copy_from_slice(&self.free_blocks.to_le_bytes());
+        sb[40..48].copy_from_slice(&self.layout.journal_blocks.to_le_bytes());
+        sb[48..56].copy_from_slice(&self.sequence.to_le_bytes());
+        sb
+    }
+
+    fn read_raw(&mut self, block: u64, buf: &mut [u8]) -> Result<()> {
+        self.device.read_sectors(block * SECTORS_PER_BLOCK, buf)
+    }
+
+    fn write_raw(&mut self, block: u64, buf: &[u8]) -> Result<()> {
+        self.device.write_sectors(block * SECTORS_PER_BLOCK, buf)
+    }
+
+    /// Read a metadata block, as changed by the current operation
+    fn read_meta(&mut self, block: u64) -> Result<Vec<u8>> {
+        if let Some(data) = self.dirty.get(&block) {
+            return Ok(data.clone());
+        }
+        let mut data = vec![0u8; BLOCK];
+        self.read_raw(block, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_meta(&mut self, block: u64, data: Vec<u8>) {
+        self.dirty.insert(block, data);
+    }
+
+    fn is_used(&self, block: u64) -> bool {
+        self.bitmap[(block / 8) as usize] & (1 << (block % 8)) != 0
+    }
+
+    fn set_used(&mut self, block: u64, used: bool) {
+        let byte = &mut self.bitmap[(block / 8) as usize];
+        if used {
+            *byte |= 1 << (block % 8);
+        } else {
+            *byte &= !(1 << (block % 8));
+        }
+        self.dirty_bitmap.insert(block / BITS_PER_BLOCK);
+    }
+
+    /// Allocate `count` blocks, preferably from `goal` on
+    fn allocate(&mut self, count: u64, goal: Option<u64>) -> Result<Vec<Extent>> {
+        if count > self.free_blocks {
+            return Err(fs_error(ErrorKind::StorageFull, "No space left in the vault".to_string()));
+        }
+        let layout = self.layout;
+        let mut block = goal
+            .filter(|&b| b >= layout.data_start && b < layout.block_count)
+            .unwrap_or(self.next_block);
+        let mut extents = Vec::new();
+        let mut remaining = count;
+        let mut scanned = 0;
+        while remaining > 0 {
+            if scanned > 2 * layout.block_count {
+                return Err(corrupted("The free block count does not match the bitmap".to_string()));
+            }
+            if block >= layout.block_count {
+                block = layout.data_start;
+            }
+            if block.is_multiple_of(8) && self.bitmap[(block / 8) as usize] == 0xFF {
+                block += 8;
+                scanned += 8;
+                continue;
+            }
+            if self.is_used(block) {
+                block += 1;
+                scanned += 1;
+                continue;
+            }
+            let start = block;
+            while block < layout.block_count && block - start < remaining && !self.is_used(block) {
+                self.set_used(block, true);
+                block += 1;
+            }
+            let len = block - start;
+            push_extent(&mut extents, Extent { start, len });
+            remaining -= len;
+            scanned += len;
+        }
+        self.free_blocks -= count;
+        self.next_block = block;
+        Ok(extents)
+    }
+
+    /// Free an extent once the current operation is written
+    fn release(&mut self, extent: Extent) {
+        for block in extent.start..extent.start + extent.len {
+            self.dirty.remove(&block);
+        }
+        self.released.push(extent);
+    }
+
+    fn allocate_inode(&mut self) -> Result<u32> {
+        if self.free_inodes == 0 {
+            return Err(fs_error(ErrorKind::StorageFull, "No free inode left in the vault".to_string()));
+        }
+        let blocks = self.layout.inode_blocks();
+        let first = self.next_inode as u64 / INODES_PER_BLOCK;
+        for step in 0..=blocks {
+            let index = (first + step) % blocks;
+            let data = self.read_meta(self.layout.inode_start + index)?;
+            for slot in 0..INODES_PER_BLOCK {
+                let ino = (index * INODES_PER_BLOCK + slot) as u32;
+                if ino > ROOT_INODE && data[(slot as usize) * INODE_SIZE] == 0 {
+                    self.free_inodes -= 1;
+                    self.next_inode = ino + 1;
+                    return Ok(ino);
+                }
+            }
+        }
+        Err(corrupted("The free inode count does not match the inode table".to_string()))
+    }
+
+    fn inode_location(&self, ino: u32) -> (u64, usize) {
+        let block = self.layout.inode_start + ino as u64 / INODES_PER_BLOCK;
+        (block, (ino as u64 % INODES_PER_BLOCK) as usize * INODE_SIZE)
+    }
+
+    fn load_inode(&mut self, ino: u32) -> Result<Inode> {
+        if ino == 0 || ino >= self.layout.inode_count {
+            return Err(corrupted(format!("Invalid inode number {}", ino)));
+        }
+        let (block, offset) = self.inode_location(ino);
+        let data = self.read_meta(block)?;
+        let raw = &data[offset..offset + INODE_SIZE];
+        let kind = match raw[0] {
+            KIND_FILE => FileKind::File,
+            KIND_DIRECTORY => FileKind::Directory,
+            _ => return Err(corrupted(format!("Inode {} is not in use", ino))),
+        };
+        let count = u32_at(raw, 4) as usize;
+        let mut inode = Inode {
+            kind,
+            size: u64_at(raw, 8),
+            created: u64_at(raw, 16),
+            modified: u64_at(raw, 24),
+            extents: Vec::with_capacity(count),
+            chain: Vec::new(),
+        };
+        for i in 0..count.min(INLINE_EXTENTS) {
+            inode.extents.push(read_extent(raw, 40 + i * EXTENT_SIZE));
+        }
+        let mut next = u64_at(raw, 32);
+        while inode.extents.len() < count {
+            if !self.in_data_area(next, 1) || inode.chain.len() as u64 >= self.layout.block_count {
+                return Err(corrupted(format!("Broken extent chain in inode {}", ino)));
+            }
+            inode.chain.push(next);
+            let block = self.read_meta(next)?;
+            let stored = (u32_at(&block, 8) as usize).min(CHAIN_EXTENTS);
+            for i in 0..stored.min(count - inode.extents.len()) {
+                inode.extents.push(read_extent(&block, 16 + i * EXTENT_SIZE));
+            }
+            next = u64_at(&block, 0);
+        }
+        if inode.extents.iter().any(|e| e.len == 0 || !self.in_data_area(e.start, e.len))
+            || inode.blocks() != inode.size.div_ceil(FS_BLOCK_SIZE)
+        {
+            return Err(corrupted(format!("Invalid extents in inode {}", ino)));
+        }
+        Ok(inode)
+    }
+
+    /// Write an inode and its extent chain, resizing the chain as needed
+    fn store_inode(&mut self, ino: u32, inode: &mut Inode) -> Result<()> {
+        let needed = inode.extents.len().saturating_sub(INLINE_EXTENTS).div_ceil(CHAIN_EXTENTS);
+        while inode.chain.len() > needed {
+            let block = inode.chain.pop().unwrap();
+            self.release(Extent { start: block, len: 1 });
+        }
+        if inode.chain.len() < needed {
+            let goal = inode.chain.last().map(|b| b + 1);
+            for extent in self.allocate((needed - inode.chain.len()) as u64, goal)? {
+                inode.chain.extend(extent.start..extent.start + extent.len);
+            }
+        }
+
+        let overflow = inode.extents.get(INLINE_EXTENTS..).unwrap_or(&[]);
+        for (i, extents) in overflow.chunks(CHAIN_EXTENTS).enumerate() {
+            let mut block = vec![0u8; BLOCK];
+            let next = inode.chain.get(i + 1).copied().unwrap_or(0);
+            block[0..8].copy_from_slice(&next.to_le_bytes());
+            block[8..12].copy_from_slice(&(extents.len() as u32).to_le_bytes());
+            for (j, extent) in extents.iter().enumerate() {
+                write_extent(&mut block, 16 + j * EXTENT_SIZE, extent);
+            }
+            self.write_meta(inode.chain[i], block);
+        }
+
+        let (block, offset) = self.inode_location(ino);
+        let mut data = self.read_meta(block)?;
+        let raw = &mut data[offset..offset + INODE_SIZE];
+        raw.fill(0);
+        raw[0] = match inode.kind {
+            FileKind::File => KIND_FILE,
+            FileKind::Directory => KIND_DIRECTORY,
+        };
+        raw[4..8].copy_from_slice(&(inode.extents.len() as u32).to_le_bytes());
+        raw[8..16].copy_from_slice(&inode.size.to_le_bytes());
+        raw[16..24].copy_from_slice(&inode.created.to_le_bytes());
+        raw[24..32].copy_from_slice(&inode.modified.to_le_bytes());
+        raw[32..40].copy_from_slice(&inode.chain.first().copied().unwrap_or(0).to_le_bytes());
+        for (i, extent) in inode.extents.iter().take(INLINE_EXTENTS).enumerate() {
+            write_extent(raw, 40 + i * EXTENT_SIZE, extent);
+        }
+        self.write_meta(block, data);
+        Ok(())
+    }
+
+    /// Release the blocks and the inode of a file or empty directory
+    fn free_inode(&mut self, ino: u32, inode: Inode) -> Result<()> {
+        for extent in inode.extents {
+            self.release(extent);
+        }
+        for block in inode.chain {
+            self.release(Extent { start: block, len: 1 });
+        }
+        let (block, offset) = self.inode_location(ino);
+        let mut data = self.read_meta(block)?;
+        data[offset..offset + INODE_SIZE].fill(0);
+        self.write_meta(block, data);
+        self.free_inodes += 1;
+        Ok(())
+    }
+
+    fn in_data_area(&self, start: u64, len: u64) -> bool {
+        start >= self.layout.data_start && start.checked_add(len).is_some_and(|end| end <= self.layout.block_count)
+    }
+
+    /// Allocate or release blocks so that the inode holds `blocks` blocks
+    ///
+    /// New blocks are not initialized.
+    fn set_block_count(&mut self, inode: &mut Inode, blocks: u64) -> Result<()> {
+        let current = inode.blocks();
+        if blocks > current {
+            let goal = inode.extents.last().map(|e| e.start + e.len);
+            for extent in self.allocate(blocks - current, goal)? {
+                push_extent(&mut inode.extents, extent);
+            }
+        } else if blocks < current {
+            let mut keep = blocks;
+            for extent in std::mem::take(&mut inode.extents) {
+                if keep >= extent.len {
+                    keep -= extent.len;
+                    inode.extents.push(extent);
+                    continue;
+                }
+                if keep > 0 {
+                    inode.extents.push(Extent { start: extent.start, len: keep });
+                }
+                self.release(Extent {
+                    start: extent.start + keep,
+                    len: extent.len - keep,
+                });
+                keep = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_data(&mut self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let len = (buf.len() as u64).min(inode.size - offset) as usize;
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let skip = (pos % FS_BLOCK_SIZE) as usize;
+            let (physical, run) = inode.locate(pos / FS_BLOCK_SIZE)?;
+            let count = run.min(MAX_IO_BLOCKS).min(((skip + len - done) as u64).div_ceil(FS_BLOCK_SIZE));
+            let mut chunk = vec![0u8; (count as usize) * BLOCK];
+            self.read_raw(physical, &mut chunk)?;
+            let n = (chunk.len() - skip).min(len - done);
+            buf[done..done + n].copy_from_slice(&chunk[skip..skip + n]);
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Write file contents, allocating blocks past the end of the file
+    ///
+    /// A gap after the end of the file is zero-filled. Nothing within the
+    /// old size is changed unless `data` covers it.
+    fn write_data(&mut self, inode: &mut Inode, offset: u64, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let end = offset
+            .checked_add(data.len() as u64)
+            .ok_or_else(|| DeepVaultError::InvalidInput("Write past the largest file size".to_string()))?;
+        let old_blocks = inode.blocks();
+        let first = offset / FS_BLOCK_SIZE;
+        if offset > inode.size {
+            self.zero_tail(inode)?;
+        }
+        self.set_block_count(inode, old_blocks.max(end.div_ceil(FS_BLOCK_SIZE)))?;
+        if first > old_blocks {
+            self.zero_blocks(inode, old_blocks, first)?;
+        }
+
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let logical = pos / FS_BLOCK_SIZE;
+            let skip = (pos % FS_BLOCK_SIZE) as usize;
+            let (physical, run) = inode.locate(logical)?;
+            let count = run
+                .min(MAX_IO_BLOCKS)
+                .min(((skip + data.len() - done) as u64).div_ceil(FS_BLOCK_SIZE));
+            let mut chunk = vec![0u8; (count as usize) * BLOCK];
+            let n = (chunk.len() - skip).min(data.len() - done);
+            let tail = skip + n;
+
+            // Keep the existing bytes of partially written old blocks
+            if skip != 0 && logical < old_blocks {
+                self.read_raw(physical, &mut chunk[..BLOCK])?;
+            }
+            let last = (tail / BLOCK) as u64;
+            if !tail.is_multiple_of(BLOCK) && logical + last < old_blocks && (last > 0 || skip == 0) {
+                let at = (last as usize) * BLOCK;
+                self.read_raw(physical + last, &mut chunk[at..at + BLOCK])?;
+            }
+            chunk[skip..tail].copy_from_slice(&data[done..done + n]);
+            self.write_raw(physical, &chunk)?;
+            done += n;
+        }
+        inode.size = inode.size.max(end);
+        Ok(())
+    }
+
+    fn zero_blocks(&mut self, inode: &Inode, from: u64, to: u64) -> Result<()> {
+        let zeros = vec![0u8; (MAX_IO_BLOCKS as usize) * BLOCK];
+        let mut logical = from;
+        while logical < to {
+            let (physical, run) = inode.locate(logical)?;
+            let count = run.min(MAX_IO_BLOCKS).min(to - logical);
+            self.write_raw(physical, &zeros[..(count as usize) * BLOCK])?;
+            logical += count;
+        }
+        Ok(())
+    }
+
+    /// Change the size of a file
+    ///
+    /// Shrinking only releases blocks: the bytes left past the end of the
+    /// last block are zeroed when the file grows again.
+    fn set_size(&mut self, inode: &mut Inode, size: u64) -> Result<()> {
+        let old_blocks = inode.blocks();
+        let blocks = size.div_ceil(FS_BLOCK_SIZE);
+        if size > inode.size {
+            self.zero_tail(inode)?;
+        }
+        self.set_block_count(inode, blocks)?;
+        if blocks > old_blocks {
+            self.zero_blocks(inode, old_blocks, blocks)?;
+        }
+        inode.size = size;
+        Ok(())
+    }
+
+    /// Zero the last block of a file past its end, before it grows
+    ///
+    /// These bytes are not part of the file yet, so a power loss before
+    /// the growth is written exposes nothing.
+    fn zero_tail(&mut self, inode: &Inode) -> Result<()> {
+        let tail = (inode.size % FS_BLOCK_SIZE) as usize;
+        if tail == 0 {
+            return Ok(());
+        }
+        let (physical, _) = inode.locate(inode.size / FS_BLOCK_SIZE)?;
+        let mut block = vec![0u8; BLOCK];
+        self.read_raw(physical, &mut block)?;
+        block[tail..].fill(0);
+        self.write_raw(physical, &block)
+    }
+
+    /// Inode number of the entry at `path`
+    fn resolve(&mut self, path: &str) -> Result<u32> {
+        let mut ino = ROOT_INODE;
+        for name in components(path)? {
+            ino = self
+                .lookup(ino, name)?
+                .ok_or_else(|| not_found(path))?
+                .inode;
+        }
+        Ok(ino)
+    }
+
+    /// Parent directory and name of the entry at `path`
+    fn resolve_parent(&mut self, path: &str) -> Result<(u32, String)> {
+        let mut names = components(path)?;
+        let name = names
+            .pop()
+            .ok_or_else(|| DeepVaultError::InvalidInput("The root directory cannot be changed".to_string()))?;
+        let mut ino = ROOT_INODE;
+        for parent in names {
+            ino = self
+                .lookup(ino, parent)?
+                .ok_or_else(|| not_found(path))?
+                .inode;
+        }
+        Ok((ino, name.to_string()))
+    }
+
+    fn lookup(&mut self, dir: u32, name: &str) -> Result<Option<RawEntry>> {
+        let (_, entries) = self.read_entries(dir)?;
+        Ok(entries.into_iter().find(|e| e.name == name))
+    }
+
+    fn file_inode(&mut self, path: &str) -> Result<(u32, Inode)> {
+        let ino = self.resolve(path)?;
+        let inode = self.load_inode(ino)?;
+        if inode.kind == FileKind::Directory {
+            return Err(fs_error(ErrorKind::IsADirectory, format!("{} is a directory", path)));
+        }
+        Ok((ino, inode))
+    }
+
+    /// Load the inode of a directory
+    fn load_dir(&mut self, ino: u32) -> Result<Inode> {
+        let inode = self.load_inode(ino)?;
+        if inode.kind != FileKind::Directory {
+            return Err(fs_error(ErrorKind::NotADirectory, "Not a directory".to_string()));
+        }
+        Ok(inode)
+    }
+
+    fn read_entries(&mut self, ino: u32) -> Result<(Inode, Vec<RawEntry>)> {
+        let inode = self.load_dir(ino)?;
+        let mut entries = Vec::new();
+        for logical in 0..inode.blocks() {
+            let (physical, _) = inode.locate(logical)?;
+            let block = self.read_meta(physical)?;
+            entries.extend(parse_entries(ino, &block)?);
+        }
+        Ok((inode, entries))
+    }
+
+    /// Add an entry at the end of a directory, in its last block if it
+    /// has room
+    fn add_entry(&mut self, dir: u32, entry: RawEntry) -> Result<()> {
+        let mut inode = self.load_dir(dir)?;
+        let blocks = inode.blocks();
+        let mut last = Vec::new();
+        let mut logical = blocks;
+        if blocks > 0 {
+            let (physical, _) = inode.locate(blocks - 1)?;
+            last = parse_entries(dir, &self.read_meta(physical)?)?;
+            if entries_len(&last) + ENTRY_HEADER + entry.name.len() <= BLOCK {
+                logical -= 1;
+            } else {
+                last.clear();
+            }
+        }
+        if logical == blocks {
+            self.set_block_count(&mut inode, blocks + 1)?;
+        }
+        last.push(entry);
+        let (physical, _) = inode.locate(logical)?;
+        self.write_meta(physical, encode_entries(&last));
+        self.store_dir(dir, &mut inode)
+    }
+
+    /// Remove the entry `name` from a directory, dropping its block if it
+    /// becomes empty
+    fn remove_entry(&mut self, dir: u32, name: &str) -> Result<Option<RawEntry>> {
+        let mut inode = self.load_dir(dir)?;
+        for logical in 0..inode.blocks() {
+            let (physical, _) = inode.locate(logical)?;
+            let mut entries = parse_entries(dir, &self.read_meta(physical)?)?;
+            let Some(index) = entries.iter().position(|e| e.name == name) else {
+                continue;
+            };
+            let entry = entries.remove(index);
+            if entries.is_empty() {
+                self.remove_block(&mut inode, logical);
+            } else {
+                self.write_meta(physical, encode_entries(&entries));
+            }
+            self.store_dir(dir, &mut inode)?;
+            return Ok(Some(entry));
+        }
+        Ok(None)
+    }
+
+    /// Release one block from the middle of an inode
+    fn remove_block(&mut self, inode: &mut Inode, logical: u64) {
+        let mut first = 0;
+        for extent in std::mem::take(&mut inode.extents) {
+            if logical < first || logical >= first + extent.len {
+                first += extent.len;
+                inode.extents.push(extent);
+                continue;
+            }
+            let before = logical - first;
+            if before > 0 {
+                inode.extents.push(Extent { start: extent.start, len: before });
+            }
+            self.release(Extent { start: extent.start + before, len: 1 });
+            if before + 1 < extent.len {
+                inode.extents.push(Extent {
+                    start: extent.start + before + 1,
+                    len: extent.len - before - 1,
+                });
+            }
+            first += extent.len;
+        }
+    }
+
+    fn store_dir(&mut self, ino: u32, inode: &mut Inode) -> Result<()> {
+        inode.size = inode.blocks() * FS_BLOCK_SIZE;
+        inode.modified = utils::now();
+        self.store_inode(ino, inode)
+    }
+
+    fn create_node(&mut self, path: &str, kind: FileKind) -> Result<(u32, Inode)> {
+        let (parent, name) = self.resolve_parent(path)?;
+        if self.lookup(parent, &name)?.is_some() {
+            return Err(fs_error(ErrorKind::AlreadyExists, format!("{} already exists", path)));
+        }
+        let ino = self.allocate_inode()?;
+        let mut inode = Inode::new(kind);
+        self.store_inode(ino, &mut inode)?;
+        self.add_entry(parent, RawEntry { inode: ino, kind, name })?;
+        Ok((ino, inode))
+    }
+
+    fn remove_node(&mut self, path: &str) -> Result<()> {
+        let (parent, name) = self.resolve_parent(path)?;
+        let entry = self.lookup(parent, &name)?.ok_or_else(|| not_found(path))?;
+        self.free_node(entry.inode)?;
+        self.remove_entry(parent, &name)?;
+        Ok(())
+    }
+
+    /// Free the inode of a file or an empty directory
+    fn free_node(&mut self, ino: u32) -> Result<()> {
+        let inode = self.load_inode(ino)?;
+        if inode.kind == FileKind::Directory && !self.read_entries(ino)?.1.is_empty() {
+            return Err(fs_error(ErrorKind::DirectoryNotEmpty, "The directory is not empty".to_string()));
+        }
+        self.free_inode(ino, inode)
+    }
+
+    /// Remove what fits in one transaction of the tree at `path`
+    ///
+    /// Returns whether the whole tree is gone.
+    fn remove_tree_part(&mut self, path: &str) -> Result<bool> {
+        let (parent, name) = self.resolve_parent(path)?;
+        let top = self.lookup(parent, &name)?.ok_or_else(|| not_found(path))?;
+        let mut pending = Vec::new();
+        if top.kind == FileKind::Directory {
+            pending.push(top.inode);
+        }
+        while let Some(dir) = pending.pop() {
+            let (_, children) = self.read_entries(dir)?;
+            for child in children {
+                if child.kind == FileKind::Directory && !self.read_entries(child.inode)?.1.is_empty() {
+                    pending.push(child.inode);
+                    continue;
+                }
+                if !self.journal_has_room() {
+                    return Ok(false);
+                }
+                self.free_node(child.inode)?;
+                self.remove_entry(dir, &child.name)?;
+            }
+        }
+        if top.kind == FileKind::Directory && !self.read_entries(top.inode)?.1.is_empty() {
+            return Ok(false);
+        }
+        self.free_node(top.inode)?;
+        self.remove_entry(parent, &name)?;
+        Ok(true)
+    }
+
+    /// Whether the current operation can remove one more entry and still
+    /// fit in the journal
+    fn journal_has_room(&self) -> bool {
+        let mut bitmap = self.dirty_bitmap.clone();
+        for extent in &self.released {
+            bitmap.extend(extent.start / BITS_PER_BLOCK..=(extent.start + extent.len - 1) / BITS_PER_BLOCK);
+        }
+        let count = self.dirty.len() + bitmap.len() + 1 + REMOVAL_BLOCKS;
+        descriptor_blocks(count) + count as u64 <= self.layout.journal_blocks
+    }
+
+    fn rename_node(&mut self, from: &str, to: &str) -> Result<()> {
+        let from_names = components(from)?;
+        let to_names = components(to)?;
+        if from_names == to_names {
+            self.resolve(from)?;
+            return Ok(());
+        }
+        if !from_names.is_empty() && to_names.starts_with(&from_names) {
+            return Err(DeepVaultError::InvalidInput(format!("Cannot move {} inside itself", from)));
+        }
+
+        let (src_parent, src_name) = self.resolve_parent(from)?;
+        let (dst_parent, dst_name) = self.resolve_parent(to)?;
+        let mut entry = self.lookup(src_parent, &src_name)?.ok_or_else(|| not_found(from))?;
+        self.load_dir(dst_parent)?;
+        entry.name = dst_name;
+        self.replace_target(dst_parent, &entry)?;
+        self.remove_entry(src_parent, &src_name)?;
+        self.add_entry(dst_parent, entry)
+    }
+
+    /// Remove the entry that `entry` is about to replace, if any
+    fn replace_target(&mut self, dir: u32, entry: &RawEntry) -> Result<()> {
+        let Some(target) = self.lookup(dir, &entry.name)? else {
+            return Ok(());
+        };
+        match (entry.kind, target.kind) {
+            (FileKind::File, FileKind::Directory) => {
+                return Err(fs_error(ErrorKind::IsADirectory, format!("{} is a directory", entry.name)));
+            }
+            (FileKind::Directory, FileKind::File) => {
+                return Err(fs_error(ErrorKind::NotADirectory, format!("{} is not a directory", entry.name)));
+            }
+            _ => {}
+        }
+        self.free_node(target.inode)?;
+        self.remove_entry(dir, &target.name)?;
+        Ok(())
+    }
+}
+
+/// Entries stored in one directory block
+fn parse_entries(ino: u32, block: &[u8]) -> Result<Vec<RawEntry>> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos + ENTRY_HEADER <= block.len() && u32_at(block, pos) != 0 {
+        let header = &block[pos..pos + ENTRY_HEADER];
+        let kind = match header[4] {
+            KIND_FILE => FileKind::File,
+            KIND_DIRECTORY => FileKind::Directory,
+            _ => return Err(corrupted(format!("Invalid entry in directory {}", ino))),
+        };
+        let name_len = header[5] as usize;
+        let name = block
+            .get(pos + ENTRY_HEADER..pos + ENTRY_HEADER + name_len)
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .ok_or_else(|| corrupted(format!("Invalid name in directory {}", ino)))?;
+        entries.push(RawEntry {
+            inode: u32_at(header, 0),
+            kind,
+            name: name.to_string(),
+        });
+        pos += ENTRY_HEADER + name_len;
+    }
+    Ok(entries)
+}
+
+/// Directory block holding `entries`, which must fit in it
+fn encode_entries(entries: &[RawEntry]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(BLOCK);
+    for entry in entries {
+        block.extend_from_slice(&entry.inode.to_le_bytes());
+        block.push(match entry.kind {
+            FileKind::File => KIND_FILE,
+            FileKind::Directory => KIND_DIRECTORY,
+        });
+        block.push(entry.name.len() as u8);
+        block.extend_from_slice(entry.name.as_bytes());
+    }
+    block.resize(BLOCK, 0);
+    block
+}
+
+fn entries_len(entries: &[RawEntry]) -> usize {
+    entries.iter().map(|e| ENTRY_HEADER + e.name.len()).sum()
+}
+
+/// Names of the components of `path`, which is relative to the root
+fn components(path: &str) -> Result<Vec<&str>> {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            if name == "." || name == ".." || name.contains('\0') || name.len() > MAX_NAME_LEN {
+                return Err(fs_error(
+                    ErrorKind::InvalidFilename,
+                    format!("Invalid file name {:?} (at most {} bytes)", name, MAX_NAME_LEN),
+                ));
+            }
+            Ok(name)
+        })
+        .collect()
+}
+
+/// Blocks taken by the descriptor of a journal record of `count` blocks
+fn descriptor_blocks(count: usize) -> u64 {
+    ((DESCRIPTOR_HEADER + count * 8) as u64).div_ceil(FS_BLOCK_SIZE)
+}
+
+/// SHA-256 of a journal record, without its checksum field
+fn journal_checksum(record: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(&record[..16]);
+    hasher.update(&record[DESCRIPTOR_HEADER..]);
+    hasher.finalize().into()
+}
+
+/// Append an extent, merging it with the last one when contiguous
+fn push_extent(extents: &mut Vec<Extent>, extent: Extent) {
+    if let Some(last) = extents.last_mut() {
+        if last.start + last.len == extent.start && last.len + extent.len <= u32::MAX as u64 {
+            last.len += extent.len;
+            return;
+        }
+    }
+    extents.push(extent);
+}
+
+fn read_extent(bytes: &[u8], offset: usize) -> Extent {
+    Extent {
+        start: u64_at(bytes, offset),
+        len: u32_at(bytes, offset + 8) as u64,
+    }
+}
+
+fn write_extent(bytes: &mut [u8], offset: usize, extent: &Extent) {
+    bytes[offset..offset + 8].copy_from_slice(&extent.start.to_le_bytes());
+    bytes[offset + 8..offset + 12].copy_from_slice(&(extent.len as u32).to_le_bytes());
+}
+
+fn fs_error(kind: ErrorKind, message: String) -> DeepVaultError {
+    DeepVaultError::Io(std::io::Error::new(kind, message))
+}
+
+fn not_found(path: &str) -> DeepVaultError {
+    fs_error(ErrorKind::NotFound, format!("{} not found", path))
+}
+
+fn corrupted(message: String) -> DeepVaultError {
+    DeepVaultError::InvalidHeader(format!("Vault file system: {}", message))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -113,10 +113,10 @@ impl WipeManager {
         
         // Overwrite file with random data multiple times
         let file = std::fs::File::open(file_path)
-            .map_err(|e| DeepVaultError::Io(e))?;
+            .map_err(DeepVaultError::Io)?;
         
         let file_size = file.metadata()
-            .map_err(|e| DeepVaultError::Io(e))?
+            .map_err(DeepVaultError::Io)?
             .len();
         
         // Perform multiple overwrite passes
@@ -124,7 +124,7 @@ impl WipeManager {
             let mut file = std::fs::OpenOptions::new()
                 .write(true)
                 .open(file_path)
-                .map_err(|e| DeepVaultError::Io(e))?;
+                .map_err(DeepVaultError::Io)?;
             
             match pass {
                 0 => self.overwrite_with_pattern(&mut file, file_size, &[0xFF]).await?,
@@ -136,7 +136,7 @@ impl WipeManager {
         
         // Finally delete the file
         std::fs::remove_file(file_path)
-            .map_err(|e| DeepVaultError::Io(e))?;
+            .map_err(DeepVaultError::Io)?;
 
         Ok(())
     }
@@ -158,9 +158,9 @@ impl WipeManager {
         }
         
         file.write_all(&buffer)
-            .map_err(|e| DeepVaultError::Io(e))?;
+            .map_err(DeepVaultError::Io)?;
         file.sync_all()
-            .map_err(|e| DeepVaultError::Io(e))?;
+            .map_err(DeepVaultError::Io)?;
 
         Ok(())
     }
@@ -182,9 +182,9 @@ impl WipeManager {
         }
         
         file.write_all(&buffer)
-            .map_err(|e| DeepVaultError::Io(e))?;
+            .map_err(DeepVaultError::Io)?;
         file.sync_all()
-            .map_err(|e| DeepVaultError::Io(e))?;
+            .map_err(DeepVaultError::Io)?;
 
         Ok(())
     }
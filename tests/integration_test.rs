@@ -27,43 +27,88 @@ async fn test_crypto_manager_creation() {
 #[tokio::test]
 async fn test_key_derivation() {
     let crypto_manager = crate::crypto::CryptoManager::new().unwrap();
-    let password = "TestPassword123!";
+    let password = SecretString::from("TestPassword123!");
 
-    let key = crypto_manager.derive_key(password).unwrap();
+    let key = crypto_manager.derive_key(&password).unwrap();
     assert_eq!(key.len(), 32); // 256 bits
 
     // Test de vérification
-    let is_valid = crypto_manager.verify_password(password, &key).unwrap();
+    let is_valid = crypto_manager.verify_password(&password, &key).unwrap();
     assert!(is_valid);
 
     // Test avec mauvais mot de passe
     let is_invalid = crypto_manager
-        .verify_password("WrongPassword", &key)
+        .verify_password(&SecretString::from("WrongPassword"), &key)
         .unwrap();
     assert!(!is_invalid);
 }
 
+#[tokio::test]
+async fn test_encrypt_decrypt() {
+    let crypto_manager = crate::crypto::CryptoManager::new().unwrap();
+    let key = crypto_manager.derive_key(&SecretString::from("TestPassword123!")).unwrap();
+    let plaintext = b"Donnees confidentielles";
+
+    let ciphertext = crypto_manager.encrypt(&key, plaintext).unwrap();
+    assert_eq!(
+        ciphertext.len(),
+        plaintext.len() + crate::crypto::NONCE_LEN + crate::crypto::TAG_LEN
+    );
+    assert_eq!(crypto_manager.decrypt(&key, &ciphertext).unwrap(), plaintext);
+
+    // Deux chiffrements du même message utilisent des nonces différents
+    let other = crypto_manager.encrypt(&key, plaintext).unwrap();
+    assert_ne!(ciphertext, other);
+
+    // Test avec données associées
+    let sealed = crypto_manager
+        .encrypt_with_aad(&key, plaintext, b"header")
+        .unwrap();
+    assert!(crypto_manager
+        .decrypt_with_aad(&key, &sealed, b"header")
+        .is_ok());
+    assert!(matches!(
+        crypto_manager.decrypt_with_aad(&key, &sealed, b"other"),
+        Err(DeepVaultError::Crypto(_))
+    ));
+
+    // Test avec un tag modifié
+    let mut tampered = ciphertext.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 0x01;
+    assert!(matches!(
+        crypto_manager.decrypt(&key, &tampered),
+        Err(DeepVaultError::Crypto(_))
+    ));
+
+    // Test avec une mauvaise clé
+    let wrong_key = crypto_manager.derive_key(&SecretString::from("WrongPassword")).unwrap();
+    assert!(crypto_manager.decrypt(&wrong_key, &ciphertext).is_err());
+}
+
 #[tokio::test]
 async fn test_password_validation() {
-    // Test mot de passe valide
-    let valid_password = "ValidPass123!";
-    assert!(crate::utils::validate_password(valid_password).is_ok());
+    // Test phrase de passe longue, sans symbole ni majuscule
+    let long_passphrase = "la vieille horloge chante sous la pluie";
+    assert!(crate::utils::validate_password(long_passphrase).is_ok());
 
     // Test mot de passe trop court
     let short_password = "Short1!";
     assert!(crate::utils::validate_password(short_password).is_err());
 
-    // Test mot de passe sans majuscule
-    let no_upper = "validpass123!";
-    assert!(crate::utils::validate_password(no_upper).is_err());
+    // Test mot de passe courant, malgré toutes les classes de caractères
+    assert!(crate::utils::validate_password("Password1!").is_err());
+    assert!(crate::utils::validate_password("P@ssw0rd2024").is_err());
 
-    // Test mot de passe sans chiffre
-    let no_digit = "ValidPassword!";
-    assert!(crate::utils::validate_password(no_digit).is_err());
+    // Test motifs prévisibles
+    assert!(crate::utils::validate_password("qwertyuiop123").is_err());
+    assert!(crate::utils::validate_password("abcabcabcabc").is_err());
 
-    // Test mot de passe sans caractère spécial
-    let no_special = "ValidPassword123";
-    assert!(crate::utils::validate_password(no_special).is_err());
+    // Test phrase de passe très longue, puis entrée démesurée
+    let very_long = "la vieille horloge chante sous la pluie, ".repeat(5);
+    assert!(crate::utils::validate_password(&very_long).is_ok());
+    assert!(crate::utils::validate_password(&"a".repeat(129)).is_err());
+    assert!(crate::utils::validate_password(&"Zq8!vT2#".repeat(200)).is_err());
 }
 
 #[tokio::test]
@@ -96,6 +141,14 @@ async fn test_utils() {
     let random_string = crate::utils::generate_random_string(16).unwrap();
     assert_eq!(random_string.len(), 16);
 
+    // Test génération de phrase de passe
+    let passphrase = crate::utils::generate_passphrase(6, " ").unwrap();
+    let words: Vec<&str> = passphrase.expose_secret().split(' ').collect();
+    assert_eq!(words.len(), 6);
+    assert!(words.iter().all(|word| crate::utils::wordlist().contains(word)));
+    assert!(crate::utils::validate_password(passphrase.expose_secret()).is_ok());
+    assert!(crate::utils::generate_passphrase(0, "-").is_err());
+
     // Test validation de chemin de périphérique
     let valid_path = PathBuf::from("/dev/sdb");
     let invalid_path = PathBuf::from("/invalid/path");
@@ -18,6 +18,8 @@ fn test_crypto_config_creation() {
         kdf_params: KeyDerivation::default(),
         salt: vec![1, 2, 3, 4],
         header_file: ".dv_meta".to_string(),
+        scrypt_params: Default::default(),
+        pbkdf2_params: Default::default(),
     };
 
     assert_eq!(config.algorithm, "Argon2id");
@@ -108,3 +110,1892 @@ fn test_serialization() {
     assert_eq!(config.ui.theme, deserialized.ui.theme);
     assert_eq!(config.ui.language, deserialized.ui.language);
 }
+
+/// Paramètres KDF réduits pour garder les tests rapides
+fn fast_kdf() -> KdfParams {
+    KdfParams::Argon2id(KeyDerivation {
+        memory: 1024,
+        iterations: 1,
+        parallelism: 1,
+    })
+}
+
+#[test]
+fn test_vault_header_roundtrip() {
+    let (header, master_key) =
+        VaultHeader::create(&"HeaderPass123!".into(), &fast_kdf(), header::DEFAULT_DATA_OFFSET, 1 << 30).unwrap();
+    let bytes = header.to_bytes();
+    assert_eq!(bytes.len(), header::HEADER_SIZE);
+    assert!(VaultHeader::is_vault_header(&bytes));
+
+    let parsed = VaultHeader::from_bytes(&bytes).unwrap();
+    assert_eq!(parsed, header);
+    assert_eq!(parsed.data_size, 1 << 30);
+    assert_eq!(parsed.unlock(&"HeaderPass123!".into()).unwrap(), master_key);
+
+    // Test avec mauvais mot de passe
+    assert!(matches!(parsed.unlock(&"WrongPass123!".into()), Err(DeepVaultError::Crypto(_))));
+
+    // Test avec un header modifié : le MAC doit échouer
+    let mut tampered = bytes.clone();
+    tampered[40] ^= 0x01;
+    let tampered = VaultHeader::from_bytes(&tampered).unwrap();
+    assert!(matches!(
+        tampered.unlock(&"HeaderPass123!".into()),
+        Err(DeepVaultError::InvalidHeader(_))
+    ));
+}
+
+#[test]
+fn test_vault_header_compatibility() {
+    // Des données aléatoires ne sont pas un header
+    let random = vec![0xA5u8; header::HEADER_SIZE];
+    assert!(!VaultHeader::is_vault_header(&random));
+    assert!(matches!(
+        VaultHeader::from_bytes(&random),
+        Err(DeepVaultError::InvalidHeader(_))
+    ));
+
+    let (header, master_key) =
+        VaultHeader::create(&"HeaderPass123!".into(), &fast_kdf(), header::DEFAULT_DATA_OFFSET, 1 << 20).unwrap();
+
+    // Version majeure inconnue : refusée
+    let mut bytes = header.to_bytes();
+    bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+    assert!(VaultHeader::from_bytes(&bytes).is_err());
+
+    // Version mineure plus récente : lisible mais en lecture seule
+    let mut newer = header.clone();
+    newer.version_minor = header::VERSION_MINOR + 1;
+    newer.seal(&master_key).unwrap_err();
+    let parsed = VaultHeader::from_bytes(&newer.to_bytes()).unwrap();
+    assert_eq!(parsed.version_minor, header::VERSION_MINOR + 1);
+
+    // Drapeau incompatible inconnu : refusé
+    let mut bytes = header.to_bytes();
+    bytes[12] = 0x80;
+    assert!(VaultHeader::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn test_keyslot_management() {
+    let (mut header, master_key) =
+        VaultHeader::create(&"Alice-Pass123!".into(), &fast_kdf(), header::DEFAULT_DATA_OFFSET, 1 << 20).unwrap();
+    assert_eq!(header.list_keyslots().len(), 1);
+
+    // Ajout d'une phrase de passe par personne
+    let bob = header.add_keyslot(&master_key, &"Bob-Pass123!".into(), &fast_kdf()).unwrap();
+    assert_eq!(bob, 1);
+    let header = VaultHeader::from_bytes(&header.to_bytes()).unwrap();
+    assert_eq!(header.unlock(&"Alice-Pass123!".into()).unwrap(), master_key);
+    assert_eq!(header.unlock(&"Bob-Pass123!".into()).unwrap(), master_key);
+    assert_eq!(header.unlock_keyslot(bob, &Credentials::passphrase(&"Bob-Pass123!".into())).unwrap(), master_key);
+    assert!(header.unlock_keyslot(0, &Credentials::passphrase(&"Bob-Pass123!".into())).is_err());
+
+    // Changement de phrase de passe sans toucher à la clé maître
+    let mut header = header;
+    assert_eq!(header.change_passphrase(&"Bob-Pass123!".into(), &"Bob-NewPass456!".into()).unwrap(), bob);
+    assert!(header.unlock(&"Bob-Pass123!".into()).is_err());
+    assert_eq!(header.unlock(&"Bob-NewPass456!".into()).unwrap(), master_key);
+
+    // Révocation
+    header.remove_keyslot(&master_key, bob).unwrap();
+    assert!(header.unlock(&"Bob-NewPass456!".into()).is_err());
+    assert_eq!(header.list_keyslots().len(), 1);
+    assert_eq!(header.list_keyslots()[0].kind, KeyslotKind::Password);
+
+    // Le dernier keyslot ne peut pas être supprimé
+    assert!(header.remove_keyslot(&master_key, 0).is_err());
+    assert!(header.remove_keyslot(&master_key, 5).is_err());
+}
+
+#[test]
+fn test_sector_cipher_xts_vector() {
+    // Vecteur de référence XTS-AES-256 (clé 00..3f, secteur 5)
+    let key: Vec<u8> = (0u8..64).collect();
+    let cipher = SectorCipher::new(&key).unwrap();
+    let plaintext: Vec<u8> = (0..512).map(|i| (i % 256) as u8).collect();
+
+    let mut sector = plaintext.clone();
+    cipher.encrypt_sector(5, &mut sector);
+    assert_eq!(
+        hex::encode(&sector[..32]),
+        "f87ca2f29b117c1b024a6ec8e8c5994e76f7d16b43eed21e6936126969e00dab"
+    );
+    assert_eq!(
+        hex::encode(&sector[480..]),
+        "eb6523fbfb5ca033725f703578b7dbb0e790ce5900c47286caaef5e457fecc4b"
+    );
+
+    cipher.decrypt_sector(5, &mut sector);
+    assert_eq!(sector, plaintext);
+}
+
+#[test]
+fn test_encrypted_volume_random_access() {
+    use std::io::Cursor;
+
+    let (header, master_key) =
+        VaultHeader::create(&"VolumePass123!".into(), &fast_kdf(), header::DEFAULT_DATA_OFFSET, 64 * 1024).unwrap();
+    let mut image = header.to_bytes();
+    image.resize((header.data_offset + header.data_size) as usize, 0);
+
+    {
+        let mut volume = EncryptedVolume::open(Cursor::new(&mut image), &header, &master_key).unwrap();
+        assert_eq!(volume.sector_count(), 128);
+
+        // Écriture à cheval sur deux secteurs
+        volume.write_at(1000, b"secret sur deux secteurs").unwrap();
+        volume.write_sectors(127, &[0x42u8; 512]).unwrap();
+        assert!(volume.write_sectors(128, &[0u8; 512]).is_err());
+    }
+
+    // Les données ne sont pas en clair sur le support
+    let data_area = &image[header.data_offset as usize..];
+    assert!(!data_area.windows(6).any(|w| w == b"secret"));
+    assert_ne!(&data_area[127 * 512..], &[0x42u8; 512][..]);
+
+    let mut volume = EncryptedVolume::open(Cursor::new(&mut image), &header, &master_key).unwrap();
+    let mut buf = vec![0u8; 24];
+    volume.read_at(1000, &mut buf).unwrap();
+    assert_eq!(&buf, b"secret sur deux secteurs");
+    let mut sector = vec![0u8; 512];
+    volume.read_sectors(127, &mut sector).unwrap();
+    assert_eq!(sector, vec![0x42u8; 512]);
+}
+
+#[test]
+fn test_keyfile_credentials() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyfile_a = dir.path().join("a.key");
+    let keyfile_b = dir.path().join("b.key");
+    keyfile::generate_keyfile(&keyfile_a).unwrap();
+    keyfile::generate_keyfile(&keyfile_b).unwrap();
+    // Un fichier clé existant n'est jamais écrasé
+    assert!(keyfile::generate_keyfile(&keyfile_a).is_err());
+
+    // Phrase de passe + fichier clé comme second facteur
+    let both = Credentials::passphrase(&"Ops-Pass123!".into())
+        .with_keyfile(&keyfile_a)
+        .unwrap();
+    let (mut header, master_key) = VaultHeader::create_with_credentials(
+        &both,
+        &fast_kdf(),
+        header::DEFAULT_DATA_OFFSET,
+        1 << 20,
+    )
+    .unwrap();
+    assert_eq!(header.unlock_with_credentials(&both).unwrap(), master_key);
+    assert!(header.unlock(&"Ops-Pass123!".into()).is_err());
+    assert!(header
+        .unlock_with_credentials(&Credentials::new().with_keyfile(&keyfile_a).unwrap())
+        .is_err());
+
+    // Mode fichiers clés uniquement, l'ordre des fichiers n'importe pas
+    let keyfiles_only = Credentials::new()
+        .with_keyfile(&keyfile_a)
+        .unwrap()
+        .with_keyfile(&keyfile_b)
+        .unwrap();
+    header
+        .add_keyslot_with_credentials(&master_key, &keyfiles_only, &fast_kdf())
+        .unwrap();
+    let reordered = Credentials::new()
+        .with_keyfile(&keyfile_b)
+        .unwrap()
+        .with_keyfile(&keyfile_a)
+        .unwrap();
+    assert_eq!(header.unlock_with_credentials(&reordered).unwrap(), master_key);
+
+    // Identifiants vides ou fichier clé vide refusés
+    assert!(matches!(
+        header.unlock_with_credentials(&Credentials::new()),
+        Err(DeepVaultError::InvalidInput(_))
+    ));
+    assert!(Credentials::new().with_keyfile_bytes(&[]).is_err());
+    assert!(Credentials::new()
+        .with_keyfile(&dir.path().join("absent.key"))
+        .is_err());
+}
+
+#[test]
+fn test_kdf_algorithms() {
+    use deepvault_core::kdf::{Pbkdf2Params, ScryptParams};
+
+    let argon2 = KeyDerivation {
+        memory: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+    let all = [
+        KdfParams::Argon2id(argon2.clone()),
+        KdfParams::Argon2i(argon2.clone()),
+        KdfParams::Argon2d(argon2),
+        KdfParams::Scrypt(ScryptParams { log_n: 10, r: 8, p: 1 }),
+        KdfParams::Pbkdf2Sha512(Pbkdf2Params { iterations: 1000 }),
+    ];
+
+    // Chaque algorithme produit une clé différente pour le même secret
+    let mut keys = Vec::new();
+    for kdf in &all {
+        let mut key = [0u8; 32];
+        kdf.derive(b"secret", &[7u8; 32], &mut key).unwrap();
+        assert!(!keys.contains(&key), "{} duplique une autre clé", kdf.algorithm());
+        keys.push(key);
+    }
+
+    // L'algorithme est conservé dans le keyslot
+    let (mut header, master_key) =
+        VaultHeader::create(&"KdfPass123!".into(), &all[0], header::DEFAULT_DATA_OFFSET, 1 << 20).unwrap();
+    for kdf in &all[1..] {
+        header.add_keyslot(&master_key, &format!("{}-Pass", kdf.algorithm()).into(), kdf).unwrap();
+    }
+    let header = VaultHeader::from_bytes(&header.to_bytes()).unwrap();
+    let stored: Vec<KdfParams> = header.list_keyslots().into_iter().filter_map(|slot| slot.kdf).collect();
+    assert_eq!(stored, all);
+    assert_eq!(header.unlock(&"scrypt-Pass".into()).unwrap(), master_key);
+    assert_eq!(header.unlock(&"PBKDF2-SHA512-Pass".into()).unwrap(), master_key);
+
+    // Noms d'algorithmes dans la configuration
+    assert_eq!("argon2i".parse::<KdfAlgorithm>().unwrap(), KdfAlgorithm::Argon2i);
+    assert_eq!("PBKDF2-SHA512".parse::<KdfAlgorithm>().unwrap(), KdfAlgorithm::Pbkdf2Sha512);
+    assert!("MD5".parse::<KdfAlgorithm>().is_err());
+}
+
+#[test]
+fn test_kdf_config_validation() {
+    let mut config = AppConfig::default();
+    config.security.default_algorithm = "scrypt".to_string();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.security.default_kdf().unwrap().algorithm(), KdfAlgorithm::Scrypt);
+
+    // Paramètres invalides pour chaque algorithme
+    config.security.default_scrypt_params.r = 0;
+    assert!(config.validate().is_err());
+    config.security.default_scrypt_params.r = 8;
+    config.security.default_scrypt_params.p = u32::MAX;
+    assert!(config.validate().is_err());
+    config.security.default_scrypt_params.p = kdf::MAX_SCRYPT_PARALLELISM + 1;
+    assert!(config.validate().is_err());
+    config.security.default_scrypt_params.p = 1;
+
+    config.security.default_pbkdf2_params.iterations = 10;
+    assert!(config.validate().is_err());
+    config.security.default_pbkdf2_params.iterations = u32::MAX;
+    assert!(config.validate().is_err());
+    config.security.default_pbkdf2_params.iterations = 210_000;
+
+    config.security.default_kdf_params.parallelism = 0;
+    assert!(config.validate().is_err());
+    config.security.default_kdf_params.parallelism = 1;
+
+    // Un nombre de passes démesuré bloquerait chaque déverrouillage
+    config.security.default_kdf_params.iterations = u32::MAX;
+    assert!(config.validate().is_err());
+    config.security.default_kdf_params.iterations = 3;
+
+    // L'ancien format de configuration reste lisible
+    let legacy = r#"{"default_algorithm":"Argon2d","default_kdf_params":{"memory":65536,"iterations":3,"parallelism":1},
+        "enable_hidden_volumes":true,"auto_unmount_timeout":300,"secure_wipe_passes":3}"#;
+    let security: SecurityConfig = serde_json::from_str(legacy).unwrap();
+    assert_eq!(security.default_kdf().unwrap().algorithm(), KdfAlgorithm::Argon2d);
+}
+
+#[test]
+fn test_kdf_calibration() {
+    use deepvault_core::kdf::{calibrate, CalibrationTarget};
+    use std::time::Duration;
+
+    let target = CalibrationTarget {
+        unlock_time: Duration::from_millis(30),
+        max_memory: 4096,
+        max_parallelism: 2,
+    };
+    let calibration = calibrate(KdfAlgorithm::Argon2i, &target).unwrap();
+    assert_eq!(calibration.kdf.algorithm(), KdfAlgorithm::Argon2i);
+    let params = calibration.kdf.argon2().unwrap();
+    assert!(params.memory <= 4096);
+    assert!(params.parallelism <= 2);
+    assert!(params.iterations >= 1);
+    assert!(calibration.kdf.validate().is_ok());
+
+    // Les paramètres calibrés remplacent ceux du gestionnaire
+    let mut manager = crypto::CryptoManager::with_config(CryptoConfig::for_kdf(&fast_kdf(), &[0u8; 32]));
+    let calibration = manager.calibrate_kdf(&target).unwrap();
+    assert_eq!(manager.config().kdf().unwrap(), calibration.kdf);
+
+    // Cibles invalides
+    assert!(calibrate(KdfAlgorithm::Scrypt, &target).is_err());
+    let zero = CalibrationTarget {
+        unlock_time: Duration::ZERO,
+        ..target.clone()
+    };
+    assert!(calibrate(KdfAlgorithm::Argon2id, &zero).is_err());
+    let tiny = CalibrationTarget { max_memory: 4, ..target };
+    assert!(calibrate(KdfAlgorithm::Argon2id, &tiny).is_err());
+}
+
+#[test]
+fn test_secret_types() {
+    let password = SecretString::from("Secret-Pass123!");
+    let key = SecretKey::from(vec![0x42u8; 32]);
+
+    // Le contenu n'apparaît jamais dans Debug
+    assert!(!format!("{:?}", password).contains("Secret-Pass"));
+    assert!(!format!("{:?}", key).contains("42"));
+    assert!(!format!("{:?}", Credentials::passphrase(&password)).contains("Secret-Pass"));
+
+    // Comparaison en temps constant
+    assert_eq!(password, SecretString::from("Secret-Pass123!"));
+    assert_ne!(password, SecretString::from("Secret-Pass123?"));
+    assert_eq!(key, SecretKey::from_slice(&[0x42u8; 32]));
+    assert_ne!(key, SecretKey::from_slice(&[0x42u8; 31]));
+
+    // Verrouillage mémoire au mieux : un clone reste verrouillé
+    let mut locked = key.clone();
+    if locked.lock_memory() {
+        assert!(locked.clone().is_locked());
+    }
+    assert_eq!(locked.expose_secret(), key.expose_secret());
+}
+
+#[test]
+fn test_stream_encryption() {
+    use std::io::{Read, Write};
+
+    let key = SecretKey::from(vec![0x11u8; 32]);
+    let encrypt = |data: &[u8]| {
+        let mut writer = StreamWriter::with_segment_size(Vec::new(), &key, 1024).unwrap();
+        // Écritures de tailles irrégulières
+        for chunk in data.chunks(700) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap()
+    };
+    let decrypt = |stream: &[u8], key: &SecretKey| -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        StreamReader::new(stream, key)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+            .read_to_end(&mut output)?;
+        Ok(output)
+    };
+
+    // Aller-retour sur des tailles limites
+    for len in [0usize, 1, 1023, 1024, 1025, 3 * 1024, 5000] {
+        let data: Vec<u8> = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+        let stream = encrypt(&data);
+        assert_eq!(stream.len(), 28 + len + 16 * (len.max(1).div_ceil(1024)));
+        assert_eq!(decrypt(&stream, &key).unwrap(), data);
+    }
+
+    let data = vec![0xABu8; 3000];
+    let stream = encrypt(&data);
+    let segment = 1024 + 16;
+
+    // Troncature à une frontière de segment ou au milieu
+    assert!(decrypt(&stream[..28 + segment], &key).is_err());
+    assert!(decrypt(&stream[..stream.len() - 1], &key).is_err());
+    assert!(decrypt(&stream[..28], &key).is_err());
+
+    // Segments permutés
+    let mut swapped = stream.clone();
+    swapped[28..28 + segment].copy_from_slice(&stream[28 + segment..28 + 2 * segment]);
+    swapped[28 + segment..28 + 2 * segment].copy_from_slice(&stream[28..28 + segment]);
+    assert!(decrypt(&swapped, &key).is_err());
+
+    // Données ajoutées après le dernier segment
+    let mut extended = stream.clone();
+    extended.extend_from_slice(&[0u8; 16]);
+    assert!(decrypt(&extended, &key).is_err());
+
+    // Mauvaise clé ou en-tête modifié
+    assert!(decrypt(&stream, &SecretKey::from(vec![0x22u8; 32])).is_err());
+    let mut tampered = stream.clone();
+    tampered[20] ^= 1;
+    assert!(decrypt(&tampered, &key).is_err());
+    assert!(StreamReader::new(&b"not a stream"[..], &key).is_err());
+}
+
+#[test]
+fn test_header_backup_restore() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("vault.img");
+    let backup = dir.path().join("vault.hdr");
+    let password = SecretString::from("Backup-Pass123!");
+
+    let (header, master_key) =
+        VaultHeader::create(&password, &fast_kdf(), header::DEFAULT_DATA_OFFSET, 64 * 1024).unwrap();
+    let mut bytes = header.to_bytes();
+    bytes.resize((header.data_offset + header.data_size) as usize, 0x5A);
+    std::fs::write(&image, &bytes).unwrap();
+
+    backup_header(&image, &backup).unwrap();
+    assert_eq!(std::fs::read(&backup).unwrap().len() as u64, header.data_offset);
+    // Une sauvegarde existante n'est jamais écrasée
+    assert!(backup_header(&image, &backup).is_err());
+
+    // Un secteur endommagé dans les keyslots rend le volume illisible
+    let mut damaged = bytes.clone();
+    damaged[100..612].fill(0xFF);
+    std::fs::write(&image, &damaged).unwrap();
+    assert!(VaultHeader::from_bytes(&damaged).and_then(|h| h.unlock(&password)).is_err());
+
+    // Mauvaise phrase de passe : rien n'est écrit
+    let wrong = Credentials::passphrase(&"Wrong-Pass123!".into());
+    assert!(restore_header(&backup, &image, &wrong, false).is_err());
+    assert_eq!(std::fs::read(&image).unwrap(), damaged);
+
+    let restored = restore_header(&backup, &image, &Credentials::passphrase(&password), false).unwrap();
+    assert_eq!(restored.uuid, header.uuid);
+    let repaired = std::fs::read(&image).unwrap();
+    assert_eq!(repaired, bytes);
+    assert_eq!(VaultHeader::from_bytes(&repaired).unwrap().unlock(&password).unwrap(), master_key);
+
+    // Sauvegarde d'un autre volume : UUID différent, refusée
+    let other_image = dir.path().join("other.img");
+    let (other, _) = VaultHeader::create(&password, &fast_kdf(), header::DEFAULT_DATA_OFFSET, 64 * 1024).unwrap();
+    let mut other_bytes = other.to_bytes();
+    other_bytes.resize(bytes.len(), 0);
+    std::fs::write(&other_image, &other_bytes).unwrap();
+    assert!(restore_header(&backup, &other_image, &Credentials::passphrase(&password), false).is_err());
+    assert_eq!(std::fs::read(&other_image).unwrap(), other_bytes);
+
+    // Header totalement effacé : l'UUID ne peut plus être vérifié
+    let mut wiped = bytes.clone();
+    wiped[..header::HEADER_SIZE].fill(0);
+    std::fs::write(&image, &wiped).unwrap();
+    assert!(restore_header(&backup, &image, &Credentials::passphrase(&password), false).is_err());
+    restore_header(&backup, &image, &Credentials::passphrase(&password), true).unwrap();
+    assert_eq!(std::fs::read(&image).unwrap(), bytes);
+
+    // Un volume caché créé après la sauvegarde survit à la restauration
+    let slot = hidden::HIDDEN_HEADER_OFFSET as usize..hidden::HIDDEN_HEADER_OFFSET as usize + hidden::HIDDEN_HEADER_SIZE;
+    let mut with_hidden = damaged.clone();
+    with_hidden[slot.clone()].fill(0xC3);
+    std::fs::write(&image, &with_hidden).unwrap();
+    restore_header(&backup, &image, &Credentials::passphrase(&password), false).unwrap();
+    let repaired = std::fs::read(&image).unwrap();
+    assert!(repaired[slot.clone()].iter().all(|&b| b == 0xC3));
+    assert_eq!(repaired[..slot.start], bytes[..slot.start]);
+    assert_eq!(repaired[slot.end..], bytes[slot.end..]);
+
+    // Support trop petit pour la zone de données
+    std::fs::write(&image, &bytes[..bytes.len() - 512]).unwrap();
+    assert!(restore_header(&backup, &image, &Credentials::passphrase(&password), false).is_err());
+}
+
+#[test]
+fn test_recovery_key() {
+    let (mut header, master_key) = VaultHeader::create(
+        &"Owner-Pass123!".into(),
+        &fast_kdf(),
+        header::DEFAULT_DATA_OFFSET,
+        1 << 20,
+    )
+    .unwrap();
+    let (slot, recovery_key) = header.add_recovery_key(&master_key, &fast_kdf()).unwrap();
+    assert_eq!(header.list_keyslots()[slot].kind, KeyslotKind::Recovery);
+
+    // Format imprimable : 8 groupes de 6 caractères base32
+    let printed = recovery_key.to_formatted();
+    let groups: Vec<&str> = printed.expose_secret().split('-').collect();
+    assert_eq!(groups.len(), 8);
+    assert!(groups.iter().all(|g| g.len() == 6));
+    assert!(!format!("{:?}", recovery_key).contains(groups[0]));
+
+    // Saisie tolérante : minuscules, espaces, chiffres confondus avec des lettres
+    let typed = printed.expose_secret().to_lowercase().replace('-', " ").replace('o', "0");
+    let parsed = RecoveryKey::parse(&typed).unwrap();
+    assert_eq!(parsed, recovery_key);
+
+    let header = VaultHeader::from_bytes(&header.to_bytes()).unwrap();
+    assert_eq!(header.unlock_with_recovery_key(&parsed).unwrap(), master_key);
+    // La clé de récupération n'est pas une phrase de passe
+    assert!(header.unlock(&printed).is_err());
+    assert!(header.unlock_with_recovery_key(&RecoveryKey::generate()).is_err());
+
+    // Une faute de frappe est détectée par la somme de contrôle
+    let mut chars: Vec<char> = printed.expose_secret().chars().collect();
+    chars[3] = if chars[3] == 'A' { 'B' } else { 'A' };
+    let typo: String = chars.into_iter().collect();
+    assert!(matches!(RecoveryKey::parse(&typo), Err(DeepVaultError::InvalidInput(_))));
+    assert!(RecoveryKey::parse("ABCDEF").is_err());
+
+    // Feuille de récupération
+    let sheet = RecoverySheet::new(&header, slot, &recovery_key);
+    let text = sheet.to_text();
+    assert!(text.expose_secret().contains(&header.uuid.to_string()));
+    assert!(text.expose_secret().contains(&groups[..4].join("-")));
+    let svg = sheet.to_svg();
+    assert!(svg.expose_secret().starts_with("<svg"));
+    assert!(svg.expose_secret().contains(&groups[4..].join("-")));
+}
+
+#[test]
+fn test_shamir_shares() {
+    let (mut header, master_key) = VaultHeader::create(
+        &"Admin-Pass123!".into(),
+        &fast_kdf(),
+        header::DEFAULT_DATA_OFFSET,
+        1 << 20,
+    )
+    .unwrap();
+    let manager = crypto::CryptoManager::with_config(CryptoConfig::for_kdf(&fast_kdf(), &[0u8; 32]));
+    let (slot, shares) = manager.split_master_key(&mut header, &master_key, 3, 5).unwrap();
+    assert_eq!(shares.len(), 5);
+    assert_eq!(header.list_keyslots()[slot].kind, KeyslotKind::Shamir);
+    let header = VaultHeader::from_bytes(&header.to_bytes()).unwrap();
+
+    // N'importe quel groupe de 3 parts suffit
+    for group in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+        let chosen: Vec<Share> = group.iter().map(|&i| shares[i].clone()).collect();
+        assert_eq!(manager.unlock_with_shares(&header, &chosen).unwrap(), master_key);
+    }
+    // Deux parts ne suffisent pas, une part en double non plus
+    assert!(header.unlock_with_shares(&shares[..2]).is_err());
+    let duplicated = vec![shares[0].clone(), shares[0].clone(), shares[1].clone()];
+    assert!(header.unlock_with_shares(&duplicated).is_err());
+
+    // Export en code imprimable et en fichier
+    let code = shares[3].to_code();
+    assert_eq!(Share::from_code(&code.expose_secret().to_lowercase()).unwrap(), shares[3]);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("part.dvs");
+    shares[4].save(&path).unwrap();
+    assert!(shares[4].save(&path).is_err());
+    let loaded = Share::load(&path).unwrap();
+    assert_eq!(loaded, shares[4]);
+
+    // Une part corrompue est détectée
+    let mut bytes = shares[0].to_bytes();
+    bytes[30] ^= 1;
+    assert!(Share::from_bytes(&bytes).is_err());
+
+    // Parts d'un autre volume refusées, paramètres invalides refusés
+    let (mut other, other_key) =
+        VaultHeader::create(&"Other-Pass123!".into(), &fast_kdf(), header::DEFAULT_DATA_OFFSET, 1 << 20).unwrap();
+    assert!(other.unlock_with_shares(&shares[..3]).is_err());
+    assert!(other.add_shared_keyslot(&other_key, 1, 3, &fast_kdf()).is_err());
+    assert!(other.add_shared_keyslot(&other_key, 4, 3, &fast_kdf()).is_err());
+    assert_eq!(other.list_keyslots().len(), 1);
+}
+
+#[test]
+fn test_recipient_keyslots() {
+    let (mut header, master_key) = VaultHeader::create(
+        &"User-Pass123!".into(),
+        &fast_kdf(),
+        header::DEFAULT_DATA_OFFSET,
+        1 << 20,
+    )
+    .unwrap();
+    let admin = Identity::generate();
+    let recipient = admin.to_recipient();
+
+    // Le destinataire s'exporte et se réimporte
+    let text = recipient.to_string();
+    assert!(text.starts_with("dvr1"));
+    assert_eq!(text.parse::<Recipient>().unwrap(), recipient);
+    let mut typo = text.into_bytes();
+    typo[10] = if typo[10] == b'a' { b'b' } else { b'a' };
+    assert!(Recipient::parse(std::str::from_utf8(&typo).unwrap()).is_err());
+
+    // Le keyslot s'ouvre avec l'identité, sans phrase de passe
+    let slot = header.add_recipient_keyslot(&master_key, &recipient).unwrap();
+    let info = &header.list_keyslots()[slot];
+    assert_eq!(info.kind, KeyslotKind::Recipient);
+    assert_eq!(info.kdf, None);
+    assert_eq!(info.recipient, Some(recipient.to_string()));
+    let header = VaultHeader::from_bytes(&header.to_bytes()).unwrap();
+    assert_eq!(header.unlock_with_identity(&admin).unwrap(), master_key);
+    assert_eq!(header.unlock(&"User-Pass123!".into()).unwrap(), master_key);
+    assert!(header.unlock_with_identity(&Identity::generate()).is_err());
+
+    // Fichier d'identité: sauvegarde, relecture, et refus comme destinataire
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("admin.key");
+    admin.save(&path).unwrap();
+    assert!(admin.save(&path).is_err());
+    let loaded = Identity::load(&path).unwrap();
+    assert_eq!(loaded.to_recipient(), recipient);
+    assert_eq!(header.unlock_with_identity(&loaded).unwrap(), master_key);
+    assert!(Recipient::load(&path).is_err());
+    let recipient_path = dir.path().join("admin.pub");
+    std::fs::write(&recipient_path, format!("# admin\n{}\n", recipient)).unwrap();
+    assert_eq!(Recipient::load(&recipient_path).unwrap(), recipient);
+}
+
+/// Agent SSH de substitution: liste une seule clé ed25519 et signe avec elle
+#[cfg(unix)]
+fn spawn_test_agent(socket: &std::path::Path, key: ed25519_dalek::SigningKey) {
+    let listener = std::os::unix::net::UnixListener::bind(socket).unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let key = key.clone();
+            std::thread::spawn(move || serve_test_agent(stream, &key));
+        }
+    });
+}
+
+#[cfg(unix)]
+fn serve_test_agent(mut stream: std::os::unix::net::UnixStream, key: &ed25519_dalek::SigningKey) {
+    use ed25519_dalek::Signer;
+    use std::io::{Read, Write};
+
+    fn put_string(buffer: &mut Vec<u8>, bytes: &[u8]) {
+        buffer.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(bytes);
+    }
+
+    let blob = SshPublicKey::from_bytes(key.verifying_key().to_bytes()).to_blob();
+    let mut len = [0u8; 4];
+    while stream.read_exact(&mut len).is_ok() {
+        let mut message = vec![0u8; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut message).unwrap();
+
+        let mut reply = Vec::new();
+        match message[0] {
+            11 => {
+                reply.push(12);
+                reply.extend_from_slice(&1u32.to_be_bytes());
+                put_string(&mut reply, &blob);
+                put_string(&mut reply, b"admin@deepvault");
+            }
+            13 if message[5..5 + blob.len()] == blob[..] => {
+                let data = &message[9 + blob.len()..message.len() - 4];
+                let mut signature = Vec::new();
+                put_string(&mut signature, b"ssh-ed25519");
+                put_string(&mut signature, &key.sign(data).to_bytes());
+                reply.push(14);
+                put_string(&mut reply, &signature);
+            }
+            _ => reply.push(5),
+        }
+        stream.write_all(&(reply.len() as u32).to_be_bytes()).unwrap();
+        stream.write_all(&reply).unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_ssh_agent_keyslots() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("agent.sock");
+    let other_socket = dir.path().join("other.sock");
+    spawn_test_agent(&socket, ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]));
+    spawn_test_agent(&other_socket, ed25519_dalek::SigningKey::from_bytes(&[8u8; 32]));
+
+    let mut agent = SshAgent::connect_to(&socket).unwrap();
+    let identities = agent.identities().unwrap();
+    assert_eq!(identities.len(), 1);
+    let (ssh_key, comment) = &identities[0];
+    assert_eq!(comment, "admin@deepvault");
+    assert!(ssh_key.fingerprint().starts_with("SHA256:"));
+
+    // Format OpenSSH des clés publiques
+    let line = format!("{} admin@deepvault", ssh_key);
+    assert_eq!(SshPublicKey::parse(&line).unwrap(), *ssh_key);
+    assert!(SshPublicKey::parse("ssh-rsa AAAAB3NzaC1yc2E admin").is_err());
+
+    let (mut header, master_key) = VaultHeader::create(
+        &"User-Pass123!".into(),
+        &fast_kdf(),
+        header::DEFAULT_DATA_OFFSET,
+        1 << 20,
+    )
+    .unwrap();
+    let slot = header.add_ssh_agent_keyslot(&master_key, ssh_key, &mut agent).unwrap();
+    let info = &header.list_keyslots()[slot];
+    assert_eq!(info.kind, KeyslotKind::SshAgent);
+    assert_eq!(info.ssh_key, Some(ssh_key.fingerprint()));
+
+    // Déverrouillage sans phrase de passe, sur une nouvelle connexion
+    let header = VaultHeader::from_bytes(&header.to_bytes()).unwrap();
+    let mut agent = SshAgent::connect_to(&socket).unwrap();
+    assert_eq!(header.unlock_with_ssh_agent(&mut agent).unwrap(), master_key);
+
+    // Un agent qui ne détient pas la clé ne déverrouille pas le volume
+    let mut other = SshAgent::connect_to(&other_socket).unwrap();
+    assert!(header.unlock_with_ssh_agent(&mut other).is_err());
+    assert!(other.sign(ssh_key, b"challenge").is_err());
+}
+
+#[test]
+fn test_duress_passphrase() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("vault.img");
+    let password = SecretString::from("Real-Pass123!");
+    let duress = SecretString::from("Duress-Pass123!");
+
+    let (mut header, master_key) =
+        VaultHeader::create(&password, &fast_kdf(), header::DEFAULT_DATA_OFFSET, 64 * 1024).unwrap();
+    let slot = header.add_duress_keyslot(&master_key, &duress, &fast_kdf()).unwrap();
+    assert!(header.add_duress_keyslot(&master_key, &password, &fast_kdf()).is_err());
+    // Sur le disque, le keyslot de contrainte ressemble à un keyslot normal
+    assert_eq!(header.list_keyslots()[slot].kind, KeyslotKind::Password);
+    let mut bytes = header.to_bytes();
+    bytes.resize((header.data_offset + header.data_size) as usize, 0x5A);
+    std::fs::write(&image, &bytes).unwrap();
+
+    let open = |credentials: &Credentials, security: &SecurityConfig| {
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&image).unwrap();
+        VaultHeader::open(&mut file, credentials, security)
+    };
+    let failures = |result: Result<(VaultHeader, SecretKey)>| match result {
+        Err(DeepVaultError::UnlockFailed { failures, remaining: None }) => failures,
+        other => panic!("erreur inattendue: {:?}", other.map(|(header, _)| header.uuid)),
+    };
+    let real = Credentials::passphrase(&password);
+    let coerced = Credentials::passphrase(&duress);
+    let wrong = Credentials::passphrase(&"Wrong-Pass123!".into());
+
+    // Désactivée par défaut: la phrase de contrainte échoue sans rien détruire
+    let mut security = SecurityConfig {
+        attempt_policy: AttemptPolicy::default(),
+        ..SecurityConfig::default()
+    };
+    assert!(!security.enable_duress_passphrase);
+    assert_eq!(failures(open(&wrong, &security)), 1);
+    assert_eq!(failures(open(&coerced, &security)), 2);
+    assert_eq!(open(&real, &security).unwrap().1, master_key);
+    assert_eq!(std::fs::read(&image).unwrap(), bytes);
+
+    // Activée: même erreur qu'un échec, mais les keyslots sont détruits
+    security.enable_duress_passphrase = true;
+    assert_eq!(failures(open(&coerced, &security)), 1);
+    let destroyed = std::fs::read(&image).unwrap();
+    assert_ne!(destroyed[..header::HEADER_SIZE], bytes[..header::HEADER_SIZE]);
+    assert_eq!(destroyed[header::HEADER_SIZE..], bytes[header::HEADER_SIZE..]);
+
+    // L'en-tête reste lisible mais plus aucune phrase de passe ne l'ouvre
+    let after = VaultHeader::from_bytes(&destroyed).unwrap();
+    assert_eq!(after.uuid, header.uuid);
+    assert_eq!(after.list_keyslots().len(), 2);
+    assert_eq!(failures(open(&real, &security)), 2);
+    assert_eq!(failures(open(&coerced, &security)), 3);
+}
+
+#[test]
+fn test_attempt_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("vault.img");
+    let password = SecretString::from("Real-Pass123!");
+
+    let (mut header, master_key) =
+        VaultHeader::create(&password, &fast_kdf(), header::DEFAULT_DATA_OFFSET, 64 * 1024).unwrap();
+    assert_eq!(header.attempt_policy(), AttemptPolicy::default());
+    let policy = AttemptPolicy {
+        wipe_after: 3,
+        base_delay: 0,
+        max_delay: 0,
+    };
+    assert!(header
+        .set_attempt_policy(&master_key, AttemptPolicy { max_delay: 10, base_delay: 20, ..policy })
+        .is_err());
+    header.set_attempt_policy(&master_key, policy).unwrap();
+    let mut bytes = header.to_bytes();
+    bytes.resize((header.data_offset + header.data_size) as usize, 0);
+    std::fs::write(&image, &bytes).unwrap();
+
+    let open = |credentials: &Credentials| {
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&image).unwrap();
+        VaultHeader::open(&mut file, credentials, &SecurityConfig::default())
+    };
+    let outcome = |result: Result<(VaultHeader, SecretKey)>| match result {
+        Err(DeepVaultError::UnlockFailed { failures, remaining }) => (failures, remaining),
+        other => panic!("erreur inattendue: {:?}", other.map(|(header, _)| header.uuid)),
+    };
+    let real = Credentials::passphrase(&password);
+    let wrong = Credentials::passphrase(&"Wrong-Pass123!".into());
+
+    // Chaque échec annonce les tentatives restantes, un succès remet à zéro
+    assert_eq!(outcome(open(&wrong)), (1, Some(2)));
+    assert_eq!(outcome(open(&wrong)), (2, Some(1)));
+    let (opened, key) = open(&real).unwrap();
+    assert_eq!(key, master_key);
+    assert_eq!(opened.attempt_policy(), policy);
+    assert_eq!(opened.failed_attempts(), AttemptCounter::default());
+    assert_eq!(std::fs::read(&image).unwrap(), bytes);
+
+    // La politique est couverte par le MAC
+    let mut tampered = bytes.clone();
+    tampered[2112] ^= 0x01;
+    std::fs::write(&image, &tampered).unwrap();
+    assert!(open(&real).is_err());
+    std::fs::write(&image, &bytes).unwrap();
+
+    // Le compteur est authentifié par la chaîne d'échecs: les échecs
+    // enregistrés se vérifient, un compteur remis à zéro ou une date
+    // déplacée sont détectés
+    let load = || VaultHeader::from_bytes(&std::fs::read(&image).unwrap()).unwrap();
+    assert_eq!(outcome(open(&wrong)), (1, Some(2)));
+    assert_eq!(outcome(open(&wrong)), (2, Some(1)));
+    load().verify_attempts(&master_key).unwrap();
+    let failed = std::fs::read(&image).unwrap();
+    for (offset, value) in [(2124, 0u8), (2124, 1), (2128, 0)] {
+        let mut tampered = failed.clone();
+        tampered[offset..offset + 4].copy_from_slice(&[value, 0, 0, 0]);
+        std::fs::write(&image, &tampered).unwrap();
+        assert!(load().verify_attempts(&master_key).is_err());
+    }
+    let mut reset = failed.clone();
+    reset[2124..2200].fill(0);
+    std::fs::write(&image, &reset).unwrap();
+    assert!(load().verify_attempts(&master_key).is_err());
+    // Le bon mot de passe ouvre quand même le volume et repart de zéro
+    open(&real).unwrap();
+    assert_eq!(std::fs::read(&image).unwrap(), bytes);
+    load().verify_attempts(&master_key).unwrap();
+
+    // Au bout de trois échecs consécutifs, les keyslots sont détruits
+    assert_eq!(outcome(open(&wrong)), (1, Some(2)));
+    assert_eq!(outcome(open(&wrong)), (2, Some(1)));
+    assert_eq!(outcome(open(&wrong)), (3, Some(0)));
+    let destroyed = std::fs::read(&image).unwrap();
+    assert_eq!(destroyed[header::HEADER_SIZE..], bytes[header::HEADER_SIZE..]);
+    assert!(matches!(outcome(open(&real)), (4, Some(0))));
+
+    // Avec un délai, même le bon mot de passe doit attendre
+    let (mut header, master_key) =
+        VaultHeader::create(&password, &fast_kdf(), header::DEFAULT_DATA_OFFSET, 64 * 1024).unwrap();
+    let policy = AttemptPolicy {
+        wipe_after: 0,
+        base_delay: 60,
+        max_delay: 600,
+    };
+    header.set_attempt_policy(&master_key, policy).unwrap();
+    std::fs::write(&image, header.to_bytes()).unwrap();
+    assert_eq!(outcome(open(&wrong)), (1, None));
+    match open(&real) {
+        Err(DeepVaultError::RetryLater(seconds)) => assert!(seconds > 0 && seconds <= 60),
+        other => panic!("erreur inattendue: {:?}", other.map(|(header, _)| header.uuid)),
+    }
+
+    // Délais doublés à chaque échec, plafonnés
+    assert_eq!(policy.delay(0).as_secs(), 0);
+    assert_eq!(policy.delay(1).as_secs(), 60);
+    assert_eq!(policy.delay(3).as_secs(), 240);
+    assert_eq!(policy.delay(40).as_secs(), 600);
+    let counter = AttemptCounter {
+        failures: 1,
+        last_failure: 1000,
+    };
+    assert_eq!(counter.retry_after(&policy, 1030).unwrap().as_secs(), 30);
+    assert!(counter.retry_after(&policy, 1060).is_none());
+    // Une horloge reculée n'allonge pas l'attente au-delà d'un délai
+    assert_eq!(counter.retry_after(&policy, 0).unwrap().as_secs(), 60);
+
+    // Un nouveau volume reçoit la politique de la configuration
+    let mut config = AppConfig::default();
+    config.security.attempt_policy.wipe_after = 7;
+    let path = dir.path().join("politique.dvc");
+    let options = ContainerOptions {
+        size: 1024 * 1024,
+        kdf: fast_kdf(),
+        attempt_policy: config.security.attempt_policy,
+        ..Default::default()
+    };
+    Container::create(&path, &real, &options).unwrap().close().unwrap();
+    let stored = VaultHeader::load(&mut std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(stored.attempt_policy(), config.security.attempt_policy);
+    assert_eq!(stored.attempt_policy().wipe_after, 7);
+}
+
+#[test]
+fn test_password_strength() {
+    // Mots de passe courants: score nul quelles que soient les classes
+    let weak = strength::estimate("Password1!");
+    assert_eq!(weak.score, 0);
+    assert!(!weak.is_acceptable());
+    assert!(weak.crack_time < std::time::Duration::from_secs(1));
+    assert!(!weak.feedback.is_empty());
+    assert!(strength::estimate("P@ssw0rd").entropy_bits < strength::estimate("Password1!").entropy_bits + 1.0);
+
+    // Motifs reconnus
+    assert!(strength::estimate("qwertyuiop").entropy_bits < 15.0);
+    assert!(strength::estimate("azertyuiop").entropy_bits < 15.0);
+    assert!(strength::estimate("abcdefghijkl").entropy_bits < 15.0);
+    assert!(strength::estimate("aaaaaaaaaaaaaaaa").entropy_bits < 15.0);
+    assert!(strength::estimate("1987").entropy_bits < 10.0);
+    let random = strength::estimate("kX9#mP2$vL7q");
+    assert!(random.entropy_bits > 50.0);
+
+    // Longue phrase sans symbole: forte
+    let passphrase = strength::estimate("la vieille horloge chante sous la pluie");
+    assert_eq!(passphrase.score, 4);
+    assert!(passphrase.feedback.is_empty());
+
+    // Chaque mot de la liste vaut 11 bits, le séparateur répété presque rien
+    let words = utils::wordlist();
+    assert_eq!(words.len(), 2048);
+    let generated = strength::estimate("abandon-zoo-kitchen-orbit-wagon-vivid");
+    assert!(generated.entropy_bits > 66.0 && generated.entropy_bits < 80.0);
+    assert!(generated.is_acceptable());
+    assert!(strength::estimate("abandon-zoo").entropy_bits < strength::estimate("abandon-zoo-kitchen").entropy_bits);
+
+    // Vide
+    let empty = strength::estimate("");
+    assert_eq!(empty.score, 0);
+    assert_eq!(empty.entropy_bits, 0.0);
+}
+
+#[test]
+fn test_integrity_tags() {
+    use std::io::Cursor;
+
+    let (mut header, master_key) =
+        VaultHeader::create(&"IntegrityPass123!".into(), &fast_kdf(), header::DEFAULT_DATA_OFFSET, 64 * 1024).unwrap();
+    let mut image = header.to_bytes();
+    image.resize((header.data_offset + header.data_size) as usize, 0);
+
+    // Sans le mode intégrité, une modification passe inaperçue
+    assert!(!header.integrity());
+    assert!(EncryptedVolume::open_authenticated(Cursor::new(&mut image), &header, &master_key).is_err());
+
+    header.enable_integrity(&master_key).unwrap();
+    assert!(header.integrity());
+    let reparsed = VaultHeader::from_bytes(&header.to_bytes()).unwrap();
+    assert!(reparsed.integrity());
+    reparsed.verify_mac(&master_key).unwrap();
+    image[..header::HEADER_SIZE].copy_from_slice(&header.to_bytes());
+    assert!(EncryptedVolume::open(Cursor::new(&mut image), &header, &master_key).is_err());
+
+    // 128 secteurs bruts: 7 groupes complets de 17 et un groupe partiel
+    let data_area = FileDevice::new(Cursor::new(&mut image), header.data_offset, header.data_size);
+    let tagged = AuthenticatedDevice::format(data_area, &master_key).unwrap();
+    assert_eq!(tagged.sector_count(), 7 * 16 + 8);
+    assert_eq!(AuthenticatedDevice::<FileDevice<Cursor<Vec<u8>>>>::data_sectors(17), 16);
+
+    {
+        let mut volume = sector::open_volume(Cursor::new(&mut image), &header, &master_key).unwrap();
+        assert_eq!(volume.sector_count(), 120);
+        volume.write_at(60000, b"donnees authentifiees sur plusieurs groupes").unwrap();
+        let pattern: Vec<u8> = (0..20 * 512).map(|i| (i % 251) as u8).collect();
+        volume.write_sectors(10, &pattern).unwrap();
+        volume.write_sectors(119, &[0x42u8; 512]).unwrap();
+        assert!(volume.write_sectors(120, &[0u8; 512]).is_err());
+
+        let mut buf = vec![0u8; pattern.len()];
+        volume.read_sectors(10, &mut buf).unwrap();
+        assert_eq!(buf, pattern);
+        let mut buf = vec![0u8; 43];
+        volume.read_at(60000, &mut buf).unwrap();
+        assert_eq!(&buf, b"donnees authentifiees sur plusieurs groupes");
+    }
+
+    // Un bit modifié sur le support: lecture refusée pour ce secteur seulement
+    // (secteur de données 20 = secteur brut 22 du deuxième groupe)
+    let offset = header.data_offset as usize + 22 * 512 + 100;
+    image[offset] ^= 0x01;
+    let mut volume = EncryptedVolume::open_authenticated(Cursor::new(&mut image), &header, &master_key).unwrap();
+    let mut sector = vec![0u8; 512];
+    assert!(matches!(
+        volume.read_sectors(20, &mut sector),
+        Err(DeepVaultError::IntegrityViolation { sector: 20 })
+    ));
+    let mut range = vec![0u8; 4 * 512];
+    assert!(matches!(
+        volume.read_sectors(18, &mut range),
+        Err(DeepVaultError::IntegrityViolation { sector: 20 })
+    ));
+    volume.read_sectors(21, &mut sector).unwrap();
+    assert_eq!(volume.get_mut().scan().unwrap(), vec![20]);
+
+    // Deux secteurs échangés: les deux sont refusés
+    drop(volume);
+    image[offset] ^= 0x01;
+    let base = header.data_offset as usize;
+    let (first, second) = (base + 11 * 512, base + 12 * 512);
+    let saved = image[first..first + 512].to_vec();
+    image.copy_within(second..second + 512, first);
+    image[second..second + 512].copy_from_slice(&saved);
+    let data_area = FileDevice::new(Cursor::new(&mut image), header.data_offset, header.data_size);
+    let mut tagged = AuthenticatedDevice::new(data_area, &master_key).unwrap();
+    assert_eq!(tagged.scan().unwrap(), vec![10, 11]);
+
+    // Une réécriture légitime répare le secteur
+    tagged.write_sectors(10, &[0u8; 512]).unwrap();
+    assert_eq!(tagged.scan().unwrap(), vec![11]);
+
+    // Un autre volume ne valide pas ces étiquettes
+    let other_key = SecretKey::new(vec![7u8; 32]);
+    let mut other = AuthenticatedDevice::new(tagged.into_inner(), &other_key).unwrap();
+    assert!(other.read_sectors(0, &mut sector).is_err());
+}
+
+#[test]
+fn test_hidden_volume() {
+    use std::io::Cursor;
+
+    let size = 256 * 1024;
+    let (mut outer, outer_key) =
+        VaultHeader::create(&"Outer-Pass123!".into(), &fast_kdf(), header::DEFAULT_DATA_OFFSET, size).unwrap();
+    let mut storage = Cursor::new(vec![0u8; (outer.data_offset + outer.data_size) as usize]);
+    outer.initialize(&mut storage).unwrap();
+    let slot_range = hidden::HIDDEN_HEADER_OFFSET as usize..hidden::HIDDEN_HEADER_OFFSET as usize + hidden::HIDDEN_HEADER_SIZE;
+    let decoy = storage.get_ref()[slot_range.clone()].to_vec();
+
+    // Tout nouveau volume porte un emplacement aléatoire, volume caché ou non
+    let mut counts = [0usize; 256];
+    decoy.iter().for_each(|&b| counts[b as usize] += 1);
+    assert!(counts.iter().all(|&count| count < 80), "emplacement non aléatoire");
+    assert_eq!(VaultHeader::load(&mut storage).unwrap(), outer);
+
+    // Sans volume caché, l'emplacement ne s'ouvre avec aucune phrase
+    let hidden_password = SecretString::from("Hidden-Pass456!");
+    let hidden_credentials = Credentials::passphrase(&hidden_password);
+    assert!(hidden::open(&mut storage, &hidden_credentials, &fast_kdf()).is_err());
+
+    // Taille invalide: trop petite ou aussi grande que le volume externe
+    assert!(hidden::create(&mut storage, &outer, &hidden_credentials, &fast_kdf(), 4096).is_err());
+    assert!(hidden::create(&mut storage, &outer, &hidden_credentials, &fast_kdf(), size).is_err());
+
+    // Refusé tant que la zone de données externe n'est pas remplie d'aléa
+    assert!(matches!(
+        hidden::create(&mut storage, &outer, &hidden_credentials, &fast_kdf(), 64 * 1024),
+        Err(DeepVaultError::InvalidInput(_))
+    ));
+    block::fill_random(&mut storage, outer.data_offset, outer.data_size).unwrap();
+    outer.enable_random_fill(&outer_key).unwrap();
+    outer.store(&mut storage).unwrap();
+
+    let (hidden, hidden_key) =
+        hidden::create(&mut storage, &outer, &hidden_credentials, &fast_kdf(), 64 * 1024).unwrap();
+    assert_eq!(hidden.data_offset + hidden.data_size, outer.data_offset + outer.data_size);
+    assert_ne!(hidden_key, outer_key);
+
+    // L'en-tête caché ressemble à des données aléatoires
+    let slot = storage.get_ref()[slot_range.clone()].to_vec();
+    assert_ne!(slot, decoy);
+    assert!(!VaultHeader::is_vault_header(&slot));
+    assert!(!slot.windows(4).any(|w| w == header::MAGIC));
+    let mut counts = [0usize; 256];
+    slot.iter().for_each(|&b| counts[b as usize] += 1);
+    assert!(counts.iter().all(|&count| count < 80), "distribution non uniforme");
+    // L'en-tête externe n'a pas changé
+    assert_eq!(storage.get_ref()[..header::HEADER_SIZE], outer.to_bytes()[..]);
+
+    // Seule la bonne phrase ouvre le volume caché
+    assert!(hidden::open_with_password(&mut storage, &"Outer-Pass123!".into(), &fast_kdf()).is_err());
+    let (opened, key) = hidden::open(&mut storage, &hidden_credentials, &fast_kdf()).unwrap();
+    assert_eq!(opened, hidden);
+    assert_eq!(key, hidden_key);
+    assert!(hidden::open(&mut storage, &hidden_credentials, &KdfParams::default()).is_err());
+
+    // Données du volume caché
+    {
+        let mut volume = EncryptedVolume::open(&mut storage, &hidden, &hidden_key).unwrap();
+        assert_eq!(volume.sector_count(), 128);
+        volume.write_at(0, b"document cache").unwrap();
+    }
+
+    // Volume externe protégé: les écritures hors de la zone cachée passent
+    let protected = hidden::hidden_sectors(&outer, &hidden).unwrap();
+    assert_eq!(protected, 384..512);
+    {
+        let mut volume = hidden::open_protected(&mut storage, &outer, &outer_key, &hidden).unwrap();
+        volume.write_sectors(0, &[0x11u8; 512]).unwrap();
+        volume.write_sectors(383, &[0x22u8; 512]).unwrap();
+        assert!(!volume.tripped());
+
+        // Une écriture qui déborde sur le volume caché est refusée, puis toutes les suivantes
+        assert!(matches!(
+            volume.write_sectors(383, &[0x33u8; 1024]),
+            Err(DeepVaultError::Permission(_))
+        ));
+        assert!(volume.tripped());
+        assert!(volume.write_sectors(0, &[0x44u8; 512]).is_err());
+        let mut sector = vec![0u8; 512];
+        volume.read_sectors(383, &mut sector).unwrap();
+        assert_eq!(sector, vec![0x22u8; 512]);
+    }
+
+    // Le volume caché est intact
+    let mut volume = EncryptedVolume::open(&mut storage, &hidden, &hidden_key).unwrap();
+    let mut buf = vec![0u8; 14];
+    volume.read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf, b"document cache");
+
+    // Sans protection, le volume externe écrase le volume caché
+    let mut outer_volume = EncryptedVolume::open(&mut storage, &outer, &outer_key).unwrap();
+    outer_volume.write_sectors(384, &[0u8; 512]).unwrap();
+    let mut volume = EncryptedVolume::open(&mut storage, &hidden, &hidden_key).unwrap();
+    volume.read_at(0, &mut buf).unwrap();
+    assert_ne!(&buf, b"document cache");
+
+    // Montage d'un conteneur avec protection de son volume caché
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("externe.dvc");
+    let outer_credentials = Credentials::passphrase(&"Outer-Pass123!".into());
+    let options = ContainerOptions { size: 1024 * 1024, kdf: fast_kdf(), fill_random: true, ..Default::default() };
+    Container::create(&path, &outer_credentials, &options).unwrap().close().unwrap();
+    {
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let outer = VaultHeader::load(&mut file).unwrap();
+        hidden::create(&mut file, &outer, &hidden_credentials, &fast_kdf(), 256 * 1024).unwrap();
+    }
+    let manager = MountManager::new(path);
+    let security = SecurityConfig::default();
+    let kdf = fast_kdf();
+    let protect = ProtectHidden { credentials: &hidden_credentials, kdf: &kdf };
+    let mut volume = manager.open_volume(&outer_credentials, &security, false, Some(&protect)).unwrap();
+    volume.write_sectors(1535, &[1u8; 512]).unwrap();
+    assert!(matches!(volume.write_sectors(1536, &[1u8; 512]), Err(DeepVaultError::Permission(_))));
+    drop(volume);
+
+    // Montage du volume caché lui-même: les données écrites se relisent
+    let mut volume = manager.open_hidden_volume(&hidden_credentials, &kdf, false).unwrap();
+    assert_eq!(volume.sector_count(), 512);
+    volume.write_sectors(0, &[0x5Au8; 512]).unwrap();
+    volume.flush().unwrap();
+    drop(volume);
+    let mut volume = manager.open_hidden_volume(&hidden_credentials, &kdf, true).unwrap();
+    let mut sector = vec![0u8; 512];
+    volume.read_sectors(0, &mut sector).unwrap();
+    assert_eq!(sector, vec![0x5Au8; 512]);
+    assert!(volume.write_sectors(0, &[0u8; 512]).is_err());
+    drop(volume);
+    assert!(manager.open_hidden_volume(&outer_credentials, &kdf, true).is_err());
+    let wrong = ProtectHidden { credentials: &outer_credentials, kdf: &kdf };
+    assert!(manager.open_volume(&outer_credentials, &security, false, Some(&wrong)).is_err());
+    let mut volume = manager.open_volume(&outer_credentials, &security, false, None).unwrap();
+    volume.write_sectors(1536, &[1u8; 512]).unwrap();
+}
+
+#[test]
+fn test_container() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("coffre.dvc");
+    let password = SecretString::from("Container-Pass123!");
+    let credentials = Credentials::passphrase(&password);
+    let security = SecurityConfig::default();
+    let options = ContainerOptions {
+        size: 2 * 1024 * 1024,
+        kdf: fast_kdf(),
+        ..Default::default()
+    };
+
+    // Taille invalide
+    let tiny = ContainerOptions { size: 4096, ..options.clone() };
+    assert!(Container::create(&path, &credentials, &tiny).is_err());
+    assert!(!path.exists());
+    let huge = ContainerOptions { size: u64::MAX / 512 * 512, ..options.clone() };
+    assert!(matches!(Container::create(&path, &credentials, &huge), Err(DeepVaultError::InvalidInput(_))));
+    assert!(!path.exists());
+
+    let mut container = Container::create(&path, &credentials, &options).unwrap();
+    assert_eq!(container.size(), options.size);
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().len(),
+        header::DEFAULT_DATA_OFFSET + options.size
+    );
+    container.write_at(4096, b"contenu du conteneur").unwrap();
+
+    // Le fichier ne peut ni être écrasé ni ouvert deux fois
+    assert!(Container::create(&path, &credentials, &options).is_err());
+    assert!(Container::open(&path, &credentials, &security).is_err());
+    container.close().unwrap();
+
+    // Les données sont chiffrées sur le disque
+    let raw = std::fs::read(&path).unwrap();
+    assert!(!raw.windows(20).any(|w| w == b"contenu du conteneur"));
+
+    assert!(Container::open(&path, &Credentials::passphrase(&"Wrong-Pass123!".into()), &security).is_err());
+    let mut container = Container::open(&path, &credentials, &security).unwrap();
+    assert_eq!(container.path(), path.as_path());
+    let mut buf = [0u8; 20];
+    container.read_at(4096, &mut buf).unwrap();
+    assert_eq!(&buf, b"contenu du conteneur");
+    container.close().unwrap();
+
+    // Conteneur authentifié: une modification du fichier est détectée
+    let path = dir.path().join("integre.dvc");
+    let options = ContainerOptions { integrity: true, ..options };
+    let mut container = Container::create(&path, &credentials, &options).unwrap();
+    assert!(container.header().integrity());
+    assert!(container.size() < options.size);
+    container.write_at(0, &[0x42u8; 512]).unwrap();
+    container.close().unwrap();
+
+    let mut raw = std::fs::read(&path).unwrap();
+    raw[header::DEFAULT_DATA_OFFSET as usize + 512 + 10] ^= 1;
+    std::fs::write(&path, &raw).unwrap();
+    let mut container = Container::open(&path, &credentials, &security).unwrap();
+    assert!(matches!(
+        container.read_at(0, &mut [0u8; 512]),
+        Err(DeepVaultError::IntegrityViolation { sector: 0 })
+    ));
+
+    // Un fichier tronqué est refusé
+    drop(container);
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(header::DEFAULT_DATA_OFFSET + 4096).unwrap();
+    drop(file);
+    assert!(Container::open(&path, &credentials, &security).is_err());
+}
+
+/// Secteur de démarrage FAT32 minimal annonçant `sectors` secteurs
+fn fat32_boot_sector(sectors: u32) -> [u8; 512] {
+    let mut boot = [0u8; 512];
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+    boot[32..36].copy_from_slice(&sectors.to_le_bytes());
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510] = 0x55;
+    boot[511] = 0xAA;
+    boot
+}
+
+#[test]
+fn test_resize() {
+    use std::io::Cursor;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("coffre.dvc");
+    let password = SecretString::from("Container-Pass123!");
+    let credentials = Credentials::passphrase(&password);
+    let security = SecurityConfig::default();
+    let mib = 1024 * 1024;
+    let options = ContainerOptions {
+        size: 2 * mib,
+        kdf: fast_kdf(),
+        ..Default::default()
+    };
+
+    // Conteneur sans système de fichiers reconnu: réduction refusée sans force
+    let mut container = Container::create(&path, &credentials, &options).unwrap();
+    assert!(container.resize(mib, false).is_err());
+    assert_eq!(container.size(), 2 * mib);
+
+    // Agrandissement: le fichier suit et les données restent lisibles
+    container.write_sectors(0, &fat32_boot_sector(4096)).unwrap();
+    container.write_at(mib, b"donnees existantes").unwrap();
+    container.resize(4 * mib, false).unwrap();
+    assert_eq!(container.size(), 4 * mib);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), header::DEFAULT_DATA_OFFSET + 4 * mib);
+    container.write_at(3 * mib, b"nouvelle zone").unwrap();
+    container.close().unwrap();
+
+    let mut container = Container::open(&path, &credentials, &security).unwrap();
+    assert_eq!(container.size(), 4 * mib);
+    let mut buf = [0u8; 18];
+    container.read_at(mib, &mut buf).unwrap();
+    assert_eq!(&buf, b"donnees existantes");
+    assert_eq!(resize::filesystem_size(&mut container).unwrap(), Some(2 * mib));
+
+    // Réduction: refusée sous la taille du système de fichiers
+    assert!(container.resize(mib, false).is_err());
+    assert!(container.resize(3 * mib + 1, false).is_err());
+    let huge = u64::MAX / 512 * 512;
+    assert!(matches!(container.resize(huge, false), Err(DeepVaultError::InvalidInput(_))));
+    container.resize(2 * mib, false).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), header::DEFAULT_DATA_OFFSET + 2 * mib);
+    container.read_at(mib, &mut buf).unwrap();
+    assert_eq!(&buf, b"donnees existantes");
+    container.resize(mib, true).unwrap();
+    assert_eq!(container.size(), mib);
+    assert!(!container.header().random_fill());
+    container.close().unwrap();
+
+    // Conteneur rempli d'aléa: la zone ajoutée l'est aussi
+    let random_path = dir.path().join("aleatoire.dvc");
+    let mut container =
+        Container::create(&random_path, &credentials, &ContainerOptions { fill_random: true, ..options.clone() }).unwrap();
+    assert!(container.header().random_fill());
+    container.resize(3 * mib, true).unwrap();
+    container.close().unwrap();
+    let image = std::fs::read(&random_path).unwrap();
+    let grown = &image[(header::DEFAULT_DATA_OFFSET + 2 * mib) as usize..];
+    assert_eq!(grown.len() as u64, mib);
+    assert!(grown.chunks(512).all(|sector| sector.iter().any(|&b| b != 0)));
+
+    // Volume sur un support fixe, en mode intégrité
+    let (mut header, key) =
+        VaultHeader::create(&password, &fast_kdf(), header::DEFAULT_DATA_OFFSET, 64 * 1024).unwrap();
+    header.enable_integrity(&key).unwrap();
+    let mut storage = Cursor::new(vec![0u8; (header::DEFAULT_DATA_OFFSET + 128 * 1024) as usize]);
+    header.store(&mut storage).unwrap();
+    {
+        let data_area = FileDevice::new(&mut storage, header.data_offset, header.data_size);
+        AuthenticatedDevice::format(data_area, &key).unwrap();
+        let mut volume = EncryptedVolume::open_authenticated(&mut storage, &header, &key).unwrap();
+        volume.write_at(512, b"avant").unwrap();
+    }
+
+    // Le support est trop petit pour 256 KiB
+    assert!(resize::resize(&mut storage, &mut header, &key, 256 * 1024, false).is_err());
+    assert!(matches!(
+        resize::resize(&mut storage, &mut header, &key, u64::MAX / 512 * 512, false),
+        Err(DeepVaultError::InvalidInput(_))
+    ));
+    resize::resize(&mut storage, &mut header, &key, 128 * 1024, false).unwrap();
+    assert_eq!(resize::usable_size(&header, 128 * 1024), 240 * 512);
+    let loaded = VaultHeader::load(&mut storage).unwrap();
+    assert_eq!(loaded.data_size, 128 * 1024);
+    loaded.verify_mac(&key).unwrap();
+
+    // Les secteurs ajoutés sont étiquetés, les anciens sont intacts
+    let mut volume = EncryptedVolume::open_authenticated(&mut storage, &loaded, &key).unwrap();
+    assert_eq!(volume.size(), 240 * 512);
+    let mut before = [0u8; 5];
+    volume.read_at(512, &mut before).unwrap();
+    assert_eq!(&before, b"avant");
+    volume.read_at(200 * 512, &mut [0u8; 512]).unwrap();
+}
+
+#[test]
+fn test_sparse_container() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dynamique.dvc");
+    let password = SecretString::from("Container-Pass123!");
+    let credentials = Credentials::passphrase(&password);
+    let security = SecurityConfig::default();
+    let mib = 1024 * 1024;
+    let block = sparse::SPARSE_BLOCK_SIZE;
+    let options = ContainerOptions {
+        size: 32 * mib,
+        kdf: fast_kdf(),
+        sparse: true,
+        ..Default::default()
+    };
+    let file_len = || std::fs::metadata(&path).unwrap().len();
+
+    // Incompatible avec le mode intégrité
+    let tagged = ContainerOptions { integrity: true, ..options.clone() };
+    assert!(Container::create(&path, &credentials, &tagged).is_err());
+
+    // Seule la table d'allocation est écrite à la création
+    let mut container = Container::create(&path, &credentials, &options).unwrap();
+    assert!(container.header().sparse());
+    assert_eq!(container.size(), 32 * mib);
+    let empty_len = header::DEFAULT_DATA_OFFSET + block;
+    assert_eq!(file_len(), empty_len);
+    let mut buf = vec![0xFFu8; 1024];
+    container.read_at(5 * mib, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0));
+
+    // Le fichier grandit d'un bloc à la première écriture, pas pour des zéros
+    container.write_at(10 * mib + 100, b"bloc A").unwrap();
+    assert_eq!(file_len(), empty_len + block);
+    container.write_at(12 * mib, &[0u8; 4096]).unwrap();
+    assert_eq!(file_len(), empty_len + block);
+    container.write_at(20 * mib, b"bloc B").unwrap();
+    container.write_at(30 * mib, b"bloc C").unwrap();
+    assert_eq!(file_len(), empty_len + 3 * block);
+    container.close().unwrap();
+
+    // Données chiffrées, table rechargée à l'ouverture
+    let raw = std::fs::read(&path).unwrap();
+    assert!(!raw.windows(6).any(|w| w == b"bloc A" || w == b"bloc C"));
+    let mut container = Container::open(&path, &credentials, &security).unwrap();
+    let mut word = [0u8; 6];
+    container.read_at(10 * mib + 100, &mut word).unwrap();
+    assert_eq!(&word, b"bloc A");
+    container.read_at(10 * mib, &mut word).unwrap();
+    assert_eq!(word, [0u8; 6]);
+
+    // Bloc A remis à zéro, bloc B libéré: compact rend deux blocs
+    container.write_at(10 * mib, &vec![0u8; block as usize]).unwrap();
+    container.discard(20 * mib / 512, block / 512).unwrap();
+    container.read_at(20 * mib, &mut word).unwrap();
+    assert_eq!(word, [0u8; 6]);
+    assert_eq!(container.compact().unwrap(), 2 * block);
+    assert_eq!(file_len(), empty_len + block);
+    container.read_at(30 * mib, &mut word).unwrap();
+    assert_eq!(&word, b"bloc C");
+    assert_eq!(container.compact().unwrap(), 0);
+
+    // Redimensionnement dans la limite de la table d'allocation
+    container.resize(64 * mib, false).unwrap();
+    assert_eq!(file_len(), empty_len + block);
+    assert!(container.resize(4096 * mib, false).is_err());
+    container.resize(16 * mib, true).unwrap();
+    assert_eq!(container.compact().unwrap(), block);
+    assert_eq!(file_len(), empty_len);
+    container.close().unwrap();
+
+    let mut container = Container::open(&path, &credentials, &security).unwrap();
+    assert_eq!(container.size(), 16 * mib);
+    container.resize(32 * mib, false).unwrap();
+    container.read_at(30 * mib, &mut word).unwrap();
+    assert_eq!(word, [0u8; 6]);
+    container.close().unwrap();
+
+    // Un conteneur ordinaire ne se compacte pas
+    let path = dir.path().join("fixe.dvc");
+    let options = ContainerOptions { sparse: false, size: 2 * mib, ..options };
+    let mut container = Container::create(&path, &credentials, &options).unwrap();
+    assert!(container.compact().is_err());
+}
+
+/// Requête FUSE: en-tête `fuse_in_header` suivi du corps
+#[cfg(target_os = "linux")]
+fn fuse_request(opcode: u32, unique: u64, node: u64, body: &[u8]) -> Vec<u8> {
+    let mut request = Vec::new();
+    request.extend_from_slice(&(40 + body.len() as u32).to_le_bytes());
+    request.extend_from_slice(&opcode.to_le_bytes());
+    request.extend_from_slice(&unique.to_le_bytes());
+    request.extend_from_slice(&node.to_le_bytes());
+    request.extend_from_slice(&[0u8; 16]);
+    request.extend_from_slice(body);
+    request
+}
+
+/// Code d'erreur et contenu d'une réponse FUSE
+#[cfg(target_os = "linux")]
+fn fuse_reply(reply: &[u8], unique: u64) -> (i32, Vec<u8>) {
+    assert_eq!(u32::from_le_bytes(reply[..4].try_into().unwrap()) as usize, reply.len());
+    assert_eq!(u64::from_le_bytes(reply[8..16].try_into().unwrap()), unique);
+    (i32::from_le_bytes(reply[4..8].try_into().unwrap()), reply[16..].to_vec())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_fuse_protocol() {
+    use deepvault_core::fuse::{FuseOptions, ImageFs, IMAGE_NAME};
+    use std::io::Cursor;
+
+    let (header, key) = VaultHeader::create(&"Fuse-Pass123!".into(), &fast_kdf(), 4096, 64 * 1024).unwrap();
+    let mut image = header.to_bytes();
+    image.resize(4096 + 64 * 1024, 0);
+    let volume = EncryptedVolume::open(Cursor::new(image), &header, &key).unwrap();
+    let mut fs = ImageFs::new(volume, &FuseOptions::default());
+
+    // INIT: protocole 7.x négocié
+    let mut init = Vec::new();
+    for value in [7u32, 38, 128 * 1024, 1 << 5] {
+        init.extend_from_slice(&value.to_le_bytes());
+    }
+    init.resize(64, 0);
+    let (error, out) = fuse_reply(&fs.handle(&fuse_request(26, 1, 0, &init)).unwrap(), 1);
+    assert_eq!(error, 0);
+    assert_eq!(out.len(), 64);
+    assert_eq!(&out[..8], &[7, 0, 0, 0, 31, 0, 0, 0]);
+    let (error, _) = fuse_reply(&fs.handle(&fuse_request(26, 2, 0, &[6u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap(), 2);
+    assert_eq!(error, -libc::EPROTO);
+
+    // LOOKUP de l'image, puis d'un nom inconnu
+    let name = format!("{}\0", IMAGE_NAME);
+    let (error, entry) = fuse_reply(&fs.handle(&fuse_request(1, 3, 1, name.as_bytes())).unwrap(), 3);
+    assert_eq!(error, 0);
+    assert_eq!(u64::from_le_bytes(entry[..8].try_into().unwrap()), 2);
+    assert_eq!(u64::from_le_bytes(entry[48..56].try_into().unwrap()), 64 * 1024);
+    let (error, _) = fuse_reply(&fs.handle(&fuse_request(1, 4, 1, b"autre\0")).unwrap(), 4);
+    assert_eq!(error, -libc::ENOENT);
+
+    // WRITE puis READ au travers du chiffrement
+    let mut write = Vec::new();
+    write.extend_from_slice(&0u64.to_le_bytes());
+    write.extend_from_slice(&1000u64.to_le_bytes());
+    write.extend_from_slice(&12u32.to_le_bytes());
+    write.resize(40, 0);
+    write.extend_from_slice(b"donnees fuse");
+    let (error, out) = fuse_reply(&fs.handle(&fuse_request(16, 5, 2, &write)).unwrap(), 5);
+    assert_eq!(error, 0);
+    assert_eq!(u32::from_le_bytes(out[..4].try_into().unwrap()), 12);
+
+    let read = |offset: u64, size: u32| {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0u64.to_le_bytes());
+        body.extend_from_slice(&offset.to_le_bytes());
+        body.extend_from_slice(&size.to_le_bytes());
+        body.resize(40, 0);
+        body
+    };
+    let (error, data) = fuse_reply(&fs.handle(&fuse_request(15, 6, 2, &read(1000, 12))).unwrap(), 6);
+    assert_eq!(error, 0);
+    assert_eq!(&data, b"donnees fuse");
+    // Lecture tronquée à la fin de l'image
+    let (_, data) = fuse_reply(&fs.handle(&fuse_request(15, 7, 2, &read(64 * 1024 - 100, 4096))).unwrap(), 7);
+    assert_eq!(data.len(), 100);
+
+    // READDIR: ".", ".." et l'image
+    let (error, entries) = fuse_reply(&fs.handle(&fuse_request(28, 8, 1, &read(0, 4096))).unwrap(), 8);
+    assert_eq!(error, 0);
+    assert!(entries.windows(IMAGE_NAME.len()).any(|w| w == IMAGE_NAME.as_bytes()));
+    let (_, rest) = fuse_reply(&fs.handle(&fuse_request(28, 9, 1, &read(3, 4096))).unwrap(), 9);
+    assert!(rest.is_empty());
+
+    // FORGET sans réponse, opération inconnue refusée
+    assert!(fs.handle(&fuse_request(2, 10, 2, &[0u8; 8])).is_none());
+    let (error, _) = fuse_reply(&fs.handle(&fuse_request(9999, 11, 1, &[])).unwrap(), 11);
+    assert_eq!(error, -libc::ENOSYS);
+
+    // Le contenu est bien chiffré dans le volume
+    let mut volume = fs.into_inner();
+    let mut buf = [0u8; 12];
+    volume.read_at(1000, &mut buf).unwrap();
+    assert_eq!(&buf, b"donnees fuse");
+    let raw = volume.into_inner().into_inner().into_inner();
+    assert!(!raw.windows(7).any(|w| w == b"donnees"));
+
+    // Montage en lecture seule: écritures refusées
+    let (header, key) = VaultHeader::create(&"Fuse-Pass123!".into(), &fast_kdf(), 4096, 64 * 1024).unwrap();
+    let mut image = header.to_bytes();
+    image.resize(4096 + 64 * 1024, 0);
+    let volume = EncryptedVolume::open(Cursor::new(image), &header, &key).unwrap();
+    let mut fs = ImageFs::new(volume, &FuseOptions { read_only: true });
+    let (error, _) = fuse_reply(&fs.handle(&fuse_request(16, 12, 2, &write)).unwrap(), 12);
+    assert_eq!(error, -libc::EROFS);
+    let (error, _) = fuse_reply(&fs.handle(&fuse_request(14, 13, 2, &2u32.to_le_bytes())).unwrap(), 13);
+    assert_eq!(error, -libc::EROFS);
+}
+
+
+/// Type d'erreur d'entrée/sortie renvoyé par le système de fichiers du coffre
+fn io_kind<T: std::fmt::Debug>(result: Result<T>) -> std::io::ErrorKind {
+    match result.unwrap_err() {
+        DeepVaultError::Io(e) => e.kind(),
+        e => panic!("erreur inattendue: {}", e),
+    }
+}
+
+#[test]
+fn test_vault_filesystem() {
+    use deepvault_core::vaultfs::{FileKind, FS_BLOCK_SIZE};
+    use std::io::{Cursor, ErrorKind};
+
+    let mut image = vec![0u8; 4 * 1024 * 1024];
+    let mut fs = VaultFs::format(FileDevice::new(Cursor::new(&mut image), 0, 4 * 1024 * 1024)).unwrap();
+    let empty = fs.statfs();
+    assert!(fs.read_dir("/").unwrap().is_empty());
+    assert!(fs.metadata("/").unwrap().is_dir());
+
+    // Noms longs, sous-répertoires, erreurs typées
+    let long_name = format!("/docs/{}.txt", "n".repeat(200));
+    fs.create_dir("/docs").unwrap();
+    fs.write(&long_name, b"contenu du fichier").unwrap();
+    assert_eq!(fs.read(&long_name).unwrap(), b"contenu du fichier");
+    assert_eq!(io_kind(fs.create_dir("/docs")), ErrorKind::AlreadyExists);
+    assert_eq!(io_kind(fs.read("/absent")), ErrorKind::NotFound);
+    assert_eq!(io_kind(fs.write("/docs/x/y", b"")), ErrorKind::NotFound);
+    assert_eq!(io_kind(fs.read("/docs")), ErrorKind::IsADirectory);
+    assert_eq!(io_kind(fs.remove("/docs")), ErrorKind::DirectoryNotEmpty);
+    assert_eq!(io_kind(fs.create(&format!("/{}", "n".repeat(256)))), ErrorKind::InvalidFilename);
+
+    // Supprimer un fichier n'abîme pas les autres
+    fs.write("/a", b"fichier a").unwrap();
+    fs.write("/b", &vec![0xB0u8; 10_000]).unwrap();
+    fs.write("/c", b"fichier c").unwrap();
+    fs.remove("/b").unwrap();
+    fs.write("/d", b"fichier d").unwrap();
+    assert_eq!(fs.read("/a").unwrap(), b"fichier a");
+    assert_eq!(fs.read("/c").unwrap(), b"fichier c");
+    assert_eq!(fs.read("/d").unwrap(), b"fichier d");
+    let names: Vec<_> = fs.read_dir("/").unwrap().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, ["docs", "a", "c", "d"]);
+
+    // Écriture à un décalage: le trou se lit comme des zéros
+    fs.write_at("/a", 3 * FS_BLOCK_SIZE + 10, b"loin").unwrap();
+    assert_eq!(fs.metadata("/a").unwrap().size, 3 * FS_BLOCK_SIZE + 14);
+    let data = fs.read("/a").unwrap();
+    assert_eq!(&data[..9], b"fichier a");
+    assert!(data[9..3 * FS_BLOCK_SIZE as usize + 10].iter().all(|&b| b == 0));
+    let mut word = [0u8; 4];
+    assert_eq!(fs.read_at("/a", 3 * FS_BLOCK_SIZE + 10, &mut word).unwrap(), 4);
+    assert_eq!(&word, b"loin");
+    fs.truncate("/a", 4).unwrap();
+    fs.truncate("/a", 20).unwrap();
+    assert_eq!(fs.read("/a").unwrap(), b"fich\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+
+    // Renommage entre répertoires, remplacement d'un fichier
+    fs.rename("/c", "/docs/c").unwrap();
+    assert_eq!(io_kind(fs.read("/c")), ErrorKind::NotFound);
+    fs.rename("/d", "/docs/c").unwrap();
+    assert_eq!(fs.read("/docs/c").unwrap(), b"fichier d");
+    assert!(fs.rename("/docs", "/docs/sous").is_err());
+    assert_eq!(io_kind(fs.rename("/a", "/docs")), ErrorKind::IsADirectory);
+    fs.create_dir("/vide").unwrap();
+    assert_eq!(io_kind(fs.rename("/vide", "/docs/c")), ErrorKind::NotADirectory);
+    fs.rename("/docs", "/archives").unwrap();
+    assert_eq!(fs.read("/archives/c").unwrap(), b"fichier d");
+
+    // Fichier très fragmenté: extents chaînés hors de l'inode
+    for i in 0..60 {
+        fs.write(&format!("/vide/{}", i), &[i as u8; 100]).unwrap();
+    }
+    for i in (0..60).step_by(2) {
+        fs.remove(&format!("/vide/{}", i)).unwrap();
+    }
+    let big: Vec<u8> = (0..400_000u32).map(|i| (i % 251) as u8).collect();
+    fs.write("/gros", &big).unwrap();
+    assert_eq!(fs.read("/gros").unwrap(), big);
+
+    // Espace insuffisant: rien n'est modifié
+    let before = fs.statfs();
+    assert_eq!(io_kind(fs.write("/trop", &vec![1u8; 8 * 1024 * 1024])), ErrorKind::StorageFull);
+    assert_eq!(io_kind(fs.metadata("/trop")), ErrorKind::NotFound);
+    assert_eq!(fs.statfs().free_blocks, before.free_blocks);
+
+    // Tout est relu après réouverture
+    drop(fs);
+    let mut fs = VaultFs::open(FileDevice::new(Cursor::new(&mut image), 0, 4 * 1024 * 1024)).unwrap();
+    assert_eq!(fs.read("/gros").unwrap(), big);
+    assert_eq!(fs.read(&long_name.replace("/docs", "/archives")).unwrap(), b"contenu du fichier");
+    assert_eq!(fs.read("/vide/59").unwrap(), [59u8; 100]);
+    let entry = &fs.read_dir("/archives").unwrap()[1];
+    assert_eq!((entry.name.as_str(), entry.metadata.kind), ("c", FileKind::File));
+
+    // Suppression récursive: tout l'espace est rendu
+    for name in ["/a", "/gros", "/archives", "/vide"] {
+        fs.remove_all(name).unwrap();
+    }
+    let stats = fs.statfs();
+    assert_eq!((stats.free_blocks, stats.free_inodes), (empty.free_blocks, empty.free_inodes));
+    fs.check().unwrap();
+    drop(fs);
+
+    // Un volume sans système de fichiers est refusé
+    assert!(VaultFs::open(FileDevice::new(Cursor::new(vec![0u8; 1024 * 1024]), 0, 1024 * 1024)).is_err());
+}
+
+/// Périphérique qui compte les secteurs écrits
+struct WriteCounter<D> {
+    inner: D,
+    written: u64,
+}
+
+impl<D: BlockDevice> BlockDevice for WriteCounter<D> {
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_sectors(first, buf)
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        self.written += (buf.len() / 512) as u64;
+        self.inner.write_sectors(first, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_vault_large_directory() {
+    use deepvault_core::vaultfs::FS_BLOCK_SIZE;
+    use std::io::Cursor;
+
+    // 16 Mio: journal de 128 blocs, 1024 inodes
+    let size = 16 * 1024 * 1024;
+    let device = WriteCounter { inner: FileDevice::new(Cursor::new(vec![0u8; size]), 0, size as u64), written: 0 };
+    let mut fs = VaultFs::format(device).unwrap();
+    let empty = fs.statfs();
+    fs.create_dir("/d").unwrap();
+    fs.create_dir("/d/sous").unwrap();
+    let name = |i: usize| format!("/d/sous/{:04}{}", i, "n".repeat(240));
+    for i in 0..1000 {
+        if i % 10 == 0 {
+            fs.write(&name(i), &[i as u8; 100]).unwrap();
+        } else {
+            fs.create(&name(i)).unwrap();
+        }
+    }
+    let dir_size = fs.metadata("/d/sous").unwrap().size;
+    assert!(dir_size > 60 * FS_BLOCK_SIZE);
+
+    // Modifier un grand répertoire ne réécrit que quelques blocs
+    let mut device = fs.into_inner();
+    for op in 0..3 {
+        let written = device.written;
+        let mut fs = VaultFs::open(device).unwrap();
+        match op {
+            0 => fs.create("/d/sous/nouveau").unwrap(),
+            1 => fs.remove(&name(500)).unwrap(),
+            _ => fs.rename(&name(10), "/d/renomme").unwrap(),
+        }
+        device = fs.into_inner();
+        let blocks = (device.written - written) / (FS_BLOCK_SIZE / 512);
+        assert!(blocks < 24, "{} blocs écrits", blocks);
+    }
+
+    let mut fs = VaultFs::open(device).unwrap();
+    assert_eq!(fs.read("/d/renomme").unwrap(), [10u8; 100]);
+    assert_eq!(fs.read_dir("/d/sous").unwrap().len(), 999);
+    fs.check().unwrap();
+
+    // Suppression de tout l'arbre, en plusieurs transactions si besoin
+    fs.remove_all("/d").unwrap();
+    let stats = fs.statfs();
+    assert_eq!((stats.free_blocks, stats.free_inodes), (empty.free_blocks, empty.free_inodes));
+    assert!(fs.read_dir("/").unwrap().is_empty());
+    fs.check().unwrap();
+}
+
+/// Périphérique qui subit une coupure de courant après un nombre donné de
+/// secteurs écrits ou de vidages. Comme un vrai disque, il garde en cache les
+/// écritures jusqu'au vidage suivant: à la coupure, elles sont toutes perdues
+/// ou, s'il réordonne, seule la dernière atteint le support
+struct PowerCut<D> {
+    inner: D,
+    budget: u64,
+    reorder: bool,
+    pending: Vec<(u64, Vec<u8>)>,
+    cut: bool,
+}
+
+impl<D: BlockDevice> PowerCut<D> {
+    fn new(inner: D, budget: u64, reorder: bool) -> Self {
+        Self { inner, budget, reorder, pending: Vec::new(), cut: false }
+    }
+
+    fn power_off(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        if self.reorder {
+            if let Some((first, data)) = pending.last() {
+                self.inner.write_sectors(*first, data)?;
+            }
+        }
+        self.cut = true;
+        Err(DeepVaultError::Io(std::io::Error::other("coupure de courant")))
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for PowerCut<D> {
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_sectors(first, buf)?;
+        let end = first + (buf.len() / 512) as u64;
+        for (start, data) in &self.pending {
+            let stop = start + (data.len() / 512) as u64;
+            let (from, to) = ((*start).max(first), stop.min(end));
+            if from < to {
+                let src = ((from - start) * 512) as usize;
+                let dst = ((from - first) * 512) as usize;
+                let len = ((to - from) * 512) as usize;
+                buf[dst..dst + len].copy_from_slice(&data[src..src + len]);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, first: u64, buf: &[u8]) -> Result<()> {
+        if self.cut {
+            return Err(DeepVaultError::Io(std::io::Error::other("coupure de courant")));
+        }
+        let count = (buf.len() / 512) as u64;
+        let written = count.min(self.budget);
+        if written > 0 {
+            self.pending.push((first, buf[..written as usize * 512].to_vec()));
+        }
+        self.budget -= written;
+        if written < count {
+            return self.power_off();
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.cut {
+            return Err(DeepVaultError::Io(std::io::Error::other("coupure de courant")));
+        }
+        if self.budget == 0 {
+            return self.power_off();
+        }
+        self.budget -= 1;
+        for (first, data) in std::mem::take(&mut self.pending) {
+            self.inner.write_sectors(first, &data)?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Contenu complet d'un système de fichiers du coffre, répertoires compris
+fn vault_tree<D: BlockDevice>(fs: &mut VaultFs<D>) -> std::collections::BTreeMap<String, Option<Vec<u8>>> {
+    let mut tree = std::collections::BTreeMap::new();
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs.read_dir(&format!("{}/", dir)).unwrap() {
+            let path = format!("{}/{}", dir, entry.name);
+            if entry.metadata.is_dir() {
+                pending.push(path.clone());
+                tree.insert(path, None);
+            } else {
+                tree.insert(path.clone(), Some(fs.read(&path).unwrap()));
+            }
+        }
+    }
+    tree
+}
+
+#[test]
+fn test_vault_power_loss() {
+    use std::fs::{File, OpenOptions};
+
+    type Operation = fn(&mut VaultFs<PowerCut<FileDevice<File>>>) -> Result<()>;
+    let size = 1024 * 1024;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("coffre.img");
+    let open_image = |budget: u64, reorder: bool| {
+        let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        PowerCut::new(FileDevice::new(file, 0, size), budget, reorder)
+    };
+
+    // Image de départ: quelques fichiers, dont un assez fragmenté
+    std::fs::write(&path, vec![0u8; size as usize]).unwrap();
+    let mut fs = VaultFs::format(open_image(u64::MAX, false)).unwrap();
+    fs.create_dir("/docs").unwrap();
+    fs.create_dir("/docs/vide").unwrap();
+    for i in 0..20 {
+        fs.write(&format!("/docs/{}", i), &vec![i as u8; 5000]).unwrap();
+    }
+    for i in (0..20).step_by(2) {
+        fs.remove(&format!("/docs/{}", i)).unwrap();
+    }
+    fs.write("/notes.txt", b"version 1").unwrap();
+    drop(fs);
+    let base = std::fs::read(&path).unwrap();
+
+    let operations: [(&str, Operation); 7] = [
+        ("nouveau fichier", |fs| fs.write("/docs/nouveau", &[7u8; 70_000])),
+        ("remplacement", |fs| fs.write("/notes.txt", &vec![2u8; 30_000])),
+        ("ajout", |fs| fs.write_at("/docs/1", 5000, &[9u8; 9000])),
+        ("troncature", |fs| fs.truncate("/docs/3", 100)),
+        ("renommage", |fs| fs.rename("/docs/5", "/docs/vide/cinq")),
+        ("répertoire", |fs| fs.create_dir("/archives")),
+        ("suppression", |fs| fs.remove_all("/docs")),
+    ];
+    for (name, operation) in operations {
+        // États attendus avant et après l'opération
+        std::fs::write(&path, &base).unwrap();
+        let mut fs = VaultFs::open(open_image(u64::MAX, false)).unwrap();
+        let before = vault_tree(&mut fs);
+        operation(&mut fs).unwrap();
+        let after = vault_tree(&mut fs);
+        assert_ne!(before, after);
+        drop(fs);
+
+        // Coupure après chaque secteur écrit ou vidage, jusqu'à ce que
+        // l'opération aboutisse, en perdant ou en réordonnant le cache
+        let mut replayed = 0;
+        for reorder in [false, true] {
+            for budget in 0.. {
+                std::fs::write(&path, &base).unwrap();
+                let mut fs = VaultFs::open(open_image(budget, reorder)).unwrap();
+                let completed = operation(&mut fs).is_ok();
+                drop(fs);
+
+                let mut fs = VaultFs::open(open_image(u64::MAX, false)).unwrap();
+                fs.check().unwrap_or_else(|e| panic!("{}: coupure au pas {}: {}", name, budget, e));
+                let tree = vault_tree(&mut fs);
+                if completed {
+                    assert_eq!(tree, after, "{}", name);
+                    break;
+                }
+                if tree == after {
+                    replayed += 1;
+                } else {
+                    assert_eq!(tree, before, "{}: coupure au pas {}", name, budget);
+                }
+            }
+        }
+        // Le journal complet est rejoué à l'ouverture suivante
+        assert!(replayed > 0, "{}", name);
+    }
+}